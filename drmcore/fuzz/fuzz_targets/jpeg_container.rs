@@ -0,0 +1,12 @@
+#![no_main]
+
+use drmcore::jpeg::container::JFIFContainer;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(container) = JFIFContainer::try_from(&Vec::from(data)) {
+        let bytes = Vec::try_from(container).unwrap();
+
+        assert_eq!(bytes, data);
+    }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use drmcore::webp_container::{Chunk, RIFFContainer};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(container) = RIFFContainer::try_from(&Vec::from(data)) {
+        assert_eq!(container.to_bytes(), data);
+    }
+});
@@ -0,0 +1,569 @@
+use crate::file_format::{detect_format, FileFormat};
+use crate::file_joiner::join_auto;
+use crate::file_splitter::{split_auto, SplitResult};
+use crate::metadata::{read_metadata, write_metadata};
+use crate::watermark_task::{is_animated_webp, set_target_auto, OriginX, OriginY, WatermarkTask};
+use anyhow::{anyhow, Result};
+use image::{imageops, load_from_memory_with_format, DynamicImage, GenericImageView, ImageFormat};
+use std::io::{Cursor, Read, Write};
+use thiserror::Error;
+use zip::read::ZipFile;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+pub const MANIFEST_NAME: &str = "milf-manifest.txt";
+const MANIFEST_HEADER: &str = "MILFMANIFEST1.0";
+
+// Limits on the decompressed size, a single entry and every entry read from one archive
+const MAX_ENTRY_SIZE: u64 = 256 * 1024 * 1024;
+const MAX_ARCHIVE_SIZE: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ArchiveError {
+    #[error("Invalid archive")]
+    InvalidArchive,
+
+    #[error("Cannot write archive")]
+    CannotWriteArchive,
+
+    #[error("Invalid manifest")]
+    InvalidManifest,
+
+    #[error("Cannot find page : `{0}`")]
+    PageNotFound(String),
+
+    #[error("Cannot watermark page : `{0}`")]
+    CannotWatermarkPage(String),
+
+    #[error("Cannot restore page : `{0}`")]
+    CannotRestorePage(String),
+
+    #[error("Entry too large : `{0}`")]
+    EntryTooLarge(String),
+
+    #[error("Archive too large")]
+    ArchiveTooLarge,
+}
+
+pub struct PagePlacement {
+    pub x: u32,
+    pub y: u32,
+    pub origin_x: OriginX,
+    pub origin_y: OriginY,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct ArchiveManifest {
+    pages: Vec<String>,
+}
+
+impl ArchiveManifest {
+    pub fn get_pages(&self) -> &Vec<String> {
+        &self.pages
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.pages.iter().any(|f| f == name)
+    }
+
+    pub fn push_page(&mut self, name: &str) {
+        self.pages.push(String::from(name));
+    }
+
+    // Header line followed by one page name per line
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = String::from(MANIFEST_HEADER);
+
+        self.pages.iter().for_each(|f| {
+            result.push('\n');
+            result.push_str(f);
+        });
+
+        result.into_bytes()
+    }
+}
+
+impl TryFrom<&[u8]> for ArchiveManifest {
+    type Error = ArchiveError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let text = std::str::from_utf8(value).map_err(|_| ArchiveError::InvalidManifest)?;
+        let mut lines = text.lines();
+
+        if lines.next() != Some(MANIFEST_HEADER) {
+            return Err(ArchiveError::InvalidManifest);
+        }
+
+        Ok(ArchiveManifest {
+            pages: lines.filter(|f| !f.is_empty()).map(String::from).collect(),
+        })
+    }
+}
+
+// Only still images go through the regular watermark/join cycle
+fn is_page(inp: &[u8]) -> bool {
+    match detect_format(inp) {
+        Ok(FileFormat::Jpeg) | Ok(FileFormat::Png) | Ok(FileFormat::Tiff) => true,
+        Ok(FileFormat::WebP) => !is_animated_webp(inp),
+        _ => false,
+    }
+}
+
+fn encode_image(img: &Option<DynamicImage>, format: ImageFormat) -> Result<Vec<u8>> {
+    let img = img.as_ref().ok_or(anyhow!("Task not yet processed"))?;
+    let mut bytes: Vec<u8> = Vec::new();
+    img.write_to(&mut Cursor::new(&mut bytes), format)?;
+
+    Ok(bytes)
+}
+
+fn watermark_page(
+    inp: &[u8],
+    watermark: &DynamicImage,
+    placement: &PagePlacement,
+    enc_key: &[u8; 32],
+) -> Result<Vec<u8>> {
+    let mut watermark_task = WatermarkTask::new();
+    set_target_auto(&mut watermark_task, inp)?;
+    watermark_task.set_watermark(Some(watermark.clone()));
+    watermark_task.set_position(
+        placement.x,
+        placement.y,
+        placement.origin_x,
+        placement.origin_y,
+    );
+
+    let format = watermark_task
+        .get_target_format()
+        .ok_or(anyhow!("Target format unknown"))?
+        .get_image_format();
+    let target_dim = watermark_task.get_target_dimension().unwrap();
+    let watermark_dim = watermark_task.get_watermark_dimension().unwrap();
+    let position = watermark_task.get_absolute_watermark_position().unwrap();
+
+    let fits =
+        |start: u32, size: u32, limit: u32| start.checked_add(size).is_some_and(|f| f <= limit);
+
+    if !fits(position.x, watermark_dim.width, target_dim.width)
+        || !fits(position.y, watermark_dim.height, target_dim.height)
+    {
+        return Err(anyhow!("Watermark does not fit the page"));
+    }
+
+    watermark_task.process()?;
+
+    let output = encode_image(watermark_task.get_output(), format)?;
+    let output = write_metadata(&output, &watermark_task.get_output_metadata()?)?;
+    let mut old_bytes = encode_image(watermark_task.get_old_section(), format)?;
+    let watermark_pos: [u8; 8] = position.into();
+    let watermark_dim: [u8; 8] = watermark_dim.into();
+    old_bytes.extend(watermark_pos);
+    old_bytes.extend(watermark_dim);
+
+    Ok(join_auto(&output, &old_bytes, enc_key)?)
+}
+
+// Re-encoding through image also drops the custom block from the page. The page is decoded and
+// encoded again, so a restored JPEG page has the original pixels only up to the lossy encoding
+// and is never byte-identical to the page before watermarking
+fn restore_page(inp: &[u8], enc_key: &[u8; 32]) -> Result<Vec<u8>> {
+    let format = detect_format(inp)?.get_image_format();
+    let split_result = split_auto(&Vec::from(inp), enc_key)?;
+    let section = load_from_memory_with_format(&split_result.old_section_img, format)?;
+    let mut img = load_from_memory_with_format(inp, format)?;
+
+    if section.dimensions() != (split_result.dimension.width, split_result.dimension.height) {
+        return Err(anyhow!("Section does not match payload dimension"));
+    }
+
+    imageops::replace(
+        &mut img,
+        &section,
+        split_result.position.x as i64,
+        split_result.position.y as i64,
+    );
+
+    let mut bytes: Vec<u8> = Vec::new();
+    img.write_to(&mut Cursor::new(&mut bytes), format)?;
+
+    Ok(write_metadata(&bytes, &read_metadata(inp))?)
+}
+
+fn open_archive(inp: &[u8]) -> Result<ZipArchive<Cursor<&[u8]>>, ArchiveError> {
+    ZipArchive::new(Cursor::new(inp)).map_err(|_| ArchiveError::InvalidArchive)
+}
+
+// The declared size is checked before decompressing, and reading stops at it so an entry
+// cannot inflate past what its header claims
+fn read_file(file: &mut ZipFile<'_>) -> Result<Vec<u8>, ArchiveError> {
+    let size = file.size();
+
+    if size > MAX_ENTRY_SIZE {
+        return Err(ArchiveError::EntryTooLarge(String::from(file.name())));
+    }
+
+    let mut bytes = Vec::with_capacity(size as usize);
+    let mut rest = [0u8; 1];
+
+    file.take(size)
+        .read_to_end(&mut bytes)
+        .map_err(|_| ArchiveError::InvalidArchive)?;
+
+    let extra = file
+        .read(&mut rest)
+        .map_err(|_| ArchiveError::InvalidArchive)?;
+
+    if bytes.len() as u64 != size || extra != 0 {
+        return Err(ArchiveError::InvalidArchive);
+    }
+
+    Ok(bytes)
+}
+
+// Adds an entry to the bytes read so far from one archive
+fn add_read_size(total: &mut u64, bytes: &[u8]) -> Result<(), ArchiveError> {
+    *total += bytes.len() as u64;
+
+    if *total > MAX_ARCHIVE_SIZE {
+        return Err(ArchiveError::ArchiveTooLarge);
+    }
+
+    Ok(())
+}
+
+fn read_entry(
+    archive: &mut ZipArchive<Cursor<&[u8]>>,
+    name: &str,
+) -> Result<Vec<u8>, ArchiveError> {
+    let mut file = archive
+        .by_name(name)
+        .map_err(|_| ArchiveError::PageNotFound(String::from(name)))?;
+
+    read_file(&mut file)
+}
+
+pub fn get_manifest(inp: &[u8]) -> Result<ArchiveManifest, ArchiveError> {
+    let mut archive = open_archive(inp)?;
+    let bytes = read_entry(&mut archive, MANIFEST_NAME).map_err(|err| match err {
+        ArchiveError::PageNotFound(_) => ArchiveError::InvalidManifest,
+        err => err,
+    })?;
+
+    ArchiveManifest::try_from(&bytes[..])
+}
+
+// Pages are visited in archive order, the placement callback receives the page index and
+// entry name and returns None to leave that page untouched
+pub fn watermark_archive<F>(
+    inp: &[u8],
+    watermark: &DynamicImage,
+    placement: F,
+    enc_key: &[u8; 32],
+) -> Result<Vec<u8>, ArchiveError>
+where
+    F: Fn(usize, &str) -> Option<PagePlacement>,
+{
+    let mut archive = open_archive(inp)?;
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let mut manifest = ArchiveManifest::default();
+    let mut page_index = 0usize;
+    let mut read_size = 0u64;
+
+    for index in 0..archive.len() {
+        let mut file = archive
+            .by_index(index)
+            .map_err(|_| ArchiveError::InvalidArchive)?;
+        let name = String::from(file.name());
+
+        if name == MANIFEST_NAME {
+            continue;
+        }
+
+        let bytes = match file.is_dir() {
+            true => Vec::new(),
+            false => read_file(&mut file)?,
+        };
+
+        add_read_size(&mut read_size, &bytes)?;
+
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .last_modified_time(file.last_modified());
+        drop(file);
+
+        let page_placement = if is_page(&bytes) {
+            page_index += 1;
+            placement(page_index - 1, &name)
+        } else {
+            None
+        };
+
+        match page_placement {
+            Some(page_placement) => {
+                let output = watermark_page(&bytes, watermark, &page_placement, enc_key)
+                    .map_err(|_| ArchiveError::CannotWatermarkPage(name.clone()))?;
+
+                writer
+                    .start_file(name.as_str(), options)
+                    .map_err(|_| ArchiveError::CannotWriteArchive)?;
+                writer
+                    .write_all(&output)
+                    .map_err(|_| ArchiveError::CannotWriteArchive)?;
+                manifest.push_page(&name);
+            }
+            None => {
+                let file = archive
+                    .by_index_raw(index)
+                    .map_err(|_| ArchiveError::InvalidArchive)?;
+
+                writer
+                    .raw_copy_file(file)
+                    .map_err(|_| ArchiveError::CannotWriteArchive)?;
+            }
+        }
+    }
+
+    writer
+        .start_file(MANIFEST_NAME, FileOptions::default())
+        .map_err(|_| ArchiveError::CannotWriteArchive)?;
+    writer
+        .write_all(&manifest.to_bytes())
+        .map_err(|_| ArchiveError::CannotWriteArchive)?;
+
+    let output = writer
+        .finish()
+        .map_err(|_| ArchiveError::CannotWriteArchive)?;
+
+    Ok(output.into_inner())
+}
+
+pub fn split_archive_page(
+    inp: &[u8],
+    name: &str,
+    enc_key: &[u8; 32],
+) -> Result<SplitResult, ArchiveError> {
+    let mut archive = open_archive(inp)?;
+    let bytes = read_entry(&mut archive, name)?;
+
+    split_auto(&bytes, enc_key).map_err(|_| ArchiveError::CannotRestorePage(String::from(name)))
+}
+
+pub fn restore_archive_page(
+    inp: &[u8],
+    name: &str,
+    enc_key: &[u8; 32],
+) -> Result<Vec<u8>, ArchiveError> {
+    let mut archive = open_archive(inp)?;
+    let bytes = read_entry(&mut archive, name)?;
+
+    restore_page(&bytes, enc_key).map_err(|_| ArchiveError::CannotRestorePage(String::from(name)))
+}
+
+// Restores every page listed in the manifest and drops the manifest itself
+pub fn restore_archive(inp: &[u8], enc_key: &[u8; 32]) -> Result<Vec<u8>, ArchiveError> {
+    let manifest = get_manifest(inp)?;
+    let mut archive = open_archive(inp)?;
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let mut read_size = 0u64;
+
+    for index in 0..archive.len() {
+        let mut file = archive
+            .by_index(index)
+            .map_err(|_| ArchiveError::InvalidArchive)?;
+        let name = String::from(file.name());
+
+        if name == MANIFEST_NAME {
+            continue;
+        }
+
+        if !manifest.contains(&name) {
+            drop(file);
+
+            let file = archive
+                .by_index_raw(index)
+                .map_err(|_| ArchiveError::InvalidArchive)?;
+
+            writer
+                .raw_copy_file(file)
+                .map_err(|_| ArchiveError::CannotWriteArchive)?;
+            continue;
+        }
+
+        let bytes = read_file(&mut file)?;
+
+        add_read_size(&mut read_size, &bytes)?;
+
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Stored)
+            .last_modified_time(file.last_modified());
+        let output = restore_page(&bytes, enc_key)
+            .map_err(|_| ArchiveError::CannotRestorePage(name.clone()))?;
+
+        writer
+            .start_file(name.as_str(), options)
+            .map_err(|_| ArchiveError::CannotWriteArchive)?;
+        writer
+            .write_all(&output)
+            .map_err(|_| ArchiveError::CannotWriteArchive)?;
+    }
+
+    let output = writer
+        .finish()
+        .map_err(|_| ArchiveError::CannotWriteArchive)?;
+
+    Ok(output.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encryption::BASIC_KEY;
+    use image::{Rgba, RgbaImage};
+
+    fn sample_page(format: ImageFormat) -> Vec<u8> {
+        let img = RgbaImage::from_fn(32, 24, |x, y| {
+            Rgba([(x * 8) as u8, (y * 10) as u8, 64, 255])
+        });
+        let img = DynamicImage::ImageRgba8(img);
+        let mut bytes: Vec<u8> = Vec::new();
+
+        match format {
+            ImageFormat::Jpeg => DynamicImage::ImageRgb8(img.to_rgb8())
+                .write_to(&mut Cursor::new(&mut bytes), format)
+                .unwrap(),
+            _ => img.write_to(&mut Cursor::new(&mut bytes), format).unwrap(),
+        }
+
+        bytes
+    }
+
+    fn sample_archive() -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let entries = [
+            ("ComicInfo.xml", Vec::from(&b"<ComicInfo/>"[..])),
+            ("001.png", sample_page(ImageFormat::Png)),
+            ("002.jpg", sample_page(ImageFormat::Jpeg)),
+            ("003.png", sample_page(ImageFormat::Png)),
+        ];
+
+        for (name, bytes) in entries {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(&bytes).unwrap();
+        }
+
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn sample_watermark() -> DynamicImage {
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([255, 0, 0, 255])))
+    }
+
+    #[test]
+    fn manifest_round_trip() {
+        let mut manifest = ArchiveManifest::default();
+        manifest.push_page("001.jpg");
+        manifest.push_page("pages/002.webp");
+
+        let bytes = manifest.to_bytes();
+
+        assert_eq!(ArchiveManifest::try_from(&bytes[..]), Ok(manifest));
+        assert_eq!(
+            ArchiveManifest::try_from(&b"001.jpg"[..]),
+            Err(ArchiveError::InvalidManifest)
+        );
+    }
+
+    #[test]
+    fn watermark_and_restore_archive() {
+        let inp = sample_archive();
+        let placement = |index: usize, _: &str| {
+            if index == 2 {
+                return None;
+            }
+
+            Some(PagePlacement {
+                x: index as u32 + 1,
+                y: 2,
+                origin_x: OriginX::Left,
+                origin_y: OriginY::Top,
+            })
+        };
+        let output = watermark_archive(&inp, &sample_watermark(), placement, &BASIC_KEY).unwrap();
+        let manifest = get_manifest(&output).unwrap();
+
+        assert_eq!(manifest.get_pages(), &["001.png", "002.jpg"]);
+
+        let mut archive = open_archive(&output).unwrap();
+        let mut original = open_archive(&inp).unwrap();
+
+        assert_eq!(
+            read_entry(&mut archive, "ComicInfo.xml").unwrap(),
+            b"<ComicInfo/>"
+        );
+        assert_eq!(
+            read_entry(&mut archive, "003.png").unwrap(),
+            read_entry(&mut original, "003.png").unwrap()
+        );
+
+        let split_result = split_archive_page(&output, "002.jpg", &BASIC_KEY).unwrap();
+
+        assert_eq!(split_result.position.x, 2);
+        assert_eq!(split_result.position.y, 2);
+        assert_eq!(split_result.dimension.width, 4);
+
+        let page = restore_archive_page(&output, "001.png", &BASIC_KEY).unwrap();
+        let original_page = read_entry(&mut original, "001.png").unwrap();
+        let page = load_from_memory_with_format(&page, ImageFormat::Png).unwrap();
+        let original_page = load_from_memory_with_format(&original_page, ImageFormat::Png).unwrap();
+
+        assert_eq!(page.to_rgba8(), original_page.to_rgba8());
+
+        let restored = restore_archive(&output, &BASIC_KEY).unwrap();
+        let mut restored_archive = open_archive(&restored).unwrap();
+
+        assert_eq!(restored_archive.len(), 4);
+        assert!(restored_archive.by_name(MANIFEST_NAME).is_err());
+        assert!(split_auto(
+            &read_entry(&mut restored_archive, "001.png").unwrap(),
+            &BASIC_KEY
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn watermark_archive_failed() {
+        let inp = sample_archive();
+        let placement = |_: usize, _: &str| {
+            Some(PagePlacement {
+                x: 30,
+                y: 0,
+                origin_x: OriginX::Left,
+                origin_y: OriginY::Top,
+            })
+        };
+
+        assert_eq!(
+            watermark_archive(&inp, &sample_watermark(), placement, &BASIC_KEY),
+            Err(ArchiveError::CannotWatermarkPage(String::from("001.png")))
+        );
+        assert_eq!(
+            watermark_archive(b"not a zip", &sample_watermark(), placement, &BASIC_KEY),
+            Err(ArchiveError::InvalidArchive)
+        );
+        assert_eq!(get_manifest(&inp), Err(ArchiveError::InvalidManifest));
+
+        let overflow = |_: usize, _: &str| {
+            Some(PagePlacement {
+                x: u32::MAX,
+                y: 0,
+                origin_x: OriginX::Left,
+                origin_y: OriginY::Top,
+            })
+        };
+
+        assert_eq!(
+            watermark_archive(&inp, &sample_watermark(), overflow, &BASIC_KEY),
+            Err(ArchiveError::CannotWatermarkPage(String::from("001.png")))
+        );
+    }
+}
@@ -5,7 +5,7 @@ use drmcore::jpeg::container::ToBytes;
 use std::fs::{read, write as writefs};
 
 pub fn main() -> Result<()> {
-    let jpeg_test = read("./test.jpeg")?;
+    let jpeg_test = read(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/test.jpeg"))?;
     println!("Hi");
     println!("File length : {}", jpeg_test.len());
 
@@ -17,19 +17,21 @@ pub fn main() -> Result<()> {
             println!("Segment count : {}", segments.len());
             for segment in segments {
                 match segment {
-                    JFIFSegment::SOI => println!("Segment SOI, Len : {}", JFIFSegment::SOI.to_bytes().len()),
-                    JFIFSegment::EOI => println!("Segment EOI, Len : {}", JFIFSegment::EOI.to_bytes().len()),
-                    JFIFSegment::DQT(_) => println!("Segment DQT, Len : {}", segment.to_bytes().len()),
-                    JFIFSegment::DHT(_) => println!("Segment DHT, Len : {}", segment.to_bytes().len()),
-                    JFIFSegment::SOF2(_) => println!("Segment SOF2, Len : {}", segment.to_bytes().len()),
-                    JFIFSegment::SOS(_) => println!("Segment SOS, Len : {}", segment.to_bytes().len()),
+                    JFIFSegment::SOI => println!("Segment SOI, Len : {}", JFIFSegment::SOI.to_bytes()?.len()),
+                    JFIFSegment::EOI => println!("Segment EOI, Len : {}", JFIFSegment::EOI.to_bytes()?.len()),
+                    JFIFSegment::DQT(_) => println!("Segment DQT, Len : {}", segment.to_bytes()?.len()),
+                    JFIFSegment::DHT(_) => println!("Segment DHT, Len : {}", segment.to_bytes()?.len()),
+                    JFIFSegment::SOF2(_) => println!("Segment SOF2, Len : {}", segment.to_bytes()?.len()),
+                    JFIFSegment::SOF(sof, _) => println!("Segment SOF{}, Len : {}", sof, segment.to_bytes()?.len()),
+                    JFIFSegment::SOS(_) => println!("Segment SOS, Len : {}", segment.to_bytes()?.len()),
                     JFIFSegment::IMGDATA(seg) => println!("Segment IMGDATA, Len : {}", seg.len()),
-                    JFIFSegment::APP(app, _) => println!("Segment APP{}, Len : {}", app, segment.to_bytes().len()),
+                    JFIFSegment::TRAILER(seg) => println!("Segment TRAILER, Len : {}", seg.len()),
+                    JFIFSegment::APP(app, _) => println!("Segment APP{}, Len : {}", app, segment.to_bytes()?.len()),
                     _ => println!("Unknown segment {:?}", segment),
                 }
             }
 
-            let convert_back = Vec::from(container);
+            let convert_back = Vec::try_from(container)?;
 
             println!("Output length : {}", convert_back.len());
             writefs("output.jpeg", &convert_back[..])?;
@@ -18,10 +18,10 @@ fn get_size_to_vec(size: usize) -> [u8; 4] {
 
 fn get_size_from_vec(size: &[u8]) -> usize {
     let mut real_size: usize = 0;
-    real_size = real_size | ((size[0] as usize) << 24);
-    real_size = real_size | ((size[1] as usize) << 16);
-    real_size = real_size | ((size[2] as usize) << 8);
-    real_size = real_size | (size[3] as usize);
+    real_size |= (size[0] as usize) << 24 ;
+    real_size |= (size[1] as usize) << 16 ;
+    real_size |= (size[2] as usize) << 8 ;
+    real_size |= size[3] as usize ;
 
     real_size
 }
@@ -63,7 +63,7 @@ fn transform_block(block: &[u8]) -> GenericArray<u8, U16> {
     new_block
 }
 
-fn get_vec_without_size(inp: &Vec<u8>) -> Option<Vec<u8>> {
+fn get_vec_without_size(inp: &[u8]) -> Option<Vec<u8>> {
     if inp.len() < 5 {
         return None;
     }
@@ -75,14 +75,14 @@ fn get_vec_without_size(inp: &Vec<u8>) -> Option<Vec<u8>> {
         return None;
     }
 
-    let mut result = inp.clone().split_off(4);
+    let mut result = inp.to_vec().split_off(4);
     let _ = result.split_off(real_size);
 
     Some(result)
 }
 
 pub fn encrypt(inp: &[u8], key: &[u8]) -> Vec<u8> {
-    let copy_inp = get_vec_with_size(&inp);
+    let copy_inp = get_vec_with_size(inp);
     let iter = copy_inp.chunks(16);
     let key_block = transform_key(key);
     let mut blocks: Vec<GenericArray<u8, U16>> = Vec::new();
@@ -0,0 +1,199 @@
+use crate::file_joiner::be_to_usize;
+use crate::gif_container::{GIF87A_HEADER, GIF89A_HEADER};
+use crate::isobmff_container::HEIF_BRANDS;
+use crate::png_container::PNG_SIGNATURE;
+use image::ImageFormat;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq)]
+pub enum FormatError {
+    #[error("Unsupported format")]
+    UnsupportedFormat,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum FileFormat {
+    Jpeg,
+    WebP,
+    Png,
+    Heif,
+    Gif,
+    Tiff,
+}
+
+impl FileFormat {
+    pub fn get_image_format(&self) -> ImageFormat {
+        match self {
+            FileFormat::Jpeg => ImageFormat::Jpeg,
+            FileFormat::WebP => ImageFormat::WebP,
+            FileFormat::Png => ImageFormat::Png,
+            FileFormat::Heif => ImageFormat::Avif,
+            FileFormat::Gif => ImageFormat::Gif,
+            FileFormat::Tiff => ImageFormat::Tiff,
+        }
+    }
+}
+
+fn is_jpeg(inp: &[u8]) -> bool {
+    inp.get(0..2) == Some(&[0xFF, 0xD8])
+}
+
+fn is_webp(inp: &[u8]) -> bool {
+    let riff_header = inp.get(0..4);
+    let frame_id = inp.get(8..12);
+
+    riff_header == Some("RIFF".as_bytes()) && frame_id == Some("WEBP".as_bytes())
+}
+
+fn is_png(inp: &[u8]) -> bool {
+    inp.get(0..8) == Some(&PNG_SIGNATURE)
+}
+
+fn is_heif(inp: &[u8]) -> bool {
+    let box_size = inp.get(0..4).map(be_to_usize).unwrap_or(0);
+    let box_type = inp.get(4..8);
+    let brands = inp.get(8..box_size.min(inp.len())).unwrap_or(&[]);
+
+    if box_type != Some("ftyp".as_bytes()) {
+        return false;
+    }
+
+    // Major brand, minor version then compatible brands
+    brands
+        .chunks_exact(4)
+        .enumerate()
+        .filter(|(i, _)| *i != 1)
+        .any(|(_, f)| HEIF_BRANDS.iter().any(|p| p.as_bytes() == f))
+}
+
+fn is_gif(inp: &[u8]) -> bool {
+    let header = inp.get(0..6);
+
+    header == Some(&GIF87A_HEADER[..]) || header == Some(&GIF89A_HEADER[..])
+}
+
+fn is_tiff(inp: &[u8]) -> bool {
+    let header = inp.get(0..4);
+
+    header == Some(&[0x49, 0x49, 0x2A, 0x00]) || header == Some(&[0x4D, 0x4D, 0x00, 0x2A])
+}
+
+pub fn detect_format(inp: &[u8]) -> Result<FileFormat, FormatError> {
+    if is_jpeg(inp) {
+        return Ok(FileFormat::Jpeg);
+    }
+
+    if is_webp(inp) {
+        return Ok(FileFormat::WebP);
+    }
+
+    if is_png(inp) {
+        return Ok(FileFormat::Png);
+    }
+
+    if is_heif(inp) {
+        return Ok(FileFormat::Heif);
+    }
+
+    if is_gif(inp) {
+        return Ok(FileFormat::Gif);
+    }
+
+    if is_tiff(inp) {
+        return Ok(FileFormat::Tiff);
+    }
+
+    Err(FormatError::UnsupportedFormat)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_jpeg_format() {
+        let inp = [0xFFu8, 0xD8, 0xFF, 0xE0, 0x00, 0x10];
+        let format = detect_format(&inp);
+
+        assert_eq!(format, Ok(FileFormat::Jpeg));
+        assert_eq!(format.unwrap().get_image_format(), ImageFormat::Jpeg);
+    }
+
+    #[test]
+    fn detect_webp_format() {
+        let inp = [
+            0x52u8, 0x49, 0x46, 0x46, 0x04, 0, 0, 0, 0x57, 0x45, 0x42, 0x50,
+        ];
+        let format = detect_format(&inp);
+
+        assert_eq!(format, Ok(FileFormat::WebP));
+        assert_eq!(format.unwrap().get_image_format(), ImageFormat::WebP);
+    }
+
+    #[test]
+    fn detect_png_format() {
+        let inp = [0x89u8, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00];
+        let format = detect_format(&inp);
+
+        assert_eq!(format, Ok(FileFormat::Png));
+        assert_eq!(format.unwrap().get_image_format(), ImageFormat::Png);
+    }
+
+    #[test]
+    fn detect_heif_format() {
+        let inp = [
+            0x00u8, 0x00, 0x00, 0x14, 0x66, 0x74, 0x79, 0x70, 0x6D, 0x69, 0x66, 0x31, 0, 0, 0, 0,
+            0x61, 0x76, 0x69, 0x66,
+        ];
+        let format = detect_format(&inp);
+
+        assert_eq!(format, Ok(FileFormat::Heif));
+        assert_eq!(format.unwrap().get_image_format(), ImageFormat::Avif);
+    }
+
+    #[test]
+    fn detect_gif_format() {
+        let inp = [0x47u8, 0x49, 0x46, 0x38, 0x39, 0x61, 0x01, 0x00];
+        let format = detect_format(&inp);
+
+        assert_eq!(format, Ok(FileFormat::Gif));
+        assert_eq!(format.unwrap().get_image_format(), ImageFormat::Gif);
+
+        let inp = [0x47u8, 0x49, 0x46, 0x38, 0x37, 0x61];
+        assert_eq!(detect_format(&inp), Ok(FileFormat::Gif));
+    }
+
+    #[test]
+    fn detect_tiff_format() {
+        let inp = [0x49u8, 0x49, 0x2A, 0x00, 0x08, 0, 0, 0];
+        let format = detect_format(&inp);
+
+        assert_eq!(format, Ok(FileFormat::Tiff));
+        assert_eq!(format.unwrap().get_image_format(), ImageFormat::Tiff);
+
+        let inp = [0x4Du8, 0x4D, 0x00, 0x2A, 0, 0, 0, 0x08];
+        assert_eq!(detect_format(&inp), Ok(FileFormat::Tiff));
+
+        let inp = [0x49u8, 0x49, 0x2B, 0x00];
+        assert_eq!(detect_format(&inp), Err(FormatError::UnsupportedFormat));
+    }
+
+    #[test]
+    fn detect_unsupported_format() {
+        let inp = [
+            0x52u8, 0x49, 0x46, 0x46, 0x04, 0, 0, 0, 0x57, 0x41, 0x56, 0x45,
+        ];
+        assert_eq!(detect_format(&inp), Err(FormatError::UnsupportedFormat));
+
+        let inp = [0x47u8, 0x49, 0x46, 0x38, 0x38, 0x61];
+        assert_eq!(detect_format(&inp), Err(FormatError::UnsupportedFormat));
+
+        let inp = [
+            0x00u8, 0x00, 0x00, 0x10, 0x66, 0x74, 0x79, 0x70, 0x69, 0x73, 0x6F, 0x6D, 0, 0, 0, 0,
+        ];
+        assert_eq!(detect_format(&inp), Err(FormatError::UnsupportedFormat));
+
+        let inp = [0xFFu8];
+        assert_eq!(detect_format(&inp), Err(FormatError::UnsupportedFormat));
+    }
+}
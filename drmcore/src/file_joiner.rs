@@ -1,6 +1,13 @@
 use crate::encryption::encrypt;
+use crate::file_format::{detect_format, FileFormat};
+use crate::gif_container::{GIFBlock, GIFContainer, CUSTOM_APPLICATION_ID};
+use crate::isobmff_container::{ISOBMFFContainer, ISOBox, CUSTOM_BOX_UUID};
 use crate::jpeg::container::{JFIFContainer, JFIFSegment};
-use crate::jpeg::custom_segment::{split_bytes, CustomSegment};
+use crate::jpeg::custom_segment::{split_stream_bytes, CustomSegment};
+use crate::payload_stream::PayloadStream;
+use crate::png_container::{PNGChunk, PNGContainer, CUSTOM_CHUNK_TYPE};
+use crate::tiff_container::{TIFFContainer, TIFFEntry, CUSTOM_TAG};
+use crate::wav_container::{WAVContainer, CUSTOM_CHUNK_ID as WAV_CHUNK_ID};
 use crate::webp_container::{Chunk, RIFFContainer, RegularChunk};
 use thiserror::Error;
 
@@ -12,110 +19,135 @@ pub enum JoinError {
     #[error("Invalid Jpeg container")]
     InvalidJpegFile,
 
+    #[error("Invalid Png file")]
+    InvalidPngFile,
+
+    #[error("Invalid HEIF file")]
+    InvalidHeifFile,
+
+    #[error("Invalid Gif file")]
+    InvalidGifFile,
+
+    #[error("Invalid Wav file")]
+    InvalidWavFile,
+
+    #[error("Invalid Tiff file")]
+    InvalidTiffFile,
+
     #[error("Cannot bind to jpeg file")]
     CannotInsertCustomSegment,
+
+    #[error("Unsupported format")]
+    UnsupportedFormat,
+}
+
+// Where the payload goes, placing it early lets a streaming reader restore the image before
+// the image data has been downloaded
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum PayloadPlacement {
+    Early,
+    #[default]
+    AfterMetadata,
+    End,
 }
 
 pub fn le_to_u32(inp: &[u8]) -> u32 {
     let len = inp.len();
-    let output = match len {
+
+    match len {
         0 => 0,
         1 => {
-            return inp[0].into();
+            inp[0].into()
         }
         2 => {
             let mut out: u32 = inp[0].into();
-            out = (inp[1] as u32) << 8 | out;
+            out |= (inp[1] as u32) << 8;
 
-            return out;
+            out
         }
         3 => {
             let mut out: u32 = inp[0].into();
-            out = (inp[1] as u32) << 8 | out;
-            out = (inp[2] as u32) << 16 | out;
+            out |= (inp[1] as u32) << 8;
+            out |= (inp[2] as u32) << 16;
 
-            return out;
+            out
         }
         4 => {
             let mut out: u32 = inp[0].into();
-            out = (inp[1] as u32) << 8 | out;
-            out = (inp[2] as u32) << 16 | out;
-            out = (inp[3] as u32) << 24 | out;
+            out |= (inp[1] as u32) << 8;
+            out |= (inp[2] as u32) << 16;
+            out |= (inp[3] as u32) << 24;
 
-            return out;
+            out
         }
-        _ => std::u32::MAX,
-    };
-
-    output
+        _ => u32::MAX,
+    }
 }
 
 pub fn be_to_usize(inp: &[u8]) -> usize {
     let len = inp.len();
-    let output = match len {
+
+    match len {
         0 => 0,
         1 => {
-            return inp[0].into();
+            inp[0].into()
         }
         2 => {
             let mut out: usize = inp[1].into();
-            out = (inp[0] as usize) << 8 | out;
+            out |= (inp[0] as usize) << 8;
 
-            return out;
+            out
         }
         3 => {
             let mut out: usize = inp[2].into();
-            out = (inp[1] as usize) << 8 | out;
-            out = (inp[0] as usize) << 16 | out;
+            out |= (inp[1] as usize) << 8;
+            out |= (inp[0] as usize) << 16;
 
-            return out;
+            out
         }
         4 => {
             let mut out: usize = inp[3].into();
-            out = (inp[2] as usize) << 8 | out;
-            out = (inp[1] as usize) << 16 | out;
-            out = (inp[0] as usize) << 24 | out;
+            out |= (inp[2] as usize) << 8;
+            out |= (inp[1] as usize) << 16;
+            out |= (inp[0] as usize) << 24;
 
-            return out;
+            out
         }
-        _ => std::usize::MAX,
-    };
-
-    output
+        _ => usize::MAX,
+    }
 }
 
 pub fn le_to_usize(inp: &[u8]) -> usize {
     let len = inp.len();
-    let output = match len {
+
+    match len {
         0 => 0,
         1 => {
-            return inp[0].into();
+            inp[0].into()
         }
         2 => {
             let mut out: usize = inp[0].into();
-            out = (inp[1] as usize) << 8 | out;
+            out |= (inp[1] as usize) << 8;
 
-            return out;
+            out
         }
         3 => {
             let mut out: usize = inp[0].into();
-            out = (inp[1] as usize) << 8 | out;
-            out = (inp[2] as usize) << 16 | out;
+            out |= (inp[1] as usize) << 8;
+            out |= (inp[2] as usize) << 16;
 
-            return out;
+            out
         }
         4 => {
             let mut out: usize = inp[0].into();
-            out = (inp[1] as usize) << 8 | out;
-            out = (inp[2] as usize) << 16 | out;
-            out = (inp[3] as usize) << 24 | out;
+            out |= (inp[1] as usize) << 8;
+            out |= (inp[2] as usize) << 16;
+            out |= (inp[3] as usize) << 24;
 
-            return out;
+            out
         }
-        _ => std::usize::MAX,
-    };
-
-    output
+        _ => usize::MAX,
+    }
 }
 
 pub fn usize_to_le(inp: usize) -> [u8; 4] {
@@ -141,53 +173,175 @@ pub fn usize_to_be(inp: usize) -> [u8; 4] {
 }
 
 pub fn join_webp(inp: &[u8], target: &[u8], enc_key: &[u8; 32]) -> Result<Vec<u8>, JoinError> {
+    join_webp_with_placement(inp, target, enc_key, PayloadPlacement::End)
+}
+
+pub fn join_webp_with_placement(
+    inp: &[u8],
+    target: &[u8],
+    enc_key: &[u8; 32],
+    placement: PayloadPlacement,
+) -> Result<Vec<u8>, JoinError> {
+    join_webp_stream(inp, target, enc_key, placement, &PayloadStream::default())
+}
+
+// Streams are independent, joining one keeps the payloads of the others
+pub fn join_webp_stream(
+    inp: &[u8],
+    target: &[u8],
+    enc_key: &[u8; 32],
+    placement: PayloadPlacement,
+    stream: &PayloadStream,
+) -> Result<Vec<u8>, JoinError> {
     let inp_vec = Vec::from(inp);
     let target_vec = encrypt(target, enc_key);
     let mut inp_container =
         RIFFContainer::try_from(&inp_vec).map_err(|_| JoinError::InvalidWebpFile)?;
 
-    let regular = RegularChunk {
-        chunk_data: target_vec,
-        chunk_id: String::from("milf"),
-    };
+    inp_container
+        .ensure_extended()
+        .map_err(|_| JoinError::InvalidWebpFile)?;
+
+    let regular = stream.to_chunk(target_vec);
+    let position = inp_container.get_payload_position(placement);
 
-    inp_container.push_subchunk(Box::new(regular));
+    inp_container.insert_subchunk(position, Box::new(regular));
 
     Ok(inp_container.to_bytes())
 }
 
 pub fn join_jpeg(inp: &[u8], target: &[u8], enc_key: &[u8; 32]) -> Result<Vec<u8>, JoinError> {
+    join_jpeg_with_placement(inp, target, enc_key, PayloadPlacement::AfterMetadata)
+}
+
+pub fn join_jpeg_with_placement(
+    inp: &[u8],
+    target: &[u8],
+    enc_key: &[u8; 32],
+    placement: PayloadPlacement,
+) -> Result<Vec<u8>, JoinError> {
+    join_jpeg_stream(inp, target, enc_key, placement, &PayloadStream::default())
+}
+
+pub fn join_jpeg_stream(
+    inp: &[u8],
+    target: &[u8],
+    enc_key: &[u8; 32],
+    placement: PayloadPlacement,
+    stream: &PayloadStream,
+) -> Result<Vec<u8>, JoinError> {
     let inp_vec = Vec::from(inp);
     let inp_container = JFIFContainer::try_from(&inp_vec);
     let target_vec = encrypt(target, enc_key);
-    let custom_segments: Vec<CustomSegment> = split_bytes(&target_vec);
+    let custom_segments: Vec<CustomSegment> = split_stream_bytes(&target_vec, stream.get_id());
 
-    if let Err(_) = inp_container {
+    if inp_container.is_err() {
         return Err(JoinError::InvalidJpegFile);
     }
 
     let mut inp_container = inp_container.unwrap();
+    let (mpf_images, trailer_suffix) = inp_container.get_mpf_parts();
     let app_segment: Vec<JFIFSegment> = custom_segments
         .iter()
         .map(|f| {
-            return JFIFSegment::from(f);
+            f.to_segment(stream.get_namespace())
         })
         .collect();
 
-    for f in app_segment {
-        let result = inp_container.put_custom_segment(f);
+    inp_container
+        .put_custom_segments(app_segment, placement)
+        .ok_or(JoinError::CannotInsertCustomSegment)?;
 
-        if result == None {
-            return Err(JoinError::CannotInsertCustomSegment);
-        }
-    }
+    // The custom segments move the secondary images, so their MP entries are rewritten. A stale
+    // MPF segment is dropped
+    inp_container
+        .set_mpf_images(&mpf_images, trailer_suffix)
+        .map_err(|_| JoinError::InvalidJpegFile)?;
+
+    Vec::try_from(inp_container).map_err(|_| JoinError::CannotInsertCustomSegment)
+}
+
+pub fn join_png(inp: &[u8], target: &[u8], enc_key: &[u8; 32]) -> Result<Vec<u8>, JoinError> {
+    let inp_vec = Vec::from(inp);
+    let target_vec = encrypt(target, enc_key);
+    let mut inp_container =
+        PNGContainer::try_from(&inp_vec).map_err(|_| JoinError::InvalidPngFile)?;
+
+    inp_container.set_chunk(PNGChunk::new(CUSTOM_CHUNK_TYPE, target_vec));
+
+    Ok(inp_container.to_bytes())
+}
+
+pub fn join_heif(inp: &[u8], target: &[u8], enc_key: &[u8; 32]) -> Result<Vec<u8>, JoinError> {
+    let inp_vec = Vec::from(inp);
+    let target_vec = encrypt(target, enc_key);
+    let mut inp_container =
+        ISOBMFFContainer::try_from(&inp_vec).map_err(|_| JoinError::InvalidHeifFile)?;
+
+    inp_container
+        .insert_box(ISOBox::new_uuid(CUSTOM_BOX_UUID, target_vec))
+        .map_err(|_| JoinError::InvalidHeifFile)?;
+
+    Ok(inp_container.to_bytes())
+}
+
+pub fn join_gif(inp: &[u8], target: &[u8], enc_key: &[u8; 32]) -> Result<Vec<u8>, JoinError> {
+    let inp_vec = Vec::from(inp);
+    let target_vec = encrypt(target, enc_key);
+    let mut inp_container =
+        GIFContainer::try_from(&inp_vec).map_err(|_| JoinError::InvalidGifFile)?;
+
+    inp_container.push_block(GIFBlock::new_application_extension(
+        CUSTOM_APPLICATION_ID,
+        &target_vec,
+    ));
+
+    Ok(inp_container.to_bytes())
+}
+
+pub fn join_wav(inp: &[u8], target: &[u8], enc_key: &[u8; 32]) -> Result<Vec<u8>, JoinError> {
+    let inp_vec = Vec::from(inp);
+    let target_vec = encrypt(target, enc_key);
+    let mut inp_container =
+        WAVContainer::try_from(&inp_vec).map_err(|_| JoinError::InvalidWavFile)?;
+
+    inp_container.push_chunk(Box::new(RegularChunk {
+        chunk_data: target_vec,
+        chunk_id: String::from(WAV_CHUNK_ID),
+    }));
 
-    Ok(inp_container.into())
+    Ok(inp_container.to_bytes())
+}
+
+pub fn join_tiff(inp: &[u8], target: &[u8], enc_key: &[u8; 32]) -> Result<Vec<u8>, JoinError> {
+    let inp_vec = Vec::from(inp);
+    let target_vec = encrypt(target, enc_key);
+    let mut inp_container =
+        TIFFContainer::try_from(&inp_vec).map_err(|_| JoinError::InvalidTiffFile)?;
+
+    inp_container.put_entry(TIFFEntry::new_undefined(CUSTOM_TAG, target_vec));
+
+    Ok(inp_container.to_bytes())
+}
+
+pub fn join_auto(inp: &[u8], target: &[u8], enc_key: &[u8; 32]) -> Result<Vec<u8>, JoinError> {
+    let format = detect_format(inp).map_err(|_| JoinError::UnsupportedFormat)?;
+
+    match format {
+        FileFormat::Jpeg => join_jpeg(inp, target, enc_key),
+        FileFormat::WebP => join_webp(inp, target, enc_key),
+        FileFormat::Png => join_png(inp, target, enc_key),
+        FileFormat::Heif => join_heif(inp, target, enc_key),
+        FileFormat::Gif => join_gif(inp, target, enc_key),
+        FileFormat::Tiff => join_tiff(inp, target, enc_key),
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::file_splitter::StreamSplitter;
+    use crate::watermark_task::Point;
 
     #[test]
     fn le_to_u32_success() {
@@ -224,4 +378,185 @@ mod tests {
 
         assert_eq!(output, [0x00u8, 0x7A, 0x44, 0x9A]);
     }
+
+    #[test]
+    fn join_webp_adds_vp8x() {
+        let content = std::fs::read(fixture!("test.webp")).unwrap();
+        let joined = join_webp(&content, &[0x01, 0x02, 0x03], &[0u8; 32]).unwrap();
+        let container = RIFFContainer::try_from(&joined).unwrap();
+        let vp8x = container.get_vp8x().unwrap();
+        let chunks = container.get_chunk_data().unwrap();
+
+        assert_eq!((vp8x.canvas_width, vp8x.canvas_height), (480, 360));
+        assert_eq!(chunks[0].get_chunk_id(), "VP8X");
+        assert_eq!(chunks[chunks.len() - 1].get_chunk_id(), "milf");
+        assert!(image::load_from_memory_with_format(&joined, image::ImageFormat::WebP).is_ok());
+    }
+
+    #[test]
+    fn join_jpeg_payload_placement() {
+        use crate::jpeg::container::GeneralSegment;
+        use crate::metadata::XMP_HEADER;
+
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let mut container = JFIFContainer::try_from(&content).unwrap();
+        let xmp = GeneralSegment::new(Vec::from(&XMP_HEADER[..]));
+
+        container.get_segments_mut().insert(2, JFIFSegment::APP(1, xmp));
+
+        let content = Vec::try_from(container).unwrap();
+        let mut positions = Vec::new();
+
+        for placement in [
+            PayloadPlacement::Early,
+            PayloadPlacement::AfterMetadata,
+            PayloadPlacement::End,
+        ] {
+            let joined =
+                join_jpeg_with_placement(&content, &[0x01; 70000], &[0u8; 32], placement).unwrap();
+            let container = JFIFContainer::try_from(&joined).unwrap();
+            let position = container
+                .get_segments()
+                .iter()
+                .position(|p| CustomSegment::try_from(p).is_ok())
+                .unwrap();
+
+            assert_eq!(container.get_custom_segment().len(), 2);
+            assert!(image::load_from_memory_with_format(&joined, image::ImageFormat::Jpeg).is_ok());
+            positions.push(position);
+        }
+
+        // Behind APP0, behind the XMP segment and right before SOS
+        assert_eq!(positions, [2, 3, 10]);
+    }
+
+    #[test]
+    fn join_stripped_jpeg() {
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let mut container = JFIFContainer::try_from(&content).unwrap();
+
+        container.remove_segments(|p| matches!(p, JFIFSegment::APP(_, _)));
+
+        let content = Vec::try_from(container).unwrap();
+
+        for placement in [PayloadPlacement::Early, PayloadPlacement::AfterMetadata] {
+            let joined =
+                join_jpeg_with_placement(&content, &[0x01; 100], &[0u8; 32], placement).unwrap();
+            let container = JFIFContainer::try_from(&joined).unwrap();
+
+            assert!(CustomSegment::try_from(&container.get_segments()[1]).is_ok());
+            assert!(image::load_from_memory_with_format(&joined, image::ImageFormat::Jpeg).is_ok());
+        }
+    }
+
+    #[test]
+    fn join_webp_early_payload() {
+        let content = std::fs::read(fixture!("test.webp")).unwrap();
+        let mut payload = vec![0xAA, 0xBB];
+
+        [5u32, 6, 7, 8].iter().for_each(|f| payload.extend(f.to_le_bytes()));
+
+        let joined =
+            join_webp_with_placement(&content, &payload, &[0u8; 32], PayloadPlacement::Early)
+                .unwrap();
+        let container = RIFFContainer::try_from(&joined).unwrap();
+        let chunk_ids: Vec<&str> = container
+            .get_chunk_data()
+            .unwrap()
+            .iter()
+            .map(|f| f.get_chunk_id())
+            .collect();
+
+        assert_eq!(chunk_ids[..3], ["VP8X", "milf", "VP8L"]);
+
+        // The payload is available long before the image data has been read
+        let mut splitter = StreamSplitter::new(FileFormat::WebP, &[0u8; 32]).unwrap();
+        let split_result = splitter.push(&joined[..joined.len() / 4]).unwrap().unwrap();
+
+        assert_eq!(split_result.old_section_img, [0xAA, 0xBB]);
+        assert_eq!(split_result.position, Point { x: 5, y: 6 });
+    }
+
+    #[test]
+    fn join_webp_early_payload_after_header_chunks() {
+        let content = std::fs::read(fixture!("test.webp")).unwrap();
+        let mut container = RIFFContainer::try_from(&content).unwrap();
+        let iccp = RegularChunk {
+            chunk_id: String::from("ICCP"),
+            chunk_data: vec![0x00; 16],
+        };
+
+        // A writer that put the color profile behind the image data
+        container.ensure_extended().unwrap();
+        container.insert_subchunk(usize::MAX, Box::new(iccp));
+
+        let content = container.to_bytes();
+        let joined =
+            join_webp_with_placement(&content, &[0x01; 32], &[0u8; 32], PayloadPlacement::Early)
+                .unwrap();
+        let mut container = RIFFContainer::try_from(&joined).unwrap();
+        let chunk_ids: Vec<&str> = container
+            .get_chunk_data()
+            .unwrap()
+            .iter()
+            .map(|f| f.get_chunk_id())
+            .collect();
+
+        assert_eq!(chunk_ids, ["VP8X", "VP8L", "ICCP", "milf"]);
+
+        // Only the payload chunk keeps the image crate from decoding the file
+        container.remove_subchunk("milf");
+
+        let original = image::load_from_memory(&content).unwrap();
+        let restored = image::load_from_memory(&container.to_bytes()).unwrap();
+
+        assert_eq!(restored.to_rgba8(), original.to_rgba8());
+    }
+
+    #[test]
+    fn join_jpeg_keeps_mpf_images() {
+        use crate::jpeg::mpf::MPImage;
+
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let mut container = JFIFContainer::try_from(&content).unwrap();
+        let images = vec![MPImage {
+            attribute: 0x0002_0002,
+            dependent_images: [0, 0],
+            data: vec![0xFF, 0xD8, 0x0A, 0x0B, 0xFF, 0xD9],
+        }];
+
+        container.set_mpf_images(&images, Some(vec![0x0C])).unwrap();
+
+        let content = Vec::try_from(container).unwrap();
+        let joined = join_jpeg(&content, &[0x01; 70000], &[0u8; 32]).unwrap();
+        let container = JFIFContainer::try_from(&joined).unwrap();
+
+        assert_eq!(container.get_mpf_images().unwrap(), images);
+        assert_eq!(container.get_trailer_suffix().unwrap(), Some(vec![0x0C]));
+    }
+
+    #[test]
+    fn join_jpeg_drops_stale_mpf() {
+        use crate::jpeg::mpf::{is_mpf_segment, MPImage};
+
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let mut container = JFIFContainer::try_from(&content).unwrap();
+        let images = vec![MPImage {
+            attribute: 0x0002_0002,
+            dependent_images: [0, 0],
+            data: vec![0xFF, 0xD8, 0x0A, 0x0B, 0xFF, 0xD9],
+        }];
+
+        // An editor dropped the secondary image but left the MPF directory behind
+        container.set_mpf_images(&images, None).unwrap();
+        container.set_trailer(Some(vec![0x0C]));
+
+        let content = Vec::try_from(container).unwrap();
+        let joined = join_jpeg(&content, &[0x01; 32], &[0u8; 32]).unwrap();
+        let container = JFIFContainer::try_from(&joined).unwrap();
+
+        assert!(!container.get_segments().iter().any(is_mpf_segment));
+        assert_eq!(container.get_trailer(), Some(&vec![0x0C]));
+        assert_eq!(&joined[joined.len() - 3..], [0xFF, 0xD9, 0x0C]);
+    }
 }
@@ -1,8 +1,18 @@
 use crate::encryption::decrypt;
-use crate::jpeg::container::JFIFContainer;
-use crate::jpeg::custom_segment::join_bytes;
-use crate::watermark_task::{Dimension, Point};
-use crate::webp_container::RIFFContainer;
+use crate::file_format::{detect_format, FileFormat};
+use crate::frame_payload::{restore_frames, unpack_frames, FramePayload};
+use crate::gif_container::{GIFContainer, CUSTOM_APPLICATION_ID};
+use crate::img::encode_frames_webp;
+use crate::isobmff_container::{ISOBMFFContainer, CUSTOM_BOX_UUID};
+use crate::jpeg::container::{JFIFContainer, JFIFSegment};
+use crate::jpeg::custom_segment::{join_bytes, join_stream_bytes, CustomSegment};
+use crate::jpeg::reader::{JFIFEvent, JFIFPushParser};
+use crate::payload_stream::{PayloadStream, DEFAULT_STREAM_ID};
+use crate::png_container::{PNGContainer, CUSTOM_CHUNK_TYPE};
+use crate::tiff_container::{TIFFContainer, CUSTOM_TAG};
+use crate::watermark_task::{get_frames_webp, Dimension, Point};
+use crate::wav_container::{SampleSection, WAVContainer, CUSTOM_CHUNK_ID as WAV_CHUNK_ID};
+use crate::webp_container::{Chunk, RIFFContainer, RIFFEvent, RIFFPushParser, CUSTOM_CHUNK_ID};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -13,11 +23,35 @@ pub enum SplitError {
     #[error("Invalid Jpeg container")]
     InvalidJpegFile,
 
+    #[error("Invalid Png file")]
+    InvalidPngFile,
+
+    #[error("Invalid HEIF file")]
+    InvalidHeifFile,
+
+    #[error("Invalid Gif file")]
+    InvalidGifFile,
+
+    #[error("Invalid Wav file")]
+    InvalidWavFile,
+
+    #[error("Invalid Tiff file")]
+    InvalidTiffFile,
+
+    #[error("Cannot restore frames")]
+    CannotRestoreFrames,
+
     #[error("Cannot find custom block")]
     CannotFindCustomBlock,
 
     #[error("Corrupted custom block")]
     CorruptedCustomBlock,
+
+    #[error("Unsupported format")]
+    UnsupportedFormat,
+
+    #[error("Animated WebP file, its frames are read with split_webp_frames")]
+    AnimatedWebpFile,
 }
 
 pub struct SplitResult {
@@ -26,75 +60,464 @@ pub struct SplitResult {
     pub old_section_img: Vec<u8>,
 }
 
-fn process_subchunk(chunk_data: &Vec<u8>, enc_key: &[u8; 32]) -> Result<SplitResult, SplitError> {
-    let chunk_decrypted = decrypt(&chunk_data, enc_key).unwrap();
+pub struct FramesSplitResult {
+    pub position: Point,
+    pub dimension: Dimension,
+    pub frames: Vec<FramePayload>,
+}
+
+fn process_subchunk(chunk_data: &[u8], enc_key: &[u8; 32]) -> Result<SplitResult, SplitError> {
+    let chunk_decrypted = decrypt(chunk_data, enc_key).ok_or(SplitError::CorruptedCustomBlock)?;
     let chunk_len = chunk_decrypted.len();
-    let original_img = chunk_decrypted.get(0..chunk_len - 16);
-    let watermark_pos = chunk_decrypted.get(chunk_len - 16..chunk_len - 8);
-    let watermark_dim = chunk_decrypted.get(chunk_len - 8..chunk_len);
-
-    match (original_img, watermark_pos, watermark_dim) {
-        (Some(img_arr), Some(pos_arr), Some(dim_arr)) => {
-            let position = Point::try_from(pos_arr)
-                .map_err(|_| -> SplitError { SplitError::CorruptedCustomBlock })?;
-            let dimension = Dimension::try_from(dim_arr)
-                .map_err(|_| -> SplitError { SplitError::CorruptedCustomBlock })?;
-
-            let split_result = SplitResult {
-                dimension,
-                position,
-                old_section_img: Vec::from(img_arr),
-            };
+    let img_len = chunk_len.checked_sub(16).ok_or(SplitError::CorruptedCustomBlock)?;
+    let original_img = chunk_decrypted.get(0..img_len);
+    let watermark_pos = chunk_decrypted.get(img_len..img_len + 8);
+    let watermark_dim = chunk_decrypted.get(img_len + 8..chunk_len);
 
-            return Ok(split_result);
-        }
-        _ => {}
+    if let (Some(img_arr), Some(pos_arr), Some(dim_arr)) = (original_img, watermark_pos, watermark_dim) {
+        let position = Point::try_from(pos_arr)
+            .map_err(|_| -> SplitError { SplitError::CorruptedCustomBlock })?;
+        let dimension = Dimension::try_from(dim_arr)
+            .map_err(|_| -> SplitError { SplitError::CorruptedCustomBlock })?;
+
+        let split_result = SplitResult {
+            dimension,
+            position,
+            old_section_img: Vec::from(img_arr),
+        };
+
+        return Ok(split_result);
     }
 
     Err(SplitError::CorruptedCustomBlock)
 }
 
-pub fn split_webp(inp_vec: &Vec<u8>, enc_key: &[u8;32]) -> Result<SplitResult, SplitError> {
-    let mut inp_container =
-        RIFFContainer::try_from(inp_vec).map_err(|_| SplitError::InvalidWebpFile)?;
-    let subchunk = inp_container.find_subchunk("milf");
+fn find_webp_payload(container: &RIFFContainer, stream: &PayloadStream) -> Option<Vec<u8>> {
+    let chunk_id = stream.get_chunk_id();
+
+    container
+        .get_chunk_data()?
+        .iter()
+        .filter(|f| f.get_chunk_id() == chunk_id)
+        .find_map(|f| stream.read_chunk(&chunk_id, &f.get_chunk_bytes()).map(Vec::from))
+}
+
+fn find_jpeg_payload(container: &JFIFContainer, stream: &PayloadStream) -> Option<Vec<u8>> {
+    let custom_segments = container.get_namespace_segments(stream.get_namespace());
+    let payload = join_stream_bytes(&custom_segments, stream.get_id());
+
+    Some(payload).filter(|f| !f.is_empty())
+}
 
-    if let Some(chunk) = subchunk {
-        let chunk_data = chunk.get_chunk_bytes();
+fn split_webp_payload(
+    inp_container: &RIFFContainer,
+    enc_key: &[u8; 32],
+) -> Result<SplitResult, SplitError> {
+    let subchunk = find_webp_payload(inp_container, &PayloadStream::default());
 
+    if let Some(chunk_data) = subchunk {
         return process_subchunk(&chunk_data, enc_key);
     }
 
     Err(SplitError::CannotFindCustomBlock)
 }
 
+pub fn split_webp(inp_vec: &Vec<u8>, enc_key: &[u8;32]) -> Result<SplitResult, SplitError> {
+    let inp_container =
+        RIFFContainer::try_from(inp_vec).map_err(|_| SplitError::InvalidWebpFile)?;
+
+    if inp_container.is_animated() {
+        return Err(SplitError::AnimatedWebpFile);
+    }
+
+    split_webp_payload(&inp_container, enc_key)
+}
+
+// An animated webp carries one section per frame like a gif
+pub fn split_webp_frames(
+    inp_vec: &Vec<u8>,
+    enc_key: &[u8; 32],
+) -> Result<FramesSplitResult, SplitError> {
+    let inp_container =
+        RIFFContainer::try_from(inp_vec).map_err(|_| SplitError::InvalidWebpFile)?;
+
+    if !inp_container.is_animated() {
+        return Err(SplitError::UnsupportedFormat);
+    }
+
+    let split_result = split_webp_payload(&inp_container, enc_key)?;
+    let frames =
+        unpack_frames(&split_result.old_section_img).ok_or(SplitError::CorruptedCustomBlock)?;
+
+    Ok(FramesSplitResult {
+        position: split_result.position,
+        dimension: split_result.dimension,
+        frames,
+    })
+}
+
 pub fn split_jpeg(inp_vec: &Vec<u8>, enc_key: &[u8;32]) -> Result<SplitResult, SplitError> {
     let inp_container =
         JFIFContainer::try_from(inp_vec).map_err(|_| SplitError::InvalidJpegFile)?;
-    let mut custom_segments = inp_container.get_custom_segment();
-    custom_segments.sort_by(|a, b| a.order.cmp(&b.order));
-    let subchunk = join_bytes(&custom_segments);
-    let subchunk_len = subchunk.len();
+    let subchunk = find_jpeg_payload(&inp_container, &PayloadStream::default());
 
-    if subchunk_len > 0 {
+    if let Some(subchunk) = subchunk {
         return process_subchunk(&subchunk, enc_key);
     }
 
     Err(SplitError::CannotFindCustomBlock)
 }
 
+// Returns the decrypted payload of one stream, its content is up to the application
+pub fn extract_webp(
+    inp_vec: &Vec<u8>,
+    enc_key: &[u8; 32],
+    stream: &PayloadStream,
+) -> Result<Vec<u8>, SplitError> {
+    let inp_container =
+        RIFFContainer::try_from(inp_vec).map_err(|_| SplitError::InvalidWebpFile)?;
+    let subchunk =
+        find_webp_payload(&inp_container, stream).ok_or(SplitError::CannotFindCustomBlock)?;
+
+    decrypt(&subchunk, enc_key).ok_or(SplitError::CorruptedCustomBlock)
+}
+
+pub fn extract_jpeg(
+    inp_vec: &Vec<u8>,
+    enc_key: &[u8; 32],
+    stream: &PayloadStream,
+) -> Result<Vec<u8>, SplitError> {
+    let inp_container =
+        JFIFContainer::try_from(inp_vec).map_err(|_| SplitError::InvalidJpegFile)?;
+    let subchunk =
+        find_jpeg_payload(&inp_container, stream).ok_or(SplitError::CannotFindCustomBlock)?;
+
+    decrypt(&subchunk, enc_key).ok_or(SplitError::CorruptedCustomBlock)
+}
+
+pub fn extract_auto(
+    inp_vec: &Vec<u8>,
+    enc_key: &[u8; 32],
+    stream: &PayloadStream,
+) -> Result<Vec<u8>, SplitError> {
+    match detect_format(inp_vec) {
+        Ok(FileFormat::Jpeg) => extract_jpeg(inp_vec, enc_key, stream),
+        Ok(FileFormat::WebP) => extract_webp(inp_vec, enc_key, stream),
+        _ => Err(SplitError::UnsupportedFormat),
+    }
+}
+
+pub fn split_png(inp_vec: &Vec<u8>, enc_key: &[u8; 32]) -> Result<SplitResult, SplitError> {
+    let inp_container =
+        PNGContainer::try_from(inp_vec).map_err(|_| SplitError::InvalidPngFile)?;
+    let chunk = inp_container.find_chunk(CUSTOM_CHUNK_TYPE);
+
+    if let Some(chunk) = chunk {
+        return process_subchunk(&chunk.chunk_data, enc_key);
+    }
+
+    Err(SplitError::CannotFindCustomBlock)
+}
+
+pub fn split_heif(inp_vec: &Vec<u8>, enc_key: &[u8; 32]) -> Result<SplitResult, SplitError> {
+    let inp_container =
+        ISOBMFFContainer::try_from(inp_vec).map_err(|_| SplitError::InvalidHeifFile)?;
+    let custom_box = inp_container.find_uuid_box(&CUSTOM_BOX_UUID);
+
+    if let Some(custom_box) = custom_box {
+        return process_subchunk(&custom_box.box_data, enc_key);
+    }
+
+    Err(SplitError::CannotFindCustomBlock)
+}
+
+// Every frame is the original frame as a gif of its own, at its offset on the canvas
+pub fn split_gif_frames(
+    inp_vec: &Vec<u8>,
+    enc_key: &[u8; 32],
+) -> Result<FramesSplitResult, SplitError> {
+    let inp_container =
+        GIFContainer::try_from(inp_vec).map_err(|_| SplitError::InvalidGifFile)?;
+    let data = inp_container
+        .find_application_extension(CUSTOM_APPLICATION_ID)
+        .and_then(|f| f.get_application_data())
+        .ok_or(SplitError::CannotFindCustomBlock)?;
+    let split_result = process_subchunk(&data, enc_key)?;
+    let frames =
+        unpack_frames(&split_result.old_section_img).ok_or(SplitError::CorruptedCustomBlock)?;
+
+    Ok(FramesSplitResult {
+        position: split_result.position,
+        dimension: split_result.dimension,
+        frames,
+    })
+}
+
+pub fn split_tiff(inp_vec: &Vec<u8>, enc_key: &[u8; 32]) -> Result<SplitResult, SplitError> {
+    let inp_container =
+        TIFFContainer::try_from(inp_vec).map_err(|_| SplitError::InvalidTiffFile)?;
+    let entry = inp_container.find_entry(CUSTOM_TAG);
+
+    if let Some(entry) = entry {
+        return process_subchunk(&entry.value, enc_key);
+    }
+
+    Err(SplitError::CannotFindCustomBlock)
+}
+
+// The original frame blocks are put back, so the file is the same as before watermarking
+pub fn restore_gif(inp_vec: &Vec<u8>, enc_key: &[u8; 32]) -> Result<Vec<u8>, SplitError> {
+    let split_result = split_gif_frames(inp_vec, enc_key)?;
+    let mut container =
+        GIFContainer::try_from(inp_vec).map_err(|_| SplitError::InvalidGifFile)?;
+    let mut header = *container.get_header();
+
+    container.remove_application_extension(CUSTOM_APPLICATION_ID);
+
+    for payload in split_result.frames {
+        let frame_container = GIFContainer::try_from(&payload.section_img)
+            .map_err(|_| SplitError::CorruptedCustomBlock)?;
+        let left = u16::try_from(payload.position.x);
+        let top = u16::try_from(payload.position.y);
+
+        header = *frame_container.get_header();
+
+        let (control, image) = match (left, top) {
+            (Ok(left), Ok(top)) => frame_container.take_frame(left, top),
+            _ => None,
+        }
+        .ok_or(SplitError::CorruptedCustomBlock)?;
+
+        container
+            .set_frame(payload.index, control, image)
+            .ok_or(SplitError::CannotRestoreFrames)?;
+    }
+
+    container.set_header(&header);
+
+    Ok(container.to_bytes())
+}
+
+pub fn restore_webp(inp_vec: &Vec<u8>, enc_key: &[u8; 32]) -> Result<Vec<u8>, SplitError> {
+    let payloads = split_webp_frames(inp_vec, enc_key)?.frames;
+    let mut frames = get_frames_webp(inp_vec).map_err(|_| SplitError::InvalidWebpFile)?;
+    let indices: Vec<usize> = payloads.iter().map(|f| f.index).collect();
+
+    restore_frames(&mut frames, &payloads).map_err(|_| SplitError::CannotRestoreFrames)?;
+
+    let restored = encode_frames_webp(inp_vec, &frames, &indices)
+        .map_err(|_| SplitError::CannotRestoreFrames)?;
+    let mut container =
+        RIFFContainer::try_from(&restored).map_err(|_| SplitError::InvalidWebpFile)?;
+    container.remove_subchunk(CUSTOM_CHUNK_ID);
+
+    Ok(container.to_bytes())
+}
+
+pub fn split_wav(inp_vec: &Vec<u8>, enc_key: &[u8; 32]) -> Result<SampleSection, SplitError> {
+    let inp_container =
+        WAVContainer::try_from(inp_vec).map_err(|_| SplitError::InvalidWavFile)?;
+    let chunk = inp_container
+        .find_chunk(WAV_CHUNK_ID)
+        .ok_or(SplitError::CannotFindCustomBlock)?;
+    let chunk_decrypted =
+        decrypt(&chunk.get_chunk_bytes(), enc_key).ok_or(SplitError::CorruptedCustomBlock)?;
+
+    SampleSection::try_from(&chunk_decrypted[..]).map_err(|_| SplitError::CorruptedCustomBlock)
+}
+
+pub fn restore_wav(inp_vec: &Vec<u8>, enc_key: &[u8; 32]) -> Result<Vec<u8>, SplitError> {
+    let section = split_wav(inp_vec, enc_key)?;
+    let mut inp_container =
+        WAVContainer::try_from(inp_vec).map_err(|_| SplitError::InvalidWavFile)?;
+
+    inp_container
+        .restore_section(&section)
+        .map_err(|_| SplitError::CorruptedCustomBlock)?;
+    inp_container.remove_chunk(WAV_CHUNK_ID);
+
+    Ok(inp_container.to_bytes())
+}
+
+// A gif or an animated webp carries one section per frame, those are read with
+// split_gif_frames and split_webp_frames
+pub fn split_auto(inp_vec: &Vec<u8>, enc_key: &[u8; 32]) -> Result<SplitResult, SplitError> {
+    let format = detect_format(inp_vec).map_err(|_| SplitError::UnsupportedFormat)?;
+
+    match format {
+        FileFormat::Jpeg => split_jpeg(inp_vec, enc_key),
+        FileFormat::WebP => split_webp(inp_vec, enc_key),
+        FileFormat::Png => split_png(inp_vec, enc_key),
+        FileFormat::Heif => split_heif(inp_vec, enc_key),
+        FileFormat::Tiff => split_tiff(inp_vec, enc_key),
+        FileFormat::Gif => Err(SplitError::UnsupportedFormat),
+    }
+}
+
+enum StreamParser {
+    Jpeg(JFIFPushParser, Vec<CustomSegment>),
+    WebP(RIFFPushParser),
+}
+
+// Decrypts the payload while the image is still downloading, the custom segments of a jpeg
+// are complete at the next scan, the webp payload is complete once its chunk is
+pub struct StreamSplitter {
+    parser: StreamParser,
+    enc_key: [u8; 32],
+    done: bool,
+}
+
+impl StreamSplitter {
+    pub fn new(format: FileFormat, enc_key: &[u8; 32]) -> Result<Self, SplitError> {
+        let parser = match format {
+            FileFormat::Jpeg => StreamParser::Jpeg(JFIFPushParser::new(), Vec::new()),
+            FileFormat::WebP => StreamParser::WebP(RIFFPushParser::new()),
+            _ => return Err(SplitError::UnsupportedFormat),
+        };
+
+        Ok(StreamSplitter {
+            parser,
+            enc_key: *enc_key,
+            done: false,
+        })
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    // Returns the payload or the first error once, bytes pushed after that are ignored
+    pub fn push(&mut self, data: &[u8]) -> Result<Option<SplitResult>, SplitError> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let result = self.push_inner(data);
+
+        if !matches!(result, Ok(None)) {
+            self.done = true;
+        }
+
+        result
+    }
+
+    fn push_inner(&mut self, data: &[u8]) -> Result<Option<SplitResult>, SplitError> {
+        let subchunk = match &mut self.parser {
+            StreamParser::Jpeg(parser, custom_segments) => {
+                let events = parser.push(data).map_err(|_| SplitError::InvalidJpegFile)?;
+                let mut complete = false;
+
+                for event in events {
+                    match event {
+                        JFIFEvent::Segment(segment @ JFIFSegment::APP(_, _)) => {
+                            match CustomSegment::try_from(&segment) {
+                                Ok(custom_segment)
+                                    if custom_segment.stream == DEFAULT_STREAM_ID =>
+                                {
+                                    custom_segments.push(custom_segment)
+                                }
+                                _ => {}
+                            }
+                        }
+                        // Custom segments are inserted together, so the next scan ends them
+                        JFIFEvent::Segment(JFIFSegment::SOS(_)) if !custom_segments.is_empty() => {
+                            complete = true;
+                            break;
+                        }
+                        JFIFEvent::Segment(JFIFSegment::EOI) => {
+                            return Err(SplitError::CannotFindCustomBlock);
+                        }
+                        _ => {}
+                    }
+                }
+
+                if !complete {
+                    return Ok(None);
+                }
+
+                custom_segments.sort_by_key(|a| a.order);
+                join_bytes(custom_segments)
+            }
+            StreamParser::WebP(parser) => {
+                let events = parser.push(data).map_err(|_| SplitError::InvalidWebpFile)?;
+                let mut payload = None;
+
+                for event in events {
+                    if let RIFFEvent::Chunk(chunk) = event {
+                        // The payload never goes in front of ANIM, so an animated file is known first
+                        if chunk.chunk_id == "ANIM" || chunk.chunk_id == "ANMF" {
+                            return Err(SplitError::AnimatedWebpFile);
+                        }
+
+                        payload = PayloadStream::default()
+                            .read_chunk(&chunk.chunk_id, &chunk.chunk_data)
+                            .map(Vec::from);
+
+                        if payload.is_some() {
+                            break;
+                        }
+                    }
+                }
+
+                match payload {
+                    Some(chunk) => chunk,
+                    None => return Ok(None),
+                }
+            }
+        };
+
+        process_subchunk(&subchunk, &self.enc_key).map(Some)
+    }
+
+    pub fn finish(&self) -> Result<(), SplitError> {
+        if self.done {
+            return Ok(());
+        }
+
+        match &self.parser {
+            StreamParser::Jpeg(parser, _) => {
+                parser.finish().map_err(|_| SplitError::InvalidJpegFile)?
+            }
+            StreamParser::WebP(parser) => {
+                parser.finish().map_err(|_| SplitError::InvalidWebpFile)?
+            }
+        }
+
+        Err(SplitError::CannotFindCustomBlock)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::encryption::BASIC_KEY;
+    use crate::file_joiner::{
+        join_gif, join_heif, join_jpeg_stream, join_png, join_tiff, join_wav, join_webp,
+        join_webp_stream, PayloadPlacement,
+    };
+    use crate::jpeg::container::GeneralSegment;
+    use crate::payload_stream::PayloadNamespace;
+    use crate::frame_payload::{encode_frame_sections, encode_gif_frames, pack_frames};
+    use crate::img::{encode_animation_gif, encode_frames_gif};
+    use crate::isobmff_container::ISOBox;
+    use crate::watermark_task::{
+        get_frames_gif, set_target_auto, set_target_gif, AnimationFrame, OriginX, OriginY,
+        WatermarkTask,
+    };
+    use crate::wav_container::{WaveFormat, TONE_AMPLITUDE, TONE_FREQUENCY};
+    use crate::webp_container::{AnimChunk, AnmfChunk, RegularChunk};
+    use image::codecs::webp::WebPEncoder;
+    use image::ColorType;
+    use image::{Delay, DynamicImage, GenericImageView, ImageFormat, Rgba, RgbaImage};
+    use std::io::Cursor;
     use std::fs;
 
     #[test]
     fn test_split_webp() {
-        let content = fs::read("../crop.webp").unwrap();
+        let content = fs::read(fixture!("crop.webp")).unwrap();
         let split_result = split_webp(&content, &BASIC_KEY);
 
-        assert_eq!(split_result.is_ok(), true);
+        assert!(split_result.is_ok());
 
         let split_data = split_result.unwrap();
 
@@ -105,10 +528,10 @@ mod tests {
 
     #[test]
     fn test_split_jpeg() {
-        let content = fs::read("../crop.jpeg").unwrap();
+        let content = fs::read(fixture!("crop.jpeg")).unwrap();
         let split_result = split_jpeg(&content, &BASIC_KEY);
 
-        assert_eq!(split_result.is_ok(), true);
+        assert!(split_result.is_ok());
 
         let split_data = split_result.unwrap();
 
@@ -116,4 +539,490 @@ mod tests {
         println!("position  : {:?}", split_data.position);
         println!("dimension : {:?}", split_data.dimension);
     }
+
+    #[test]
+    fn stream_splitter_returns_payload_early() {
+        for (name, format) in [
+            (fixture!("crop.jpeg"), FileFormat::Jpeg),
+            (fixture!("crop.webp"), FileFormat::WebP),
+        ] {
+            let content = fs::read(name).unwrap();
+            let expected = split_auto(&content, &BASIC_KEY).unwrap();
+            let mut splitter = StreamSplitter::new(format, &BASIC_KEY).unwrap();
+            let mut received = None;
+
+            for (index, chunk) in content.chunks(512).enumerate() {
+                if let Some(result) = splitter.push(chunk).unwrap() {
+                    received = Some((index, result));
+                }
+            }
+
+            let (index, result) = received.unwrap();
+
+            assert!(splitter.is_done());
+            assert!(splitter.finish().is_ok());
+            assert_eq!(result.position, expected.position);
+            assert_eq!(result.dimension, expected.dimension);
+            assert_eq!(result.old_section_img, expected.old_section_img);
+
+            // The jpeg payload sits in the headers, long before the image data ends
+            if format == FileFormat::Jpeg {
+                assert!(index * 512 < content.len() / 2);
+            }
+        }
+
+        let content = fs::read(fixture!("test.jpeg")).unwrap();
+        let mut splitter = StreamSplitter::new(FileFormat::Jpeg, &BASIC_KEY).unwrap();
+
+        assert!(matches!(
+            splitter.push(&content),
+            Err(SplitError::CannotFindCustomBlock)
+        ));
+        assert!(matches!(
+            StreamSplitter::new(FileFormat::Png, &BASIC_KEY),
+            Err(SplitError::UnsupportedFormat)
+        ));
+    }
+
+    #[test]
+    fn wrong_key_is_a_corrupted_block() {
+        let mut wrong_key = BASIC_KEY;
+        wrong_key[0] = 0x00;
+
+        for name in [fixture!("crop.jpeg"), fixture!("crop.webp")] {
+            let content = fs::read(name).unwrap();
+            let format = detect_format(&content).unwrap();
+            let mut splitter = StreamSplitter::new(format, &wrong_key).unwrap();
+
+            assert!(matches!(
+                split_auto(&content, &wrong_key),
+                Err(SplitError::CorruptedCustomBlock)
+            ));
+            assert!(matches!(
+                splitter.push(&content),
+                Err(SplitError::CorruptedCustomBlock)
+            ));
+            assert!(splitter.is_done());
+            assert!(matches!(splitter.push(&content), Ok(None)));
+        }
+    }
+
+    #[test]
+    fn truncated_payload_is_a_corrupted_block() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(16, 16));
+        let mut content: Vec<u8> = Vec::new();
+        img.write_to(&mut Cursor::new(&mut content), ImageFormat::Png)
+            .unwrap();
+
+        for target in [vec![], vec![0x0Au8; 15]] {
+            let joined = join_png(&content, &target, &BASIC_KEY).unwrap();
+
+            assert!(matches!(
+                split_png(&joined, &BASIC_KEY),
+                Err(SplitError::CorruptedCustomBlock)
+            ));
+        }
+    }
+
+    #[test]
+    fn extract_independent_streams() {
+        let license = b"license: personal use";
+        let acme = PayloadNamespace::new("ACME", "acme").unwrap();
+        let streams = [
+            PayloadNamespace::default().stream(1).unwrap(),
+            acme.stream(0).unwrap(),
+        ];
+
+        for (name, format) in [
+            (fixture!("crop.jpeg"), FileFormat::Jpeg),
+            (fixture!("crop.webp"), FileFormat::WebP),
+        ] {
+            let mut content = fs::read(name).unwrap();
+            let expected = split_auto(&content, &BASIC_KEY).unwrap();
+
+            for (index, stream) in streams.iter().enumerate() {
+                let data = [&license[..], &[index as u8]].concat();
+
+                content = match format {
+                    FileFormat::Jpeg => join_jpeg_stream(
+                        &content,
+                        &data,
+                        &BASIC_KEY,
+                        PayloadPlacement::AfterMetadata,
+                        stream,
+                    ),
+                    _ => join_webp_stream(
+                        &content,
+                        &data,
+                        &BASIC_KEY,
+                        PayloadPlacement::End,
+                        stream,
+                    ),
+                }
+                .unwrap();
+            }
+
+            let split_result = split_auto(&content, &BASIC_KEY).unwrap();
+            let mut splitter = StreamSplitter::new(format, &BASIC_KEY).unwrap();
+
+            assert_eq!(split_result.old_section_img, expected.old_section_img);
+            assert_eq!(
+                splitter.push(&content).unwrap().unwrap().old_section_img,
+                expected.old_section_img
+            );
+
+            for (index, stream) in streams.iter().enumerate() {
+                let data = extract_auto(&content, &BASIC_KEY, stream).unwrap();
+
+                assert_eq!(data, [&license[..], &[index as u8]].concat());
+            }
+
+            assert!(matches!(
+                extract_auto(&content, &BASIC_KEY, &acme.stream(1).unwrap()),
+                Err(SplitError::CannotFindCustomBlock)
+            ));
+        }
+    }
+
+    #[test]
+    fn foreign_app10_segment_is_ignored() {
+        let content = fs::read(fixture!("crop.jpeg")).unwrap();
+        let expected = split_jpeg(&content, &BASIC_KEY).unwrap();
+        let mut container = JFIFContainer::try_from(&content).unwrap();
+        let segment_count = container.get_custom_segment().len();
+        let foreign = GeneralSegment::new(Vec::from(&b"MILFDATA\x00\x00\x01\x02"[..]));
+
+        container
+            .get_segments_mut()
+            .insert(2, JFIFSegment::APP(10, foreign));
+
+        let content = Vec::try_from(container).unwrap();
+        let container = JFIFContainer::try_from(&content).unwrap();
+
+        assert_eq!(container.get_custom_segment().len(), segment_count);
+        assert_eq!(
+            split_jpeg(&content, &BASIC_KEY).unwrap().old_section_img,
+            expected.old_section_img
+        );
+    }
+
+    #[test]
+    fn test_split_auto() {
+        let content = fs::read(fixture!("crop.webp")).unwrap();
+        let split_result = split_auto(&content, &BASIC_KEY);
+
+        assert!(split_result.is_ok());
+
+        let content = fs::read(fixture!("crop.jpeg")).unwrap();
+        let split_result = split_auto(&content, &BASIC_KEY);
+
+        assert!(split_result.is_ok());
+
+        let content = vec![0x42u8, 0x4D, 0x46, 0x38, 0x39, 0x61];
+        let split_result = split_auto(&content, &BASIC_KEY);
+
+        assert!(matches!(split_result, Err(SplitError::UnsupportedFormat)));
+    }
+
+    #[test]
+    fn test_split_png() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::new(16, 16));
+        let mut content: Vec<u8> = Vec::new();
+        img.write_to(&mut Cursor::new(&mut content), ImageFormat::Png)
+            .unwrap();
+
+        let mut target = vec![0x0Au8, 0x0B, 0x0C];
+        target.extend([2u8, 0, 0, 0, 3, 0, 0, 0]);
+        target.extend([4u8, 0, 0, 0, 5, 0, 0, 0]);
+
+        let joined = join_png(&content, &target, &BASIC_KEY).unwrap();
+        let split_data = split_png(&joined, &BASIC_KEY).unwrap();
+
+        assert_eq!(split_data.old_section_img, [0x0A, 0x0B, 0x0C]);
+        assert_eq!(split_data.position.x, 2);
+        assert_eq!(split_data.position.y, 3);
+        assert_eq!(split_data.dimension.width, 4);
+        assert_eq!(split_data.dimension.height, 5);
+        assert!(image::load_from_memory_with_format(&joined, ImageFormat::Png).is_ok());
+
+        let mut second_target = vec![0x0Du8];
+        second_target.extend([6u8, 0, 0, 0, 7, 0, 0, 0]);
+        second_target.extend([8u8, 0, 0, 0, 9, 0, 0, 0]);
+
+        let rejoined = join_png(&joined, &second_target, &BASIC_KEY).unwrap();
+        let split_data = split_png(&rejoined, &BASIC_KEY).unwrap();
+
+        assert_eq!(split_data.old_section_img, [0x0D]);
+        assert_eq!(split_data.position.x, 6);
+        assert_eq!(
+            PNGContainer::try_from(&rejoined)
+                .unwrap()
+                .get_chunks()
+                .iter()
+                .filter(|f| f.chunk_type == CUSTOM_CHUNK_TYPE)
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_split_heif() {
+        let mut ftyp_data = Vec::from("avif".as_bytes());
+        ftyp_data.extend([0, 0, 0, 0]);
+        ftyp_data.extend("mif1".as_bytes());
+        let ftyp = ISOBox::new("ftyp", ftyp_data);
+        let mdat = ISOBox::new("mdat", vec![0x01, 0x02]);
+        let content = ISOBMFFContainer::new(vec![ftyp, mdat]).to_bytes();
+
+        let mut target = vec![0x0Au8, 0x0B];
+        target.extend([2u8, 0, 0, 0, 3, 0, 0, 0]);
+        target.extend([4u8, 0, 0, 0, 5, 0, 0, 0]);
+
+        let joined = join_heif(&content, &target, &BASIC_KEY).unwrap();
+        let split_data = split_auto(&joined, &BASIC_KEY).unwrap();
+
+        assert_eq!(split_data.old_section_img, [0x0A, 0x0B]);
+        assert_eq!(split_data.position.x, 2);
+        assert_eq!(split_data.dimension.height, 5);
+        assert_eq!(
+            &joined[joined.len() - 10..],
+            [0, 0, 0, 10, 0x6D, 0x64, 0x61, 0x74, 0x01, 0x02]
+        );
+    }
+
+    #[test]
+    fn test_split_gif() {
+        let colors = [Rgba([255u8, 0, 0, 255]), Rgba([0u8, 0, 255, 255])];
+        let frames: Vec<AnimationFrame> = colors
+            .iter()
+            .map(|f| AnimationFrame {
+                image: DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 16, *f)),
+                left: 0,
+                top: 0,
+                delay: Delay::from_numer_denom_ms(100, 1),
+            })
+            .collect();
+        let content = encode_animation_gif(&frames).unwrap();
+        let watermark = RgbaImage::from_pixel(4, 4, Rgba([0, 255, 0, 255]));
+        let mut watermark_task = WatermarkTask::new();
+
+        set_target_gif(&mut watermark_task, &content).unwrap();
+        watermark_task.set_watermark(Some(DynamicImage::ImageRgba8(watermark)));
+        watermark_task.set_position(2, 2, OriginX::Right, OriginY::Bottom);
+        watermark_task.process().unwrap();
+
+        let output_frames = watermark_task.get_output_frames().as_ref().unwrap();
+        let sections = watermark_task.get_old_section_frames().as_ref().unwrap();
+        let indices: Vec<usize> = sections.iter().map(|f| f.index).collect();
+
+        assert_eq!(output_frames.len(), 2);
+        assert_eq!(indices, [0, 1]);
+        assert_eq!(output_frames[1].image.get_pixel(11, 11), Rgba([0, 255, 0, 255]));
+
+        let output = encode_frames_gif(&content, output_frames, &indices).unwrap();
+        let mut target = pack_frames(&encode_gif_frames(&content, &indices).unwrap());
+        let watermark_pos: [u8; 8] = watermark_task
+            .get_absolute_watermark_position()
+            .unwrap()
+            .into();
+        let watermark_dim: [u8; 8] = watermark_task.get_watermark_dimension().unwrap().into();
+        target.extend(watermark_pos);
+        target.extend(watermark_dim);
+
+        let joined = join_gif(&output, &target, &BASIC_KEY).unwrap();
+        let joined_frames = get_frames_gif(&joined).unwrap();
+        let split_data = split_gif_frames(&joined, &BASIC_KEY).unwrap();
+
+        assert_eq!(joined_frames[1].image.get_pixel(11, 11), Rgba([0, 255, 0, 255]));
+        assert_eq!(joined_frames[1].image.get_pixel(1, 1), colors[1]);
+        assert_eq!(split_data.position, Point { x: 10, y: 10 });
+        assert_eq!(split_data.dimension, Dimension::new(4, 4));
+        assert_eq!(split_data.frames.len(), 2);
+
+        for (frame, color) in split_data.frames.iter().zip(colors) {
+            let frame_img =
+                image::load_from_memory_with_format(&frame.section_img, ImageFormat::Gif).unwrap();
+
+            assert_eq!(frame.position, Point { x: 0, y: 0 });
+            assert_eq!(frame_img.get_pixel(11, 11), color);
+        }
+
+        assert!(matches!(
+            split_auto(&joined, &BASIC_KEY),
+            Err(SplitError::UnsupportedFormat)
+        ));
+        assert_eq!(restore_gif(&joined, &BASIC_KEY).unwrap(), content);
+    }
+
+    fn encode_webp_frame(color: Rgba<u8>, width: u32, height: u32) -> Vec<u8> {
+        let frame_img = RgbaImage::from_pixel(width, height, color);
+        let mut bytes: Vec<u8> = Vec::new();
+        WebPEncoder::new_lossless(&mut bytes)
+            .encode(&frame_img, width, height, ColorType::Rgba8)
+            .unwrap();
+
+        bytes
+    }
+
+    #[test]
+    fn test_restore_webp_animated() {
+        let colors = [Rgba([255u8, 0, 0, 255]), Rgba([0u8, 0, 255, 255])];
+        let mut vp8x_data = vec![0x12u8, 0, 0, 0];
+        vp8x_data.extend([15, 0, 0, 15, 0, 0]);
+        let mut subchunks: Vec<Box<dyn Chunk>> = vec![
+            Box::new(RegularChunk {
+                chunk_id: String::from("VP8X"),
+                chunk_data: vp8x_data,
+            }),
+            Box::new(AnimChunk {
+                background_color: [0, 0, 0, 0],
+                loop_count: 0,
+            }),
+        ];
+
+        for (i, color) in colors.iter().enumerate() {
+            let mut frame = AnmfChunk {
+                x: 0,
+                y: i as u32 * 8,
+                width: 16,
+                height: 8,
+                duration: 100 + i as u32,
+                blend: i == 0,
+                dispose: i == 1,
+                frame_chunks: Vec::new(),
+            };
+            frame
+                .set_frame_webp(&encode_webp_frame(*color, 16, 8))
+                .unwrap();
+            subchunks.push(Box::new(frame));
+        }
+
+        let content = RIFFContainer::new("WEBP", subchunks).to_bytes();
+        let watermark = RgbaImage::from_pixel(4, 4, Rgba([0, 255, 0, 255]));
+        let mut watermark_task = WatermarkTask::new();
+
+        set_target_auto(&mut watermark_task, &content).unwrap();
+        watermark_task.set_watermark(Some(DynamicImage::ImageRgba8(watermark)));
+        watermark_task.set_position(2, 2, OriginX::Right, OriginY::Bottom);
+        watermark_task.process().unwrap();
+
+        let output_frames = watermark_task.get_output_frames().as_ref().unwrap();
+        let sections = watermark_task.get_old_section_frames().as_ref().unwrap();
+
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].index, 1);
+        assert_eq!(sections[0].position, Point { x: 10, y: 2 });
+
+        let indices: Vec<usize> = sections.iter().map(|f| f.index).collect();
+        let output = encode_frames_webp(&content, output_frames, &indices).unwrap();
+        let mut target = pack_frames(&encode_frame_sections(sections).unwrap());
+        let watermark_pos: [u8; 8] = watermark_task
+            .get_absolute_watermark_position()
+            .unwrap()
+            .into();
+        let watermark_dim: [u8; 8] = watermark_task.get_watermark_dimension().unwrap().into();
+        target.extend(watermark_pos);
+        target.extend(watermark_dim);
+
+        let joined = join_webp(&output, &target, &BASIC_KEY).unwrap();
+        let joined_frames = get_frames_webp(&joined).unwrap();
+
+        assert_eq!(joined_frames[1].image.get_pixel(11, 3), Rgba([0, 255, 0, 255]));
+
+        let split_frames = split_webp_frames(&joined, &BASIC_KEY).unwrap();
+
+        assert!(matches!(
+            split_webp(&joined, &BASIC_KEY),
+            Err(SplitError::AnimatedWebpFile)
+        ));
+        assert!(matches!(
+            StreamSplitter::new(FileFormat::WebP, &BASIC_KEY).unwrap().push(&joined),
+            Err(SplitError::AnimatedWebpFile)
+        ));
+        assert_eq!(split_frames.frames.len(), 1);
+        assert_eq!(split_frames.frames[0].index, 1);
+
+        let restored = restore_webp(&joined, &BASIC_KEY).unwrap();
+        let container = RIFFContainer::try_from(&restored).unwrap();
+        let frames = container.get_frames();
+        let restored_frames = get_frames_webp(&restored).unwrap();
+
+        assert!(!restored.windows(4).any(|w| w == b"milf"));
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[1].x, frames[1].y), (0, 8));
+        assert_eq!(frames[1].duration, 101);
+        assert!(!frames[1].blend);
+        assert!(frames[1].dispose);
+        assert_eq!(restored_frames[1].image.get_pixel(11, 3), colors[1]);
+
+        let source = RIFFContainer::try_from(&content).unwrap();
+
+        assert_eq!(frames[0].to_bytes(), source.get_frames()[0].to_bytes());
+    }
+
+    #[test]
+    fn test_restore_wav() {
+        let format = WaveFormat {
+            audio_format: 1,
+            channels: 2,
+            sample_rate: 8000,
+            byte_rate: 32000,
+            block_align: 4,
+            bits_per_sample: 16,
+        };
+        let data: Vec<u8> = (0..16000u32).map(|f| (f % 251) as u8).collect();
+        let content = RIFFContainer::new(
+            "WAVE",
+            vec![
+                Box::new(RegularChunk {
+                    chunk_id: String::from("fmt "),
+                    chunk_data: format.to_bytes(),
+                }),
+                Box::new(RegularChunk {
+                    chunk_id: String::from("data"),
+                    chunk_data: data,
+                }),
+            ],
+        )
+        .to_bytes();
+        let mut container = WAVContainer::try_from(&content).unwrap();
+        let section = container
+            .overlay_tone(100, 250, TONE_FREQUENCY, TONE_AMPLITUDE)
+            .unwrap();
+        let target: Vec<u8> = (&section).into();
+        let joined = join_wav(&container.to_bytes(), &target, &BASIC_KEY).unwrap();
+
+        assert_ne!(joined[0..content.len()], content[..]);
+
+        let split_data = split_wav(&joined, &BASIC_KEY).unwrap();
+
+        assert_eq!(split_data.offset, 3200);
+        assert_eq!(split_data.samples.len(), 8000);
+        assert_eq!(restore_wav(&joined, &BASIC_KEY).unwrap(), content);
+    }
+
+    #[test]
+    fn test_split_tiff() {
+        let img = image::load_from_memory_with_format(
+            &fs::read(fixture!("test.webp")).unwrap(),
+            ImageFormat::WebP,
+        )
+        .unwrap();
+        let mut content: Vec<u8> = Vec::new();
+        img.write_to(&mut Cursor::new(&mut content), ImageFormat::Tiff)
+            .unwrap();
+
+        let mut target = vec![0x0Au8, 0x0B, 0x0C];
+        target.extend([2u8, 0, 0, 0, 3, 0, 0, 0]);
+        target.extend([4u8, 0, 0, 0, 5, 0, 0, 0]);
+
+        let joined = join_tiff(&content, &target, &BASIC_KEY).unwrap();
+        let split_data = split_auto(&joined, &BASIC_KEY).unwrap();
+        let output = image::load_from_memory_with_format(&joined, ImageFormat::Tiff).unwrap();
+
+        assert_eq!(split_data.old_section_img, [0x0A, 0x0B, 0x0C]);
+        assert_eq!(split_data.position.y, 3);
+        assert_eq!(split_data.dimension.width, 4);
+        assert_eq!(output.to_rgba8(), img.to_rgba8());
+    }
 }
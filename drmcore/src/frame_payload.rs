@@ -0,0 +1,171 @@
+use crate::file_joiner::{le_to_u32, usize_to_le};
+use crate::gif_container::GIFContainer;
+use crate::watermark_task::{AnimationFrame, FrameSection, Point};
+use image::{imageops, load_from_memory_with_format, DynamicImage, ImageFormat, ImageResult};
+use std::io::Cursor;
+
+#[derive(Debug, PartialEq)]
+pub struct FramePayload {
+    pub index: usize,
+    pub position: Point,
+    pub section_img: Vec<u8>,
+}
+
+pub fn encode_frame_sections(sections: &[FrameSection]) -> ImageResult<Vec<FramePayload>> {
+    sections
+        .iter()
+        .map(|f| {
+            let mut section_img: Vec<u8> = Vec::new();
+            let mut cur = Cursor::new(&mut section_img);
+            f.section.write_to(&mut cur, ImageFormat::Png)?;
+
+            Ok(FramePayload {
+                index: f.index,
+                position: Point {
+                    x: f.position.x,
+                    y: f.position.y,
+                },
+                section_img,
+            })
+        })
+        .collect()
+}
+
+// The original frames as gifs of their own placed at their offset on the canvas, restoring puts
+// their blocks back into the file as they were
+pub fn encode_gif_frames(source: &[u8], indices: &[usize]) -> Option<Vec<FramePayload>> {
+    let container = GIFContainer::try_from(&Vec::from(source)).ok()?;
+
+    indices
+        .iter()
+        .map(|f| {
+            let (_, image) = container.get_frame(*f)?;
+            let (left, top) = image.get_position();
+
+            Some(FramePayload {
+                index: *f,
+                position: Point {
+                    x: left as u32,
+                    y: top as u32,
+                },
+                section_img: container.frame_to_bytes(*f)?,
+            })
+        })
+        .collect()
+}
+
+// Frame count, then index, position, section length and section bytes per frame
+pub fn pack_frames(frames: &[FramePayload]) -> Vec<u8> {
+    let mut result = Vec::from(usize_to_le(frames.len()));
+
+    frames.iter().for_each(|f| {
+        result.extend(usize_to_le(f.index));
+        result.extend(usize_to_le(f.position.x as usize));
+        result.extend(usize_to_le(f.position.y as usize));
+        result.extend(usize_to_le(f.section_img.len()));
+        result.extend(&f.section_img);
+    });
+
+    result
+}
+
+pub fn unpack_frames(inp: &[u8]) -> Option<Vec<FramePayload>> {
+    let frame_count = le_to_u32(inp.get(0..4)?) as usize;
+    let mut result = Vec::new();
+    let mut counter = 4usize;
+
+    for _ in 0..frame_count {
+        let section_start = counter.checked_add(16)?;
+        let header = inp.get(counter..section_start)?;
+        let index = le_to_u32(&header[0..4]) as usize;
+        let position = Point::try_from(&header[4..12]).ok()?;
+        let section_len = le_to_u32(&header[12..16]) as usize;
+        let section_end = section_start.checked_add(section_len)?;
+        let section_img = inp.get(section_start..section_end)?;
+
+        counter = section_end;
+        result.push(FramePayload {
+            index,
+            position,
+            section_img: Vec::from(section_img),
+        });
+    }
+
+    Some(result)
+}
+
+pub fn restore_frames(frames: &mut [AnimationFrame], payloads: &[FramePayload]) -> ImageResult<()> {
+    for payload in payloads {
+        let section = load_from_memory_with_format(&payload.section_img, ImageFormat::Png)?;
+
+        if let Some(frame) = frames.get_mut(payload.index) {
+            let mut frame_img = frame.image.to_rgba8();
+            imageops::replace(
+                &mut frame_img,
+                &section.to_rgba8(),
+                payload.position.x as i64,
+                payload.position.y as i64,
+            );
+            frame.image = DynamicImage::ImageRgba8(frame_img);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Delay, Rgba, RgbaImage};
+
+    #[test]
+    fn pack_unpack_frames() {
+        let frames = vec![
+            FramePayload {
+                index: 0,
+                position: Point { x: 1, y: 2 },
+                section_img: vec![0x01, 0x02, 0x03],
+            },
+            FramePayload {
+                index: 3,
+                position: Point { x: 4, y: 5 },
+                section_img: vec![],
+            },
+        ];
+        let bytes = pack_frames(&frames);
+
+        assert_eq!(bytes.len(), 4 + 16 + 3 + 16);
+        assert_eq!(unpack_frames(&bytes).as_ref(), Some(&frames));
+        assert_eq!(unpack_frames(&bytes[0..bytes.len() - 1]), None);
+
+        let mut bytes = pack_frames(&frames[0..1]);
+        bytes[16..20].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFF]);
+
+        assert_eq!(unpack_frames(&bytes), None);
+    }
+
+    #[test]
+    fn restore_frames_from_sections() {
+        let frame_img = RgbaImage::from_pixel(8, 8, Rgba([0, 0, 0, 255]));
+        let mut frames = vec![AnimationFrame {
+            image: DynamicImage::ImageRgba8(frame_img),
+            left: 0,
+            top: 0,
+            delay: Delay::from_numer_denom_ms(100, 1),
+        }];
+        let section = FrameSection {
+            index: 0,
+            position: Point { x: 2, y: 3 },
+            section: DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 255]))),
+        };
+        let payloads = encode_frame_sections(&[section]).unwrap();
+
+        restore_frames(&mut frames, &payloads).unwrap();
+
+        let output = frames[0].image.to_rgba8();
+
+        assert_eq!(*output.get_pixel(2, 3), Rgba([255, 0, 0, 255]));
+        assert_eq!(*output.get_pixel(3, 4), Rgba([255, 0, 0, 255]));
+        assert_eq!(*output.get_pixel(4, 4), Rgba([0, 0, 0, 255]));
+    }
+}
@@ -0,0 +1,546 @@
+use std::convert::TryFrom;
+use thiserror::Error;
+
+pub const GIF87A_HEADER: &[u8; 6] = b"GIF87a";
+pub const GIF89A_HEADER: &[u8; 6] = b"GIF89a";
+pub const CUSTOM_APPLICATION_ID: &[u8; 11] = b"MILFDATA1.0";
+
+const EXTENSION_INTRODUCER: u8 = 0x21;
+const IMAGE_SEPARATOR: u8 = 0x2C;
+const TRAILER: u8 = 0x3B;
+const APPLICATION_LABEL: u8 = 0xFF;
+const GRAPHIC_CONTROL_LABEL: u8 = 0xF9;
+const MAX_SUB_BLOCK_SIZE: usize = 0xFF;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum GifContainerError {
+    #[error("Invalid gif header")]
+    InvalidHeader,
+
+    #[error("File ended prematurely")]
+    FileEndedPrematurely,
+
+    #[error("Unknown block found : `{0}`")]
+    UnknownBlock(u8),
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct GIFImage {
+    pub descriptor: [u8; 9],
+    pub local_color_table: Option<Vec<u8>>,
+    pub lzw_min_code_size: u8,
+    pub data: Vec<Vec<u8>>,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub enum GIFBlock {
+    Extension(u8, Vec<Vec<u8>>),
+    Image(GIFImage),
+}
+
+pub struct GIFContainer {
+    header: [u8; 6],
+    screen_descriptor: [u8; 7],
+    global_color_table: Option<Vec<u8>>,
+    blocks: Vec<GIFBlock>,
+}
+
+fn color_table_size(packed: u8) -> Option<usize> {
+    if packed & 0x80 == 0 {
+        return None;
+    }
+
+    Some(3 * (1 << ((packed & 0x07) + 1)))
+}
+
+fn sub_blocks_to_bytes(sub_blocks: &[Vec<u8>]) -> Vec<u8> {
+    let mut result = Vec::new();
+
+    sub_blocks.iter().for_each(|f| {
+        result.push(f.len() as u8);
+        result.extend(f);
+    });
+    result.push(0x00);
+
+    result
+}
+
+struct BlockReader<'a> {
+    inp: &'a [u8],
+    position: usize,
+}
+
+impl<'a> BlockReader<'a> {
+    fn read(&mut self, size: usize) -> Result<&'a [u8], GifContainerError> {
+        let result = self
+            .inp
+            .get(self.position..self.position + size)
+            .ok_or(GifContainerError::FileEndedPrematurely)?;
+        self.position += size;
+
+        Ok(result)
+    }
+
+    fn read_byte(&mut self) -> Result<u8, GifContainerError> {
+        Ok(self.read(1)?[0])
+    }
+
+    fn read_sub_blocks(&mut self) -> Result<Vec<Vec<u8>>, GifContainerError> {
+        let mut result = Vec::new();
+
+        loop {
+            let size = self.read_byte()? as usize;
+
+            if size == 0 {
+                break;
+            }
+
+            result.push(Vec::from(self.read(size)?));
+        }
+
+        Ok(result)
+    }
+}
+
+impl GIFImage {
+    pub fn get_position(&self) -> (u16, u16) {
+        let left = u16::from_le_bytes([self.descriptor[0], self.descriptor[1]]);
+        let top = u16::from_le_bytes([self.descriptor[2], self.descriptor[3]]);
+
+        (left, top)
+    }
+
+    pub fn get_dimension(&self) -> (u16, u16) {
+        let width = u16::from_le_bytes([self.descriptor[4], self.descriptor[5]]);
+        let height = u16::from_le_bytes([self.descriptor[6], self.descriptor[7]]);
+
+        (width, height)
+    }
+}
+
+impl GIFBlock {
+    pub fn new_application_extension(application_id: &[u8; 11], data: &[u8]) -> Self {
+        let mut sub_blocks = vec![Vec::from(&application_id[..])];
+
+        data.chunks(MAX_SUB_BLOCK_SIZE)
+            .for_each(|f| sub_blocks.push(Vec::from(f)));
+
+        GIFBlock::Extension(APPLICATION_LABEL, sub_blocks)
+    }
+
+    // Keeps the delay and disposal of `control` with a new transparent color index, a frame
+    // with neither does not need a graphic control extension
+    pub fn new_graphic_control(
+        control: Option<&GIFBlock>,
+        transparent_index: Option<u8>,
+    ) -> Option<Self> {
+        let data = match control {
+            Some(GIFBlock::Extension(GRAPHIC_CONTROL_LABEL, sub_blocks)) => sub_blocks.first(),
+            _ if transparent_index.is_none() => return None,
+            _ => None,
+        };
+        let mut data = data.filter(|f| f.len() == 4).cloned().unwrap_or(vec![0u8; 4]);
+        data[0] = (data[0] & !0x01) | u8::from(transparent_index.is_some());
+        data[3] = transparent_index.unwrap_or(0);
+
+        Some(GIFBlock::Extension(GRAPHIC_CONTROL_LABEL, vec![data]))
+    }
+
+    pub fn get_transparent_index(&self) -> Option<u8> {
+        match self {
+            GIFBlock::Extension(GRAPHIC_CONTROL_LABEL, sub_blocks) => {
+                let data = sub_blocks.first().filter(|f| f.len() == 4)?;
+
+                Some(data[3]).filter(|_| data[0] & 0x01 != 0)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_application_id(&self) -> Option<&[u8]> {
+        match self {
+            GIFBlock::Extension(APPLICATION_LABEL, sub_blocks) => {
+                sub_blocks.first().map(|f| &f[..])
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_application_data(&self) -> Option<Vec<u8>> {
+        match self {
+            GIFBlock::Extension(APPLICATION_LABEL, sub_blocks) => {
+                Some(sub_blocks.iter().skip(1).flatten().copied().collect())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+
+        match self {
+            GIFBlock::Extension(label, sub_blocks) => {
+                result.push(EXTENSION_INTRODUCER);
+                result.push(*label);
+                result.extend(sub_blocks_to_bytes(sub_blocks));
+            }
+            GIFBlock::Image(image) => {
+                result.push(IMAGE_SEPARATOR);
+                result.extend(image.descriptor);
+
+                if let Some(color_table) = &image.local_color_table {
+                    result.extend(color_table);
+                }
+
+                result.push(image.lzw_min_code_size);
+                result.extend(sub_blocks_to_bytes(&image.data));
+            }
+        }
+
+        result
+    }
+}
+
+impl GIFContainer {
+    pub fn get_header(&self) -> &[u8; 6] {
+        &self.header
+    }
+
+    pub fn set_header(&mut self, header: &[u8; 6]) {
+        self.header = *header;
+    }
+
+    pub fn get_screen_dimension(&self) -> (u16, u16) {
+        let width = u16::from_le_bytes([self.screen_descriptor[0], self.screen_descriptor[1]]);
+        let height = u16::from_le_bytes([self.screen_descriptor[2], self.screen_descriptor[3]]);
+
+        (width, height)
+    }
+
+    pub fn get_blocks(&self) -> &Vec<GIFBlock> {
+        &self.blocks
+    }
+
+    pub fn get_images(&self) -> Vec<&GIFImage> {
+        self.blocks
+            .iter()
+            .filter_map(|f| match f {
+                GIFBlock::Image(image) => Some(image),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn find_application_extension(&self, application_id: &[u8; 11]) -> Option<&GIFBlock> {
+        self.blocks
+            .iter()
+            .find(|p| p.get_application_id() == Some(&application_id[..]))
+    }
+
+    // Block positions of the graphic control extension directly in front of a frame and of
+    // the frame itself
+    fn get_frame_position(&self, index: usize) -> Option<(Option<usize>, usize)> {
+        let position = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| matches!(f, GIFBlock::Image(_)))
+            .nth(index)?
+            .0;
+        let control = position
+            .checked_sub(1)
+            .filter(|f| matches!(self.blocks[*f], GIFBlock::Extension(GRAPHIC_CONTROL_LABEL, _)));
+
+        Some((control, position))
+    }
+
+    pub fn get_frame(&self, index: usize) -> Option<(Option<&GIFBlock>, &GIFImage)> {
+        let (control, position) = self.get_frame_position(index)?;
+
+        match &self.blocks[position] {
+            GIFBlock::Image(image) => Some((control.map(|f| &self.blocks[f]), image)),
+            _ => None,
+        }
+    }
+
+    // A frame with its graphic control extension as a gif of its own, the global color table
+    // is kept so the frame decodes the same as inside this file
+    pub fn frame_to_bytes(&self, index: usize) -> Option<Vec<u8>> {
+        let (control, image) = self.get_frame(index)?;
+        let (width, height) = image.get_dimension();
+        let mut image = image.clone();
+        image.descriptor[0..4].copy_from_slice(&[0, 0, 0, 0]);
+
+        let mut screen_descriptor = self.screen_descriptor;
+        screen_descriptor[0..2].copy_from_slice(&width.to_le_bytes());
+        screen_descriptor[2..4].copy_from_slice(&height.to_le_bytes());
+
+        let blocks = control
+            .cloned()
+            .into_iter()
+            .chain([GIFBlock::Image(image)])
+            .collect();
+        let container = GIFContainer {
+            header: self.header,
+            screen_descriptor,
+            global_color_table: self.global_color_table.clone(),
+            blocks,
+        };
+
+        Some(container.to_bytes())
+    }
+
+    // Takes the first frame out of the file, the frame is moved to `left` and `top`
+    pub fn take_frame(mut self, left: u16, top: u16) -> Option<(Option<GIFBlock>, GIFImage)> {
+        let (control, position) = self.get_frame_position(0)?;
+        let image = match self.blocks.remove(position) {
+            GIFBlock::Image(mut image) => {
+                image.descriptor[0..2].copy_from_slice(&left.to_le_bytes());
+                image.descriptor[2..4].copy_from_slice(&top.to_le_bytes());
+                image
+            }
+            _ => return None,
+        };
+        let control = control.map(|f| self.blocks.remove(f));
+
+        Some((control, image))
+    }
+
+    // Replaces a frame and the graphic control extension in front of it, None drops it
+    pub fn set_frame(
+        &mut self,
+        index: usize,
+        control: Option<GIFBlock>,
+        image: GIFImage,
+    ) -> Option<()> {
+        let (old_control, mut position) = self.get_frame_position(index)?;
+        self.blocks[position] = GIFBlock::Image(image);
+
+        if let Some(old_control) = old_control {
+            self.blocks.remove(old_control);
+            position -= 1;
+        }
+
+        if let Some(control) = control {
+            self.header = *GIF89A_HEADER;
+            self.blocks.insert(position, control);
+        }
+
+        Some(())
+    }
+
+    pub fn remove_application_extension(&mut self, application_id: &[u8; 11]) {
+        self.blocks
+            .retain(|p| p.get_application_id() != Some(&application_id[..]));
+    }
+
+    // Extensions only exist since 89a, so the header is upgraded when one is added. The block
+    // goes before the first frame, a graphic control extension stays attached to its image
+    pub fn push_block(&mut self, block: GIFBlock) -> usize {
+        let first_image = self
+            .blocks
+            .iter()
+            .position(|p| matches!(p, GIFBlock::Image(_)));
+        let mut position = first_image.unwrap_or(self.blocks.len());
+
+        while position > 0
+            && matches!(self.blocks[position - 1], GIFBlock::Extension(GRAPHIC_CONTROL_LABEL, _))
+        {
+            position -= 1;
+        }
+
+        if let GIFBlock::Extension(_, _) = block {
+            self.header = *GIF89A_HEADER;
+        }
+
+        self.blocks.insert(position, block);
+
+        position
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::from(self.header);
+        result.extend(self.screen_descriptor);
+
+        if let Some(color_table) = &self.global_color_table {
+            result.extend(color_table);
+        }
+
+        self.blocks.iter().for_each(|f| {
+            result.extend(f.to_bytes());
+        });
+        result.push(TRAILER);
+
+        result
+    }
+}
+
+impl TryFrom<&Vec<u8>> for GIFContainer {
+    type Error = GifContainerError;
+
+    fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
+        let mut reader = BlockReader {
+            inp: value,
+            position: 0,
+        };
+        let header_bytes = reader
+            .read(6)
+            .map_err(|_| GifContainerError::InvalidHeader)?;
+
+        if header_bytes != GIF87A_HEADER && header_bytes != GIF89A_HEADER {
+            return Err(GifContainerError::InvalidHeader);
+        }
+
+        let mut header = [0u8; 6];
+        let mut screen_descriptor = [0u8; 7];
+        header.copy_from_slice(header_bytes);
+        screen_descriptor.copy_from_slice(reader.read(7)?);
+
+        let global_color_table = match color_table_size(screen_descriptor[4]) {
+            Some(size) => Some(Vec::from(reader.read(size)?)),
+            None => None,
+        };
+        let mut blocks = Vec::new();
+
+        loop {
+            match reader.read_byte()? {
+                EXTENSION_INTRODUCER => {
+                    let label = reader.read_byte()?;
+                    let sub_blocks = reader.read_sub_blocks()?;
+
+                    blocks.push(GIFBlock::Extension(label, sub_blocks));
+                }
+                IMAGE_SEPARATOR => {
+                    let mut descriptor = [0u8; 9];
+                    descriptor.copy_from_slice(reader.read(9)?);
+
+                    let local_color_table = match color_table_size(descriptor[8]) {
+                        Some(size) => Some(Vec::from(reader.read(size)?)),
+                        None => None,
+                    };
+                    let lzw_min_code_size = reader.read_byte()?;
+                    let data = reader.read_sub_blocks()?;
+
+                    blocks.push(GIFBlock::Image(GIFImage {
+                        descriptor,
+                        local_color_table,
+                        lzw_min_code_size,
+                        data,
+                    }));
+                }
+                TRAILER => break,
+                block => return Err(GifContainerError::UnknownBlock(block)),
+            }
+        }
+
+        Ok(GIFContainer {
+            header,
+            screen_descriptor,
+            global_color_table,
+            blocks,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minimal_gif() -> Vec<u8> {
+        let mut result = Vec::from(&GIF87A_HEADER[..]);
+        result.extend([0x01, 0x00, 0x01, 0x00, 0x80, 0x00, 0x00]);
+        result.extend([0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF]);
+        result.extend([0x2C, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00]);
+        result.extend([0x02, 0x02, 0x44, 0x01, 0x00]);
+        result.push(0x3B);
+
+        result
+    }
+
+    #[test]
+    fn try_from_vec_to_gif_container_success() {
+        let bytes = minimal_gif();
+        let container = GIFContainer::try_from(&bytes).unwrap();
+        let images = container.get_images();
+
+        assert_eq!(container.get_screen_dimension(), (1, 1));
+        assert_eq!(images.len(), 1);
+        assert_eq!(images[0].get_position(), (0, 0));
+        assert_eq!(images[0].get_dimension(), (1, 1));
+        assert_eq!(images[0].data, [vec![0x44, 0x01]]);
+        assert_eq!(container.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn try_from_vec_to_gif_container_failed() {
+        let mut bytes = minimal_gif();
+        bytes[3] = 0x00;
+
+        match GIFContainer::try_from(&bytes) {
+            Ok(_) => panic!("Should failed, header is invalid"),
+            Err(err) => assert_eq!(err, GifContainerError::InvalidHeader),
+        }
+
+        let bytes = minimal_gif();
+        let bytes = Vec::from(&bytes[0..bytes.len() - 4]);
+
+        match GIFContainer::try_from(&bytes) {
+            Ok(_) => panic!("Should failed, file is truncated"),
+            Err(err) => assert_eq!(err, GifContainerError::FileEndedPrematurely),
+        }
+
+        let mut bytes = minimal_gif();
+        let len = bytes.len();
+        bytes[len - 1] = 0x10;
+
+        match GIFContainer::try_from(&bytes) {
+            Ok(_) => panic!("Should failed, block is unknown"),
+            Err(err) => assert_eq!(err, GifContainerError::UnknownBlock(0x10)),
+        }
+    }
+
+    #[test]
+    fn application_extension_keeps_graphic_control_attached() {
+        let mut bytes = minimal_gif();
+        let netscape = [
+            0x21, 0xFF, 0x0B, 0x4E, 0x45, 0x54, 0x53, 0x43, 0x41, 0x50, 0x45, 0x32, 0x2E, 0x30,
+            0x03, 0x01, 0x00, 0x00, 0x00,
+        ];
+        let graphic_control = [0x21, 0xF9, 0x04, 0x04, 0x0A, 0x00, 0x00, 0x00];
+        bytes.splice(19..19, netscape.iter().chain(graphic_control.iter()).copied());
+
+        let mut container = GIFContainer::try_from(&bytes).unwrap();
+        let block = GIFBlock::new_application_extension(CUSTOM_APPLICATION_ID, &[0x01]);
+
+        assert_eq!(container.push_block(block), 1);
+
+        let blocks = container.get_blocks();
+
+        assert!(matches!(blocks[2], GIFBlock::Extension(0xF9, _)));
+        assert!(matches!(blocks[3], GIFBlock::Image(_)));
+    }
+
+    #[test]
+    fn application_extension_round_trip() {
+        let bytes = minimal_gif();
+        let mut container = GIFContainer::try_from(&bytes).unwrap();
+        let data: Vec<u8> = (0..600).map(|f| (f % 256) as u8).collect();
+        let block = GIFBlock::new_application_extension(CUSTOM_APPLICATION_ID, &data);
+
+        assert_eq!(container.push_block(block), 0);
+
+        let output = container.to_bytes();
+        let output_container = GIFContainer::try_from(&output).unwrap();
+        let block = output_container
+            .find_application_extension(CUSTOM_APPLICATION_ID)
+            .unwrap();
+
+        assert_eq!(&output[0..6], GIF89A_HEADER);
+        assert_eq!(block.get_application_data().unwrap(), data);
+
+        if let GIFBlock::Extension(label, sub_blocks) = block {
+            assert_eq!(*label, 0xFF);
+            assert_eq!(sub_blocks.len(), 4);
+        }
+    }
+}
@@ -1,4 +1,14 @@
-use image::{load_from_memory_with_format, ImageFormat};
+use crate::gif_container::{GIFBlock, GIFContainer};
+use crate::watermark_task::{AnimationFrame, Dimension, Point};
+use crate::webp_container::{Chunk, RIFFContainer};
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::codecs::webp::WebPEncoder;
+use image::error::{DecodingError, ImageFormatHint};
+use image::imageops::{self, FilterType};
+use image::{
+    load_from_memory_with_format, ColorType, DynamicImage, Frame, GenericImageView, ImageError,
+    ImageFormat, ImageResult,
+};
 use std::io::Cursor;
 
 pub fn get_section_webp(img: &[u8], x: u32, y: u32, w: u32, h: u32) -> Vec<u8> {
@@ -22,3 +32,169 @@ pub fn get_section_jpeg(img: &[u8], x: u32, y: u32, w: u32, h: u32) -> Vec<u8> {
 
     bytes
 }
+
+pub fn get_section_png(img: &[u8], x: u32, y: u32, w: u32, h: u32) -> Vec<u8> {
+    let img = load_from_memory_with_format(img, ImageFormat::Png).unwrap();
+    let crop_img = img.crop_imm(x, y, w, h);
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut cur = Cursor::new(&mut bytes);
+    crop_img.write_to(&mut cur, ImageFormat::Png).unwrap();
+
+    bytes
+}
+
+pub fn get_section_tiff(img: &[u8], x: u32, y: u32, w: u32, h: u32) -> Vec<u8> {
+    let img = load_from_memory_with_format(img, ImageFormat::Tiff).unwrap();
+    let crop_img = img.crop_imm(x, y, w, h);
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut cur = Cursor::new(&mut bytes);
+    crop_img.write_to(&mut cur, ImageFormat::Tiff).unwrap();
+
+    bytes
+}
+
+pub const THUMBNAIL_MAX_SIZE: u32 = 160;
+
+fn encode_thumbnail(img: &DynamicImage) -> ImageResult<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let mut cur = Cursor::new(&mut bytes);
+    DynamicImage::ImageRgb8(img.to_rgb8()).write_to(&mut cur, ImageFormat::Jpeg)?;
+
+    Ok(bytes)
+}
+
+// Keeps the size of the original thumbnail when it can be decoded
+pub fn make_thumbnail(img: &DynamicImage, original: Option<&[u8]>) -> ImageResult<Vec<u8>> {
+    let original = original.and_then(|f| load_from_memory_with_format(f, ImageFormat::Jpeg).ok());
+    let thumbnail = match original {
+        Some(original) => {
+            let (width, height) = original.dimensions();
+            img.resize_exact(width, height, FilterType::Triangle)
+        }
+        None => img.thumbnail(THUMBNAIL_MAX_SIZE, THUMBNAIL_MAX_SIZE),
+    };
+
+    encode_thumbnail(&thumbnail)
+}
+
+// The watermark is scaled by the same factor as the thumbnail is to the full image
+pub fn watermark_thumbnail(
+    thumbnail: &[u8],
+    watermark: &DynamicImage,
+    position: &Point,
+    target: &Dimension,
+) -> ImageResult<Vec<u8>> {
+    let mut thumbnail = load_from_memory_with_format(thumbnail, ImageFormat::Jpeg)?;
+    let (thumbnail_w, thumbnail_h) = thumbnail.dimensions();
+    let (watermark_w, watermark_h) = watermark.dimensions();
+    let scale_x = thumbnail_w as f64 / target.width as f64;
+    let scale_y = thumbnail_h as f64 / target.height as f64;
+    let scaled_w = ((watermark_w as f64 * scale_x).round() as u32).max(1);
+    let scaled_h = ((watermark_h as f64 * scale_y).round() as u32).max(1);
+    let scaled = watermark.resize_exact(scaled_w, scaled_h, FilterType::Triangle);
+
+    imageops::overlay(
+        &mut thumbnail,
+        &scaled,
+        (position.x as f64 * scale_x).round() as i64,
+        (position.y as f64 * scale_y).round() as i64,
+    );
+
+    encode_thumbnail(&thumbnail)
+}
+
+pub fn encode_animation_gif(frames: &[AnimationFrame]) -> ImageResult<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    let gif_frames = frames
+        .iter()
+        .map(|f| Frame::from_parts(f.image.to_rgba8(), f.left, f.top, f.delay));
+
+    {
+        let mut encoder = GifEncoder::new(&mut bytes);
+        encoder.set_repeat(Repeat::Infinite)?;
+        encoder.encode_frames(gif_frames)?;
+    }
+
+    Ok(bytes)
+}
+
+// Only the listed frames are re-encoded, every other block is kept untouched. The canvas is cut
+// back to the rectangle of the original frame so disposal works as before, a watermark outside
+// of that rectangle is left out of the frame
+pub fn encode_frames_gif(
+    source: &[u8],
+    frames: &[AnimationFrame],
+    indices: &[usize],
+) -> ImageResult<Vec<u8>> {
+    let invalid_gif = || {
+        ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Gif),
+            "Invalid gif",
+        ))
+    };
+    let mut container = GIFContainer::try_from(&Vec::from(source)).map_err(|_| invalid_gif())?;
+
+    for index in indices {
+        let (control, image) = container.get_frame(*index).ok_or_else(invalid_gif)?;
+        let frame = frames.get(*index).ok_or_else(invalid_gif)?;
+        let (left, top) = image.get_position();
+        let (width, height) = image.get_dimension();
+        let section = AnimationFrame {
+            image: frame
+                .image
+                .crop_imm(left as u32, top as u32, width as u32, height as u32),
+            left: 0,
+            top: 0,
+            delay: frame.delay,
+        };
+        let section_bytes = encode_animation_gif(&[section])?;
+        let (section_control, section_image) = GIFContainer::try_from(&section_bytes)
+            .ok()
+            .and_then(|f| f.take_frame(left, top))
+            .ok_or_else(invalid_gif)?;
+        let transparent_index = section_control.and_then(|f| f.get_transparent_index());
+
+        // The section is encoded without a global color table
+        if section_image.local_color_table.is_none() {
+            return Err(invalid_gif());
+        }
+
+        let control = GIFBlock::new_graphic_control(control, transparent_index);
+        container
+            .set_frame(*index, control, section_image)
+            .ok_or_else(invalid_gif)?;
+    }
+
+    Ok(container.to_bytes())
+}
+
+// Only the listed frames are re-encoded, the other ANMF chunks are kept untouched
+pub fn encode_frames_webp(
+    source: &[u8],
+    frames: &[AnimationFrame],
+    indices: &[usize],
+) -> ImageResult<Vec<u8>> {
+    let invalid_webp = |_| {
+        ImageError::Decoding(DecodingError::new(
+            ImageFormatHint::Exact(ImageFormat::WebP),
+            "Invalid animated webp",
+        ))
+    };
+    let mut container = RIFFContainer::try_from(&Vec::from(source)).map_err(invalid_webp)?;
+
+    for (index, anmf_chunk) in container.get_frames_mut().into_iter().enumerate() {
+        if let (true, Some(frame)) = (indices.contains(&index), frames.get(index)) {
+            let frame_img = frame.image.to_rgba8();
+            let mut bytes: Vec<u8> = Vec::new();
+            WebPEncoder::new_lossless(&mut bytes).encode(
+                &frame_img,
+                frame_img.width(),
+                frame_img.height(),
+                ColorType::Rgba8,
+            )?;
+            anmf_chunk.set_frame_webp(&bytes).map_err(invalid_webp)?;
+        }
+    }
+
+    Ok(container.to_bytes())
+}
@@ -0,0 +1,856 @@
+use std::convert::TryFrom;
+use std::str;
+use thiserror::Error;
+
+pub const CUSTOM_BOX_UUID: [u8; 16] = [
+    0x4D, 0x49, 0x4C, 0x46, 0x2D, 0x44, 0x52, 0x4D, 0x8E, 0x3B, 0x11, 0xEE, 0xA5, 0x6C, 0x02, 0x42,
+];
+pub const HEIF_BRANDS: [&str; 6] = ["avif", "avis", "heic", "heix", "mif1", "msf1"];
+
+#[derive(Debug, Error, PartialEq)]
+pub enum IsoBmffError {
+    #[error("Invalid box")]
+    InvalidBox,
+
+    #[error("Missing ftyp box")]
+    MissingFileType,
+
+    #[error("Invalid item location box")]
+    InvalidItemLocation,
+
+    #[error("Offset doesn't fit item location field")]
+    OffsetOverflow,
+
+    #[error("Invalid chunk offset box")]
+    InvalidChunkOffset,
+
+    #[error("Fragmented files are not supported")]
+    FragmentedFile,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ISOBox {
+    pub box_type: String,
+    pub user_type: Option<[u8; 16]>,
+    pub box_data: Vec<u8>,
+}
+
+pub struct ISOBMFFContainer {
+    boxes: Vec<ISOBox>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct FileTypeBox {
+    pub major_brand: String,
+    pub minor_version: u32,
+    pub compatible_brands: Vec<String>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ItemInfoEntry {
+    pub item_id: u32,
+    pub item_type: String,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ItemExtent {
+    pub extent_index: u64,
+    pub extent_offset: u64,
+    pub extent_length: u64,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ItemLocation {
+    pub item_id: u32,
+    pub construction_method: u8,
+    pub data_reference_index: u16,
+    pub base_offset: u64,
+    pub extents: Vec<ItemExtent>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct ItemLocationBox {
+    pub version: u8,
+    pub flags: [u8; 3],
+    pub offset_size: u8,
+    pub length_size: u8,
+    pub base_offset_size: u8,
+    pub index_size: u8,
+    pub items: Vec<ItemLocation>,
+}
+
+fn read_be(inp: &[u8]) -> u64 {
+    inp.iter().fold(0u64, |acc, f| (acc << 8) | *f as u64)
+}
+
+fn write_be(value: u64, size: u8) -> Vec<u8> {
+    (0..size)
+        .rev()
+        .map(|f| ((value >> (f as u32 * 8)) & 0xFF) as u8)
+        .collect()
+}
+
+fn fits_field(value: u64, size: u8) -> bool {
+    size >= 8 || value < (1u64 << (size as u32 * 8))
+}
+
+struct ByteReader<'a> {
+    inp: &'a [u8],
+    position: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(inp: &'a [u8]) -> Self {
+        Self { inp, position: 0 }
+    }
+
+    fn read(&mut self, size: usize) -> Result<&'a [u8], IsoBmffError> {
+        let result = self
+            .inp
+            .get(self.position..self.position + size)
+            .ok_or(IsoBmffError::InvalidItemLocation)?;
+        self.position += size;
+
+        Ok(result)
+    }
+
+    fn read_be(&mut self, size: usize) -> Result<u64, IsoBmffError> {
+        Ok(read_be(self.read(size)?))
+    }
+}
+
+impl ISOBox {
+    pub fn new(box_type: &str, box_data: Vec<u8>) -> Self {
+        Self {
+            box_type: String::from(box_type),
+            user_type: None,
+            box_data,
+        }
+    }
+
+    pub fn new_uuid(user_type: [u8; 16], box_data: Vec<u8>) -> Self {
+        Self {
+            box_type: String::from("uuid"),
+            user_type: Some(user_type),
+            box_data,
+        }
+    }
+
+    fn is_large_size(&self) -> bool {
+        self.box_data.len() + 24 > u32::MAX as usize
+    }
+
+    pub fn get_header_size(&self) -> usize {
+        let user_type_size = if self.user_type.is_some() { 16 } else { 0 };
+        let size_field = if self.is_large_size() { 16 } else { 8 };
+
+        size_field + user_type_size
+    }
+
+    pub fn get_box_size(&self) -> usize {
+        self.get_header_size() + self.box_data.len()
+    }
+
+    // Children of container boxes, `meta` is a full box and starts with version and flags
+    pub fn get_children(&self) -> Option<Vec<ISOBox>> {
+        let offset = match self.box_type.as_str() {
+            "meta" => 4,
+            "moov" | "trak" | "mdia" | "minf" | "stbl" | "iprp" | "ipco" | "dinf" => 0,
+            _ => return None,
+        };
+        let children_bytes = self.box_data.get(offset..)?;
+
+        ISOBMFFContainer::generate_boxes(children_bytes).ok()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        let box_size = self.get_box_size();
+
+        if self.is_large_size() {
+            result.extend(1u32.to_be_bytes());
+            result.extend(self.box_type.as_bytes());
+            result.extend((box_size as u64).to_be_bytes());
+        } else {
+            result.extend((box_size as u32).to_be_bytes());
+            result.extend(self.box_type.as_bytes());
+        }
+
+        if let Some(user_type) = self.user_type {
+            result.extend(user_type);
+        }
+
+        result.extend(&self.box_data);
+
+        result
+    }
+}
+
+impl ISOBMFFContainer {
+    pub fn new(boxes: Vec<ISOBox>) -> Self {
+        Self { boxes }
+    }
+
+    pub fn generate_boxes(inp: &[u8]) -> Result<Vec<ISOBox>, IsoBmffError> {
+        let mut result = Vec::new();
+        let mut counter = 0usize;
+
+        while counter < inp.len() {
+            let box_slice = inp.get(counter..).ok_or(IsoBmffError::InvalidBox)?;
+            let (iso_box, box_size) = read_box(box_slice)?;
+
+            result.push(iso_box);
+            counter += box_size;
+        }
+
+        Ok(result)
+    }
+
+    pub fn get_boxes(&self) -> &Vec<ISOBox> {
+        &self.boxes
+    }
+
+    pub fn find_box(&self, box_type: &str) -> Option<&ISOBox> {
+        self.boxes.iter().find(|p| p.box_type == box_type)
+    }
+
+    pub fn find_uuid_box(&self, user_type: &[u8; 16]) -> Option<&ISOBox> {
+        self.boxes
+            .iter()
+            .find(|p| p.box_type == "uuid" && p.user_type.as_ref() == Some(user_type))
+    }
+
+    pub fn get_file_type(&self) -> Option<FileTypeBox> {
+        let ftyp = self.find_box("ftyp")?;
+        let data = &ftyp.box_data;
+        let major_brand = str::from_utf8(data.get(0..4)?).ok()?;
+        let minor_version = read_be(data.get(4..8)?) as u32;
+        let compatible_brands = data
+            .get(8..)?
+            .chunks_exact(4)
+            .filter_map(|f| str::from_utf8(f).ok())
+            .map(String::from)
+            .collect();
+
+        Some(FileTypeBox {
+            major_brand: String::from(major_brand),
+            minor_version,
+            compatible_brands,
+        })
+    }
+
+    pub fn get_item_infos(&self) -> Vec<ItemInfoEntry> {
+        let mut result = Vec::new();
+        let meta_children = self.find_box("meta").and_then(|f| f.get_children());
+        let iinf = meta_children
+            .as_ref()
+            .and_then(|f| f.iter().find(|p| p.box_type == "iinf"));
+
+        if let Some(iinf) = iinf {
+            let version = iinf.box_data.first().copied().unwrap_or(0);
+            let entries_offset = if version == 0 { 6 } else { 8 };
+            let entries = iinf
+                .box_data
+                .get(entries_offset..)
+                .and_then(|f| ISOBMFFContainer::generate_boxes(f).ok())
+                .unwrap_or_default();
+
+            entries
+                .iter()
+                .filter(|p| p.box_type == "infe")
+                .filter_map(parse_item_info_entry)
+                .for_each(|f| result.push(f));
+        }
+
+        result
+    }
+
+    pub fn get_item_locations(&self) -> Option<ItemLocationBox> {
+        let meta_children = self.find_box("meta")?.get_children()?;
+        let iloc = meta_children.iter().find(|p| p.box_type == "iloc")?;
+
+        ItemLocationBox::try_from(&iloc.box_data[..]).ok()
+    }
+
+    fn get_box_offset(&self, index: usize) -> usize {
+        self.boxes
+            .iter()
+            .take(index)
+            .map(|f| f.get_box_size())
+            .sum()
+    }
+
+    fn shift_item_locations(&mut self, from_offset: u64, delta: u64) -> Result<(), IsoBmffError> {
+        let meta_pos = self.boxes.iter().position(|p| p.box_type == "meta");
+
+        if let Some(meta_pos) = meta_pos {
+            let meta = &self.boxes[meta_pos];
+            let mut children = meta.get_children().ok_or(IsoBmffError::InvalidBox)?;
+            let iloc = children.iter_mut().find(|p| p.box_type == "iloc");
+
+            if let Some(iloc) = iloc {
+                let mut location_box = ItemLocationBox::try_from(&iloc.box_data[..])?;
+                location_box.shift_offsets(from_offset, delta)?;
+                iloc.box_data = location_box.to_bytes();
+            }
+
+            let mut meta_data = Vec::from(&meta.box_data[0..4]);
+            children.iter().for_each(|f| meta_data.extend(f.to_bytes()));
+            self.boxes[meta_pos].box_data = meta_data;
+        }
+
+        Ok(())
+    }
+
+    // Insert before the first mdat so the payload is available early, shifting item offsets and
+    // the chunk offsets of image sequence tracks. Track fragments can address their data from an
+    // absolute base offset, so fragmented files are rejected
+    pub fn insert_box(&mut self, iso_box: ISOBox) -> Result<usize, IsoBmffError> {
+        let mdat_pos = self.boxes.iter().position(|p| p.box_type == "mdat");
+
+        if self.find_box("moof").is_some() {
+            return Err(IsoBmffError::FragmentedFile);
+        }
+
+        match mdat_pos {
+            Some(mdat_pos) => {
+                let insert_offset = self.get_box_offset(mdat_pos) as u64;
+                let delta = iso_box.get_box_size() as u64;
+
+                self.shift_item_locations(insert_offset, delta)?;
+
+                for moov in self.boxes.iter_mut().filter(|p| p.box_type == "moov") {
+                    shift_chunk_offsets(moov, insert_offset, delta)?;
+                }
+                self.boxes.insert(mdat_pos, iso_box);
+
+                Ok(mdat_pos)
+            }
+            None => {
+                self.boxes.push(iso_box);
+
+                Ok(self.boxes.len() - 1)
+            }
+        }
+    }
+
+    pub fn is_heif(&self) -> bool {
+        match self.get_file_type() {
+            Some(file_type) => {
+                HEIF_BRANDS.contains(&file_type.major_brand.as_str())
+                    || file_type
+                        .compatible_brands
+                        .iter()
+                        .any(|f| HEIF_BRANDS.contains(&f.as_str()))
+            }
+            None => false,
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+
+        self.boxes.iter().for_each(|f| {
+            result.extend(f.to_bytes());
+        });
+
+        result
+    }
+}
+
+fn read_box(inp: &[u8]) -> Result<(ISOBox, usize), IsoBmffError> {
+    let size_bytes = inp.get(0..4).ok_or(IsoBmffError::InvalidBox)?;
+    let type_bytes = inp.get(4..8).ok_or(IsoBmffError::InvalidBox)?;
+    let box_type = str::from_utf8(type_bytes).map_err(|_| IsoBmffError::InvalidBox)?;
+    let mut header_size = 8usize;
+    let box_size = match read_be(size_bytes) {
+        0 => inp.len(),
+        1 => {
+            let large_size = inp.get(8..16).ok_or(IsoBmffError::InvalidBox)?;
+            header_size = 16;
+            usize::try_from(read_be(large_size)).map_err(|_| IsoBmffError::InvalidBox)?
+        }
+        size => size as usize,
+    };
+    let user_type = if box_type == "uuid" {
+        let user_type = inp
+            .get(header_size..header_size + 16)
+            .ok_or(IsoBmffError::InvalidBox)?;
+        header_size += 16;
+        let mut user_type_arr = [0u8; 16];
+        user_type_arr.copy_from_slice(user_type);
+
+        Some(user_type_arr)
+    } else {
+        None
+    };
+
+    if box_size < header_size {
+        return Err(IsoBmffError::InvalidBox);
+    }
+
+    let box_data = inp
+        .get(header_size..box_size)
+        .ok_or(IsoBmffError::InvalidBox)?;
+    let iso_box = ISOBox {
+        box_type: String::from(box_type),
+        user_type,
+        box_data: Vec::from(box_data),
+    };
+
+    Ok((iso_box, box_size))
+}
+
+// Chunk offsets of a track are in moov/trak/mdia/minf/stbl, as 32 bit stco or 64 bit co64 entries
+fn shift_chunk_offsets(
+    iso_box: &mut ISOBox,
+    from_offset: u64,
+    delta: u64,
+) -> Result<(), IsoBmffError> {
+    match iso_box.box_type.as_str() {
+        "stco" | "co64" => {
+            let entry_size = if iso_box.box_type == "co64" { 8 } else { 4 };
+            let entry_count = iso_box
+                .box_data
+                .get(4..8)
+                .map(read_be)
+                .ok_or(IsoBmffError::InvalidChunkOffset)? as usize;
+            let entries_end = entry_count
+                .checked_mul(entry_size)
+                .and_then(|f| f.checked_add(8))
+                .ok_or(IsoBmffError::InvalidChunkOffset)?;
+            let entries = iso_box
+                .box_data
+                .get_mut(8..entries_end)
+                .ok_or(IsoBmffError::InvalidChunkOffset)?;
+
+            for entry in entries.chunks_exact_mut(entry_size) {
+                let offset = read_be(entry);
+
+                if offset >= from_offset {
+                    let offset = offset + delta;
+
+                    if !fits_field(offset, entry_size as u8) {
+                        return Err(IsoBmffError::OffsetOverflow);
+                    }
+
+                    entry.copy_from_slice(&write_be(offset, entry_size as u8));
+                }
+            }
+        }
+        "moov" | "trak" | "mdia" | "minf" | "stbl" => {
+            let mut children = iso_box.get_children().ok_or(IsoBmffError::InvalidBox)?;
+
+            for child in children.iter_mut() {
+                shift_chunk_offsets(child, from_offset, delta)?;
+            }
+
+            iso_box.box_data = children.iter().flat_map(|f| f.to_bytes()).collect();
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn parse_item_info_entry(infe: &ISOBox) -> Option<ItemInfoEntry> {
+    let data = &infe.box_data;
+    let version = *data.first()?;
+
+    match version {
+        2 => Some(ItemInfoEntry {
+            item_id: read_be(data.get(4..6)?) as u32,
+            item_type: String::from(str::from_utf8(data.get(8..12)?).ok()?),
+        }),
+        3 => Some(ItemInfoEntry {
+            item_id: read_be(data.get(4..8)?) as u32,
+            item_type: String::from(str::from_utf8(data.get(10..14)?).ok()?),
+        }),
+        _ => None,
+    }
+}
+
+impl ItemLocationBox {
+    pub fn shift_offsets(&mut self, from_offset: u64, delta: u64) -> Result<(), IsoBmffError> {
+        for item in self.items.iter_mut() {
+            // Only file offsets in this file are affected, idat and item offsets are relative
+            if item.construction_method != 0 || item.data_reference_index != 0 {
+                continue;
+            }
+
+            if self.base_offset_size > 0 && item.base_offset > 0 {
+                if item.base_offset >= from_offset {
+                    item.base_offset += delta;
+
+                    if !fits_field(item.base_offset, self.base_offset_size) {
+                        return Err(IsoBmffError::OffsetOverflow);
+                    }
+                }
+
+                continue;
+            }
+
+            for extent in item.extents.iter_mut() {
+                if extent.extent_offset >= from_offset {
+                    extent.extent_offset += delta;
+
+                    if !fits_field(extent.extent_offset, self.offset_size) {
+                        return Err(IsoBmffError::OffsetOverflow);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = vec![self.version];
+        result.extend(self.flags);
+        result.push((self.offset_size << 4) | self.length_size);
+        result.push((self.base_offset_size << 4) | self.index_size);
+
+        if self.version < 2 {
+            result.extend(write_be(self.items.len() as u64, 2));
+        } else {
+            result.extend(write_be(self.items.len() as u64, 4));
+        }
+
+        for item in self.items.iter() {
+            if self.version < 2 {
+                result.extend(write_be(item.item_id as u64, 2));
+            } else {
+                result.extend(write_be(item.item_id as u64, 4));
+            }
+
+            if self.version == 1 || self.version == 2 {
+                result.extend(write_be(item.construction_method as u64 & 0x0F, 2));
+            }
+
+            result.extend(write_be(item.data_reference_index as u64, 2));
+            result.extend(write_be(item.base_offset, self.base_offset_size));
+            result.extend(write_be(item.extents.len() as u64, 2));
+
+            for extent in item.extents.iter() {
+                if (self.version == 1 || self.version == 2) && self.index_size > 0 {
+                    result.extend(write_be(extent.extent_index, self.index_size));
+                }
+
+                result.extend(write_be(extent.extent_offset, self.offset_size));
+                result.extend(write_be(extent.extent_length, self.length_size));
+            }
+        }
+
+        result
+    }
+}
+
+impl TryFrom<&[u8]> for ItemLocationBox {
+    type Error = IsoBmffError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let mut reader = ByteReader::new(value);
+        let version = reader.read(1)?[0];
+        let flags_bytes = reader.read(3)?;
+        let sizes = reader.read(2)?;
+        let offset_size = sizes[0] >> 4;
+        let length_size = sizes[0] & 0x0F;
+        let base_offset_size = sizes[1] >> 4;
+        let index_size = if version == 1 || version == 2 {
+            sizes[1] & 0x0F
+        } else {
+            0
+        };
+        let item_count = if version < 2 {
+            reader.read_be(2)?
+        } else {
+            reader.read_be(4)?
+        };
+        let mut items = Vec::new();
+
+        if version > 2 {
+            return Err(IsoBmffError::InvalidItemLocation);
+        }
+
+        for _ in 0..item_count {
+            let item_id = if version < 2 {
+                reader.read_be(2)?
+            } else {
+                reader.read_be(4)?
+            } as u32;
+            let construction_method = if version == 1 || version == 2 {
+                (reader.read_be(2)? & 0x0F) as u8
+            } else {
+                0
+            };
+            let data_reference_index = reader.read_be(2)? as u16;
+            let base_offset = reader.read_be(base_offset_size as usize)?;
+            let extent_count = reader.read_be(2)?;
+            let mut extents = Vec::new();
+
+            for _ in 0..extent_count {
+                let extent_index = if index_size > 0 {
+                    reader.read_be(index_size as usize)?
+                } else {
+                    0
+                };
+                let extent_offset = reader.read_be(offset_size as usize)?;
+                let extent_length = reader.read_be(length_size as usize)?;
+
+                extents.push(ItemExtent {
+                    extent_index,
+                    extent_offset,
+                    extent_length,
+                });
+            }
+
+            items.push(ItemLocation {
+                item_id,
+                construction_method,
+                data_reference_index,
+                base_offset,
+                extents,
+            });
+        }
+
+        let mut flags = [0u8; 3];
+        flags.copy_from_slice(flags_bytes);
+
+        Ok(ItemLocationBox {
+            version,
+            flags,
+            offset_size,
+            length_size,
+            base_offset_size,
+            index_size,
+            items,
+        })
+    }
+}
+
+impl TryFrom<&Vec<u8>> for ISOBMFFContainer {
+    type Error = IsoBmffError;
+
+    fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
+        let boxes = ISOBMFFContainer::generate_boxes(value)?;
+
+        match boxes.first() {
+            Some(first_box) if first_box.box_type == "ftyp" => {}
+            _ => return Err(IsoBmffError::MissingFileType),
+        }
+
+        Ok(ISOBMFFContainer { boxes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minimal_avif() -> Vec<u8> {
+        let mut ftyp_data = Vec::from("avif".as_bytes());
+        ftyp_data.extend([0, 0, 0, 0]);
+        ftyp_data.extend("avifmif1miaf".as_bytes());
+        let ftyp = ISOBox::new("ftyp", ftyp_data);
+
+        let hdlr_data = [vec![0u8; 8], Vec::from("pict".as_bytes()), vec![0u8; 13]].concat();
+        let hdlr = ISOBox::new("hdlr", hdlr_data);
+
+        let mut infe_data = vec![2u8, 0, 0, 0, 0, 1, 0, 0];
+        infe_data.extend("av01".as_bytes());
+        infe_data.push(0);
+        let infe = ISOBox::new("infe", infe_data);
+        let mut iinf_data = vec![0u8, 0, 0, 0, 0, 1];
+        iinf_data.extend(infe.to_bytes());
+        let iinf = ISOBox::new("iinf", iinf_data);
+
+        let build_meta = |mdat_offset: u32| {
+            let mut iloc_data = vec![0u8, 0, 0, 0, 0x44, 0x00, 0, 1, 0, 1, 0, 0, 0, 1];
+            iloc_data.extend(mdat_offset.to_be_bytes());
+            iloc_data.extend([0, 0, 0, 4]);
+            let iloc = ISOBox::new("iloc", iloc_data);
+
+            let mut meta_data = vec![0u8; 4];
+            meta_data.extend(hdlr.to_bytes());
+            meta_data.extend(iinf.to_bytes());
+            meta_data.extend(iloc.to_bytes());
+
+            ISOBox::new("meta", meta_data)
+        };
+        let mdat_offset = ftyp.get_box_size() + build_meta(0).get_box_size() + 8;
+        let meta = build_meta(mdat_offset as u32);
+        let mdat = ISOBox::new("mdat", vec![0xDE, 0xAD, 0xBE, 0xEF]);
+        let container = ISOBMFFContainer::new(vec![ftyp, meta, mdat]);
+
+        container.to_bytes()
+    }
+
+    fn read_item(bytes: &[u8], container: &ISOBMFFContainer) -> Vec<u8> {
+        let location_box = container.get_item_locations().unwrap();
+        let extent = &location_box.items[0].extents[0];
+        let start = extent.extent_offset as usize;
+        let end = start + extent.extent_length as usize;
+
+        Vec::from(&bytes[start..end])
+    }
+
+    #[test]
+    fn iso_box_to_bytes() {
+        let iso_box = ISOBox::new("free", vec![0x01, 0x02]);
+
+        assert_eq!(
+            iso_box.to_bytes(),
+            [0x00, 0x00, 0x00, 0x0A, 0x66, 0x72, 0x65, 0x65, 0x01, 0x02]
+        );
+
+        let uuid_box = ISOBox::new_uuid(CUSTOM_BOX_UUID, vec![0x01]);
+        let bytes = uuid_box.to_bytes();
+
+        assert_eq!(bytes.len(), 25);
+        assert_eq!(&bytes[0..4], [0x00, 0x00, 0x00, 0x19]);
+        assert_eq!(&bytes[8..24], CUSTOM_BOX_UUID);
+    }
+
+    #[test]
+    fn try_from_vec_to_isobmff_container_success() {
+        let bytes = minimal_avif();
+        let container = ISOBMFFContainer::try_from(&bytes).unwrap();
+        let file_type = container.get_file_type().unwrap();
+        let item_infos = container.get_item_infos();
+
+        assert_eq!(container.get_boxes().len(), 3);
+        assert_eq!(file_type.major_brand, "avif");
+        assert_eq!(file_type.compatible_brands, ["avif", "mif1", "miaf"]);
+        assert_eq!(
+            item_infos,
+            [ItemInfoEntry {
+                item_id: 1,
+                item_type: String::from("av01")
+            }]
+        );
+        assert!(container.is_heif());
+        assert_eq!(read_item(&bytes, &container), [0xDE, 0xAD, 0xBE, 0xEF]);
+        assert_eq!(container.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn try_from_vec_to_isobmff_container_failed() {
+        let bytes = ISOBox::new("mdat", vec![0x01]).to_bytes();
+
+        match ISOBMFFContainer::try_from(&bytes) {
+            Ok(_) => panic!("Should failed, ftyp is missing"),
+            Err(err) => assert_eq!(err, IsoBmffError::MissingFileType),
+        }
+
+        let mut bytes = minimal_avif();
+        bytes.truncate(bytes.len() - 2);
+
+        match ISOBMFFContainer::try_from(&bytes) {
+            Ok(_) => panic!("Should failed, mdat is truncated"),
+            Err(err) => assert_eq!(err, IsoBmffError::InvalidBox),
+        }
+    }
+
+    #[test]
+    fn insert_box_fix_item_offsets() {
+        let bytes = minimal_avif();
+        let mut container = ISOBMFFContainer::try_from(&bytes).unwrap();
+        let custom_box = ISOBox::new_uuid(CUSTOM_BOX_UUID, vec![0xAA; 10]);
+        let position = container.insert_box(custom_box).unwrap();
+        let output = container.to_bytes();
+        let output_container = ISOBMFFContainer::try_from(&output).unwrap();
+
+        assert_eq!(position, 2);
+        assert_eq!(output.len(), bytes.len() + 34);
+        assert_eq!(
+            read_item(&output, &output_container),
+            [0xDE, 0xAD, 0xBE, 0xEF]
+        );
+
+        let custom_box = output_container.find_uuid_box(&CUSTOM_BOX_UUID).unwrap();
+
+        assert_eq!(custom_box.box_data, [0xAA; 10]);
+    }
+
+    fn nest_box(box_types: &[&str], child: ISOBox) -> ISOBox {
+        box_types
+            .iter()
+            .rev()
+            .fold(child, |acc, f| ISOBox::new(f, acc.to_bytes()))
+    }
+
+    #[test]
+    fn insert_box_fix_chunk_offsets() {
+        let mut ftyp_data = Vec::from("avis".as_bytes());
+        ftyp_data.extend([0, 0, 0, 0]);
+        ftyp_data.extend("avismsf1".as_bytes());
+        let ftyp = ISOBox::new("ftyp", ftyp_data);
+        let stbl_path = ["moov", "trak", "mdia", "minf", "stbl"];
+        let build_moov = |chunk_offset: u32| {
+            let mut stco_data = vec![0u8, 0, 0, 0, 0, 0, 0, 2];
+            stco_data.extend(chunk_offset.to_be_bytes());
+            stco_data.extend((chunk_offset + 2).to_be_bytes());
+            let stco = ISOBox::new("stco", stco_data);
+
+            nest_box(&stbl_path, stco)
+        };
+        let mdat_offset = ftyp.get_box_size() + build_moov(0).get_box_size() + 8;
+        let moov = build_moov(mdat_offset as u32);
+        let mdat = ISOBox::new("mdat", vec![0xDE, 0xAD, 0xBE, 0xEF]);
+        let mut container = ISOBMFFContainer::new(vec![ftyp, moov, mdat]);
+
+        assert!(container.is_heif());
+
+        container
+            .insert_box(ISOBox::new_uuid(CUSTOM_BOX_UUID, vec![0xAA; 10]))
+            .unwrap();
+
+        let output = container.to_bytes();
+        let mut stco = ISOBMFFContainer::try_from(&output).unwrap().boxes.remove(1);
+
+        for box_type in &stbl_path[1..] {
+            stco = stco
+                .get_children()
+                .unwrap()
+                .into_iter()
+                .find(|p| p.box_type == *box_type)
+                .unwrap();
+        }
+
+        let stco = stco.get_children().unwrap().remove(0);
+        let first = read_be(&stco.box_data[8..12]) as usize;
+        let second = read_be(&stco.box_data[12..16]) as usize;
+
+        assert_eq!(&output[first..first + 2], [0xDE, 0xAD]);
+        assert_eq!(&output[second..second + 2], [0xBE, 0xEF]);
+
+        let moof = ISOBox::new("moof", vec![]);
+        let mut container = ISOBMFFContainer::new(vec![
+            ISOBox::new("ftyp", Vec::from("iso6".as_bytes())),
+            moof,
+            ISOBox::new("mdat", vec![0x01]),
+        ]);
+
+        assert_eq!(
+            container.insert_box(ISOBox::new_uuid(CUSTOM_BOX_UUID, vec![])),
+            Err(IsoBmffError::FragmentedFile)
+        );
+    }
+
+    #[test]
+    fn item_location_box_round_trip() {
+        let iloc_data = vec![
+            1u8, 0, 0, 0, 0x44, 0x40, 0, 2, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0x20, 0,
+            0, 0, 0x08, 0, 2, 0, 1, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 0, 0x10, 0, 0, 0, 0x04,
+        ];
+        let mut location_box = ItemLocationBox::try_from(&iloc_data[..]).unwrap();
+
+        assert_eq!(location_box.to_bytes(), iloc_data);
+        assert_eq!(location_box.items[1].construction_method, 1);
+
+        location_box.shift_offsets(0x10, 0x100).unwrap();
+
+        assert_eq!(location_box.items[0].extents[0].extent_offset, 0x120);
+        assert_eq!(location_box.items[1].extents[0].extent_offset, 0x10);
+    }
+}
@@ -2,3 +2,8 @@ pub mod container;
 pub mod reader;
 pub mod writer;
 pub mod custom_segment;
+pub mod thumbnail;
+pub mod mpf;
+pub mod scan;
+pub mod frame;
+pub mod app_segment;
@@ -0,0 +1,664 @@
+use crate::file_joiner::be_to_usize;
+use crate::jpeg::container::{GeneralSegment, JFIFContainer, JFIFSegment};
+use crate::metadata::{EXIF_HEADER, ICC_HEADER, XMP_EXTENSION_HEADER, XMP_HEADER};
+use crate::tiff_container::TIFFContainer;
+use md5::{Digest, Md5};
+use thiserror::Error;
+
+pub const JFIF_HEADER: &[u8; 5] = b"JFIF\0";
+pub const ADOBE_HEADER: &[u8; 5] = b"Adobe";
+
+// Segment length field counts itself
+const SEGMENT_MAX_SIZE: usize = 0xFFFF - 2;
+
+// Each ICC chunk also carries the ICC header and the two sequence bytes
+const ICC_CHUNK_MAX_SIZE: usize = SEGMENT_MAX_SIZE - ICC_HEADER.len() - 2;
+
+// Each extension chunk also carries the GUID, the full length and its offset
+const XMP_EXTENSION_CHUNK_MAX_SIZE: usize = SEGMENT_MAX_SIZE - XMP_EXTENSION_HEADER.len() - 32 - 8;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum AppSegmentError {
+    #[error("Not a `{0}` segment")]
+    WrongSegment(&'static str),
+
+    #[error("Invalid `{0}` segment")]
+    InvalidSegment(&'static str),
+
+    #[error("Segment too large : `{0}`")]
+    SegmentTooLarge(&'static str),
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DensityUnit {
+    None,
+    PixelsPerInch,
+    PixelsPerCm,
+}
+
+// The thumbnail is stored as 24 bit RGB pixels
+#[derive(Debug, PartialEq, Clone)]
+pub struct JFIFHeader {
+    pub version: [u8; 2],
+    pub density_unit: DensityUnit,
+    pub x_density: u16,
+    pub y_density: u16,
+    pub thumbnail_width: u8,
+    pub thumbnail_height: u8,
+    pub thumbnail: Vec<u8>,
+}
+
+// EXIF data is a TIFF structure, it is kept as bytes so unknown layouts survive unchanged
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExifSegment {
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct XmpSegment {
+    pub packet: Vec<u8>,
+}
+
+// Profiles larger than one segment are split in chunks numbered from 1
+#[derive(Debug, PartialEq, Clone)]
+pub struct IccChunk {
+    pub sequence: u8,
+    pub count: u8,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct IccProfile {
+    pub data: Vec<u8>,
+}
+
+// Part of an XMP packet too large for one segment, the GUID is the MD5 digest of the whole
+// extension in upper case hex
+#[derive(Debug, PartialEq, Clone)]
+pub struct XmpExtensionChunk {
+    pub guid: [u8; 32],
+    pub full_length: u32,
+    pub offset: u32,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExtendedXmp {
+    pub guid: [u8; 32],
+    pub packet: Vec<u8>,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ColorTransform {
+    Unknown,
+    YCbCr,
+    YCCK,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct AdobeSegment {
+    pub version: u16,
+    pub flags0: u16,
+    pub flags1: u16,
+    pub color_transform: ColorTransform,
+}
+
+fn get_app_data<'a>(
+    segment: &'a JFIFSegment,
+    app_num: u8,
+    header: &[u8],
+    name: &'static str,
+) -> Result<&'a [u8], AppSegmentError> {
+    match segment {
+        JFIFSegment::APP(num, seg) if *num == app_num => seg
+            .get_data()
+            .strip_prefix(header)
+            .ok_or(AppSegmentError::WrongSegment(name)),
+        _ => Err(AppSegmentError::WrongSegment(name)),
+    }
+}
+
+fn build_app_segment(
+    app_num: u8,
+    header: &[u8],
+    data: &[u8],
+    name: &'static str,
+) -> Result<JFIFSegment, AppSegmentError> {
+    if header.len() + data.len() > SEGMENT_MAX_SIZE {
+        return Err(AppSegmentError::SegmentTooLarge(name));
+    }
+
+    let mut segment_data = Vec::with_capacity(header.len() + data.len());
+    segment_data.extend(header);
+    segment_data.extend(data);
+
+    Ok(JFIFSegment::APP(app_num, GeneralSegment::new(segment_data)))
+}
+
+impl Default for JFIFHeader {
+    fn default() -> Self {
+        JFIFHeader {
+            version: [1, 2],
+            density_unit: DensityUnit::None,
+            x_density: 1,
+            y_density: 1,
+            thumbnail_width: 0,
+            thumbnail_height: 0,
+            thumbnail: Vec::new(),
+        }
+    }
+}
+
+impl TryFrom<&JFIFSegment> for JFIFHeader {
+    type Error = AppSegmentError;
+
+    fn try_from(value: &JFIFSegment) -> Result<Self, Self::Error> {
+        let data = get_app_data(value, 0, JFIF_HEADER, "JFIF")?;
+        let invalid = AppSegmentError::InvalidSegment("JFIF");
+
+        if data.len() < 9 {
+            return Err(invalid);
+        }
+
+        let density_unit = match data[2] {
+            0 => DensityUnit::None,
+            1 => DensityUnit::PixelsPerInch,
+            2 => DensityUnit::PixelsPerCm,
+            _ => return Err(invalid),
+        };
+        let (thumbnail_width, thumbnail_height) = (data[7], data[8]);
+
+        if data.len() != 9 + thumbnail_width as usize * thumbnail_height as usize * 3 {
+            return Err(invalid);
+        }
+
+        Ok(JFIFHeader {
+            version: [data[0], data[1]],
+            density_unit,
+            x_density: be_to_usize(&data[3..5]) as u16,
+            y_density: be_to_usize(&data[5..7]) as u16,
+            thumbnail_width,
+            thumbnail_height,
+            thumbnail: Vec::from(&data[9..]),
+        })
+    }
+}
+
+impl TryFrom<&JFIFHeader> for JFIFSegment {
+    type Error = AppSegmentError;
+
+    fn try_from(value: &JFIFHeader) -> Result<Self, Self::Error> {
+        let thumbnail_size = value.thumbnail_width as usize * value.thumbnail_height as usize * 3;
+
+        if value.thumbnail.len() != thumbnail_size {
+            return Err(AppSegmentError::InvalidSegment("JFIF"));
+        }
+
+        let density_unit = match value.density_unit {
+            DensityUnit::None => 0,
+            DensityUnit::PixelsPerInch => 1,
+            DensityUnit::PixelsPerCm => 2,
+        };
+        let mut data = vec![value.version[0], value.version[1], density_unit];
+        data.extend(value.x_density.to_be_bytes());
+        data.extend(value.y_density.to_be_bytes());
+        data.extend([value.thumbnail_width, value.thumbnail_height]);
+        data.extend(&value.thumbnail);
+
+        build_app_segment(0, JFIF_HEADER, &data, "JFIF")
+    }
+}
+
+impl ExifSegment {
+    pub fn from_tiff(container: &TIFFContainer) -> Self {
+        ExifSegment {
+            data: container.to_bytes(),
+        }
+    }
+
+    pub fn get_tiff(&self) -> Result<TIFFContainer, AppSegmentError> {
+        TIFFContainer::try_from(&self.data).map_err(|_| AppSegmentError::InvalidSegment("EXIF"))
+    }
+}
+
+impl TryFrom<&JFIFSegment> for ExifSegment {
+    type Error = AppSegmentError;
+
+    fn try_from(value: &JFIFSegment) -> Result<Self, Self::Error> {
+        let data = get_app_data(value, 1, EXIF_HEADER, "EXIF")?;
+
+        Ok(ExifSegment {
+            data: Vec::from(data),
+        })
+    }
+}
+
+impl TryFrom<&ExifSegment> for JFIFSegment {
+    type Error = AppSegmentError;
+
+    fn try_from(value: &ExifSegment) -> Result<Self, Self::Error> {
+        build_app_segment(1, EXIF_HEADER, &value.data, "EXIF")
+    }
+}
+
+impl XmpSegment {
+    pub fn get_packet_str(&self) -> Option<&str> {
+        std::str::from_utf8(&self.packet).ok()
+    }
+}
+
+impl TryFrom<&JFIFSegment> for XmpSegment {
+    type Error = AppSegmentError;
+
+    fn try_from(value: &JFIFSegment) -> Result<Self, Self::Error> {
+        let data = get_app_data(value, 1, XMP_HEADER, "XMP")?;
+
+        Ok(XmpSegment {
+            packet: Vec::from(data),
+        })
+    }
+}
+
+impl TryFrom<&XmpSegment> for JFIFSegment {
+    type Error = AppSegmentError;
+
+    fn try_from(value: &XmpSegment) -> Result<Self, Self::Error> {
+        build_app_segment(1, XMP_HEADER, &value.packet, "XMP")
+    }
+}
+
+impl TryFrom<&JFIFSegment> for IccChunk {
+    type Error = AppSegmentError;
+
+    fn try_from(value: &JFIFSegment) -> Result<Self, Self::Error> {
+        let data = get_app_data(value, 2, ICC_HEADER, "ICC")?;
+
+        if data.len() < 2 {
+            return Err(AppSegmentError::InvalidSegment("ICC"));
+        }
+
+        Ok(IccChunk {
+            sequence: data[0],
+            count: data[1],
+            data: Vec::from(&data[2..]),
+        })
+    }
+}
+
+impl TryFrom<&IccChunk> for JFIFSegment {
+    type Error = AppSegmentError;
+
+    fn try_from(value: &IccChunk) -> Result<Self, Self::Error> {
+        let mut data = vec![value.sequence, value.count];
+        data.extend(&value.data);
+
+        build_app_segment(2, ICC_HEADER, &data, "ICC")
+    }
+}
+
+impl IccProfile {
+    // Chunks can come in any order, but every sequence number up to the count must be there
+    pub fn from_chunks(mut chunks: Vec<IccChunk>) -> Result<Self, AppSegmentError> {
+        chunks.sort_by_key(|f| f.sequence);
+
+        let complete = chunks
+            .iter()
+            .enumerate()
+            .all(|(index, f)| f.sequence as usize == index + 1 && f.count as usize == chunks.len());
+
+        if chunks.is_empty() || !complete {
+            return Err(AppSegmentError::InvalidSegment("ICC"));
+        }
+
+        Ok(IccProfile {
+            data: chunks.into_iter().flat_map(|f| f.data).collect(),
+        })
+    }
+
+    pub fn to_chunks(&self) -> Result<Vec<IccChunk>, AppSegmentError> {
+        let chunks: Vec<&[u8]> = self.data.chunks(ICC_CHUNK_MAX_SIZE).collect();
+
+        if chunks.len() > 0xFF {
+            return Err(AppSegmentError::SegmentTooLarge("ICC"));
+        }
+
+        Ok(chunks
+            .iter()
+            .enumerate()
+            .map(|(index, f)| IccChunk {
+                sequence: index as u8 + 1,
+                count: chunks.len() as u8,
+                data: Vec::from(*f),
+            })
+            .collect())
+    }
+
+    pub fn to_segments(&self) -> Result<Vec<JFIFSegment>, AppSegmentError> {
+        self.to_chunks()?
+            .iter()
+            .map(JFIFSegment::try_from)
+            .collect()
+    }
+}
+
+impl TryFrom<&JFIFSegment> for XmpExtensionChunk {
+    type Error = AppSegmentError;
+
+    fn try_from(value: &JFIFSegment) -> Result<Self, Self::Error> {
+        let data = get_app_data(value, 1, XMP_EXTENSION_HEADER, "XMP extension")?;
+
+        if data.len() < 40 {
+            return Err(AppSegmentError::InvalidSegment("XMP extension"));
+        }
+
+        Ok(XmpExtensionChunk {
+            guid: data[0..32].try_into().unwrap(),
+            full_length: be_to_usize(&data[32..36]) as u32,
+            offset: be_to_usize(&data[36..40]) as u32,
+            data: Vec::from(&data[40..]),
+        })
+    }
+}
+
+impl TryFrom<&XmpExtensionChunk> for JFIFSegment {
+    type Error = AppSegmentError;
+
+    fn try_from(value: &XmpExtensionChunk) -> Result<Self, Self::Error> {
+        let mut data = Vec::from(value.guid);
+        data.extend(value.full_length.to_be_bytes());
+        data.extend(value.offset.to_be_bytes());
+        data.extend(&value.data);
+
+        build_app_segment(1, XMP_EXTENSION_HEADER, &data, "XMP extension")
+    }
+}
+
+impl ExtendedXmp {
+    pub fn new(packet: Vec<u8>) -> Self {
+        let mut guid = [0u8; 32];
+        let digest: String = Md5::digest(&packet)
+            .iter()
+            .map(|f| format!("{:02X}", f))
+            .collect();
+
+        guid.copy_from_slice(digest.as_bytes());
+
+        ExtendedXmp { guid, packet }
+    }
+
+    // Only the chunks with the GUID are used, they have to cover the whole extension and match
+    // its digest
+    pub fn from_chunks(
+        chunks: Vec<XmpExtensionChunk>,
+        guid: &[u8; 32],
+    ) -> Result<Self, AppSegmentError> {
+        let invalid = || AppSegmentError::InvalidSegment("XMP extension");
+        let mut chunks: Vec<XmpExtensionChunk> =
+            chunks.into_iter().filter(|f| f.guid == *guid).collect();
+        chunks.sort_by_key(|f| f.offset);
+
+        let full_length = chunks.first().ok_or_else(invalid)?.full_length as usize;
+        let mut packet = Vec::with_capacity(full_length);
+
+        for chunk in chunks {
+            if chunk.full_length as usize != full_length || chunk.offset as usize != packet.len() {
+                return Err(invalid());
+            }
+
+            packet.extend(chunk.data);
+        }
+
+        let extended = ExtendedXmp::new(packet);
+
+        if extended.packet.len() != full_length || extended.guid != *guid {
+            return Err(invalid());
+        }
+
+        Ok(extended)
+    }
+
+    pub fn to_chunks(&self) -> Result<Vec<XmpExtensionChunk>, AppSegmentError> {
+        let full_length = u32::try_from(self.packet.len())
+            .map_err(|_| AppSegmentError::SegmentTooLarge("XMP extension"))?;
+
+        Ok(self
+            .packet
+            .chunks(XMP_EXTENSION_CHUNK_MAX_SIZE)
+            .enumerate()
+            .map(|(index, f)| XmpExtensionChunk {
+                guid: self.guid,
+                full_length,
+                offset: (index * XMP_EXTENSION_CHUNK_MAX_SIZE) as u32,
+                data: Vec::from(f),
+            })
+            .collect())
+    }
+
+    pub fn to_segments(&self) -> Result<Vec<JFIFSegment>, AppSegmentError> {
+        self.to_chunks()?
+            .iter()
+            .map(JFIFSegment::try_from)
+            .collect()
+    }
+}
+
+impl TryFrom<&JFIFSegment> for AdobeSegment {
+    type Error = AppSegmentError;
+
+    fn try_from(value: &JFIFSegment) -> Result<Self, Self::Error> {
+        let data = get_app_data(value, 14, ADOBE_HEADER, "Adobe")?;
+        let invalid = AppSegmentError::InvalidSegment("Adobe");
+
+        if data.len() != 7 {
+            return Err(invalid);
+        }
+
+        let color_transform = match data[6] {
+            0 => ColorTransform::Unknown,
+            1 => ColorTransform::YCbCr,
+            2 => ColorTransform::YCCK,
+            _ => return Err(invalid),
+        };
+
+        Ok(AdobeSegment {
+            version: be_to_usize(&data[0..2]) as u16,
+            flags0: be_to_usize(&data[2..4]) as u16,
+            flags1: be_to_usize(&data[4..6]) as u16,
+            color_transform,
+        })
+    }
+}
+
+impl TryFrom<&AdobeSegment> for JFIFSegment {
+    type Error = AppSegmentError;
+
+    fn try_from(value: &AdobeSegment) -> Result<Self, Self::Error> {
+        let color_transform = match value.color_transform {
+            ColorTransform::Unknown => 0,
+            ColorTransform::YCbCr => 1,
+            ColorTransform::YCCK => 2,
+        };
+        let mut data = Vec::from(value.version.to_be_bytes());
+        data.extend(value.flags0.to_be_bytes());
+        data.extend(value.flags1.to_be_bytes());
+        data.push(color_transform);
+
+        build_app_segment(14, ADOBE_HEADER, &data, "Adobe")
+    }
+}
+
+impl JFIFContainer {
+    // Segments that fail to parse as the requested type are skipped
+    pub fn get_app_segments<'a, T>(&'a self) -> Vec<T>
+    where
+        T: TryFrom<&'a JFIFSegment>,
+    {
+        self.get_segments()
+            .iter()
+            .filter_map(|f| T::try_from(f).ok())
+            .collect()
+    }
+
+    pub fn get_icc_profile(&self) -> Result<Option<IccProfile>, AppSegmentError> {
+        let chunks: Vec<IccChunk> = self.get_app_segments();
+
+        if chunks.is_empty() {
+            return Ok(None);
+        }
+
+        IccProfile::from_chunks(chunks).map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg::container::ToBytes;
+    use crate::tiff_container::{TIFFEntry, ARTIST_TAG, TIFFIFD};
+
+    fn round_trip<T>(value: &T) -> T
+    where
+        T: PartialEq + std::fmt::Debug,
+        for<'a> T: TryFrom<&'a JFIFSegment, Error = AppSegmentError>,
+        for<'a> JFIFSegment: TryFrom<&'a T, Error = AppSegmentError>,
+    {
+        let segment = JFIFSegment::try_from(value).unwrap();
+
+        T::try_from(&segment).unwrap()
+    }
+
+    #[test]
+    fn read_jfif_header() {
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let container = JFIFContainer::try_from(&content).unwrap();
+        let headers: Vec<JFIFHeader> = container.get_app_segments();
+
+        assert_eq!(headers, [JFIFHeader::default()]);
+        assert!(container.get_app_segments::<ExifSegment>().is_empty());
+
+        let segment = JFIFSegment::try_from(&headers[0]).unwrap();
+
+        assert_eq!(segment.to_bytes().unwrap(), content[2..20]);
+    }
+
+    #[test]
+    fn typed_segments_round_trip() {
+        let jfif = JFIFHeader {
+            density_unit: DensityUnit::PixelsPerInch,
+            x_density: 300,
+            y_density: 72,
+            thumbnail_width: 2,
+            thumbnail_height: 1,
+            thumbnail: vec![0x10; 6],
+            ..Default::default()
+        };
+        let adobe = AdobeSegment {
+            version: 100,
+            flags0: 0,
+            flags1: 0,
+            color_transform: ColorTransform::YCCK,
+        };
+        let xmp = XmpSegment {
+            packet: Vec::from(&b"<x:xmpmeta/>"[..]),
+        };
+        let mut tiff = TIFFContainer::new(
+            crate::tiff_container::ByteOrder::BigEndian,
+            vec![TIFFIFD::new(Vec::new())],
+        );
+        tiff.put_entry(TIFFEntry::new_ascii(ARTIST_TAG, "Someone"));
+
+        let exif = ExifSegment::from_tiff(&tiff);
+
+        assert_eq!(round_trip(&jfif), jfif);
+        assert_eq!(round_trip(&adobe), adobe);
+        assert_eq!(round_trip(&xmp).get_packet_str(), Some("<x:xmpmeta/>"));
+        assert_eq!(
+            round_trip(&exif).get_tiff().unwrap().find_entry(ARTIST_TAG),
+            tiff.find_entry(ARTIST_TAG)
+        );
+        assert_eq!(
+            AdobeSegment::try_from(&JFIFSegment::try_from(&jfif).unwrap()),
+            Err(AppSegmentError::WrongSegment("Adobe"))
+        );
+        assert_eq!(
+            JFIFSegment::try_from(&JFIFHeader {
+                thumbnail_width: 1,
+                thumbnail_height: 1,
+                ..Default::default()
+            })
+            .err(),
+            Some(AppSegmentError::InvalidSegment("JFIF"))
+        );
+        assert_eq!(
+            JFIFSegment::try_from(&XmpSegment {
+                packet: vec![0x20; SEGMENT_MAX_SIZE],
+            })
+            .err(),
+            Some(AppSegmentError::SegmentTooLarge("XMP"))
+        );
+    }
+
+    #[test]
+    fn icc_profile_reassembly() {
+        let profile = IccProfile {
+            data: (0..ICC_CHUNK_MAX_SIZE * 2 + 10).map(|f| f as u8).collect(),
+        };
+        let mut segments = profile.to_segments().unwrap();
+
+        assert_eq!(segments.len(), 3);
+
+        segments.swap(0, 2);
+
+        let container = JFIFContainer::new(segments);
+
+        assert_eq!(container.get_icc_profile(), Ok(Some(profile)));
+
+        let mut chunks: Vec<IccChunk> = container.get_app_segments();
+        chunks.remove(1);
+
+        assert_eq!(
+            IccProfile::from_chunks(chunks),
+            Err(AppSegmentError::InvalidSegment("ICC"))
+        );
+        assert_eq!(JFIFContainer::new(Vec::new()).get_icc_profile(), Ok(None));
+    }
+
+    #[test]
+    fn extended_xmp_reassembly() {
+        let extended = ExtendedXmp::new(
+            (0..XMP_EXTENSION_CHUNK_MAX_SIZE * 2 + 10)
+                .map(|f| b'a' + (f % 26) as u8)
+                .collect(),
+        );
+        let mut segments = extended.to_segments().unwrap();
+
+        assert_eq!(segments.len(), 3);
+
+        segments.swap(0, 2);
+
+        let container = JFIFContainer::new(segments);
+        let chunks: Vec<XmpExtensionChunk> = container.get_app_segments();
+
+        assert!(container.get_app_segments::<XmpSegment>().is_empty());
+        assert_eq!(
+            ExtendedXmp::from_chunks(chunks.clone(), &extended.guid),
+            Ok(extended.clone())
+        );
+        assert_eq!(
+            ExtendedXmp::from_chunks(chunks.clone(), &[b'0'; 32]),
+            Err(AppSegmentError::InvalidSegment("XMP extension"))
+        );
+
+        let mut incomplete = chunks;
+        incomplete.remove(1);
+
+        assert_eq!(
+            ExtendedXmp::from_chunks(incomplete, &extended.guid),
+            Err(AppSegmentError::InvalidSegment("XMP extension"))
+        );
+        assert_eq!(
+            &ExtendedXmp::new(Vec::new()).guid,
+            b"D41D8CD98F00B204E9800998ECF8427E"
+        );
+    }
+}
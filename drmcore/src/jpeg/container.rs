@@ -1,8 +1,21 @@
-use crate::file_joiner::usize_to_be;
+use crate::file_joiner::PayloadPlacement;
+use crate::metadata::EXIF_HEADER;
+use crate::payload_stream::PayloadNamespace;
 use core::convert::From;
+use std::io;
+use thiserror::Error;
 
 use super::custom_segment::CustomSegment;
 
+// The 16 bit length field counts itself, so this is the largest payload a segment can carry
+pub const MAX_SEGMENT_DATA: usize = 0xFFFF - 2;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum JFIFContainerError {
+    #[error("Segment too large : `{0}` bytes")]
+    SegmentTooLarge(usize),
+}
+
 #[derive(Debug)]
 pub struct GeneralSegment {
     data: Vec<u8>,
@@ -23,15 +36,24 @@ pub enum JFIFSegment {
     SOI,
     SOF0(GeneralSegment),
     SOF2(GeneralSegment),
+    // Extended sequential, lossless, hierarchical and arithmetic coded frames, kept as is
+    SOF(u8, GeneralSegment),
     DHT(GeneralSegment),
+    DAC(GeneralSegment),
     DQT(GeneralSegment),
     DRI([u8; 2]),
     SOS(GeneralSegment),
     RST(u8),
     APP(u8, GeneralSegment),
     COM(GeneralSegment),
+    DNL(GeneralSegment),
+    // Any other marker with a length, such as DHP, EXP or the JPGn extensions
+    OTHER(u8, GeneralSegment),
     IMGDATA(Vec<u8>),
+    // Run of 0xFF fill bytes in front of a marker
+    FILL(usize),
     EOI,
+    TRAILER(Vec<u8>),
 }
 
 pub struct JFIFContainer {
@@ -47,46 +69,269 @@ impl JFIFContainer {
         &self.segments
     }
 
+    pub fn get_segments_mut(&mut self) -> &mut Vec<JFIFSegment> {
+        &mut self.segments
+    }
+
     pub fn get_custom_segment(&self) -> Vec<CustomSegment> {
-        let mut custom_segments: Vec<CustomSegment> = Vec::new();
-        let _ = &self
+        self.get_namespace_segments(&PayloadNamespace::default())
+    }
+
+    // Segments of every stream in the namespace, in file order
+    pub fn get_namespace_segments(&self, namespace: &PayloadNamespace) -> Vec<CustomSegment> {
+        self.segments
+            .iter()
+            .filter_map(|p| CustomSegment::from_segment(p, namespace).ok())
+            .collect()
+    }
+
+    // Bytes following EOI, such as MPF secondary images or a motion photo video
+    pub fn get_trailer(&self) -> Option<&Vec<u8>> {
+        self.segments.iter().find_map(|f| match f {
+            JFIFSegment::TRAILER(data) => Some(data),
+            _ => None,
+        })
+    }
+
+    pub fn set_trailer(&mut self, trailer: Option<Vec<u8>>) {
+        self.segments
+            .retain(|p| !matches!(p, JFIFSegment::TRAILER(_)));
+
+        if let Some(trailer) = trailer.filter(|f| !f.is_empty()) {
+            self.segments.push(JFIFSegment::TRAILER(trailer));
+        }
+    }
+
+    // Stripped files have no APP segment at all, the segment then goes right after SOI
+    pub fn put_custom_segment(
+        &mut self,
+        segment: JFIFSegment,
+    ) -> Result<usize, JFIFContainerError> {
+        segment.check_size()?;
+
+        let position = self
+            .segments
+            .iter()
+            .rposition(|p| matches!(p, JFIFSegment::APP(_, _)))
+            .map(|f| f + 1)
+            .unwrap_or(self.get_header_range().start);
+
+        self.segments.insert(position, segment);
+
+        Ok(position)
+    }
+
+    pub fn iter_segments_mut(&mut self) -> std::slice::IterMut<'_, JFIFSegment> {
+        self.segments.iter_mut()
+    }
+
+    // Marker segments can only go between SOI and EOI
+    fn get_header_range(&self) -> std::ops::Range<usize> {
+        let start = self
+            .segments
+            .iter()
+            .position(|p| matches!(p, JFIFSegment::SOI))
+            .map_or(0, |f| f + 1);
+        let end = self
+            .segments
+            .iter()
+            .position(|p| matches!(p, JFIFSegment::EOI | JFIFSegment::TRAILER(_)))
+            .unwrap_or(self.segments.len());
+
+        start..end.max(start)
+    }
+
+    fn find_marker(&self, marker: u8) -> impl Iterator<Item = usize> + '_ {
+        self.segments
+            .iter()
+            .enumerate()
+            .filter(move |(_, p)| p.get_marker() == Some([0xFF, marker]))
+            .map(|(index, _)| index)
+    }
+
+    // Goes in front of the first segment with the marker and the fill bytes before it
+    pub fn insert_before(
+        &mut self,
+        marker: u8,
+        segment: JFIFSegment,
+    ) -> Result<usize, JFIFContainerError> {
+        segment.check_size()?;
+
+        let range = self.get_header_range();
+        let position = match self.find_marker(marker).next() {
+            Some(position) => {
+                let fill_size = self.segments[..position]
+                    .iter()
+                    .rev()
+                    .take_while(|p| matches!(p, JFIFSegment::FILL(_)))
+                    .count();
+
+                (position - fill_size).clamp(range.start, range.end)
+            }
+            None => range.start,
+        };
+
+        self.segments.insert(position, segment);
+
+        Ok(position)
+    }
+
+    // Goes behind the last segment with the marker, a scan keeps its image data
+    pub fn insert_after(
+        &mut self,
+        marker: u8,
+        segment: JFIFSegment,
+    ) -> Result<usize, JFIFContainerError> {
+        segment.check_size()?;
+
+        let range = self.get_header_range();
+        let position = match self.find_marker(marker).last() {
+            Some(position) => {
+                let data_size = self.segments[position + 1..]
+                    .iter()
+                    .take_while(|p| matches!(p, JFIFSegment::IMGDATA(_)))
+                    .count();
+
+                (position + 1 + data_size).clamp(range.start, range.end)
+            }
+            None => range.start,
+        };
+
+        self.segments.insert(position, segment);
+
+        Ok(position)
+    }
+
+    // Returns the number of removed segments
+    pub fn remove_segments<F>(&mut self, mut predicate: F) -> usize
+    where
+        F: FnMut(&JFIFSegment) -> bool,
+    {
+        let segment_count = self.segments.len();
+
+        self.segments.retain(|p| !predicate(p));
+
+        segment_count - self.segments.len()
+    }
+
+    // Replaces the first matching segment and returns it
+    pub fn replace_segment<F>(
+        &mut self,
+        predicate: F,
+        segment: JFIFSegment,
+    ) -> Result<Option<JFIFSegment>, JFIFContainerError>
+    where
+        F: FnMut(&JFIFSegment) -> bool,
+    {
+        segment.check_size()?;
+
+        Ok(self
             .segments
             .iter()
-            .filter(|p| {
-                let segment = *p;
-
-                return match segment {
-                    JFIFSegment::APP(_, _) => {
-                        let custom_segment_result = CustomSegment::try_from(segment);
-
-                        return match custom_segment_result {
-                            Ok(_) => true,
-                            Err(_) => false,
-                        };
-                    }
-                    _ => false,
-                };
+            .position(predicate)
+            .map(|position| std::mem::replace(&mut self.segments[position], segment)))
+    }
+
+    // JFIF and Exif headers have to stay right after SOI, early placement goes behind them.
+    // Segments can only be inserted in front of a scan, so the end is before the last SOS
+    pub fn get_payload_position(&self, placement: PayloadPlacement) -> Option<usize> {
+        let segments = &self.segments;
+        let early = segments
+            .iter()
+            .skip(1)
+            .position(|p| match p {
+                JFIFSegment::APP(0, _) | JFIFSegment::FILL(_) => false,
+                JFIFSegment::APP(1, seg) => !seg.get_data().starts_with(EXIF_HEADER),
+                _ => true,
             })
-            .map(|p| -> CustomSegment { CustomSegment::try_from(p).unwrap() })
-            .for_each(|f| custom_segments.push(f));
+            .map(|f| f + 1);
+
+        match placement {
+            PayloadPlacement::Early => early,
+            PayloadPlacement::AfterMetadata => segments
+                .iter()
+                .rposition(|p| matches!(p, JFIFSegment::APP(_, _)))
+                .map(|f| f + 1)
+                .or(early),
+            PayloadPlacement::End => {
+                let last_scan = segments
+                    .iter()
+                    .rposition(|p| matches!(p, JFIFSegment::SOS(_)))?;
+                let fill_size = segments[..last_scan]
+                    .iter()
+                    .rev()
+                    .take_while(|p| matches!(p, JFIFSegment::FILL(_)))
+                    .count();
+
+                Some(last_scan - fill_size)
+            }
+        }
+    }
+
+    pub fn put_custom_segments(
+        &mut self,
+        segments: Vec<JFIFSegment>,
+        placement: PayloadPlacement,
+    ) -> Option<usize> {
+        let position = self.get_payload_position(placement)?;
+
+        self.segments.splice(position..position, segments);
 
-        custom_segments
+        Some(position)
     }
+}
 
-    pub fn put_custom_segment(&mut self, segment: JFIFSegment) -> Option<usize> {
-        let latest_app = self.segments.iter().rposition(|p| {
-            return match *p {
-                JFIFSegment::APP(_, _) => true,
-                _ => false,
-            };
-        });
+// Builds a container from scratch, SOI and EOI are added around the pushed segments
+pub struct JFIFContainerBuilder {
+    segments: Vec<JFIFSegment>,
+    trailer: Option<Vec<u8>>,
+}
 
-        if let Some(latest_app) = latest_app {
-            self.segments.insert(latest_app + 1, segment);
-            return Some(latest_app + 1);
+impl JFIFContainerBuilder {
+    pub fn new() -> Self {
+        Self {
+            segments: vec![JFIFSegment::SOI],
+            trailer: None,
         }
+    }
 
-        None
+    pub fn segment(mut self, segment: JFIFSegment) -> Self {
+        self.segments.push(segment);
+        self
+    }
+
+    pub fn app(self, app_num: u8, data: Vec<u8>) -> Self {
+        self.segment(JFIFSegment::APP(app_num, GeneralSegment::new(data)))
+    }
+
+    // The scan header followed by its entropy coded data
+    pub fn scan(mut self, header: Vec<u8>, data: Vec<u8>) -> Self {
+        self.segments
+            .push(JFIFSegment::SOS(GeneralSegment::new(header)));
+        self.segments.push(JFIFSegment::IMGDATA(data));
+        self
+    }
+
+    pub fn trailer(mut self, data: Vec<u8>) -> Self {
+        self.trailer = Some(data);
+        self
+    }
+
+    // Fails when a pushed segment does not fit its length field
+    pub fn build(self) -> Result<JFIFContainer, JFIFContainerError> {
+        self.segments.iter().try_for_each(|f| f.check_size())?;
+
+        let mut container = JFIFContainer::new(self.segments);
+
+        container.segments.push(JFIFSegment::EOI);
+        container.set_trailer(self.trailer);
+        Ok(container)
+    }
+}
+
+impl Default for JFIFContainerBuilder {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -97,7 +342,7 @@ impl GeneralSegment {
 }
 
 pub trait ToBytes {
-    fn to_bytes(&self) -> Vec<u8>;
+    fn to_bytes(&self) -> io::Result<Vec<u8>>;
     fn get_marker(&self) -> Option<[u8; 2]>;
 }
 
@@ -106,89 +351,201 @@ impl ToBytes for GeneralSegment {
         None
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
         let mut result = Vec::new();
-        let data_len = self.get_size();
-        let len_bytes = usize_to_be(data_len);
+        let data_len = u16::try_from(self.get_size())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Segment too large"))?;
 
-        result.push(len_bytes[2]);
-        result.push(len_bytes[3]);
+        result.extend_from_slice(&data_len.to_be_bytes());
         result.extend_from_slice(&self.data[..]);
 
-        result
+        Ok(result)
     }
 }
 
+// Segment bytes are written from the borrowed view, so both containers share one writer
 impl ToBytes for JFIFSegment {
     fn get_marker(&self) -> Option<[u8; 2]> {
+        self.as_segment_ref().get_marker()
+    }
+
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        self.as_segment_ref().to_bytes()
+    }
+}
+
+impl JFIFSegment {
+    fn check_size(&self) -> Result<(), JFIFContainerError> {
+        match self.as_segment_ref().get_data() {
+            Some(data) if data.len() > MAX_SEGMENT_DATA => {
+                Err(JFIFContainerError::SegmentTooLarge(data.len()))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn as_segment_ref(&self) -> JFIFSegmentRef<'_> {
         match self {
-            JFIFSegment::SOI => Some([0xFF, 0xD8]),
-            JFIFSegment::SOF0(_) => Some([0xFF, 0xC0]),
-            JFIFSegment::SOF2(_) => Some([0xFF, 0xC2]),
-            JFIFSegment::DHT(_) => Some([0xFF, 0xC4]),
-            JFIFSegment::DQT(_) => Some([0xFF, 0xDB]),
-            JFIFSegment::DRI(_) => Some([0xFF, 0xDD]),
-            JFIFSegment::SOS(_) => Some([0xFF, 0xDA]),
-            JFIFSegment::COM(_) => Some([0xFF, 0xFE]),
-            JFIFSegment::EOI => Some([0xFF, 0xD9]),
-            JFIFSegment::APP(seg, _) => Some([0xFF, 0xE0 | (0xF & *seg)]),
-            JFIFSegment::RST(seg) => Some([0xFF, 0xD0 | (0b111 & seg)]),
-            JFIFSegment::IMGDATA(_) => None,
+            JFIFSegment::SOI => JFIFSegmentRef::SOI,
+            JFIFSegment::SOF0(seg) => JFIFSegmentRef::SOF0(&seg.data),
+            JFIFSegment::SOF2(seg) => JFIFSegmentRef::SOF2(&seg.data),
+            JFIFSegment::SOF(num, seg) => JFIFSegmentRef::SOF(*num, &seg.data),
+            JFIFSegment::DHT(seg) => JFIFSegmentRef::DHT(&seg.data),
+            JFIFSegment::DAC(seg) => JFIFSegmentRef::DAC(&seg.data),
+            JFIFSegment::DQT(seg) => JFIFSegmentRef::DQT(&seg.data),
+            JFIFSegment::DRI(payload) => JFIFSegmentRef::DRI(*payload),
+            JFIFSegment::SOS(seg) => JFIFSegmentRef::SOS(&seg.data),
+            JFIFSegment::RST(num) => JFIFSegmentRef::RST(*num),
+            JFIFSegment::APP(num, seg) => JFIFSegmentRef::APP(*num, &seg.data),
+            JFIFSegment::COM(seg) => JFIFSegmentRef::COM(&seg.data),
+            JFIFSegment::DNL(seg) => JFIFSegmentRef::DNL(&seg.data),
+            JFIFSegment::OTHER(marker, seg) => JFIFSegmentRef::OTHER(*marker, &seg.data),
+            JFIFSegment::IMGDATA(vec) => JFIFSegmentRef::IMGDATA(vec),
+            JFIFSegment::FILL(size) => JFIFSegmentRef::FILL(*size),
+            JFIFSegment::EOI => JFIFSegmentRef::EOI,
+            JFIFSegment::TRAILER(vec) => JFIFSegmentRef::TRAILER(vec),
         }
     }
+}
 
-    fn to_bytes(&self) -> Vec<u8> {
+// Borrowed counterpart of JFIFSegment, the slices point into the parsed input
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JFIFSegmentRef<'a> {
+    SOI,
+    SOF0(&'a [u8]),
+    SOF2(&'a [u8]),
+    SOF(u8, &'a [u8]),
+    DHT(&'a [u8]),
+    DAC(&'a [u8]),
+    DQT(&'a [u8]),
+    DRI([u8; 2]),
+    SOS(&'a [u8]),
+    RST(u8),
+    APP(u8, &'a [u8]),
+    COM(&'a [u8]),
+    DNL(&'a [u8]),
+    OTHER(u8, &'a [u8]),
+    IMGDATA(&'a [u8]),
+    FILL(usize),
+    EOI,
+    TRAILER(&'a [u8]),
+}
+
+impl JFIFSegmentRef<'_> {
+    // Payload of segments that carry a length field
+    pub fn get_data(&self) -> Option<&[u8]> {
         match self {
-            JFIFSegment::SOI => Vec::from(self.get_marker().unwrap()),
-            JFIFSegment::EOI => Vec::from(self.get_marker().unwrap()),
-            JFIFSegment::RST(_) => Vec::from(self.get_marker().unwrap()),
-            JFIFSegment::IMGDATA(vec) => vec.clone(),
-            JFIFSegment::DRI(payload) => {
-                let mut result = Vec::from(self.get_marker().unwrap());
-                result.push(0x00);
-                result.push(0x04);
-                result.extend(payload);
-                result
-            }
-            JFIFSegment::SOF0(seg) => {
-                let mut result = Vec::from(self.get_marker().unwrap());
-                result.extend_from_slice(&seg.to_bytes());
-                result
-            }
-            JFIFSegment::SOF2(seg) => {
-                let mut result = Vec::from(self.get_marker().unwrap());
-                result.extend_from_slice(&seg.to_bytes());
-                result
-            }
-            JFIFSegment::SOS(seg) => {
-                let mut result = Vec::from(self.get_marker().unwrap());
-                result.extend_from_slice(&seg.to_bytes());
-                result
-            }
-            JFIFSegment::COM(seg) => {
-                let mut result = Vec::from(self.get_marker().unwrap());
-                result.extend_from_slice(&seg.to_bytes());
-                result
-            }
-            JFIFSegment::DQT(seg) => {
-                let mut result = Vec::from(self.get_marker().unwrap());
-                result.extend_from_slice(&seg.to_bytes());
-                result
-            }
-            JFIFSegment::DHT(seg) => {
-                let mut result = Vec::from(self.get_marker().unwrap());
-                result.extend_from_slice(&seg.to_bytes());
-                result
-            }
-            JFIFSegment::APP(_, seg) => {
-                let mut result = Vec::from(self.get_marker().unwrap());
-                result.extend_from_slice(&seg.to_bytes());
-                result
-            }
+            JFIFSegmentRef::SOF0(data)
+            | JFIFSegmentRef::SOF2(data)
+            | JFIFSegmentRef::SOF(_, data)
+            | JFIFSegmentRef::DHT(data)
+            | JFIFSegmentRef::DAC(data)
+            | JFIFSegmentRef::DQT(data)
+            | JFIFSegmentRef::SOS(data)
+            | JFIFSegmentRef::APP(_, data)
+            | JFIFSegmentRef::COM(data)
+            | JFIFSegmentRef::DNL(data)
+            | JFIFSegmentRef::OTHER(_, data) => Some(data),
+            _ => None,
         }
     }
 }
 
+impl From<&JFIFSegmentRef<'_>> for JFIFSegment {
+    fn from(value: &JFIFSegmentRef<'_>) -> Self {
+        let general = |data: &[u8]| GeneralSegment::new(Vec::from(data));
+
+        match *value {
+            JFIFSegmentRef::SOI => JFIFSegment::SOI,
+            JFIFSegmentRef::SOF0(data) => JFIFSegment::SOF0(general(data)),
+            JFIFSegmentRef::SOF2(data) => JFIFSegment::SOF2(general(data)),
+            JFIFSegmentRef::SOF(num, data) => JFIFSegment::SOF(num, general(data)),
+            JFIFSegmentRef::DHT(data) => JFIFSegment::DHT(general(data)),
+            JFIFSegmentRef::DAC(data) => JFIFSegment::DAC(general(data)),
+            JFIFSegmentRef::DQT(data) => JFIFSegment::DQT(general(data)),
+            JFIFSegmentRef::DRI(payload) => JFIFSegment::DRI(payload),
+            JFIFSegmentRef::SOS(data) => JFIFSegment::SOS(general(data)),
+            JFIFSegmentRef::RST(num) => JFIFSegment::RST(num),
+            JFIFSegmentRef::APP(num, data) => JFIFSegment::APP(num, general(data)),
+            JFIFSegmentRef::COM(data) => JFIFSegment::COM(general(data)),
+            JFIFSegmentRef::DNL(data) => JFIFSegment::DNL(general(data)),
+            JFIFSegmentRef::OTHER(marker, data) => JFIFSegment::OTHER(marker, general(data)),
+            JFIFSegmentRef::IMGDATA(data) => JFIFSegment::IMGDATA(Vec::from(data)),
+            JFIFSegmentRef::FILL(size) => JFIFSegment::FILL(size),
+            JFIFSegmentRef::EOI => JFIFSegment::EOI,
+            JFIFSegmentRef::TRAILER(data) => JFIFSegment::TRAILER(Vec::from(data)),
+        }
+    }
+}
+
+impl ToBytes for JFIFSegmentRef<'_> {
+    fn get_marker(&self) -> Option<[u8; 2]> {
+        match self {
+            JFIFSegmentRef::SOI => Some([0xFF, 0xD8]),
+            JFIFSegmentRef::SOF0(_) => Some([0xFF, 0xC0]),
+            JFIFSegmentRef::SOF2(_) => Some([0xFF, 0xC2]),
+            JFIFSegmentRef::SOF(seg, _) => Some([0xFF, 0xC0 | (0xF & *seg)]),
+            JFIFSegmentRef::DHT(_) => Some([0xFF, 0xC4]),
+            JFIFSegmentRef::DAC(_) => Some([0xFF, 0xCC]),
+            JFIFSegmentRef::DQT(_) => Some([0xFF, 0xDB]),
+            JFIFSegmentRef::DRI(_) => Some([0xFF, 0xDD]),
+            JFIFSegmentRef::SOS(_) => Some([0xFF, 0xDA]),
+            JFIFSegmentRef::COM(_) => Some([0xFF, 0xFE]),
+            JFIFSegmentRef::DNL(_) => Some([0xFF, 0xDC]),
+            JFIFSegmentRef::OTHER(marker, _) => Some([0xFF, *marker]),
+            JFIFSegmentRef::EOI => Some([0xFF, 0xD9]),
+            JFIFSegmentRef::APP(seg, _) => Some([0xFF, 0xE0 | (0xF & *seg)]),
+            JFIFSegmentRef::RST(seg) => Some([0xFF, 0xD0 | (0b111 & seg)]),
+            JFIFSegmentRef::IMGDATA(_) => None,
+            JFIFSegmentRef::FILL(_) => None,
+            JFIFSegmentRef::TRAILER(_) => None,
+        }
+    }
+
+    // Fails for segments whose payload does not fit the 16 bit length field
+    fn to_bytes(&self) -> io::Result<Vec<u8>> {
+        let mut result = Vec::with_capacity(self.get_size());
+
+        self.write_to(&mut result)?;
+        Ok(result)
+    }
+}
+
+pub struct JFIFContainerRef<'a> {
+    segments: Vec<JFIFSegmentRef<'a>>,
+}
+
+impl<'a> JFIFContainerRef<'a> {
+    pub fn new(segments: Vec<JFIFSegmentRef<'a>>) -> Self {
+        Self { segments }
+    }
+
+    pub fn get_segments(&self) -> &Vec<JFIFSegmentRef<'a>> {
+        &self.segments
+    }
+
+    pub fn get_trailer(&self) -> Option<&'a [u8]> {
+        self.segments.iter().find_map(|f| match f {
+            JFIFSegmentRef::TRAILER(data) => Some(*data),
+            _ => None,
+        })
+    }
+}
+
+// Copies every segment once, the owned container is edited segment by segment so it cannot
+// keep pointing into the input. Code that only reads should stay on JFIFContainerRef
+impl From<JFIFContainerRef<'_>> for JFIFContainer {
+    fn from(value: JFIFContainerRef<'_>) -> Self {
+        JFIFContainer::new(value.segments.iter().map(JFIFSegment::from).collect())
+    }
+}
+
+impl JFIFContainer {
+    pub fn as_container_ref(&self) -> JFIFContainerRef<'_> {
+        JFIFContainerRef::new(self.segments.iter().map(|f| f.as_segment_ref()).collect())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -218,7 +575,7 @@ mod tests {
         let segment = GeneralSegment {
             data: vec![0x0F, 0x0D, 0x44],
         };
-        let bytes = segment.to_bytes();
+        let bytes = segment.to_bytes().unwrap();
 
         assert_eq!(bytes, [0x00, 0x05, 0x0F, 0x0D, 0x44]);
     }
@@ -234,7 +591,7 @@ mod tests {
     #[test]
     fn segment_soi_bytes() {
         let segment = JFIFSegment::SOI;
-        let bytes = segment.to_bytes();
+        let bytes = segment.to_bytes().unwrap();
 
         assert_eq!(bytes, [0xFF, 0xD8]);
     }
@@ -250,7 +607,7 @@ mod tests {
     #[test]
     fn segment_eoi_bytes() {
         let segment = JFIFSegment::EOI;
-        let bytes = segment.to_bytes();
+        let bytes = segment.to_bytes().unwrap();
 
         assert_eq!(bytes, [0xFF, 0xD9]);
     }
@@ -269,12 +626,25 @@ mod tests {
     fn segment_rst_bytes() {
         for i in 0..8 {
             let segment = JFIFSegment::RST(i);
-            let bytes = segment.to_bytes();
+            let bytes = segment.to_bytes().unwrap();
 
             assert_eq!(bytes, [0xFF, 0xD0 + i]);
         }
     }
 
+    #[test]
+    fn segment_sof_bytes() {
+        let segment = JFIFSegment::SOF(0x09, GeneralSegment::new(vec![0x08]));
+        let bytes = segment.to_bytes().unwrap();
+
+        assert_eq!(bytes, [0xFF, 0xC9, 0x00, 0x03, 0x08]);
+
+        let segment = JFIFSegment::OTHER(0xF7, GeneralSegment::new(vec![0x08]));
+        let bytes = segment.to_bytes().unwrap();
+
+        assert_eq!(bytes, [0xFF, 0xF7, 0x00, 0x03, 0x08]);
+    }
+
     #[test]
     fn segment_dri_marker() {
         let segment = JFIFSegment::DRI([0x04, 0x02]);
@@ -286,7 +656,7 @@ mod tests {
     #[test]
     fn segment_dri_bytes() {
         let segment = JFIFSegment::DRI([0x04, 0x02]);
-        let bytes = segment.to_bytes();
+        let bytes = segment.to_bytes().unwrap();
 
         assert_eq!(bytes, [0xFF, 0xDD, 0x00, 0x04, 0x04, 0x02]);
     }
@@ -302,7 +672,7 @@ mod tests {
     #[test]
     fn segment_img_bytes() {
         let segment = JFIFSegment::IMGDATA(vec![0x0A, 0x0B]);
-        let bytes = segment.to_bytes();
+        let bytes = segment.to_bytes().unwrap();
 
         assert_eq!(bytes, [0x0A, 0x0B]);
     }
@@ -330,7 +700,7 @@ mod tests {
                 data: vec![0x02, 0x04],
             },
         );
-        let bytes = segment.to_bytes();
+        let bytes = segment.to_bytes().unwrap();
 
         assert_eq!(bytes, [0xFF, 0xE0, 0x00, 0x04, 0x02, 0x04]);
     }
@@ -350,7 +720,7 @@ mod tests {
         let segment = JFIFSegment::COM(GeneralSegment {
             data: vec![0x02, 0x04],
         });
-        let bytes = segment.to_bytes();
+        let bytes = segment.to_bytes().unwrap();
 
         assert_eq!(bytes, [0xFF, 0xFE, 0x00, 0x04, 0x02, 0x04]);
     }
@@ -370,7 +740,7 @@ mod tests {
         let segment = JFIFSegment::SOF0(GeneralSegment {
             data: vec![0x02, 0x04],
         });
-        let bytes = segment.to_bytes();
+        let bytes = segment.to_bytes().unwrap();
 
         assert_eq!(bytes, [0xFF, 0xC0, 0x00, 0x04, 0x02, 0x04]);
     }
@@ -390,7 +760,7 @@ mod tests {
         let segment = JFIFSegment::SOF2(GeneralSegment {
             data: vec![0x02, 0x04],
         });
-        let bytes = segment.to_bytes();
+        let bytes = segment.to_bytes().unwrap();
 
         assert_eq!(bytes, [0xFF, 0xC2, 0x00, 0x04, 0x02, 0x04]);
     }
@@ -410,7 +780,7 @@ mod tests {
         let segment = JFIFSegment::DHT(GeneralSegment {
             data: vec![0x02, 0x04],
         });
-        let bytes = segment.to_bytes();
+        let bytes = segment.to_bytes().unwrap();
 
         assert_eq!(bytes, [0xFF, 0xC4, 0x00, 0x04, 0x02, 0x04]);
     }
@@ -430,11 +800,19 @@ mod tests {
         let segment = JFIFSegment::DQT(GeneralSegment {
             data: vec![0x02, 0x04],
         });
-        let bytes = segment.to_bytes();
+        let bytes = segment.to_bytes().unwrap();
 
         assert_eq!(bytes, [0xFF, 0xDB, 0x00, 0x04, 0x02, 0x04]);
     }
 
+    #[test]
+    fn segment_trailer_bytes() {
+        let segment = JFIFSegment::TRAILER(vec![0xFF, 0xD8, 0x0A]);
+
+        assert_eq!(segment.get_marker(), None);
+        assert_eq!(segment.to_bytes().unwrap(), [0xFF, 0xD8, 0x0A]);
+    }
+
     #[test]
     fn segment_sos_marker() {
         let segment = JFIFSegment::SOS(GeneralSegment {
@@ -450,8 +828,118 @@ mod tests {
         let segment = JFIFSegment::SOS(GeneralSegment {
             data: vec![0x02, 0x04],
         });
-        let bytes = segment.to_bytes();
+        let bytes = segment.to_bytes().unwrap();
 
         assert_eq!(bytes, [0xFF, 0xDA, 0x00, 0x04, 0x02, 0x04]);
     }
+
+    #[test]
+    fn builder_creates_parsable_container() {
+        let frame = vec![0x08, 0x00, 0x10, 0x00, 0x10, 0x01, 0x01, 0x11, 0x00];
+        let container = JFIFContainerBuilder::new()
+            .app(0, Vec::from(&b"JFIF\0\x01\x02\0\0\x01\0\x01\0\0"[..]))
+            .segment(JFIFSegment::DQT(GeneralSegment::new(vec![0x00; 65])))
+            .segment(JFIFSegment::SOF0(GeneralSegment::new(frame)))
+            .scan(
+                vec![0x01, 0x01, 0x00, 0x00, 0x3F, 0x00],
+                vec![0x12, 0xFF, 0x00, 0x34],
+            )
+            .trailer(vec![0xAA, 0xBB])
+            .build()
+            .unwrap();
+        let bytes = Vec::try_from(container).unwrap();
+        let parsed = JFIFContainer::try_from(&bytes).unwrap();
+
+        assert_eq!(parsed.get_segments().len(), 8);
+        assert!(matches!(parsed.get_segments()[0], JFIFSegment::SOI));
+        assert_eq!(parsed.get_trailer(), Some(&vec![0xAA, 0xBB]));
+        assert_eq!(Vec::try_from(parsed).unwrap(), bytes);
+    }
+
+    #[test]
+    fn oversized_segments_are_rejected() {
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let mut container = JFIFContainer::try_from(&content).unwrap();
+        let segment_count = container.get_segments().len();
+        let oversized = || JFIFSegment::COM(GeneralSegment::new(vec![0x00; MAX_SEGMENT_DATA + 1]));
+        let too_large = Err(JFIFContainerError::SegmentTooLarge(MAX_SEGMENT_DATA + 1));
+
+        assert_eq!(container.put_custom_segment(oversized()), too_large);
+        assert_eq!(container.insert_before(0xDA, oversized()), too_large);
+        assert_eq!(container.insert_after(0xDA, oversized()), too_large);
+        assert!(container.replace_segment(|_| true, oversized()).is_err());
+        assert_eq!(container.get_segments().len(), segment_count);
+        assert!(JFIFContainerBuilder::new()
+            .app(1, vec![0x00; MAX_SEGMENT_DATA + 1])
+            .build()
+            .is_err());
+        assert!(JFIFContainerBuilder::new()
+            .app(1, vec![0x00; MAX_SEGMENT_DATA])
+            .build()
+            .is_ok());
+    }
+
+    #[test]
+    fn edit_segments() {
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let mut container = JFIFContainer::try_from(&content).unwrap();
+        let comment = |data: &[u8]| JFIFSegment::COM(GeneralSegment::new(Vec::from(data)));
+
+        assert_eq!(
+            container.remove_segments(|p| matches!(p, JFIFSegment::APP(_, _))),
+            1
+        );
+        assert_eq!(container.put_custom_segment(comment(b"custom")), Ok(1));
+        assert_eq!(container.insert_after(0xE5, comment(b"missing")), Ok(1));
+
+        let scan = container.insert_before(0xDA, comment(b"before")).unwrap();
+
+        assert!(matches!(
+            container.get_segments()[scan + 1],
+            JFIFSegment::SOS(_)
+        ));
+        assert_eq!(
+            container.insert_after(0xDA, comment(b"after")),
+            Ok(scan + 3)
+        );
+        assert!(matches!(
+            container.get_segments()[scan + 4],
+            JFIFSegment::EOI
+        ));
+        assert_eq!(container.insert_after(0xD9, comment(b"end")), Ok(scan + 4));
+
+        let replaced = container
+            .replace_segment(
+                |p| matches!(p, JFIFSegment::COM(seg) if seg.get_data() == b"missing"),
+                comment(b"replaced"),
+            )
+            .unwrap();
+
+        assert_eq!(
+            replaced.map(|f| f.to_bytes().unwrap()),
+            Some(comment(b"missing").to_bytes().unwrap())
+        );
+
+        container.iter_segments_mut().for_each(|f| {
+            if let JFIFSegment::COM(seg) = f {
+                *seg = GeneralSegment::new(seg.get_data().to_ascii_uppercase());
+            }
+        });
+
+        let bytes = Vec::try_from(container).unwrap();
+        let container = JFIFContainer::try_from(&bytes).unwrap();
+        let comments: Vec<&[u8]> = container
+            .get_segments()
+            .iter()
+            .filter_map(|f| match f {
+                JFIFSegment::COM(seg) => Some(&seg.get_data()[..]),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(
+            comments,
+            [&b"REPLACED"[..], b"CUSTOM", b"BEFORE", b"AFTER", b"END"]
+        );
+    }
 }
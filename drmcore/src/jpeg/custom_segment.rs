@@ -2,6 +2,7 @@ use std::convert::{From, TryFrom};
 use thiserror::Error;
 use super::container::{GeneralSegment, JFIFSegment};
 use crate::file_joiner::be_to_usize;
+use crate::payload_stream::{PayloadNamespace, DEFAULT_STREAM_ID};
 
 pub const CUSTOM_SEGMENT_APP: u8 = 10;
 pub const CUSTOM_SEGMENT_NAME: &str = "MILF";
@@ -11,6 +12,7 @@ pub const CUSTOM_SEGMENT_MAX_SIZE: u16 = 0xFFFF - 0x23; // 65500 bytes
 pub struct CustomSegment {
     data: Vec<u8>,
     pub order: u16,
+    pub stream: u8,
 }
 
 #[derive(Error, Debug, PartialEq)]
@@ -23,6 +25,9 @@ pub enum CustomSegmentError {
 
     #[error("Segment missing order and data")]
     EmptyDataOrOrder,
+
+    #[error("Invalid segment identifier")]
+    InvalidIdentifier,
 }
 
 impl CustomSegment {
@@ -30,29 +35,93 @@ impl CustomSegment {
         Self {
             data: Vec::from(data),
             order,
+            stream: DEFAULT_STREAM_ID,
+        }
+    }
+
+    pub fn to_segment(&self, namespace: &PayloadNamespace) -> JFIFSegment {
+        let mut data = Vec::from(namespace.get_segment_name().as_bytes());
+        let order = self.order.to_be_bytes();
+
+        data.push(self.stream);
+        data.extend(order);
+        data.extend(&self.data);
+
+        let segment = GeneralSegment::new(data);
+
+        JFIFSegment::APP(CUSTOM_SEGMENT_APP, segment)
+    }
+
+    // APP10 is also used by other tools, only segments with our identifier are accepted
+    pub fn from_segment(
+        value: &JFIFSegment,
+        namespace: &PayloadNamespace,
+    ) -> Result<Self, CustomSegmentError> {
+        match value {
+            JFIFSegment::APP(app_num, data) => {
+                if *app_num != CUSTOM_SEGMENT_APP {
+                    return Err(CustomSegmentError::InvalidAppNum);
+                }
+
+                let raw_data = data.get_data();
+                let identifier_len = namespace.get_segment_name().len() + 1;
+                let raw_order = raw_data.get(identifier_len..identifier_len + 2);
+                let raw_bytes = raw_data.get(identifier_len + 2..);
+
+                match (raw_order,raw_bytes) {
+                    (Some(order), Some(bytes)) => {
+                        let stream = namespace
+                            .read_segment_identifier(raw_data)
+                            .ok_or(CustomSegmentError::InvalidIdentifier)?;
+                        let order_be = be_to_usize(order) as u16;
+                        let custom_segment = CustomSegment {
+                            order: order_be,
+                            stream,
+                            data: Vec::from(bytes),
+                        };
+
+                        Ok(custom_segment)
+                    },
+                    _ => {
+                        Err(CustomSegmentError::EmptyDataOrOrder)
+                    }
+                }
+            },
+            _ => {
+                Err(CustomSegmentError::InvalidSegmentType)
+            }
         }
     }
 }
 
 pub fn split_bytes(data: &[u8]) -> Vec<CustomSegment> {
+    split_stream_bytes(data, DEFAULT_STREAM_ID)
+}
+
+pub fn split_stream_bytes(data: &[u8], stream: u8) -> Vec<CustomSegment> {
     let mut result = Vec::new();
     let mut order = 0u16;
 
     data.chunks(CUSTOM_SEGMENT_MAX_SIZE.into())
         .for_each(|f| {
-            result.push(CustomSegment::new(f, order));
-            order = order + 1;
+            result.push(CustomSegment { data: Vec::from(f), order, stream });
+            order += 1;
         });
 
     result
 }
 
-pub fn join_bytes(data: &Vec<CustomSegment>) -> Vec<u8> {
+pub fn join_bytes(data: &[CustomSegment]) -> Vec<u8> {
+    join_stream_bytes(data, DEFAULT_STREAM_ID)
+}
+
+// Each stream has its own order, segments of other streams are left out
+pub fn join_stream_bytes(data: &[CustomSegment], stream: u8) -> Vec<u8> {
     let mut result = Vec::new();
     let mut vec: Vec<&CustomSegment> = Vec::new();
 
-    data.iter().for_each(|f| {vec.push(f)});
-    vec.sort_by(|a, b| a.order.cmp(&b.order));
+    data.iter().filter(|f| f.stream == stream).for_each(|f| {vec.push(f)});
+    vec.sort_by_key(|a| a.order);
     vec.iter()
         .for_each(|f| {
             result.extend(&f.data);
@@ -63,16 +132,7 @@ pub fn join_bytes(data: &Vec<CustomSegment>) -> Vec<u8> {
 
 impl From<&CustomSegment> for JFIFSegment {
     fn from(value: &CustomSegment) -> Self {
-        let mut data = Vec::from(CUSTOM_SEGMENT_NAME.as_bytes());
-        let order = value.order.to_be_bytes();
-        
-        data.push(0x00);
-        data.extend(order);
-        data.extend(&value.data);
-
-        let segment = GeneralSegment::new(data);
-
-        JFIFSegment::APP(CUSTOM_SEGMENT_APP, segment)
+        value.to_segment(&PayloadNamespace::default())
     }
 }
 
@@ -80,35 +140,7 @@ impl TryFrom<&JFIFSegment> for CustomSegment {
     type Error = CustomSegmentError;
 
     fn try_from(value: &JFIFSegment) -> Result<Self, Self::Error> {
-        match value {
-            JFIFSegment::APP(app_num, data) => {
-                if *app_num != CUSTOM_SEGMENT_APP {
-                    return Err(CustomSegmentError::InvalidAppNum);
-                }
-
-                let raw_data = data.get_data();
-                let raw_order = raw_data.get(5..7);
-                let raw_bytes = raw_data.get(7..);
-
-                match (raw_order,raw_bytes) {
-                    (Some(order), Some(bytes)) => {
-                        let order_be = be_to_usize(order) as u16;
-                        let custom_segment = CustomSegment {
-                            order: order_be,
-                            data: Vec::from(bytes),
-                        };
-        
-                        return Ok(custom_segment);
-                    },
-                    _ => {
-                        return Err(CustomSegmentError::EmptyDataOrOrder);
-                    }
-                }
-            },
-            _ => {
-                return Err(CustomSegmentError::InvalidSegmentType);
-            }
-        }
+        CustomSegment::from_segment(value, &PayloadNamespace::default())
     }
 }
 
@@ -122,12 +154,13 @@ mod tests {
         let segment = CustomSegment {
             data: vec![0xFF, 0xBA, 0x28],
             order: 0,
+            stream: 0,
         };
         let app_segment = JFIFSegment::from(&segment);
 
         match app_segment {
             JFIFSegment::APP(app,data ) => {
-                let data_vec = data.to_bytes();
+                let data_vec = data.to_bytes().unwrap();
 
                 assert_eq!(app, CUSTOM_SEGMENT_APP);
                 assert_eq!(data_vec, [0x00, 0x0C, 0x4D, 0x49, 0x4C, 0x46, 0x00, 0x00, 0x00, 0xFF, 0xBA, 0x28]);
@@ -0,0 +1,147 @@
+use crate::file_joiner::be_to_usize;
+use crate::jpeg::container::{JFIFContainerRef, JFIFSegmentRef};
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum FrameError {
+    #[error("Invalid frame header")]
+    InvalidFrameHeader,
+
+    #[error("Frame header not found")]
+    FrameNotFound,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FrameComponent {
+    pub id: u8,
+    pub horizontal_sampling: u8,
+    pub vertical_sampling: u8,
+    pub quantization_table: u8,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FrameHeader {
+    pub precision: u8,
+    pub height: u16,
+    pub width: u16,
+    pub components: Vec<FrameComponent>,
+}
+
+// Frame header together with the coding process of its SOF marker
+#[derive(Debug, PartialEq, Clone)]
+pub struct JFIFFrame {
+    pub header: FrameHeader,
+    pub progressive: bool,
+}
+
+impl TryFrom<&[u8]> for FrameHeader {
+    type Error = FrameError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let count = *value.get(5).ok_or(FrameError::InvalidFrameHeader)? as usize;
+
+        if count == 0 || value.len() != 6 + count * 3 {
+            return Err(FrameError::InvalidFrameHeader);
+        }
+
+        let components = value[6..]
+            .chunks(3)
+            .map(|f| FrameComponent {
+                id: f[0],
+                horizontal_sampling: f[1] >> 4,
+                vertical_sampling: f[1] & 0x0F,
+                quantization_table: f[2],
+            })
+            .collect();
+
+        Ok(FrameHeader {
+            precision: value[0],
+            height: be_to_usize(&value[1..3]) as u16,
+            width: be_to_usize(&value[3..5]) as u16,
+            components,
+        })
+    }
+}
+
+impl JFIFSegmentRef<'_> {
+    // Frame header payload of a SOF segment and whether its coding process is progressive
+    pub fn get_frame_data(&self) -> Option<(&[u8], bool)> {
+        match self {
+            JFIFSegmentRef::SOF0(data) => Some((*data, false)),
+            JFIFSegmentRef::SOF2(data) => Some((*data, true)),
+            JFIFSegmentRef::SOF(code, data) => Some((*data, code & 0x03 == 0x02)),
+            _ => None,
+        }
+    }
+
+    pub fn get_line_count(&self) -> Option<u16> {
+        match self {
+            JFIFSegmentRef::DNL(data) if data.len() == 2 => Some(be_to_usize(data) as u16),
+            _ => None,
+        }
+    }
+}
+
+impl JFIFContainerRef<'_> {
+    // A zero height in the frame header is defined by the DNL segment after the first scan
+    pub fn get_frame(&self) -> Result<JFIFFrame, FrameError> {
+        let segments = self.get_segments();
+        let (data, progressive) = segments
+            .iter()
+            .find_map(|f| f.get_frame_data())
+            .ok_or(FrameError::FrameNotFound)?;
+        let mut header = FrameHeader::try_from(data)?;
+
+        if header.height == 0 {
+            let lines = segments
+                .iter()
+                .find_map(|f| f.get_line_count())
+                .ok_or(FrameError::InvalidFrameHeader)?;
+
+            header.height = lines;
+        }
+
+        Ok(JFIFFrame {
+            header,
+            progressive,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_frame_header() {
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let container = JFIFContainerRef::try_from(&content[..]).unwrap();
+        let frame = container.get_frame().unwrap();
+
+        assert!(!frame.progressive);
+        assert_eq!(frame.header.precision, 8);
+        assert_eq!((frame.header.width, frame.header.height), (480, 360));
+        assert_eq!(frame.header.components.len(), 3);
+        assert_eq!(frame.header.components[0].horizontal_sampling, 1);
+        assert_eq!(frame.header.components[1].quantization_table, 1);
+    }
+
+    #[test]
+    fn read_height_from_dnl() {
+        let inp = vec![
+            0xFF, 0xD8, 0xFF, 0xC6, 0x00, 0x0B, 0x08, 0x00, 0x00, 0x00, 0x10, 0x01, 0x01, 0x22,
+            0x00, 0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x3F, 0x00, 0x0A, 0xFF, 0xDC,
+            0x00, 0x04, 0x00, 0x20, 0xFF, 0xD9,
+        ];
+        let container = JFIFContainerRef::try_from(&inp[..]).unwrap();
+        let frame = container.get_frame().unwrap();
+
+        assert!(frame.progressive);
+        assert_eq!((frame.header.width, frame.header.height), (16, 32));
+        assert_eq!(frame.header.components[0].vertical_sampling, 2);
+        assert_eq!(
+            FrameHeader::try_from(&inp[6..14]),
+            Err(FrameError::InvalidFrameHeader)
+        );
+    }
+}
@@ -0,0 +1,422 @@
+use super::container::{GeneralSegment, JFIFContainer, JFIFSegment};
+use crate::tiff_container::{ByteOrder, TIFFContainer, TIFFEntry, TIFFIFD};
+use thiserror::Error;
+
+pub const MPF_HEADER: &[u8; 4] = b"MPF\0";
+pub const PRIMARY_IMAGE_ATTRIBUTE: u32 = 0x2003_0000;
+
+const MP_VERSION_TAG: u16 = 0xB000;
+const NUMBER_OF_IMAGES_TAG: u16 = 0xB001;
+const MP_ENTRY_TAG: u16 = 0xB002;
+const MP_ENTRY_SIZE: usize = 16;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum MpfError {
+    #[error("Invalid MPF segment")]
+    InvalidSegment,
+
+    #[error("MPF image is outside of the trailer")]
+    MissingImage,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MPEntry {
+    pub attribute: u32,
+    pub size: u32,
+    pub offset: u32,
+    pub dependent_images: [u16; 2],
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct MPImage {
+    pub attribute: u32,
+    pub dependent_images: [u16; 2],
+    pub data: Vec<u8>,
+}
+
+struct MPFLayout {
+    position: usize,
+    byte_order: ByteOrder,
+    entries: Vec<MPEntry>,
+}
+
+fn parse_entries(byte_order: ByteOrder, inp: &[u8]) -> Vec<MPEntry> {
+    inp.chunks_exact(MP_ENTRY_SIZE)
+        .map(|f| MPEntry {
+            attribute: byte_order.read_u32(&f[0..4]),
+            size: byte_order.read_u32(&f[4..8]),
+            offset: byte_order.read_u32(&f[8..12]),
+            dependent_images: [
+                byte_order.read_u16(&f[12..14]),
+                byte_order.read_u16(&f[14..16]),
+            ],
+        })
+        .collect()
+}
+
+fn entries_to_bytes(byte_order: ByteOrder, entries: &[MPEntry]) -> Vec<u8> {
+    let mut result = Vec::new();
+
+    entries.iter().for_each(|f| {
+        result.extend(byte_order.write_u32(f.attribute));
+        result.extend(byte_order.write_u32(f.size));
+        result.extend(byte_order.write_u32(f.offset));
+        result.extend(byte_order.write_u16(f.dependent_images[0]));
+        result.extend(byte_order.write_u16(f.dependent_images[1]));
+    });
+
+    result
+}
+
+// Updates the MP entries of an existing MPF structure, so the MP attribute IFD survives
+fn mpf_to_bytes(
+    existing: Option<&[u8]>,
+    byte_order: ByteOrder,
+    entries: &[MPEntry],
+) -> Result<Vec<u8>, MpfError> {
+    let mut container = match existing {
+        Some(existing) => {
+            TIFFContainer::try_from(&Vec::from(existing)).map_err(|_| MpfError::InvalidSegment)?
+        }
+        None => TIFFContainer::new(
+            byte_order,
+            vec![TIFFIFD::new(vec![TIFFEntry::new_undefined(
+                MP_VERSION_TAG,
+                Vec::from(&b"0100"[..]),
+            )])],
+        ),
+    };
+
+    container.put_entry(TIFFEntry::new_long(
+        NUMBER_OF_IMAGES_TAG,
+        &[entries.len() as u32],
+        byte_order,
+    ));
+    container.put_entry(TIFFEntry::new_undefined(
+        MP_ENTRY_TAG,
+        entries_to_bytes(byte_order, entries),
+    ));
+
+    let mut result = Vec::from(&MPF_HEADER[..]);
+    result.extend(container.to_bytes());
+
+    Ok(result)
+}
+
+fn get_mpf_data(segment: &JFIFSegment) -> Option<&[u8]> {
+    match segment {
+        JFIFSegment::APP(2, seg) => seg.get_data().strip_prefix(MPF_HEADER),
+        _ => None,
+    }
+}
+
+pub fn is_mpf_segment(segment: &JFIFSegment) -> bool {
+    get_mpf_data(segment).is_some()
+}
+
+impl JFIFContainer {
+    fn get_mpf_layout(&self) -> Result<Option<MPFLayout>, MpfError> {
+        let position = self.get_segments().iter().position(is_mpf_segment);
+        let position = match position {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+        let data = get_mpf_data(&self.get_segments()[position]).unwrap();
+        let container =
+            TIFFContainer::try_from(&Vec::from(data)).map_err(|_| MpfError::InvalidSegment)?;
+        let byte_order = container.get_byte_order();
+        let entries = container
+            .find_entry(MP_ENTRY_TAG)
+            .map(|f| parse_entries(byte_order, &f.value))
+            .ok_or(MpfError::InvalidSegment)?;
+
+        Ok(Some(MPFLayout {
+            position,
+            byte_order,
+            entries,
+        }))
+    }
+
+    // Offsets in MP entries are relative to the byte following the MPF identifier
+    fn get_mp_header_offset(&self, position: usize) -> usize {
+        let before: usize = self.get_segments()[..position]
+            .iter()
+            .map(|f| f.as_segment_ref().get_size())
+            .sum();
+
+        before + 4 + MPF_HEADER.len()
+    }
+
+    fn get_primary_size(&self) -> usize {
+        self.get_segments()
+            .iter()
+            .filter(|p| !matches!(p, JFIFSegment::TRAILER(_)))
+            .map(|f| f.as_segment_ref().get_size())
+            .sum()
+    }
+
+    fn get_mpf_ranges(&self, layout: &MPFLayout) -> Result<Vec<(usize, usize)>, MpfError> {
+        let mp_header = self.get_mp_header_offset(layout.position);
+        let primary_size = self.get_primary_size();
+        let trailer_len = self.get_trailer().map(|f| f.len()).unwrap_or(0);
+        let mut result = Vec::new();
+
+        for entry in layout.entries.iter().skip(1) {
+            let start = (mp_header + entry.offset as usize)
+                .checked_sub(primary_size)
+                .ok_or(MpfError::MissingImage)?;
+            let end = start + entry.size as usize;
+
+            if end > trailer_len {
+                return Err(MpfError::MissingImage);
+            }
+
+            result.push((start, end));
+        }
+
+        Ok(result)
+    }
+
+    pub fn get_mpf_images(&self) -> Result<Vec<MPImage>, MpfError> {
+        let layout = match self.get_mpf_layout()? {
+            Some(layout) => layout,
+            None => return Ok(Vec::new()),
+        };
+        let ranges = self.get_mpf_ranges(&layout)?;
+        let trailer = self.get_trailer().map(|f| &f[..]).unwrap_or_default();
+
+        Ok(layout
+            .entries
+            .iter()
+            .skip(1)
+            .zip(ranges)
+            .map(|(entry, (start, end))| MPImage {
+                attribute: entry.attribute,
+                dependent_images: entry.dependent_images,
+                data: Vec::from(&trailer[start..end]),
+            })
+            .collect())
+    }
+
+    // Trailer bytes that do not belong to an MPF image, e.g. a motion photo video
+    pub fn get_trailer_suffix(&self) -> Result<Option<Vec<u8>>, MpfError> {
+        let trailer = match self.get_trailer() {
+            Some(trailer) => trailer,
+            None => return Ok(None),
+        };
+        let end = match self.get_mpf_layout()? {
+            Some(layout) => self
+                .get_mpf_ranges(&layout)?
+                .iter()
+                .map(|f| f.1)
+                .max()
+                .unwrap_or(0),
+            None => 0,
+        };
+
+        Ok(Some(Vec::from(&trailer[end..])).filter(|f| !f.is_empty()))
+    }
+
+    // An MPF directory that no longer points into the trailer, e.g. once an editor dropped the
+    // secondary images, is read as no images with the trailer kept verbatim
+    pub fn get_mpf_parts(&self) -> (Vec<MPImage>, Option<Vec<u8>>) {
+        match (self.get_mpf_images(), self.get_trailer_suffix()) {
+            (Ok(images), Ok(suffix)) => (images, suffix),
+            _ => (Vec::new(), self.get_trailer().cloned()),
+        }
+    }
+
+    // Rebuilds the trailer from the given images followed by the suffix and points the MP
+    // entries at them. Offsets go stale on any segment change, so read the images and the
+    // suffix first and call this after every other change
+    pub fn set_mpf_images(
+        &mut self,
+        images: &[MPImage],
+        suffix: Option<Vec<u8>>,
+    ) -> Result<(), MpfError> {
+        if images.is_empty() {
+            self.get_segments_mut().retain(|p| !is_mpf_segment(p));
+            self.set_trailer(suffix);
+
+            return Ok(());
+        }
+
+        let layout = self.get_mpf_layout()?;
+
+        let (position, byte_order, attribute, existing) = match &layout {
+            Some(layout) => (
+                layout.position,
+                layout.byte_order,
+                layout.entries.first().map(|f| f.attribute),
+                get_mpf_data(&self.get_segments()[layout.position]).map(Vec::from),
+            ),
+            None => {
+                let position = self
+                    .get_segments()
+                    .iter()
+                    .rposition(|p| matches!(p, JFIFSegment::SOI | JFIFSegment::APP(0..=2, _)))
+                    .map(|f| f + 1)
+                    .unwrap_or(0);
+
+                (position, ByteOrder::BigEndian, None, None)
+            }
+        };
+        let mut entries = vec![MPEntry {
+            attribute: attribute.unwrap_or(PRIMARY_IMAGE_ATTRIBUTE),
+            size: 0,
+            offset: 0,
+            dependent_images: [0, 0],
+        }];
+
+        images.iter().for_each(|f| {
+            entries.push(MPEntry {
+                attribute: f.attribute,
+                size: f.data.len() as u32,
+                offset: 0,
+                dependent_images: f.dependent_images,
+            })
+        });
+
+        // The segment size does not depend on the offsets, so write it once to find them
+        let segment = mpf_to_bytes(existing.as_deref(), byte_order, &entries)?;
+
+        if layout.is_some() {
+            self.get_segments_mut()[position] = JFIFSegment::APP(2, GeneralSegment::new(segment));
+        } else {
+            self.get_segments_mut()
+                .insert(position, JFIFSegment::APP(2, GeneralSegment::new(segment)));
+        }
+
+        let mut trailer: Vec<u8> = images.iter().flat_map(|f| f.data.iter().copied()).collect();
+        trailer.extend(suffix.unwrap_or_default());
+        self.set_trailer(Some(trailer));
+
+        let mp_header = self.get_mp_header_offset(position);
+        let mut running = self.get_primary_size();
+        entries[0].size = running as u32;
+
+        for entry in entries.iter_mut().skip(1) {
+            entry.offset = (running - mp_header) as u32;
+            running += entry.size as usize;
+        }
+
+        let segment = mpf_to_bytes(existing.as_deref(), byte_order, &entries)?;
+        self.get_segments_mut()[position] = JFIFSegment::APP(2, GeneralSegment::new(segment));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_image(marker: u8) -> Vec<u8> {
+        vec![0xFF, 0xD8, marker, marker, 0xFF, 0xD9]
+    }
+
+    fn sample_container() -> JFIFContainer {
+        JFIFContainer::new(vec![
+            JFIFSegment::SOI,
+            JFIFSegment::APP(0, GeneralSegment::new(vec![0x4A, 0x46])),
+            JFIFSegment::SOS(GeneralSegment::new(vec![0x00])),
+            JFIFSegment::IMGDATA(vec![0x01, 0x02]),
+            JFIFSegment::EOI,
+        ])
+    }
+
+    #[test]
+    fn mpf_images_round_trip() {
+        let mut container = sample_container();
+        let images = vec![
+            MPImage {
+                attribute: 0x0001_0001,
+                dependent_images: [0, 0],
+                data: sample_image(0x0A),
+            },
+            MPImage {
+                attribute: 0x0002_0002,
+                dependent_images: [0, 0],
+                data: sample_image(0x0B),
+            },
+        ];
+
+        container
+            .set_mpf_images(&images, Some(vec![0x0C, 0x0D]))
+            .unwrap();
+
+        let bytes = Vec::try_from(container).unwrap();
+        let container = JFIFContainer::try_from(&bytes).unwrap();
+
+        assert!(matches!(
+            container.get_segments()[2],
+            JFIFSegment::APP(2, _)
+        ));
+        assert_eq!(container.get_mpf_images().unwrap(), images);
+        assert_eq!(
+            container.get_trailer_suffix().unwrap(),
+            Some(vec![0x0C, 0x0D])
+        );
+
+        let layout = container.get_mpf_layout().unwrap().unwrap();
+        let mp_header = container.get_mp_header_offset(layout.position);
+        let entry = &layout.entries[2];
+        let start = mp_header + entry.offset as usize;
+
+        assert_eq!(
+            layout.entries[0].size as usize,
+            container.get_primary_size()
+        );
+        assert_eq!(
+            &bytes[start..start + entry.size as usize],
+            sample_image(0x0B)
+        );
+    }
+
+    #[test]
+    fn mpf_offsets_follow_segment_changes() {
+        let mut container = sample_container();
+        let images = vec![MPImage {
+            attribute: 0x0001_0001,
+            dependent_images: [0, 0],
+            data: sample_image(0x0A),
+        }];
+
+        container.set_mpf_images(&images, None).unwrap();
+
+        let images = container.get_mpf_images().unwrap();
+        container
+            .get_segments_mut()
+            .insert(3, JFIFSegment::APP(10, GeneralSegment::new(vec![0x00; 32])));
+
+        assert_eq!(container.get_mpf_images(), Err(MpfError::MissingImage));
+
+        container.set_mpf_images(&images, None).unwrap();
+
+        assert_eq!(container.get_mpf_images().unwrap(), images);
+
+        container.set_mpf_images(&[], None).unwrap();
+
+        assert!(container.get_trailer().is_none());
+        assert!(!container.get_segments().iter().any(is_mpf_segment));
+    }
+
+    #[test]
+    fn stale_mpf_keeps_trailer() {
+        let mut container = sample_container();
+        let images = vec![MPImage {
+            attribute: 0x0001_0001,
+            dependent_images: [0, 0],
+            data: sample_image(0x0A),
+        }];
+
+        container.set_mpf_images(&images, None).unwrap();
+        container.set_trailer(Some(vec![0x0C, 0x0D]));
+
+        assert_eq!(container.get_mpf_images(), Err(MpfError::MissingImage));
+        assert_eq!(
+            container.get_mpf_parts(),
+            (Vec::new(), Some(vec![0x0C, 0x0D]))
+        );
+    }
+}
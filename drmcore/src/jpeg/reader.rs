@@ -1,239 +1,690 @@
 use crate::file_joiner::be_to_usize;
-use crate::jpeg::container::GeneralSegment;
 use crate::jpeg::container::JFIFContainer;
+use crate::jpeg::container::JFIFContainerRef;
 use crate::jpeg::container::JFIFSegment;
+use crate::jpeg::container::JFIFSegmentRef;
 use crate::jpeg::container::ToBytes;
-use anyhow::anyhow;
-use anyhow::Result as AnyResult;
-use core::iter::Peekable;
-use core::slice::Iter;
 use thiserror::Error;
 use std::convert::TryFrom;
 
-#[derive(Error, Debug)]
+#[derive(Error, Debug, PartialEq)]
 pub enum JPEGParserError {
-    #[error("File ended prematurely")]
-    FileEndedPrematurely,
+    #[error("File ended prematurely at offset {offset} while reading marker `{marker:#04X}`")]
+    FileEndedPrematurely { offset: usize, marker: u8 },
 
     #[error("SOI Invalid")]
     InvalidSOI,
 
-    #[error("Invalid segment size")]
-    InvalidSegmentSize,
+    #[error("Invalid segment size at offset {offset} for marker `{marker:#04X}`")]
+    InvalidSegmentSize { offset: usize, marker: u8 },
 
-    #[error("Unsupported SOF marker")]
-    UnsupportedSOF,
+    #[error("Embedded JPGs not supported, found at offset {offset}")]
+    UnsupportedEmmbedJPEG { offset: usize },
 
-    #[error("Embedded JPGs not supported")]
-    UnsupportedEmmbedJPEG,
+    #[error("RSTN marker `{marker:#04X}` detected before SOS marker at offset {offset}")]
+    RSTNDetectedBeforeSOS { offset: usize, marker: u8 },
 
-    #[error("RSTN marker detected before SOS marker")]
-    RSTNDetectedBeforeSOS,
+    #[error("EOI detected before SOS marker at offset {offset}")]
+    EOIDetectedBeforeSOS { offset: usize },
 
-    #[error("EOI detected before SOS marker")]
-    EOIDetectedBeforeSOS,
+    #[error("Unknown marker found : `{marker:#04X}` at offset {offset}")]
+    UnknownMarker { offset: usize, marker: u8 },
 
-    #[error("Unknown marker found : `{0}`")]
-    UnknownMarker(u8),
+    #[error("Expect marker byte at offset {offset}, but not found")]
+    ExpectMarker { offset: usize },
+}
 
-    #[error("Expect marker byte, but not found")]
-    ExpectMarker,
+// Keeps track of the offset so every error can point at where the parsing stopped
+struct SegmentReader<'a> {
+    data: &'a [u8],
+    position: usize,
 }
 
-fn read_segments(iter: &mut Box<&mut Peekable<Iter<u8>>>) -> AnyResult<Vec<u8>> {
-    let size_arr = [*iter.next().unwrap(), *iter.next().unwrap()];
-    let size = be_to_usize(&size_arr);
-    let mut result = Vec::new();
+impl<'a> SegmentReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        SegmentReader { data, position: 0 }
+    }
+
+    fn read_bytes(&mut self, size: usize, marker: u8) -> Result<&'a [u8], JPEGParserError> {
+        let bytes = self
+            .position
+            .checked_add(size)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or(JPEGParserError::FileEndedPrematurely {
+                offset: self.data.len(),
+                marker,
+            })?;
+
+        self.position += size;
+
+        Ok(bytes)
+    }
 
-    if size < 2 {
-        return Err(anyhow!("Error - Size invalid"));
+    fn read_byte(&mut self, marker: u8) -> Result<u8, JPEGParserError> {
+        Ok(self.read_bytes(1, marker)?[0])
     }
 
-    for _ in 0..size - 2 {
-        result.push(*iter.next().unwrap());
+    fn read_segment(&mut self, marker: u8) -> Result<&'a [u8], JPEGParserError> {
+        let offset = self.position;
+        let size = be_to_usize(self.read_bytes(2, marker)?);
+
+        if size < 2 {
+            return Err(JPEGParserError::InvalidSegmentSize { offset, marker });
+        }
+
+        self.read_bytes(size - 2, marker)
     }
+}
+
+// Stuffed zero bytes, TEM and RST markers belong to the scan. Any other marker ends it, so the
+// scan stops at every marker read_marker accepts
+fn find_scan_end(data: &[u8], start: usize) -> Option<usize> {
+    let mut position = start;
+
+    loop {
+        position += data.get(position..)?.iter().position(|f| *f == 0xFF)?;
 
-    Ok(result)
+        let code_position = position + data[position..].iter().position(|f| *f != 0xFF)?;
+
+        match data[code_position] {
+            0x00 | 0x01 | 0xD0..=0xD7 => position = code_position + 1,
+            _ => return Some(position),
+        }
+    }
+}
+
+// Reads one marker and its segment, returning the number of fill bytes found before the marker
+fn read_marker<'a>(
+    reader: &mut SegmentReader<'a>,
+    last_marker: u8,
+    scan_found: bool,
+) -> Result<(usize, u8, JFIFSegmentRef<'a>), JPEGParserError> {
+    let offset = reader.position;
+
+    if reader.read_byte(last_marker)? != 0xFF {
+        return Err(JPEGParserError::ExpectMarker { offset });
+    }
+
+    let mut marker = reader.read_byte(last_marker)?;
+    let mut fill_size = 0;
+
+    while marker == 0xFF {
+        marker = reader.read_byte(last_marker)?;
+        fill_size += 1;
+    }
+
+    let segment = match marker {
+        0xC0 => JFIFSegmentRef::SOF0(reader.read_segment(marker)?),
+        0xC2 => JFIFSegmentRef::SOF2(reader.read_segment(marker)?),
+        0xC4 => JFIFSegmentRef::DHT(reader.read_segment(marker)?),
+        0xC1 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
+            JFIFSegmentRef::SOF(marker & 0x0F, reader.read_segment(marker)?)
+        }
+        0xCC => JFIFSegmentRef::DAC(reader.read_segment(marker)?),
+        0xD0..=0xD7 => {
+            return Err(JPEGParserError::RSTNDetectedBeforeSOS { offset, marker });
+        }
+        0xD8 => {
+            return Err(JPEGParserError::UnsupportedEmmbedJPEG { offset });
+        }
+        0xD9 => {
+            if !scan_found {
+                return Err(JPEGParserError::EOIDetectedBeforeSOS { offset });
+            }
+
+            JFIFSegmentRef::EOI
+        }
+        0xDA => JFIFSegmentRef::SOS(reader.read_segment(marker)?),
+        0xDB => JFIFSegmentRef::DQT(reader.read_segment(marker)?),
+        0xDD => {
+            let data = reader.read_segment(marker)?;
+            let interval: [u8; 2] = data
+                .try_into()
+                .map_err(|_| JPEGParserError::InvalidSegmentSize { offset, marker })?;
+
+            JFIFSegmentRef::DRI(interval)
+        }
+        0xE0..=0xEF => JFIFSegmentRef::APP(marker & 0x0F, reader.read_segment(marker)?),
+        0xDC => JFIFSegmentRef::DNL(reader.read_segment(marker)?),
+        0xFE => JFIFSegmentRef::COM(reader.read_segment(marker)?),
+        0x02..=0xBF | 0xC8 | 0xDE | 0xDF | 0xF0..=0xFD => {
+            JFIFSegmentRef::OTHER(marker, reader.read_segment(marker)?)
+        }
+        _ => {
+            return Err(JPEGParserError::UnknownMarker { offset, marker });
+        }
+    };
+
+    Ok((fill_size, marker, segment))
 }
 
 impl TryFrom<&Vec<u8>> for JFIFContainer {
     type Error = JPEGParserError;
 
     fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
-        let mut segments: Vec<JFIFSegment> = Vec::new();
-        let mut val_iter = value.into_iter().peekable();
-        let mut valid = true;
-        let mut last_byte = val_iter.next();
-        let mut current_byte = val_iter.next();
-
-        if let Some(last_byte_) = last_byte {
-            if let Some(current_byte_) = current_byte {
-                if JFIFSegment::SOI.get_marker().unwrap() == [*last_byte_, *current_byte_] {
-                    segments.push(JFIFSegment::SOI);
-                } else {
-                    return Err(JPEGParserError::InvalidSOI);
-                }
-            } else {
-                valid = false;
-            }
-        } else {
-            valid = false;
+        JFIFContainerRef::try_from(&value[..]).map(JFIFContainer::from)
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for JFIFContainerRef<'a> {
+    type Error = JPEGParserError;
+
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        let mut segments: Vec<JFIFSegmentRef<'a>> = Vec::new();
+        let mut reader = SegmentReader::new(value);
+        let mut last_marker = 0xD8;
+
+        if reader.read_bytes(2, last_marker)? != JFIFSegmentRef::SOI.get_marker().unwrap() {
+            return Err(JPEGParserError::InvalidSOI);
         }
 
-        last_byte = val_iter.next();
-        current_byte = val_iter.next();
+        segments.push(JFIFSegmentRef::SOI);
 
-        while valid {
-            match val_iter.peek() {
-                None => {
-                    return Err(JPEGParserError::FileEndedPrematurely);
-                }
-                _ => {}
+        let mut scan_found = false;
+
+        loop {
+            // Each SOS is followed by its entropy coded data, which runs until the next marker
+            if matches!(segments.last(), Some(JFIFSegmentRef::SOS(_))) {
+                let img_start = reader.position;
+                let img_end = find_scan_end(value, img_start).ok_or(
+                    JPEGParserError::FileEndedPrematurely {
+                        offset: value.len(),
+                        marker: last_marker,
+                    },
+                )?;
+
+                segments.push(JFIFSegmentRef::IMGDATA(&value[img_start..img_end]));
+                reader.position = img_end;
             }
 
-            let last_byte_ = last_byte.unwrap();
-            let mut current_byte_ = current_byte.unwrap();
+            let (fill_size, marker, segment) = read_marker(&mut reader, last_marker, scan_found)?;
 
-            if *last_byte_ != 0xFF {
-                return Err(JPEGParserError::ExpectMarker);
+            // Fill bytes are kept so that writing the container back gives the same bytes
+            if fill_size > 0 {
+                segments.push(JFIFSegmentRef::FILL(fill_size));
             }
 
-            while *last_byte_ == 0xFF && *current_byte_ == 0xFF {
-                current_byte = val_iter.next();
-                current_byte_ = current_byte.unwrap();
+            last_marker = marker;
+            scan_found |= matches!(segment, JFIFSegmentRef::SOS(_));
+            segments.push(segment);
+
+            if segment == JFIFSegmentRef::EOI {
+                break;
             }
+        }
 
-            match current_byte_ {
-                0xC0 => {
-                    let mut iter = Box::new(&mut val_iter);
-                    match read_segments(&mut iter) {
-                        Ok(data) => {
-                            segments.push(JFIFSegment::SOF0(GeneralSegment::new(data)));
-                        }
-                        Err(_) => {
-                            return Err(JPEGParserError::InvalidSegmentSize);
-                        }
-                    }
-                }
-                0xC2 => {
-                    let mut iter = Box::new(&mut val_iter);
-                    match read_segments(&mut iter) {
-                        Ok(data) => {
-                            segments.push(JFIFSegment::SOF2(GeneralSegment::new(data)));
-                        }
-                        Err(_) => {
-                            return Err(JPEGParserError::InvalidSegmentSize);
-                        }
-                    }
-                }
-                0xC4 => {
-                    let mut iter = Box::new(&mut val_iter);
-                    match read_segments(&mut iter) {
-                        Ok(data) => {
-                            segments.push(JFIFSegment::DHT(GeneralSegment::new(data)));
-                        }
-                        Err(_) => {
-                            return Err(JPEGParserError::InvalidSegmentSize);
-                        }
-                    }
+        let trailer = &value[reader.position..];
+
+        if !trailer.is_empty() {
+            segments.push(JFIFSegmentRef::TRAILER(trailer));
+        }
+
+        let result = JFIFContainerRef::new(segments);
+
+        Ok(result)
+    }
+}
+
+impl JPEGParserError {
+    fn shift_offset(self, base: usize) -> Self {
+        match self {
+            JPEGParserError::FileEndedPrematurely { offset, marker } => {
+                JPEGParserError::FileEndedPrematurely {
+                    offset: base + offset,
+                    marker,
                 }
-                0xC1 | 0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => {
-                    return Err(JPEGParserError::UnsupportedSOF);
+            }
+            JPEGParserError::InvalidSegmentSize { offset, marker } => {
+                JPEGParserError::InvalidSegmentSize {
+                    offset: base + offset,
+                    marker,
                 }
-                0xD0..=0xD7 => {
-                    return Err(JPEGParserError::RSTNDetectedBeforeSOS);
+            }
+            JPEGParserError::UnsupportedEmmbedJPEG { offset } => {
+                JPEGParserError::UnsupportedEmmbedJPEG {
+                    offset: base + offset,
                 }
-                0xD8 => {
-                    return Err(JPEGParserError::UnsupportedEmmbedJPEG);
+            }
+            JPEGParserError::RSTNDetectedBeforeSOS { offset, marker } => {
+                JPEGParserError::RSTNDetectedBeforeSOS {
+                    offset: base + offset,
+                    marker,
                 }
-                0xD9 => {
-                    return Err(JPEGParserError::EOIDetectedBeforeSOS);
+            }
+            JPEGParserError::EOIDetectedBeforeSOS { offset } => {
+                JPEGParserError::EOIDetectedBeforeSOS {
+                    offset: base + offset,
                 }
-                0xDA => {
-                    // SOS
-                    let mut iter = Box::new(&mut val_iter);
-                    match read_segments(&mut iter) {
-                        Ok(data) => {
-                            segments.push(JFIFSegment::SOS(GeneralSegment::new(data)));
-                        }
-                        Err(_) => {
-                            return Err(JPEGParserError::InvalidSegmentSize);
-                        }
+            }
+            JPEGParserError::UnknownMarker { offset, marker } => JPEGParserError::UnknownMarker {
+                offset: base + offset,
+                marker,
+            },
+            JPEGParserError::ExpectMarker { offset } => JPEGParserError::ExpectMarker {
+                offset: base + offset,
+            },
+            JPEGParserError::InvalidSOI => JPEGParserError::InvalidSOI,
+        }
+    }
+}
+
+// Scan data and trailing bytes are emitted in pieces as they arrive, everything else once complete
+pub enum JFIFEvent {
+    Segment(JFIFSegment),
+    ScanData(Vec<u8>),
+    Trailer(Vec<u8>),
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum PushState {
+    Start,
+    Marker,
+    ScanData,
+    Trailer,
+}
+
+pub struct JFIFPushParser {
+    buffer: Vec<u8>,
+    offset: usize,
+    state: PushState,
+    last_marker: u8,
+    scan_found: bool,
+}
+
+impl Default for JFIFPushParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl JFIFPushParser {
+    pub fn new() -> Self {
+        JFIFPushParser {
+            buffer: Vec::new(),
+            offset: 0,
+            state: PushState::Start,
+            last_marker: 0xD8,
+            scan_found: false,
+        }
+    }
+
+    fn consume(&mut self, size: usize) -> Vec<u8> {
+        self.offset += size;
+        self.buffer.drain(..size).collect()
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<JFIFEvent>, JPEGParserError> {
+        let mut events = Vec::new();
+
+        self.buffer.extend_from_slice(data);
+
+        loop {
+            match self.state {
+                PushState::Start => {
+                    if self.buffer.len() < 2 {
+                        break;
                     }
-                    break;
-                }
-                0xDB => {
-                    let mut iter = Box::new(&mut val_iter);
-                    match read_segments(&mut iter) {
-                        Ok(data) => {
-                            segments.push(JFIFSegment::DQT(GeneralSegment::new(data)));
-                        }
-                        Err(_) => {
-                            return Err(JPEGParserError::InvalidSegmentSize);
-                        }
+
+                    if self.buffer[..2] != [0xFF, 0xD8] {
+                        return Err(JPEGParserError::InvalidSOI);
                     }
+
+                    self.consume(2);
+                    events.push(JFIFEvent::Segment(JFIFSegment::SOI));
+                    self.state = PushState::Marker;
                 }
-                0xDD => {
-                    val_iter.next();
-                    val_iter.next();
-                    let first = val_iter.next().unwrap();
-                    let second = val_iter.next().unwrap();
-                    segments.push(JFIFSegment::DRI([*first, *second]));
-                }
-                0xE0..=0xEF => {
-                    let app_num = current_byte_ & 0x0F;
-                    let mut iter = Box::new(&mut val_iter);
-                    match read_segments(&mut iter) {
-                        Ok(data) => {
-                            segments.push(JFIFSegment::APP(app_num, GeneralSegment::new(data)));
-                        }
-                        Err(_) => {
-                            return Err(JPEGParserError::InvalidSegmentSize);
-                        }
+                PushState::Marker => {
+                    let mut reader = SegmentReader::new(&self.buffer);
+                    let (fill_size, marker, segment) =
+                        match read_marker(&mut reader, self.last_marker, self.scan_found) {
+                            Ok(result) => result,
+                            // The segment is not complete yet, wait for more data
+                            Err(JPEGParserError::FileEndedPrematurely { .. }) => break,
+                            Err(err) => return Err(err.shift_offset(self.offset)),
+                        };
+                    let segment = JFIFSegment::from(&segment);
+                    let size = reader.position;
+
+                    if fill_size > 0 {
+                        events.push(JFIFEvent::Segment(JFIFSegment::FILL(fill_size)));
                     }
+
+                    self.state = match segment {
+                        JFIFSegment::SOS(_) => PushState::ScanData,
+                        JFIFSegment::EOI => PushState::Trailer,
+                        _ => PushState::Marker,
+                    };
+                    self.last_marker = marker;
+                    self.scan_found |= self.state == PushState::ScanData;
+                    self.consume(size);
+                    events.push(JFIFEvent::Segment(segment));
                 }
-                0xFE => {
-                    let mut iter = Box::new(&mut val_iter);
-                    match read_segments(&mut iter) {
-                        Ok(data) => {
-                            segments.push(JFIFSegment::COM(GeneralSegment::new(data)));
-                        }
-                        Err(_) => {
-                            return Err(JPEGParserError::InvalidSegmentSize);
-                        }
+                PushState::ScanData => {
+                    let scan_end = find_scan_end(&self.buffer, 0);
+                    // A trailing 0xFF may start the next marker, it is held back until it is known
+                    let size = scan_end.unwrap_or_else(|| {
+                        let pending = self.buffer.iter().rev().take_while(|f| **f == 0xFF);
+
+                        self.buffer.len() - pending.count()
+                    });
+
+                    if size > 0 {
+                        let data = self.consume(size);
+                        events.push(JFIFEvent::ScanData(data));
+                    }
+
+                    if scan_end.is_none() {
+                        break;
                     }
+
+                    self.state = PushState::Marker;
                 }
-                _ => {
-                    return Err(JPEGParserError::UnknownMarker(*current_byte_));
+                PushState::Trailer => {
+                    if !self.buffer.is_empty() {
+                        let data = self.consume(self.buffer.len());
+                        events.push(JFIFEvent::Trailer(data));
+                    }
+
+                    break;
                 }
             }
+        }
+
+        Ok(events)
+    }
 
-            last_byte = val_iter.next();
-            current_byte = val_iter.next();
+    // Every byte has to be consumed and the image has to end with EOI
+    pub fn finish(&self) -> Result<(), JPEGParserError> {
+        if self.state == PushState::Trailer {
+            return Ok(());
         }
 
-        last_byte = val_iter.next();
-        current_byte = val_iter.next();
-        let mut last_byte_ = *last_byte.unwrap();
-        let mut current_byte_ = *current_byte.unwrap();
-        let mut img_data: Vec<u8> = Vec::new();
+        Err(JPEGParserError::FileEndedPrematurely {
+            offset: self.offset + self.buffer.len(),
+            marker: self.last_marker,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
 
-        // SOS already found, start read until found EOI
-        loop {
-            if last_byte_ == 0xFF && current_byte_ == 0xD9 {
-                segments.push(JFIFSegment::IMGDATA(img_data));
-                segments.push(JFIFSegment::EOI);
-                break;
-            } else {
-                img_data.push(last_byte_);
-                last_byte_ = current_byte_;
-                current_byte = val_iter.next();
-                current_byte_ = *current_byte.unwrap();
+    fn marker_segment(fill_size: usize, marker: u8, data: &[u8]) -> Vec<u8> {
+        let mut result = vec![0xFF; fill_size + 1];
+
+        result.push(marker);
+        result.extend(((data.len() + 2) as u16).to_be_bytes());
+        result.extend(data);
+        result
+    }
+
+    fn header_strategy() -> impl Strategy<Value = Vec<u8>> {
+        let markers = vec![
+            0x80, 0xC0, 0xC1, 0xC2, 0xC4, 0xC8, 0xCC, 0xDB, 0xDC, 0xDE, 0xE0, 0xE1, 0xEF, 0xF7, 0xFE,
+        ];
+
+        (
+            0..3usize,
+            prop::sample::select(markers),
+            prop::collection::vec(any::<u8>(), 0..40),
+        )
+            .prop_map(|(fill_size, marker, data)| marker_segment(fill_size, marker, &data))
+    }
+
+    // Entropy coded data only has 0xFF followed by a stuffed zero or a RST marker
+    fn scan_strategy() -> impl Strategy<Value = Vec<u8>> {
+        (
+            prop::collection::vec(any::<u8>(), 1..10),
+            prop::collection::vec((any::<u8>(), 0xCFu8..0xD8), 0..60),
+        )
+            .prop_map(|(header, data)| {
+                let mut result = marker_segment(0, 0xDA, &header);
+
+                data.iter().for_each(|(byte, code)| {
+                    result.push(*byte);
+
+                    if *byte == 0xFF {
+                        result.push(if *code == 0xCF { 0x00 } else { *code });
+                    }
+                });
+
+                result
+            })
+    }
+
+    fn push_in_chunks(inp: &[u8], chunk_size: usize) -> Result<Vec<u8>, JPEGParserError> {
+        let mut parser = JFIFPushParser::new();
+        let mut output = Vec::new();
+
+        for chunk in inp.chunks(chunk_size) {
+            parser.push(chunk)?.iter().for_each(|f| match f {
+                JFIFEvent::Segment(segment) => output.extend(segment.to_bytes().unwrap()),
+                JFIFEvent::ScanData(data) | JFIFEvent::Trailer(data) => output.extend(data),
+            });
+        }
+
+        parser.finish()?;
+
+        Ok(output)
+    }
+
+    proptest! {
+        #[test]
+        fn jpeg_round_trip_is_identity(
+            headers in prop::collection::vec(header_strategy(), 0..8),
+            scans in prop::collection::vec((header_strategy(), scan_strategy()), 1..4),
+            fill_size in 0..3usize,
+            trailer in prop::collection::vec(any::<u8>(), 0..20),
+        ) {
+            let mut inp = vec![0xFF, 0xD8];
+
+            headers.iter().for_each(|f| inp.extend(f));
+            inp.extend(marker_segment(0, 0xDD, &[0x00, 0x10]));
+            scans.iter().for_each(|(table, scan)| {
+                inp.extend(table);
+                inp.extend(scan);
+            });
+            inp.extend(vec![0xFF; fill_size]);
+            inp.extend([0xFF, 0xD9]);
+            inp.extend(&trailer);
+
+            let container = JFIFContainer::try_from(&inp).unwrap();
+            let output = Vec::try_from(container).unwrap();
+
+            prop_assert_eq!(output, inp);
+        }
+
+        #[test]
+        fn parsed_jpeg_writes_back_same_bytes(data in prop::collection::vec(any::<u8>(), 0..200)) {
+            let mut inp = vec![0xFF, 0xD8];
+            inp.extend(data);
+
+            if let Ok(container) = JFIFContainer::try_from(&inp) {
+                let output = Vec::try_from(container).unwrap();
+
+                prop_assert_eq!(output, inp);
             }
         }
 
-        let result = JFIFContainer::new(segments);
+        #[test]
+        fn push_parser_matches_whole_parser(
+            data in prop::collection::vec(any::<u8>(), 0..200),
+            chunk_size in 1..16usize,
+        ) {
+            let mut inp = vec![0xFF, 0xD8];
+            inp.extend(data);
 
-        Ok(result)
+            if JFIFContainer::try_from(&inp).is_ok() {
+                prop_assert_eq!(push_in_chunks(&inp, chunk_size), Ok(inp));
+            }
+        }
+    }
+
+    #[test]
+    fn reserved_marker_ends_scan() {
+        let mut inp = vec![0xFF, 0xD8];
+
+        inp.extend(marker_segment(0, 0xDD, &[0x00, 0x10]));
+        inp.extend(marker_segment(0, 0xDA, &[0x00]));
+        inp.extend([0x12, 0xFF, 0x00, 0xFF, 0x01, 0x34]);
+        inp.extend(marker_segment(0, 0x80, &[0xFF, 0xC0]));
+        inp.extend(marker_segment(0, 0xDA, &[0x00]));
+        inp.extend([0xFF, 0xD9]);
+
+        let container = JFIFContainer::try_from(&inp).unwrap();
+
+        assert!(matches!(
+            container.get_segments()[4],
+            JFIFSegment::OTHER(0x80, _)
+        ));
+        assert_eq!(Vec::try_from(container).unwrap(), inp);
+        assert_eq!(push_in_chunks(&inp, 3), Ok(inp));
+    }
+
+    #[test]
+    fn borrowed_container_slices_input() {
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let container = JFIFContainerRef::try_from(&content[..]).unwrap();
+        let scan_data = container
+            .get_segments()
+            .iter()
+            .find_map(|f| match f {
+                JFIFSegmentRef::IMGDATA(data) => Some(*data),
+                _ => None,
+            })
+            .unwrap();
+
+        assert!(content.as_ptr_range().contains(&scan_data.as_ptr()));
+        assert_eq!(Vec::try_from(&container).unwrap(), content);
+
+        let owned = JFIFContainer::from(container);
+
+        assert_eq!(Vec::try_from(&owned.as_container_ref()).unwrap(), content);
+    }
+
+    #[test]
+    fn jpeg_corpus_round_trip() {
+        for name in [fixture!("test.jpeg"), fixture!("crop.jpeg")] {
+            let content = std::fs::read(name).unwrap();
+            let container = JFIFContainer::try_from(&content).unwrap();
+            let output = Vec::try_from(container).unwrap();
+
+            assert_eq!(output, content);
+        }
+    }
+
+    #[test]
+    fn push_parser_reassembles_corpus() {
+        for name in [fixture!("test.jpeg"), fixture!("crop.jpeg")] {
+            let content = std::fs::read(name).unwrap();
+
+            for chunk_size in [7, 1000, content.len()] {
+                assert_eq!(push_in_chunks(&content, chunk_size), Ok(content.clone()));
+            }
+        }
+    }
+
+    #[test]
+    fn push_parser_reports_absolute_offset() {
+        let mut parser = JFIFPushParser::new();
+
+        assert_eq!(parser.push(&[0xFF, 0xD8, 0xFF]).unwrap().len(), 1);
+        assert_eq!(
+            parser.push(&[0xDB, 0x00, 0x01]).err(),
+            Some(JPEGParserError::InvalidSegmentSize {
+                offset: 4,
+                marker: 0xDB
+            })
+        );
+
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let mut parser = JFIFPushParser::new();
+
+        parser.push(&content[..content.len() - 1]).unwrap();
+
+        assert_eq!(
+            parser.finish(),
+            Err(JPEGParserError::FileEndedPrematurely {
+                offset: content.len() - 1,
+                marker: 0xDA
+            })
+        );
+    }
+
+    #[test]
+    fn truncated_jpeg_returns_error() {
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+
+        // Every header length, then a sample of the entropy coded data
+        for size in (0..2048).chain((2048..content.len()).step_by(61)) {
+            assert!(JFIFContainer::try_from(&Vec::from(&content[..size])).is_err());
+        }
+
+        assert!(JFIFContainer::try_from(&content).is_ok());
+    }
+
+    #[test]
+    fn parser_error_has_offset_and_marker() {
+        let inp = vec![0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x10, 0x45];
+        let output = JFIFContainer::try_from(&inp).err().unwrap();
+
+        assert_eq!(
+            output,
+            JPEGParserError::FileEndedPrematurely {
+                offset: 7,
+                marker: 0xE1
+            }
+        );
+
+        let inp = vec![0xFF, 0xD8, 0xFF, 0xDB, 0x00, 0x01];
+        let output = JFIFContainer::try_from(&inp).err().unwrap();
+
+        assert_eq!(
+            output,
+            JPEGParserError::InvalidSegmentSize {
+                offset: 4,
+                marker: 0xDB
+            }
+        );
+
+        let inp = vec![0xFF, 0xD8, 0xFF, 0xDD, 0x00, 0x03, 0x01];
+        let output = JFIFContainer::try_from(&inp).err().unwrap();
+
+        assert_eq!(
+            output,
+            JPEGParserError::InvalidSegmentSize {
+                offset: 2,
+                marker: 0xDD
+            }
+        );
+
+        let inp = vec![0xFF, 0xD8, 0x00, 0xDB];
+        let output = JFIFContainer::try_from(&inp).err().unwrap();
+
+        assert_eq!(output, JPEGParserError::ExpectMarker { offset: 2 });
+    }
+
+    #[test]
+    fn parse_pass_through_segments() {
+        let inp = vec![
+            0xFF, 0xD8, 0xFF, 0xC1, 0x00, 0x03, 0x08, 0xFF, 0xCC, 0x00, 0x04, 0x00, 0x10, 0xFF,
+            0xFF, 0xDC, 0x00, 0x04, 0x00, 0x20, 0xFF, 0xF7, 0x00, 0x02, 0xFF, 0xCB, 0x00, 0x02,
+            0xFF, 0xDA, 0x00, 0x02, 0x01, 0x02, 0xFF, 0xD9,
+        ];
+        let container = JFIFContainer::try_from(&inp).unwrap();
+        let segments = container.get_segments();
+
+        assert!(matches!(segments[1], JFIFSegment::SOF(0x01, _)));
+        assert!(matches!(segments[2], JFIFSegment::DAC(_)));
+        assert!(matches!(segments[3], JFIFSegment::FILL(1)));
+        assert!(matches!(segments[4], JFIFSegment::DNL(_)));
+        assert!(matches!(segments[5], JFIFSegment::OTHER(0xF7, _)));
+        assert!(matches!(segments[6], JFIFSegment::SOF(0x0B, _)));
+
+        let output = Vec::try_from(container).unwrap();
+
+        assert_eq!(output, inp);
     }
 }
@@ -0,0 +1,214 @@
+use crate::jpeg::container::{JFIFContainer, JFIFSegment};
+use crate::jpeg::mpf::is_mpf_segment;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ScanError {
+    #[error("Invalid scan header")]
+    InvalidScanHeader,
+
+    #[error("Scan not found : `{0}`")]
+    ScanNotFound(usize),
+
+    #[error("At least one scan must be kept")]
+    NoScanKept,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ScanComponent {
+    pub id: u8,
+    pub dc_table: u8,
+    pub ac_table: u8,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ScanHeader {
+    pub components: Vec<ScanComponent>,
+    pub spectral_start: u8,
+    pub spectral_end: u8,
+    pub approximation_high: u8,
+    pub approximation_low: u8,
+}
+
+// Position is the index of the SOS segment, the entropy coded data directly follows it
+#[derive(Debug, PartialEq, Clone)]
+pub struct JFIFScan {
+    pub position: usize,
+    pub header: ScanHeader,
+    pub data_size: usize,
+}
+
+impl TryFrom<&[u8]> for ScanHeader {
+    type Error = ScanError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let count = *value.first().ok_or(ScanError::InvalidScanHeader)? as usize;
+
+        if count == 0 || value.len() != 4 + count * 2 {
+            return Err(ScanError::InvalidScanHeader);
+        }
+
+        let components = value[1..1 + count * 2]
+            .chunks(2)
+            .map(|f| ScanComponent {
+                id: f[0],
+                dc_table: f[1] >> 4,
+                ac_table: f[1] & 0x0F,
+            })
+            .collect();
+        let rest = &value[1 + count * 2..];
+
+        Ok(ScanHeader {
+            components,
+            spectral_start: rest[0],
+            spectral_end: rest[1],
+            approximation_high: rest[2] >> 4,
+            approximation_low: rest[2] & 0x0F,
+        })
+    }
+}
+
+impl JFIFContainer {
+    pub fn get_scans(&self) -> Result<Vec<JFIFScan>, ScanError> {
+        let segments = self.get_segments();
+        let mut scans = Vec::new();
+
+        for (position, segment) in segments.iter().enumerate() {
+            if let JFIFSegment::SOS(seg) = segment {
+                let data_size = match segments.get(position + 1) {
+                    Some(JFIFSegment::IMGDATA(data)) => data.len(),
+                    _ => 0,
+                };
+
+                scans.push(JFIFScan {
+                    position,
+                    header: ScanHeader::try_from(&seg.get_data()[..])?,
+                    data_size,
+                });
+            }
+        }
+
+        Ok(scans)
+    }
+
+    // Tables needed by a scan go right before its SOS, so that is where new segments land
+    pub fn insert_before_scan(
+        &mut self,
+        scan: usize,
+        segment: JFIFSegment,
+    ) -> Result<usize, ScanError> {
+        let position = self
+            .get_scans()?
+            .get(scan)
+            .map(|f| f.position)
+            .ok_or(ScanError::ScanNotFound(scan))?;
+
+        self.get_segments_mut().insert(position, segment);
+
+        Ok(position)
+    }
+
+    // Keeps the first scans of a progressive image, which decodes as a lower quality preview.
+    // MPF images and trailing data are dropped as their offsets no longer apply
+    pub fn truncate_scans(&mut self, count: usize) -> Result<(), ScanError> {
+        if count == 0 {
+            return Err(ScanError::NoScanKept);
+        }
+
+        let scans = self.get_scans()?;
+
+        if count >= scans.len() {
+            return Ok(());
+        }
+
+        let segments = self.get_segments_mut();
+        let last = scans[count - 1].position;
+        let start = match segments.get(last + 1) {
+            Some(JFIFSegment::IMGDATA(_)) => last + 2,
+            _ => last + 1,
+        };
+        let end = segments
+            .iter()
+            .rposition(|p| matches!(p, JFIFSegment::EOI))
+            .unwrap_or(segments.len());
+        // DNL completes the frame header of the first scan, it has to stay
+        let kept: Vec<JFIFSegment> = segments
+            .drain(start..end)
+            .filter(|f| matches!(f, JFIFSegment::DNL(_)))
+            .collect();
+
+        segments.splice(start..start, kept);
+        segments.retain(|p| !matches!(p, JFIFSegment::TRAILER(_)));
+        segments.retain(|p| !is_mpf_segment(p));
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg::container::GeneralSegment;
+
+    fn sample_progressive() -> Vec<u8> {
+        vec![
+            0xFF, 0xD8, 0xFF, 0xC2, 0x00, 0x03, 0x08, 0xFF, 0xC4, 0x00, 0x03, 0x00, 0xFF, 0xDA,
+            0x00, 0x08, 0x01, 0x01, 0x00, 0x00, 0x00, 0x01, 0x0A, 0xFF, 0x00, 0x0B, 0xFF, 0xD0,
+            0x0C, 0xFF, 0xC4, 0x00, 0x03, 0x10, 0xFF, 0xDA, 0x00, 0x08, 0x01, 0x01, 0x01, 0x01,
+            0x3F, 0x00, 0x0D, 0x0E, 0xFF, 0xD9,
+        ]
+    }
+
+    #[test]
+    fn list_scans() {
+        let container = JFIFContainer::try_from(&sample_progressive()).unwrap();
+        let scans = container.get_scans().unwrap();
+
+        assert_eq!(scans.len(), 2);
+        assert_eq!(scans[0].position, 3);
+        assert_eq!(scans[0].data_size, 7);
+        assert_eq!(scans[0].header.spectral_end, 0);
+        assert_eq!(scans[0].header.approximation_low, 1);
+        assert_eq!(scans[1].position, 6);
+        assert_eq!(scans[1].data_size, 2);
+        assert_eq!(scans[1].header.spectral_start, 1);
+        assert_eq!(scans[1].header.spectral_end, 0x3F);
+        assert_eq!(scans[1].header.components[0].ac_table, 1);
+
+        let bytes = Vec::try_from(container).unwrap();
+
+        assert_eq!(bytes, sample_progressive());
+    }
+
+    #[test]
+    fn insert_and_truncate_scans() {
+        let mut container = JFIFContainer::try_from(&sample_progressive()).unwrap();
+        let comment = JFIFSegment::COM(GeneralSegment::new(vec![0x41]));
+
+        assert_eq!(container.insert_before_scan(1, comment), Ok(6));
+        assert_eq!(
+            container.insert_before_scan(2, JFIFSegment::EOI),
+            Err(ScanError::ScanNotFound(2))
+        );
+
+        container.set_trailer(Some(vec![0x01]));
+        container.truncate_scans(1).unwrap();
+
+        let bytes = Vec::try_from(container).unwrap();
+
+        assert_eq!(bytes[..29], sample_progressive()[..29]);
+        assert_eq!(bytes[29..], [0xFF, 0xD9]);
+    }
+
+    #[test]
+    fn truncate_scans_without_scan_data() {
+        let mut container = JFIFContainer::try_from(&sample_progressive()).unwrap();
+        container.get_segments_mut().remove(4);
+        container.truncate_scans(1).unwrap();
+
+        let bytes = Vec::try_from(container).unwrap();
+
+        assert_eq!(bytes[..22], sample_progressive()[..22]);
+        assert_eq!(bytes[22..], [0xFF, 0xD9]);
+    }
+}
@@ -0,0 +1,292 @@
+use super::container::{GeneralSegment, JFIFContainer, JFIFSegment};
+use crate::metadata::EXIF_HEADER;
+use crate::tiff_container::{
+    TIFFContainer, TIFFEntry, TiffContainerError, COMPRESSION_TAG,
+    JPEG_INTERCHANGE_FORMAT_LENGTH_TAG, JPEG_INTERCHANGE_FORMAT_TAG, TIFFIFD,
+};
+
+// Old style JPEG compression, the only one allowed for EXIF thumbnails
+const JPEG_COMPRESSION: u16 = 6;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum ThumbnailPolicy {
+    Keep,
+    #[default]
+    Remove,
+    Regenerate,
+    Watermark,
+}
+
+pub fn get_exif_thumbnail(exif: &[u8]) -> Option<Vec<u8>> {
+    let container = TIFFContainer::try_from(&Vec::from(exif)).ok()?;
+    let ifd1 = container.get_ifds().get(1)?;
+
+    ifd1.find_entry(JPEG_INTERCHANGE_FORMAT_TAG)?;
+    ifd1.data_blocks.first().cloned()
+}
+
+// Cuts the pointer to IFD1 and clears IFD1 with its values and thumbnail, so IFD0 and its sub
+// IFDs keep their bytes and offsets. The cleared bytes are dropped when nothing follows them
+fn remove_ifd1(exif: &[u8]) -> Result<Vec<u8>, TiffContainerError> {
+    let container = TIFFContainer::try_from(&Vec::from(exif))?;
+    let byte_order = container.get_byte_order();
+    let ifd1 = match container.get_ifds().get(1) {
+        Some(ifd1) => ifd1,
+        None => return Ok(Vec::from(exif)),
+    };
+
+    // Both IFDs have been read successfully, so every offset below is inside the data
+    let ifd0_offset = byte_order.read_u32(&exif[4..8]) as usize;
+    let next_pointer = ifd0_offset + 2 + byte_order.read_u16(&exif[ifd0_offset..]) as usize * 12;
+    let ifd1_offset = byte_order.read_u32(&exif[next_pointer..]) as usize;
+    let entries_offset = ifd1_offset + 2;
+    let mut cleared = Vec::new();
+
+    cleared.push(ifd1_offset..entries_offset + ifd1.entries.len() * 12 + 4);
+
+    for (index, entry) in ifd1.entries.iter().enumerate() {
+        if entry.value.len() > 4 {
+            let position = entries_offset + index * 12 + 8;
+            let value_offset = byte_order.read_u32(&exif[position..]) as usize;
+            cleared.push(value_offset..value_offset + entry.value.len());
+        }
+    }
+
+    if let Some((offsets_tag, _)) = ifd1.get_data_tags() {
+        let offsets = ifd1.find_entry(offsets_tag).unwrap().get_values(byte_order);
+
+        for (offset, block) in offsets.iter().zip(ifd1.data_blocks.iter()) {
+            cleared.push(*offset as usize..*offset as usize + block.len());
+        }
+    }
+
+    let mut result = Vec::from(exif);
+    result[next_pointer..next_pointer + 4].copy_from_slice(&[0u8; 4]);
+    cleared.iter().for_each(|f| result[f.clone()].fill(0x00));
+
+    let cut = cleared.iter().map(|f| f.start).min().unwrap();
+
+    if result[cut..].iter().all(|f| *f == 0x00) {
+        let truncated = Vec::from(&result[..cut]);
+
+        if TIFFContainer::try_from(&truncated).is_ok() {
+            return Ok(truncated);
+        }
+    }
+
+    Ok(result)
+}
+
+// Passing None drops IFD1 altogether, otherwise its thumbnail data is replaced
+pub fn set_exif_thumbnail(
+    exif: &[u8],
+    thumbnail: Option<&[u8]>,
+) -> Result<Vec<u8>, TiffContainerError> {
+    let thumbnail = match thumbnail {
+        Some(thumbnail) => thumbnail,
+        None => return remove_ifd1(exif),
+    };
+    let mut container = TIFFContainer::try_from(&Vec::from(exif))?;
+    let byte_order = container.get_byte_order();
+    let ifds = container.get_ifds_mut();
+
+    if ifds.len() < 2 {
+        ifds.truncate(1);
+        ifds.push(TIFFIFD::new(vec![TIFFEntry::new_short(
+            COMPRESSION_TAG,
+            &[JPEG_COMPRESSION],
+            byte_order,
+        )]));
+    }
+
+    let ifd1 = &mut ifds[1];
+    ifd1.put_entry(TIFFEntry::new_long(
+        JPEG_INTERCHANGE_FORMAT_TAG,
+        &[0],
+        byte_order,
+    ));
+    ifd1.put_entry(TIFFEntry::new_long(
+        JPEG_INTERCHANGE_FORMAT_LENGTH_TAG,
+        &[thumbnail.len() as u32],
+        byte_order,
+    ));
+    ifd1.data_blocks = vec![Vec::from(thumbnail)];
+
+    Ok(container.to_bytes())
+}
+
+impl JFIFContainer {
+    fn find_exif_segment(&self) -> Option<usize> {
+        self.get_segments().iter().position(|p| match p {
+            JFIFSegment::APP(1, seg) => seg.get_data().starts_with(EXIF_HEADER),
+            _ => false,
+        })
+    }
+
+    pub fn get_exif_thumbnail(&self) -> Option<Vec<u8>> {
+        let position = self.find_exif_segment()?;
+
+        match &self.get_segments()[position] {
+            JFIFSegment::APP(_, seg) => get_exif_thumbnail(&seg.get_data()[EXIF_HEADER.len()..]),
+            _ => None,
+        }
+    }
+
+    // Returns false when there is no EXIF segment to update
+    pub fn set_exif_thumbnail(
+        &mut self,
+        thumbnail: Option<&[u8]>,
+    ) -> Result<bool, TiffContainerError> {
+        let position = match self.find_exif_segment() {
+            Some(position) => position,
+            None => return Ok(false),
+        };
+        let segments = self.get_segments_mut();
+        let exif = match &segments[position] {
+            JFIFSegment::APP(_, seg) => {
+                set_exif_thumbnail(&seg.get_data()[EXIF_HEADER.len()..], thumbnail)?
+            }
+            _ => return Ok(false),
+        };
+        let mut data = Vec::from(&EXIF_HEADER[..]);
+        data.extend(exif);
+        segments[position] = JFIFSegment::APP(1, GeneralSegment::new(data));
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tiff_container::{ByteOrder, ARTIST_TAG, EXIF_IFD_TAG, MAKER_NOTE_TAG};
+
+    fn exif_with_thumbnail(thumbnail: &[u8]) -> Vec<u8> {
+        let byte_order = ByteOrder::BigEndian;
+        let ifd0 = TIFFIFD::new(vec![TIFFEntry::new_ascii(ARTIST_TAG, "Someone")]);
+        let container = TIFFContainer::new(byte_order, vec![ifd0]);
+
+        set_exif_thumbnail(&container.to_bytes(), Some(thumbnail)).unwrap()
+    }
+
+    fn jpeg_with_exif(exif: &[u8]) -> JFIFContainer {
+        let mut data = Vec::from(&EXIF_HEADER[..]);
+        data.extend(exif);
+
+        JFIFContainer::new(vec![
+            JFIFSegment::SOI,
+            JFIFSegment::APP(1, GeneralSegment::new(data)),
+            JFIFSegment::SOS(GeneralSegment::new(vec![0x00])),
+            JFIFSegment::IMGDATA(vec![0x01, 0x02]),
+            JFIFSegment::EOI,
+        ])
+    }
+
+    #[test]
+    fn exif_thumbnail_round_trip() {
+        let thumbnail = [0xFFu8, 0xD8, 0x01, 0x02, 0x03, 0xFF, 0xD9];
+        let exif = exif_with_thumbnail(&thumbnail);
+        let container = TIFFContainer::try_from(&exif).unwrap();
+        let ifd1 = &container.get_ifds()[1];
+        let offset = ifd1
+            .find_entry(JPEG_INTERCHANGE_FORMAT_TAG)
+            .unwrap()
+            .get_values(ByteOrder::BigEndian)[0] as usize;
+
+        assert_eq!(get_exif_thumbnail(&exif), Some(Vec::from(thumbnail)));
+        assert_eq!(&exif[offset..offset + thumbnail.len()], thumbnail);
+
+        let exif = set_exif_thumbnail(&exif, Some(&thumbnail[..3])).unwrap();
+
+        assert_eq!(get_exif_thumbnail(&exif), Some(Vec::from(&thumbnail[..3])));
+
+        let exif = set_exif_thumbnail(&exif, None).unwrap();
+        let container = TIFFContainer::try_from(&exif).unwrap();
+
+        assert_eq!(get_exif_thumbnail(&exif), None);
+        assert_eq!(container.get_ifds().len(), 1);
+        assert!(container.find_entry(ARTIST_TAG).is_some());
+    }
+
+    // Camera EXIF, the maker note sits right behind the header and points into itself
+    fn camera_exif(thumbnail: &[u8]) -> Vec<u8> {
+        let byte_order = ByteOrder::LittleEndian;
+        let mut maker_note = Vec::from(&b"Vendor\0\0"[..]);
+        maker_note.extend(8u32.to_le_bytes());
+        maker_note.extend([0x5A; 12]);
+
+        let mut exif_ifd = TIFFIFD::new(vec![TIFFEntry::new_undefined(MAKER_NOTE_TAG, maker_note)]);
+        exif_ifd.fixed_values.push((MAKER_NOTE_TAG, 8));
+
+        let mut ifd0 = TIFFIFD::new(vec![
+            TIFFEntry::new_ascii(ARTIST_TAG, "Someone"),
+            TIFFEntry::new_long(EXIF_IFD_TAG, &[0], byte_order),
+        ]);
+        ifd0.sub_ifds.push((EXIF_IFD_TAG, vec![exif_ifd]));
+
+        let container = TIFFContainer::new(byte_order, vec![ifd0]);
+
+        set_exif_thumbnail(&container.to_bytes(), Some(thumbnail)).unwrap()
+    }
+
+    fn get_maker_note(exif: &[u8]) -> (Vec<u8>, Vec<(u16, u32)>) {
+        let container = TIFFContainer::try_from(&Vec::from(exif)).unwrap();
+        let exif_ifd = &container.get_ifds()[0].sub_ifds[0].1[0];
+
+        (
+            exif_ifd.find_entry(MAKER_NOTE_TAG).unwrap().value.clone(),
+            exif_ifd.fixed_values.clone(),
+        )
+    }
+
+    #[test]
+    fn removing_thumbnail_keeps_maker_note() {
+        let thumbnail = [0xFFu8, 0xD8, 0x11, 0x22, 0x33, 0xFF, 0xD9];
+        let exif = camera_exif(&thumbnail);
+        let (maker_note, fixed_values) = get_maker_note(&exif);
+
+        assert_eq!(fixed_values, [(MAKER_NOTE_TAG, 8)]);
+        assert_eq!(exif[8..8 + maker_note.len()], maker_note);
+
+        let output = set_exif_thumbnail(&exif, None).unwrap();
+        let ifd0_offset = u32::from_le_bytes([exif[4], exif[5], exif[6], exif[7]]) as usize;
+        let next_pointer = ifd0_offset + 2 + 2 * 12;
+
+        // Only the pointer to IFD1 changed in front of the cleared tail
+        assert!(output.len() < exif.len());
+        assert_eq!(output[..next_pointer], exif[..next_pointer]);
+        assert_eq!(output[next_pointer..next_pointer + 4], [0u8; 4]);
+        assert_eq!(
+            output[next_pointer + 4..],
+            exif[next_pointer + 4..output.len()]
+        );
+        assert_eq!(get_exif_thumbnail(&output), None);
+        assert_eq!(
+            get_maker_note(&output),
+            (maker_note.clone(), fixed_values.clone())
+        );
+
+        let output = set_exif_thumbnail(&exif, Some(&thumbnail[..3])).unwrap();
+
+        assert_eq!(
+            get_exif_thumbnail(&output),
+            Some(Vec::from(&thumbnail[..3]))
+        );
+        assert_eq!(get_maker_note(&output), (maker_note.clone(), fixed_values));
+        assert_eq!(output[8..8 + maker_note.len()], maker_note);
+    }
+
+    #[test]
+    fn jfif_container_exif_thumbnail() {
+        let thumbnail = [0xFFu8, 0xD8, 0xFF, 0xD9];
+        let mut container = jpeg_with_exif(&exif_with_thumbnail(&thumbnail));
+
+        assert_eq!(container.get_exif_thumbnail(), Some(Vec::from(thumbnail)));
+        assert_eq!(container.set_exif_thumbnail(None), Ok(true));
+        assert_eq!(container.get_exif_thumbnail(), None);
+
+        let mut container = JFIFContainer::new(vec![JFIFSegment::SOI, JFIFSegment::EOI]);
+
+        assert_eq!(container.set_exif_thumbnail(None), Ok(false));
+    }
+}
@@ -1,15 +1,134 @@
 use super::container::JFIFContainer;
+use super::container::JFIFContainerRef;
+use super::container::JFIFSegmentRef;
 use super::container::ToBytes;
+use std::io::{self, Write};
 
-impl From<JFIFContainer> for Vec<u8> {
-    fn from(value: JFIFContainer) -> Self {
-        let mut result = Vec::new();
+impl JFIFSegmentRef<'_> {
+    // Number of bytes written by write_to
+    pub fn get_size(&self) -> usize {
+        match self {
+            JFIFSegmentRef::SOI | JFIFSegmentRef::EOI | JFIFSegmentRef::RST(_) => 2,
+            JFIFSegmentRef::IMGDATA(data) | JFIFSegmentRef::TRAILER(data) => data.len(),
+            JFIFSegmentRef::FILL(size) => *size,
+            JFIFSegmentRef::DRI(_) => 6,
+            _ => self.get_data().map(|f| f.len()).unwrap_or(0) + 4,
+        }
+    }
+
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        match self {
+            JFIFSegmentRef::SOI | JFIFSegmentRef::EOI | JFIFSegmentRef::RST(_) => {
+                writer.write_all(&self.get_marker().unwrap())
+            }
+            JFIFSegmentRef::IMGDATA(data) | JFIFSegmentRef::TRAILER(data) => writer.write_all(data),
+            JFIFSegmentRef::FILL(size) => writer.write_all(&vec![0xFF; *size]),
+            JFIFSegmentRef::DRI(payload) => {
+                write_length_segment(writer, self.get_marker().unwrap(), payload)
+            }
+            _ => write_length_segment(writer, self.get_marker().unwrap(), self.get_data().unwrap()),
+        }
+    }
+}
+
+fn write_length_segment<W: Write + ?Sized>(
+    writer: &mut W,
+    marker: [u8; 2],
+    data: &[u8],
+) -> io::Result<()> {
+    let size = u16::try_from(data.len() + 2)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Segment too large"))?;
+
+    writer.write_all(&marker)?;
+    writer.write_all(&size.to_be_bytes())?;
+    writer.write_all(data)
+}
+
+impl JFIFContainerRef<'_> {
+    pub fn get_size(&self) -> usize {
+        self.get_segments().iter().map(|f| f.get_size()).sum()
+    }
+
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        self.get_segments()
+            .iter()
+            .try_for_each(|f| f.write_to(writer))
+    }
+}
+
+impl JFIFContainer {
+    pub fn get_size(&self) -> usize {
+        self.get_segments()
+            .iter()
+            .map(|f| f.as_segment_ref().get_size())
+            .sum()
+    }
+
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        self.get_segments()
+            .iter()
+            .try_for_each(|f| f.as_segment_ref().write_to(writer))
+    }
+}
+
+// Segments are not length checked when built, so serializing can fail
+impl TryFrom<JFIFContainer> for Vec<u8> {
+    type Error = io::Error;
+
+    fn try_from(value: JFIFContainer) -> Result<Self, Self::Error> {
+        Vec::try_from(&value)
+    }
+}
+
+impl TryFrom<&JFIFContainer> for Vec<u8> {
+    type Error = io::Error;
+
+    fn try_from(value: &JFIFContainer) -> Result<Self, Self::Error> {
+        let mut result = Vec::with_capacity(value.get_size());
+
+        value.write_to(&mut result)?;
+        Ok(result)
+    }
+}
+
+impl TryFrom<&JFIFContainerRef<'_>> for Vec<u8> {
+    type Error = io::Error;
+
+    fn try_from(value: &JFIFContainerRef<'_>) -> Result<Self, Self::Error> {
+        let mut result = Vec::with_capacity(value.get_size());
+
+        value.write_to(&mut result)?;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jpeg::container::{GeneralSegment, JFIFSegment};
+
+    #[test]
+    fn write_to_matches_computed_size() {
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let container = JFIFContainer::try_from(&content).unwrap();
+        let mut output = Vec::new();
+
+        container.write_to(&mut output).unwrap();
+
+        assert_eq!(output, content);
+        assert_eq!(container.get_size(), content.len());
+    }
 
-        value.get_segments().iter().for_each(|f| {
-            let bytes = f.to_bytes();
-            result.extend_from_slice(&bytes[..]);
-        });
+    #[test]
+    fn write_to_rejects_oversized_segment() {
+        let container = JFIFContainer::new(vec![
+            JFIFSegment::SOI,
+            JFIFSegment::COM(GeneralSegment::new(vec![0x00; 0x10000])),
+        ]);
+        let mut output = Vec::new();
 
-        result
+        assert!(container.write_to(&mut output).is_err());
+        assert!(container.get_segments()[1].to_bytes().is_err());
+        assert!(Vec::try_from(container).is_err());
     }
 }
@@ -1,3 +1,11 @@
+// Test images live in tests/fixtures, found from the crate root whatever the working directory
+#[cfg(test)]
+macro_rules! fixture {
+    ($name:literal) => {
+        concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/", $name)
+    };
+}
+
 pub mod watermark_task;
 pub mod img;
 pub mod file_joiner;
@@ -5,3 +13,14 @@ pub mod file_splitter;
 pub mod jpeg;
 pub mod encryption;
 pub mod webp_container;
+pub mod file_format;
+pub mod png_container;
+pub mod isobmff_container;
+pub mod gif_container;
+pub mod frame_payload;
+pub mod wav_container;
+pub mod tiff_container;
+pub mod archive;
+pub mod metadata;
+pub mod probe;
+pub mod payload_stream;
@@ -0,0 +1,965 @@
+use crate::file_format::{detect_format, FileFormat};
+use crate::jpeg::app_segment::{
+    ExifSegment, ExtendedXmp, IccChunk, IccProfile, XmpExtensionChunk, XmpSegment,
+};
+use crate::jpeg::container::{JFIFContainer, JFIFSegment};
+use crate::jpeg::mpf::{is_mpf_segment, MPImage};
+use crate::png_container::{PNGChunk, PNGContainer};
+use crate::tiff_container::{
+    ByteOrder, TIFFContainer, TIFFEntry, ARTIST_TAG, COPYRIGHT_TAG, TIFFIFD,
+};
+use crate::webp_container::{Chunk, RIFFContainer, RegularChunk};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+use thiserror::Error;
+
+pub const EXIF_HEADER: &[u8; 6] = b"Exif\0\0";
+pub const ICC_HEADER: &[u8; 12] = b"ICC_PROFILE\0";
+pub const XMP_HEADER: &[u8; 29] = b"http://ns.adobe.com/xap/1.0/\0";
+pub const XMP_EXTENSION_HEADER: &[u8; 35] = b"http://ns.adobe.com/xmp/extension/\0";
+pub const PNG_XMP_KEYWORD: &[u8; 18] = b"XML:com.adobe.xmp\0";
+pub const PNG_ICC_NAME: &[u8; 12] = b"ICC Profile\0";
+
+// Same bound as the 255 chunks an ICC profile can be split into in a JPEG
+const MAX_PNG_INFLATED_SIZE: u64 = 255 * 65519;
+
+const XMP_PACKET_START: &str = "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+    <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n\
+    <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n";
+const XMP_PACKET_END: &str = "</rdf:RDF>\n</x:xmpmeta>\n<?xpacket end=\"w\"?>";
+const RIGHTS_DESCRIPTION_START: &str = "<rdf:Description rdf:about=\"\" \
+    xmlns:dc=\"http://purl.org/dc/elements/1.1/\" \
+    xmlns:xmpRights=\"http://ns.adobe.com/xap/1.0/rights/\">\n";
+const RIGHTS_DESCRIPTION_END: &str = "</rdf:Description>\n";
+const EXTENDED_XMP_PROPERTY: &str = "xmpNote:HasExtendedXMP";
+const EXTENDED_XMP_DESCRIPTION_START: &str =
+    "<rdf:Description rdf:about=\"\" xmlns:xmpNote=\"http://ns.adobe.com/xmp/note/\"";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum MetadataError {
+    #[error("Invalid Jpeg file")]
+    InvalidJpegFile,
+
+    #[error("Invalid WebP file")]
+    InvalidWebpFile,
+
+    #[error("Invalid Png file")]
+    InvalidPngFile,
+
+    #[error("Unsupported format")]
+    UnsupportedFormat,
+
+    #[error("Metadata too large : `{0}`")]
+    MetadataTooLarge(&'static str),
+
+    #[error("Invalid EXIF data")]
+    InvalidExif,
+
+    #[error("Cannot process EXIF thumbnail")]
+    InvalidThumbnail,
+
+    #[error("Invalid MPF data")]
+    InvalidMpf,
+}
+
+// Payloads are kept without their container specific headers, so they can be written
+// back into a JPEG, WebP or PNG output
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct ImageMetadata {
+    pub exif: Option<Vec<u8>>,
+    pub icc: Option<Vec<u8>>,
+    pub xmp: Option<Vec<u8>>,
+    pub mpf_images: Vec<MPImage>,
+    pub trailer: Option<Vec<u8>>,
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct RightsInfo {
+    pub copyright: Option<String>,
+    pub artist: Option<String>,
+    pub web_statement: Option<String>,
+}
+
+fn escape_xml(inp: &str) -> String {
+    inp.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn rights_description(rights: &RightsInfo) -> String {
+    let mut result = String::from(RIGHTS_DESCRIPTION_START);
+
+    if let Some(copyright) = &rights.copyright {
+        result.push_str(&format!(
+            "<dc:rights><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:rights>\n",
+            escape_xml(copyright)
+        ));
+        result.push_str("<xmpRights:Marked>True</xmpRights:Marked>\n");
+    }
+
+    if let Some(artist) = &rights.artist {
+        result.push_str(&format!(
+            "<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>\n",
+            escape_xml(artist)
+        ));
+    }
+
+    if let Some(web_statement) = &rights.web_statement {
+        result.push_str(&format!(
+            "<xmpRights:WebStatement>{}</xmpRights:WebStatement>\n",
+            escape_xml(web_statement)
+        ));
+    }
+
+    result.push_str(RIGHTS_DESCRIPTION_END);
+
+    result
+}
+
+// Properties written by rights_description, they replace the ones already in the packet
+fn rights_properties(rights: &RightsInfo) -> Vec<&'static str> {
+    let mut result = Vec::new();
+
+    if rights.copyright.is_some() {
+        result.extend(["dc:rights", "xmpRights:Marked"]);
+    }
+
+    if rights.artist.is_some() {
+        result.push("dc:creator");
+    }
+
+    if rights.web_statement.is_some() {
+        result.push("xmpRights:WebStatement");
+    }
+
+    result
+}
+
+// A property is either an element or, in the shorthand form, an rdf:Description attribute
+fn remove_xmp_property(packet: &mut String, name: &str) {
+    let open = format!("<{}", name);
+    let close = format!("</{}>", name);
+    let mut from = 0;
+
+    while let Some(start) = packet[from..].find(&open).map(|f| f + from) {
+        let tag = start + open.len();
+
+        // Longer names sharing the prefix, such as dc:rightsHolder
+        if !packet[tag..].starts_with(|c: char| c == '>' || c == '/' || c.is_ascii_whitespace()) {
+            from = tag;
+            continue;
+        }
+
+        let Some(tag_end) = packet[tag..].find('>').map(|f| tag + f + 1) else {
+            return;
+        };
+        let end = if packet[..tag_end].ends_with("/>") {
+            tag_end
+        } else {
+            match packet[tag_end..].find(&close) {
+                Some(position) => tag_end + position + close.len(),
+                None => return,
+            }
+        };
+
+        packet.replace_range(start..end, "");
+        from = start;
+    }
+
+    for quote in ['"', '\''] {
+        let attribute = format!("{}={}", name, quote);
+        let mut from = 0;
+
+        while let Some(start) = packet[from..].find(&attribute).map(|f| f + from) {
+            let value = start + attribute.len();
+            let is_attribute = packet[..start].ends_with(|c: char| c.is_ascii_whitespace());
+
+            match packet[value..].find(quote) {
+                Some(position) if is_attribute => {
+                    packet.replace_range(start..value + position + 1, "")
+                }
+                _ => from = value,
+            }
+        }
+    }
+}
+
+// Descriptions left empty by a previous application are dropped
+fn remove_empty_rights_descriptions(packet: &mut String) {
+    let mut from = 0;
+
+    while let Some(start) = packet[from..]
+        .find(RIGHTS_DESCRIPTION_START)
+        .map(|f| f + from)
+    {
+        let content = start + RIGHTS_DESCRIPTION_START.len();
+
+        match packet[content..].find(RIGHTS_DESCRIPTION_END) {
+            Some(position) if packet[content..content + position].trim().is_empty() => {
+                packet.replace_range(start..content + position + RIGHTS_DESCRIPTION_END.len(), "")
+            }
+            _ => from = content,
+        }
+    }
+}
+
+// The rights go into their own rdf:Description, existing values of the same properties are
+// removed first so applying the rights again replaces them
+fn rights_xmp(xmp: Option<&Vec<u8>>, rights: &RightsInfo) -> Vec<u8> {
+    let description = rights_description(rights);
+    let packet = xmp.and_then(|f| std::str::from_utf8(f).ok());
+
+    if let Some(packet) = packet {
+        let mut packet = String::from(packet);
+
+        rights_properties(rights)
+            .iter()
+            .for_each(|f| remove_xmp_property(&mut packet, f));
+        remove_empty_rights_descriptions(&mut packet);
+
+        if let Some(position) = packet.rfind("</rdf:RDF>") {
+            let mut result = String::from(&packet[..position]);
+            result.push_str(&description);
+            result.push_str(&packet[position..]);
+
+            return result.into_bytes();
+        }
+    }
+
+    format!("{}{}{}", XMP_PACKET_START, description, XMP_PACKET_END).into_bytes()
+}
+
+fn get_extended_xmp_guid(packet: &str) -> Option<[u8; 32]> {
+    let start = packet.find(EXTENDED_XMP_PROPERTY)? + EXTENDED_XMP_PROPERTY.len();
+    let value = packet[start..].trim_start_matches(['=', '"', '\'', '>']);
+
+    value.as_bytes().get(..32)?.try_into().ok()
+}
+
+// The extension is a full packet of its own, its descriptions join the standard ones
+fn merge_extended_xmp(standard: &[u8], extended: &[u8]) -> Option<Vec<u8>> {
+    let mut result = String::from(std::str::from_utf8(standard).ok()?);
+    let extended = std::str::from_utf8(extended).ok()?;
+    let content_start = extended.find("<rdf:RDF")?;
+    let content_start = content_start + extended[content_start..].find('>')? + 1;
+    let content_end = extended.rfind("</rdf:RDF>")?;
+
+    remove_xmp_property(&mut result, EXTENDED_XMP_PROPERTY);
+    result = result.replace(&format!("{} />\n", EXTENDED_XMP_DESCRIPTION_START), "");
+
+    let position = result.rfind("</rdf:RDF>")?;
+    result.insert_str(
+        position,
+        extended.get(content_start..content_end)?.trim_start(),
+    );
+
+    Some(result.into_bytes())
+}
+
+// Packets too large for one segment go whole into the extension, the standard packet only
+// points to it
+fn extended_xmp_segments(xmp: &[u8]) -> Result<Vec<JFIFSegment>, MetadataError> {
+    let packet = std::str::from_utf8(xmp).map_err(|_| MetadataError::MetadataTooLarge("XMP"))?;
+    let mut packet = String::from(packet);
+
+    remove_xmp_property(&mut packet, EXTENDED_XMP_PROPERTY);
+
+    // The extension is serialized without the xpacket wrapper
+    let start = packet.find("<x:xmpmeta").unwrap_or(0);
+    let end = packet
+        .rfind("</x:xmpmeta>")
+        .map_or(packet.len(), |f| f + "</x:xmpmeta>".len());
+    let extended = ExtendedXmp::new(Vec::from(packet.get(start..end).unwrap_or(&packet)));
+    let standard = XmpSegment {
+        packet: format!(
+            "{}{} {}=\"{}\"/>\n{}",
+            XMP_PACKET_START,
+            EXTENDED_XMP_DESCRIPTION_START,
+            EXTENDED_XMP_PROPERTY,
+            String::from_utf8_lossy(&extended.guid),
+            XMP_PACKET_END
+        )
+        .into_bytes(),
+    };
+    let mut result =
+        vec![JFIFSegment::try_from(&standard).map_err(|_| MetadataError::MetadataTooLarge("XMP"))?];
+
+    result.extend(
+        extended
+            .to_segments()
+            .map_err(|_| MetadataError::MetadataTooLarge("XMP"))?,
+    );
+
+    Ok(result)
+}
+
+// The maker note and free space of a camera EXIF are written back at their original offsets
+fn rights_exif(exif: Option<&Vec<u8>>, rights: &RightsInfo) -> Result<Vec<u8>, MetadataError> {
+    let mut container = match exif {
+        Some(exif) => TIFFContainer::try_from(exif).map_err(|_| MetadataError::InvalidExif)?,
+        None => TIFFContainer::new(ByteOrder::LittleEndian, vec![TIFFIFD::new(Vec::new())]),
+    };
+
+    if let Some(artist) = &rights.artist {
+        container.put_entry(TIFFEntry::new_ascii(ARTIST_TAG, artist));
+    }
+
+    if let Some(copyright) = &rights.copyright {
+        container.put_entry(TIFFEntry::new_ascii(COPYRIGHT_TAG, copyright));
+    }
+
+    Ok(container.to_bytes())
+}
+
+fn inflate(inp: &[u8]) -> Option<Vec<u8>> {
+    let mut result = Vec::new();
+    let mut decoder = ZlibDecoder::new(inp).take(MAX_PNG_INFLATED_SIZE + 1);
+    decoder.read_to_end(&mut result).ok()?;
+
+    match result.len() as u64 > MAX_PNG_INFLATED_SIZE {
+        true => None,
+        false => Some(result),
+    }
+}
+
+fn deflate(inp: &[u8]) -> Result<Vec<u8>, MetadataError> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(inp)
+        .map_err(|_| MetadataError::InvalidPngFile)?;
+
+    encoder.finish().map_err(|_| MetadataError::InvalidPngFile)
+}
+
+fn is_png_xmp(chunk: &PNGChunk) -> bool {
+    chunk.chunk_type == "iTXt" && chunk.chunk_data.starts_with(PNG_XMP_KEYWORD)
+}
+
+// The keyword is followed by the compression flag and method, then the language tag and the
+// translated keyword, both null terminated
+fn read_png_xmp(chunk: &PNGChunk) -> Option<Vec<u8>> {
+    let data = chunk.chunk_data.get(PNG_XMP_KEYWORD.len()..)?;
+    let compressed = *data.first()? != 0;
+    let text = data.get(2..)?.splitn(3, |f| *f == 0).nth(2)?;
+
+    match compressed {
+        true => inflate(text),
+        false => Some(Vec::from(text)),
+    }
+}
+
+// Profile name, compression method and the zlib stream
+fn read_png_icc(chunk: &PNGChunk) -> Option<Vec<u8>> {
+    let name_end = chunk.chunk_data.iter().position(|f| *f == 0)?;
+
+    inflate(chunk.chunk_data.get(name_end + 2..)?)
+}
+
+fn is_png_metadata_chunk(chunk: &PNGChunk) -> bool {
+    chunk.chunk_type == "eXIf" || chunk.chunk_type == "iCCP" || is_png_xmp(chunk)
+}
+
+fn is_metadata_segment(segment: &JFIFSegment) -> bool {
+    ExifSegment::try_from(segment).is_ok()
+        || XmpSegment::try_from(segment).is_ok()
+        || XmpExtensionChunk::try_from(segment).is_ok()
+        || IccChunk::try_from(segment).is_ok()
+        || is_mpf_segment(segment)
+}
+
+impl ImageMetadata {
+    pub fn is_empty(&self) -> bool {
+        self.exif.is_none()
+            && self.icc.is_none()
+            && self.xmp.is_none()
+            && self.mpf_images.is_empty()
+            && self.trailer.is_none()
+    }
+
+    pub fn with_rights(&self, rights: &RightsInfo) -> Result<ImageMetadata, MetadataError> {
+        let mut result = self.clone();
+
+        if rights.artist.is_some() || rights.copyright.is_some() {
+            result.exif = Some(rights_exif(self.exif.as_ref(), rights)?);
+        }
+
+        if *rights != RightsInfo::default() {
+            result.xmp = Some(rights_xmp(self.xmp.as_ref(), rights));
+        }
+
+        Ok(result)
+    }
+
+    pub fn from_jpeg(container: &JFIFContainer) -> Self {
+        let icc_chunks: Vec<IccChunk> = container.get_app_segments();
+
+        // A profile with a missing chunk is unusable, so it is dropped
+        let icc = IccProfile::from_chunks(icc_chunks).ok().map(|f| f.data);
+        let (mpf_images, trailer) = container.get_mpf_parts();
+        let xmp = container
+            .get_app_segments::<XmpSegment>()
+            .into_iter()
+            .next()
+            .map(|f| Self::with_extended_xmp(container, f.packet));
+
+        ImageMetadata {
+            exif: container
+                .get_app_segments::<ExifSegment>()
+                .into_iter()
+                .next()
+                .map(|f| f.data),
+            icc,
+            xmp,
+            mpf_images,
+            trailer,
+        }
+    }
+
+    // An extension that is incomplete or does not match its GUID is ignored
+    fn with_extended_xmp(container: &JFIFContainer, packet: Vec<u8>) -> Vec<u8> {
+        let extended = std::str::from_utf8(&packet)
+            .ok()
+            .and_then(get_extended_xmp_guid)
+            .and_then(|f| ExtendedXmp::from_chunks(container.get_app_segments(), &f).ok());
+
+        extended
+            .and_then(|f| merge_extended_xmp(&packet, &f.packet))
+            .unwrap_or(packet)
+    }
+
+    pub fn from_webp(container: &RIFFContainer) -> Self {
+        let find = |chunk_id: &str| {
+            container
+                .find_subchunk(chunk_id)
+                .map(|f| f.get_chunk_bytes())
+        };
+
+        ImageMetadata {
+            exif: find("EXIF"),
+            icc: find("ICCP"),
+            xmp: find("XMP "),
+            ..Default::default()
+        }
+    }
+
+    pub fn from_png(container: &PNGContainer) -> Self {
+        let chunks = container.get_chunks();
+
+        ImageMetadata {
+            exif: container.find_chunk("eXIf").map(|f| f.chunk_data.clone()),
+            icc: container.find_chunk("iCCP").and_then(read_png_icc),
+            xmp: chunks.iter().find(|p| is_png_xmp(p)).and_then(read_png_xmp),
+            ..Default::default()
+        }
+    }
+
+    pub fn to_jpeg_segments(&self) -> Result<Vec<JFIFSegment>, MetadataError> {
+        let mut result = Vec::new();
+
+        if let Some(exif) = &self.exif {
+            let segment = ExifSegment { data: exif.clone() };
+
+            result.push(
+                JFIFSegment::try_from(&segment)
+                    .map_err(|_| MetadataError::MetadataTooLarge("EXIF"))?,
+            );
+        }
+
+        if let Some(xmp) = &self.xmp {
+            let segment = XmpSegment {
+                packet: xmp.clone(),
+            };
+
+            match JFIFSegment::try_from(&segment) {
+                Ok(segment) => result.push(segment),
+                Err(_) => result.extend(extended_xmp_segments(xmp)?),
+            }
+        }
+
+        if let Some(icc) = &self.icc {
+            let profile = IccProfile { data: icc.clone() };
+
+            result.extend(
+                profile
+                    .to_segments()
+                    .map_err(|_| MetadataError::MetadataTooLarge("ICC"))?,
+            );
+        }
+
+        Ok(result)
+    }
+
+    // Metadata goes right after SOI and the JFIF APP0, replacing whatever the encoder wrote,
+    // MPF images and other trailing data go after EOI
+    pub fn apply_jpeg(&self, container: &mut JFIFContainer) -> Result<(), MetadataError> {
+        let new_segments = self.to_jpeg_segments()?;
+        let segments = container.get_segments_mut();
+        segments.retain(|p| !is_metadata_segment(p));
+
+        let position = segments
+            .iter()
+            .position(|p| !matches!(p, JFIFSegment::SOI | JFIFSegment::APP(0, _)))
+            .unwrap_or(segments.len());
+        segments.splice(position..position, new_segments);
+
+        // Secondary images and trailers only exist behind a JPEG primary image
+        container
+            .set_mpf_images(&self.mpf_images, self.trailer.clone())
+            .map_err(|_| MetadataError::InvalidMpf)
+    }
+
+    // ICCP must directly follow VP8X, EXIF and XMP go after the image data
+    pub fn apply_webp(&self, container: &mut RIFFContainer) -> Result<(), MetadataError> {
+        for chunk_id in ["ICCP", "EXIF", "XMP "] {
+            while container.remove_subchunk(chunk_id).is_some() {}
+        }
+
+        if self.is_empty() {
+            return Ok(());
+        }
+
+        container
+            .ensure_extended()
+            .map_err(|_| MetadataError::InvalidWebpFile)?;
+
+        let chunk = |chunk_id: &str, data: &Vec<u8>| {
+            Box::new(RegularChunk {
+                chunk_id: String::from(chunk_id),
+                chunk_data: data.clone(),
+            })
+        };
+
+        if let Some(icc) = &self.icc {
+            container.insert_subchunk(1, chunk("ICCP", icc));
+        }
+
+        if let Some(exif) = &self.exif {
+            container.push_subchunk(chunk("EXIF", exif));
+        }
+
+        if let Some(xmp) = &self.xmp {
+            container.push_subchunk(chunk("XMP ", xmp));
+        }
+
+        container
+            .ensure_extended()
+            .map_err(|_| MetadataError::InvalidWebpFile)
+    }
+
+    // iCCP and eXIf must come before the image data, so every chunk goes right after IHDR.
+    // An sRGB chunk cannot be kept next to an embedded profile
+    pub fn apply_png(&self, container: &mut PNGContainer) -> Result<(), MetadataError> {
+        let mut new_chunks = Vec::new();
+
+        if let Some(icc) = &self.icc {
+            let mut data = Vec::from(&PNG_ICC_NAME[..]);
+            data.push(0);
+            data.extend(deflate(icc)?);
+
+            new_chunks.push(PNGChunk::new("iCCP", data));
+        }
+
+        if let Some(exif) = &self.exif {
+            new_chunks.push(PNGChunk::new("eXIf", exif.clone()));
+        }
+
+        if let Some(xmp) = &self.xmp {
+            let mut data = Vec::from(&PNG_XMP_KEYWORD[..]);
+            data.extend([0, 0, 0, 0]);
+            data.extend(xmp);
+
+            new_chunks.push(PNGChunk::new("iTXt", data));
+        }
+
+        let chunks = container.get_chunks_mut();
+        chunks.retain(|p| !is_png_metadata_chunk(p));
+
+        if self.icc.is_some() {
+            chunks.retain(|p| p.chunk_type != "sRGB");
+        }
+
+        let position = chunks
+            .iter()
+            .position(|p| p.chunk_type == "IHDR")
+            .map_or(0, |f| f + 1);
+        chunks.splice(position..position, new_chunks);
+
+        Ok(())
+    }
+}
+
+pub fn read_metadata(inp: &[u8]) -> ImageMetadata {
+    let inp_vec = Vec::from(inp);
+
+    match detect_format(inp) {
+        Ok(FileFormat::Jpeg) => JFIFContainer::try_from(&inp_vec)
+            .map(|f| ImageMetadata::from_jpeg(&f))
+            .unwrap_or_default(),
+        Ok(FileFormat::WebP) => RIFFContainer::try_from(&inp_vec)
+            .map(|f| ImageMetadata::from_webp(&f))
+            .unwrap_or_default(),
+        Ok(FileFormat::Png) => PNGContainer::try_from(&inp_vec)
+            .map(|f| ImageMetadata::from_png(&f))
+            .unwrap_or_default(),
+        _ => ImageMetadata::default(),
+    }
+}
+
+// Empty metadata leaves the input untouched, formats without a metadata mapping fail rather
+// than dropping it
+pub fn write_metadata(inp: &[u8], metadata: &ImageMetadata) -> Result<Vec<u8>, MetadataError> {
+    let inp_vec = Vec::from(inp);
+
+    if metadata.is_empty() {
+        return Ok(inp_vec);
+    }
+
+    match detect_format(inp) {
+        Ok(FileFormat::Jpeg) => {
+            let mut container =
+                JFIFContainer::try_from(&inp_vec).map_err(|_| MetadataError::InvalidJpegFile)?;
+            metadata.apply_jpeg(&mut container)?;
+
+            Vec::try_from(container).map_err(|_| MetadataError::InvalidJpegFile)
+        }
+        Ok(FileFormat::WebP) => {
+            let mut container =
+                RIFFContainer::try_from(&inp_vec).map_err(|_| MetadataError::InvalidWebpFile)?;
+            metadata.apply_webp(&mut container)?;
+
+            Ok(container.to_bytes())
+        }
+        Ok(FileFormat::Png) => {
+            let mut container =
+                PNGContainer::try_from(&inp_vec).map_err(|_| MetadataError::InvalidPngFile)?;
+            metadata.apply_png(&mut container)?;
+
+            Ok(container.to_bytes())
+        }
+        _ => Err(MetadataError::UnsupportedFormat),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::webp::WebPEncoder;
+    use image::{load_from_memory_with_format, ColorType, DynamicImage, ImageFormat, RgbImage};
+    use std::io::Cursor;
+
+    fn sample_metadata() -> ImageMetadata {
+        ImageMetadata {
+            exif: Some(vec![0x49, 0x49, 0x2A, 0x00, 0x08, 0x00, 0x00, 0x00]),
+            icc: Some((0..70000).map(|f| (f % 251) as u8).collect()),
+            xmp: Some(Vec::from(&b"<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"/>"[..])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn jpeg_metadata_round_trip() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(8, 8));
+        let mut bytes: Vec<u8> = Vec::new();
+        img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg)
+            .unwrap();
+
+        let metadata = sample_metadata();
+        let output = write_metadata(&bytes, &metadata).unwrap();
+        let container = JFIFContainer::try_from(&output).unwrap();
+        let icc_segments = container
+            .get_segments()
+            .iter()
+            .filter(|p| matches!(p, JFIFSegment::APP(2, _)))
+            .count();
+
+        assert_eq!(read_metadata(&output), metadata);
+        assert_eq!(icc_segments, 2);
+        assert!(matches!(
+            container.get_segments()[1],
+            JFIFSegment::APP(0, _)
+        ));
+        assert!(load_from_memory_with_format(&output, ImageFormat::Jpeg).is_ok());
+
+        let output = write_metadata(&output, &metadata).unwrap();
+
+        assert_eq!(read_metadata(&output), metadata);
+    }
+
+    #[test]
+    fn jpeg_large_xmp_uses_extension() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(8, 8));
+        let mut bytes: Vec<u8> = Vec::new();
+        img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg)
+            .unwrap();
+
+        let description = format!(
+            "<rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+            <dc:description>{}</dc:description>\n</rdf:Description>\n",
+            "a".repeat(150000)
+        );
+        let metadata = ImageMetadata {
+            xmp: Some((XMP_PACKET_START.to_owned() + &description + XMP_PACKET_END).into_bytes()),
+            ..Default::default()
+        };
+        let output = write_metadata(&bytes, &metadata).unwrap();
+        let container = JFIFContainer::try_from(&output).unwrap();
+        let standard: Vec<XmpSegment> = container.get_app_segments();
+        let chunks: Vec<XmpExtensionChunk> = container.get_app_segments();
+
+        assert_eq!(standard.len(), 1);
+        assert!(standard[0].packet.len() < 1000);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(read_metadata(&output), metadata);
+        assert!(load_from_memory_with_format(&output, ImageFormat::Jpeg).is_ok());
+
+        let output = write_metadata(&output, &read_metadata(&output)).unwrap();
+        let container = JFIFContainer::try_from(&output).unwrap();
+
+        assert_eq!(container.get_app_segments::<XmpExtensionChunk>().len(), 3);
+        assert_eq!(read_metadata(&output), metadata);
+    }
+
+    #[test]
+    fn jpeg_incomplete_icc_is_dropped() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(8, 8));
+        let mut bytes: Vec<u8> = Vec::new();
+        img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg)
+            .unwrap();
+
+        let output = write_metadata(&bytes, &sample_metadata()).unwrap();
+        let mut container = JFIFContainer::try_from(&output).unwrap();
+        let icc_position = container
+            .get_segments()
+            .iter()
+            .position(|p| matches!(p, JFIFSegment::APP(2, _)))
+            .unwrap();
+
+        container.get_segments_mut().remove(icc_position);
+
+        let metadata = ImageMetadata::from_jpeg(&container);
+
+        assert_eq!(metadata.icc, None);
+        assert_eq!(metadata.exif, sample_metadata().exif);
+    }
+
+    #[test]
+    fn webp_metadata_round_trip() {
+        let mut bytes: Vec<u8> = Vec::new();
+        WebPEncoder::new_lossless(&mut bytes)
+            .encode(&[0x80; 4 * 4 * 3], 4, 4, ColorType::Rgb8)
+            .unwrap();
+
+        let metadata = sample_metadata();
+        let output = write_metadata(&bytes, &metadata).unwrap();
+        let container = RIFFContainer::try_from(&output).unwrap();
+        let chunk_ids: Vec<&str> = container
+            .get_chunk_data()
+            .unwrap()
+            .iter()
+            .map(|f| f.get_chunk_id())
+            .collect();
+        let vp8x = container.get_vp8x().unwrap();
+
+        assert_eq!(chunk_ids, ["VP8X", "ICCP", "VP8L", "EXIF", "XMP "]);
+        assert_eq!(vp8x.flags, 0x20 | 0x08 | 0x04);
+        assert_eq!(read_metadata(&output), metadata);
+        assert!(load_from_memory_with_format(&output, ImageFormat::WebP).is_ok());
+    }
+
+    #[test]
+    fn png_metadata_round_trip() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(2, 2));
+        let mut bytes: Vec<u8> = Vec::new();
+        img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+
+        let metadata = sample_metadata();
+        let output = write_metadata(&bytes, &metadata).unwrap();
+        let container = PNGContainer::try_from(&output).unwrap();
+        let chunk_types: Vec<&str> = container
+            .get_chunks()
+            .iter()
+            .map(|f| f.chunk_type.as_str())
+            .collect();
+
+        assert_eq!(chunk_types[..4], ["IHDR", "iCCP", "eXIf", "iTXt"]);
+        assert_eq!(read_metadata(&output), metadata);
+        assert!(load_from_memory_with_format(&output, ImageFormat::Png).is_ok());
+        assert_eq!(write_metadata(&output, &metadata).unwrap(), output);
+    }
+
+    #[test]
+    fn write_metadata_unsupported_format() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(2, 2));
+        let mut bytes: Vec<u8> = Vec::new();
+        img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Tiff)
+            .unwrap();
+
+        assert_eq!(read_metadata(&bytes), ImageMetadata::default());
+        assert_eq!(
+            write_metadata(&bytes, &ImageMetadata::default()).unwrap(),
+            bytes
+        );
+        assert_eq!(
+            write_metadata(&bytes, &sample_metadata()),
+            Err(MetadataError::UnsupportedFormat)
+        );
+    }
+
+    #[test]
+    fn rights_written_to_exif_and_xmp() {
+        let rights = RightsInfo {
+            copyright: Some(String::from("(c) 2024 Example <Studio>")),
+            artist: Some(String::from("Jane Doe")),
+            web_statement: Some(String::from("https://example.com/license")),
+        };
+        let mut bytes: Vec<u8> = Vec::new();
+        WebPEncoder::new_lossless(&mut bytes)
+            .encode(&[0x80; 4 * 4 * 3], 4, 4, ColorType::Rgb8)
+            .unwrap();
+
+        let metadata = ImageMetadata::default().with_rights(&rights).unwrap();
+        let output = write_metadata(&bytes, &metadata).unwrap();
+        let output_metadata = read_metadata(&output);
+        let exif = TIFFContainer::try_from(&output_metadata.exif.unwrap()).unwrap();
+        let xmp = String::from_utf8(output_metadata.xmp.unwrap()).unwrap();
+        let vp8x_flags = RIFFContainer::try_from(&output)
+            .unwrap()
+            .get_vp8x()
+            .unwrap()
+            .flags;
+
+        assert_eq!(
+            exif.find_entry(COPYRIGHT_TAG).unwrap().value,
+            b"(c) 2024 Example <Studio>\0"
+        );
+        assert_eq!(exif.find_entry(ARTIST_TAG).unwrap().value, b"Jane Doe\0");
+        assert!(
+            xmp.contains("<rdf:li xml:lang=\"x-default\">(c) 2024 Example &lt;Studio&gt;</rdf:li>")
+        );
+        assert!(xmp.contains(
+            "<xmpRights:WebStatement>https://example.com/license</xmpRights:WebStatement>"
+        ));
+        assert_eq!(vp8x_flags, 0x08 | 0x04);
+    }
+
+    #[test]
+    fn rights_merged_into_existing_metadata() {
+        let rights = RightsInfo {
+            copyright: Some(String::from("Example")),
+            ..Default::default()
+        };
+        let mut exif = TIFFContainer::new(ByteOrder::BigEndian, vec![TIFFIFD::new(Vec::new())]);
+        exif.put_entry(TIFFEntry::new_ascii(ARTIST_TAG, "Someone"));
+
+        let metadata = ImageMetadata {
+            exif: Some(exif.to_bytes()),
+            icc: None,
+            xmp: Some(Vec::from(
+                XMP_PACKET_START.to_owned() + "<rdf:Description/>\n" + XMP_PACKET_END,
+            )),
+            ..Default::default()
+        };
+        let output = metadata.with_rights(&rights).unwrap();
+        let exif = TIFFContainer::try_from(&output.exif.unwrap()).unwrap();
+        let xmp = String::from_utf8(output.xmp.unwrap()).unwrap();
+
+        assert_eq!(exif.get_byte_order(), ByteOrder::BigEndian);
+        assert_eq!(exif.find_entry(ARTIST_TAG).unwrap().value, b"Someone\0");
+        assert_eq!(exif.find_entry(COPYRIGHT_TAG).unwrap().value, b"Example\0");
+        assert!(xmp.contains("<rdf:Description/>\n<rdf:Description rdf:about"));
+        assert!(xmp.ends_with(XMP_PACKET_END));
+        assert_eq!(
+            ImageMetadata {
+                exif: Some(vec![0x00]),
+                ..Default::default()
+            }
+            .with_rights(&rights),
+            Err(MetadataError::InvalidExif)
+        );
+    }
+
+    #[test]
+    fn rights_keep_maker_note() {
+        use crate::tiff_container::{EXIF_IFD_TAG, MAKER_NOTE_TAG};
+
+        let byte_order = ByteOrder::LittleEndian;
+        let maker_note: Vec<u8> = (0..32).collect();
+        let mut exif_ifd = TIFFIFD::new(vec![TIFFEntry::new_undefined(
+            MAKER_NOTE_TAG,
+            maker_note.clone(),
+        )]);
+        exif_ifd.fixed_values.push((MAKER_NOTE_TAG, 8));
+
+        let mut ifd0 = TIFFIFD::new(vec![TIFFEntry::new_long(EXIF_IFD_TAG, &[0], byte_order)]);
+        ifd0.sub_ifds.push((EXIF_IFD_TAG, vec![exif_ifd]));
+
+        let metadata = ImageMetadata {
+            exif: Some(TIFFContainer::new(byte_order, vec![ifd0]).to_bytes()),
+            ..Default::default()
+        };
+        let rights = RightsInfo {
+            artist: Some(String::from("Someone")),
+            copyright: Some(String::from("Example")),
+            ..Default::default()
+        };
+        let output = metadata.with_rights(&rights).unwrap().exif.unwrap();
+        let exif = TIFFContainer::try_from(&output).unwrap();
+        let exif_ifd = &exif.get_ifds()[0].sub_ifds[0].1[0];
+
+        assert_eq!(exif.find_entry(COPYRIGHT_TAG).unwrap().value, b"Example\0");
+        assert_eq!(
+            exif_ifd.find_entry(MAKER_NOTE_TAG).unwrap().value,
+            maker_note
+        );
+        assert_eq!(exif_ifd.fixed_values, [(MAKER_NOTE_TAG, 8)]);
+        assert_eq!(output[8..40], maker_note);
+    }
+
+    #[test]
+    fn rights_applied_twice_are_replaced() {
+        let first = RightsInfo {
+            copyright: Some(String::from("First")),
+            artist: Some(String::from("First artist")),
+            web_statement: Some(String::from("https://example.com/first")),
+        };
+        let second = RightsInfo {
+            copyright: Some(String::from("Second")),
+            artist: Some(String::from("Second artist")),
+            ..Default::default()
+        };
+        let metadata = ImageMetadata {
+            xmp: Some(Vec::from(
+                XMP_PACKET_START.to_owned()
+                    + "<rdf:Description rdf:about=\"\" xmpRights:Marked=\"False\" \
+                    xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+                    <dc:rights><rdf:Alt><rdf:li xml:lang=\"x-default\">Original</rdf:li>\
+                    </rdf:Alt></dc:rights>\n<dc:rightsHolder>Holder</dc:rightsHolder>\n\
+                    </rdf:Description>\n"
+                    + XMP_PACKET_END,
+            )),
+            ..Default::default()
+        };
+        let output = metadata
+            .with_rights(&first)
+            .unwrap()
+            .with_rights(&second)
+            .unwrap();
+        let xmp = String::from_utf8(output.xmp.unwrap()).unwrap();
+
+        assert_eq!(xmp.matches("<dc:rights>").count(), 1);
+        assert_eq!(xmp.matches("<dc:creator>").count(), 1);
+        assert_eq!(xmp.matches("<xmpRights:Marked>").count(), 1);
+        assert!(!xmp.contains("xmpRights:Marked="));
+        assert_eq!(xmp.matches(RIGHTS_DESCRIPTION_START).count(), 2);
+        assert!(xmp.contains(">Second</rdf:li>"));
+        assert!(xmp.contains("<rdf:li>Second artist</rdf:li>"));
+        assert!(xmp.contains("https://example.com/first"));
+        assert!(xmp.contains("<dc:rightsHolder>Holder</dc:rightsHolder>"));
+        assert!(!xmp.contains("Original") && !xmp.contains("First"));
+        assert!(xmp.ends_with(XMP_PACKET_END));
+    }
+}
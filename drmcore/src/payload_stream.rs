@@ -0,0 +1,241 @@
+use crate::jpeg::custom_segment::CUSTOM_SEGMENT_NAME;
+use crate::webp_container::{RegularChunk, CUSTOM_CHUNK_ID};
+use thiserror::Error;
+
+pub const DEFAULT_STREAM_ID: u8 = 0;
+pub const MAX_STREAM_ID: u8 = 0x1F;
+
+const SEGMENT_NAME_MAX_SIZE: usize = 16;
+
+// Other streams use the uppercase chunk id, which must not collide with a chunk a decoder reads
+const RESERVED_CHUNK_IDS: [&str; 9] = [
+    "RIFF", "WEBP", "VP8L", "VP8X", "ALPH", "ANIM", "ANMF", "ICCP", "EXIF",
+];
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PayloadStreamError {
+    #[error("Invalid segment name `{0}`")]
+    InvalidSegmentName(String),
+
+    #[error("Invalid chunk id `{0}`")]
+    InvalidChunkId(String),
+
+    #[error("Invalid stream id `{0}`")]
+    InvalidStreamId(u8),
+}
+
+// Identifies the payloads of one application. A jpeg segment starts with the segment name
+// followed by the stream id, a NUL for the default stream. Names are alphanumeric and stream
+// ids are control characters, so the names of two namespaces cannot be mistaken for each other
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct PayloadNamespace {
+    segment_name: String,
+    chunk_id: String,
+}
+
+// The default stream of a webp uses the chunk id of the namespace, other streams use the
+// uppercase chunk id and start their data with the stream id
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct PayloadStream {
+    namespace: PayloadNamespace,
+    id: u8,
+}
+
+impl PayloadNamespace {
+    pub fn new(segment_name: &str, chunk_id: &str) -> Result<Self, PayloadStreamError> {
+        if segment_name.is_empty()
+            || segment_name.len() > SEGMENT_NAME_MAX_SIZE
+            || !segment_name.bytes().all(|f| f.is_ascii_alphanumeric())
+        {
+            return Err(PayloadStreamError::InvalidSegmentName(String::from(
+                segment_name,
+            )));
+        }
+
+        if chunk_id.len() != 4
+            || !chunk_id.bytes().all(|f| f.is_ascii_lowercase())
+            || RESERVED_CHUNK_IDS.contains(&chunk_id.to_ascii_uppercase().as_str())
+        {
+            return Err(PayloadStreamError::InvalidChunkId(String::from(chunk_id)));
+        }
+
+        Ok(PayloadNamespace {
+            segment_name: String::from(segment_name),
+            chunk_id: String::from(chunk_id),
+        })
+    }
+
+    pub fn get_segment_name(&self) -> &str {
+        &self.segment_name
+    }
+
+    pub fn get_chunk_id(&self) -> &str {
+        &self.chunk_id
+    }
+
+    pub fn stream(&self, id: u8) -> Result<PayloadStream, PayloadStreamError> {
+        if id > MAX_STREAM_ID {
+            return Err(PayloadStreamError::InvalidStreamId(id));
+        }
+
+        Ok(PayloadStream {
+            namespace: self.clone(),
+            id,
+        })
+    }
+
+    // Returns the stream id of a jpeg segment identifier belonging to this namespace
+    pub fn read_segment_identifier(&self, data: &[u8]) -> Option<u8> {
+        let name_len = self.segment_name.len();
+
+        match data.get(..name_len + 1) {
+            Some(identifier)
+                if &identifier[..name_len] == self.segment_name.as_bytes()
+                    && identifier[name_len] <= MAX_STREAM_ID =>
+            {
+                Some(identifier[name_len])
+            }
+            _ => None,
+        }
+    }
+
+    // Returns the stream id and payload of a webp chunk belonging to this namespace
+    pub fn read_chunk<'a>(&self, chunk_id: &str, data: &'a [u8]) -> Option<(u8, &'a [u8])> {
+        if chunk_id == self.chunk_id {
+            return Some((DEFAULT_STREAM_ID, data));
+        }
+
+        match data.split_first() {
+            Some((id, payload))
+                if chunk_id == self.chunk_id.to_ascii_uppercase()
+                    && *id != DEFAULT_STREAM_ID
+                    && *id <= MAX_STREAM_ID =>
+            {
+                Some((*id, payload))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for PayloadNamespace {
+    fn default() -> Self {
+        PayloadNamespace {
+            segment_name: String::from(CUSTOM_SEGMENT_NAME),
+            chunk_id: String::from(CUSTOM_CHUNK_ID),
+        }
+    }
+}
+
+impl PayloadStream {
+    pub fn get_namespace(&self) -> &PayloadNamespace {
+        &self.namespace
+    }
+
+    pub fn get_id(&self) -> u8 {
+        self.id
+    }
+
+    pub fn get_segment_identifier(&self) -> Vec<u8> {
+        let mut result = Vec::from(self.namespace.segment_name.as_bytes());
+        result.push(self.id);
+
+        result
+    }
+
+    pub fn get_chunk_id(&self) -> String {
+        match self.id {
+            DEFAULT_STREAM_ID => self.namespace.chunk_id.clone(),
+            _ => self.namespace.chunk_id.to_ascii_uppercase(),
+        }
+    }
+
+    pub fn to_chunk(&self, data: Vec<u8>) -> RegularChunk {
+        let chunk_data = match self.id {
+            DEFAULT_STREAM_ID => data,
+            _ => {
+                let mut result = vec![self.id];
+                result.extend(data);
+                result
+            }
+        };
+
+        RegularChunk {
+            chunk_id: self.get_chunk_id(),
+            chunk_data,
+        }
+    }
+
+    pub fn read_chunk<'a>(&self, chunk_id: &str, data: &'a [u8]) -> Option<&'a [u8]> {
+        match self.namespace.read_chunk(chunk_id, data) {
+            Some((id, payload)) if id == self.id => Some(payload),
+            _ => None,
+        }
+    }
+}
+
+impl Default for PayloadStream {
+    fn default() -> Self {
+        PayloadStream {
+            namespace: PayloadNamespace::default(),
+            id: DEFAULT_STREAM_ID,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn namespace_validation() {
+        assert!(PayloadNamespace::new("LICENSE1", "licn").is_ok());
+        assert_eq!(
+            PayloadNamespace::new("MI\0F", "milf"),
+            Err(PayloadStreamError::InvalidSegmentName(String::from(
+                "MI\0F"
+            )))
+        );
+        assert_eq!(
+            PayloadNamespace::new("MILF", "MILF"),
+            Err(PayloadStreamError::InvalidChunkId(String::from("MILF")))
+        );
+        assert_eq!(
+            PayloadNamespace::new("MILF", "iccp"),
+            Err(PayloadStreamError::InvalidChunkId(String::from("iccp")))
+        );
+        assert_eq!(
+            PayloadNamespace::default().stream(0x20),
+            Err(PayloadStreamError::InvalidStreamId(0x20))
+        );
+    }
+
+    #[test]
+    fn stream_identifiers() {
+        let namespace = PayloadNamespace::default();
+        let license = namespace.stream(2).unwrap();
+
+        assert_eq!(PayloadStream::default().get_segment_identifier(), b"MILF\0");
+        assert_eq!(license.get_segment_identifier(), b"MILF\x02");
+        assert_eq!(namespace.read_segment_identifier(b"MILF\x02\x00"), Some(2));
+        assert_eq!(namespace.read_segment_identifier(b"MILFDATA"), None);
+        assert_eq!(namespace.read_segment_identifier(b"MIL"), None);
+
+        let chunk = license.to_chunk(vec![0xAA, 0xBB]);
+
+        assert_eq!(chunk.chunk_id, "MILF");
+        assert_eq!(chunk.chunk_data, [0x02, 0xAA, 0xBB]);
+        assert_eq!(
+            license.read_chunk(&chunk.chunk_id, &chunk.chunk_data),
+            Some(&[0xAA, 0xBB][..])
+        );
+        assert_eq!(
+            PayloadStream::default().read_chunk(&chunk.chunk_id, &chunk.chunk_data),
+            None
+        );
+        assert_eq!(
+            PayloadStream::default().read_chunk("milf", &[0x02]),
+            Some(&[0x02][..])
+        );
+    }
+}
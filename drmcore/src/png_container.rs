@@ -0,0 +1,371 @@
+use std::convert::TryFrom;
+use std::str;
+use thiserror::Error;
+
+use crate::file_joiner::{be_to_usize, usize_to_be};
+
+pub const PNG_SIGNATURE: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
+// Ancillary, private, reserved bit clear and safe-to-copy
+pub const CUSTOM_CHUNK_TYPE: &str = "drMf";
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PngContainerError {
+    #[error("Invalid png signature")]
+    InvalidSignature,
+
+    #[error("Invalid chunk")]
+    InvalidChunk,
+
+    #[error("CRC mismatch on chunk `{0}`")]
+    CrcMismatch(String),
+
+    #[error("Missing IEND chunk")]
+    MissingIEND,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct PNGChunk {
+    pub chunk_type: String,
+    pub chunk_data: Vec<u8>,
+}
+
+pub struct PNGContainer {
+    chunks: Vec<PNGChunk>,
+}
+
+// Built at compile time, iterators are not usable in a const fn
+const CRC32_TABLE: [u32; 256] = crc32_table();
+
+const fn crc32_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+
+        while k < 8 {
+            if c & 1 == 1 {
+                c = 0xEDB88320 ^ (c >> 1);
+            } else {
+                c >>= 1;
+            }
+
+            k += 1;
+        }
+
+        table[n] = c;
+        n += 1;
+    }
+
+    table
+}
+
+pub fn crc32(inp: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFFu32;
+
+    for byte in inp {
+        crc = CRC32_TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+
+    crc ^ 0xFFFFFFFF
+}
+
+impl PNGChunk {
+    pub fn new(chunk_type: &str, chunk_data: Vec<u8>) -> Self {
+        Self {
+            chunk_type: String::from(chunk_type),
+            chunk_data,
+        }
+    }
+
+    pub fn get_crc(&self) -> u32 {
+        let mut crc_input = Vec::from(self.chunk_type.as_bytes());
+        crc_input.extend(&self.chunk_data);
+
+        crc32(&crc_input)
+    }
+
+    fn get_property_bit(&self, index: usize) -> bool {
+        match self.chunk_type.as_bytes().get(index) {
+            Some(byte) => byte & 0x20 != 0,
+            None => false,
+        }
+    }
+
+    pub fn is_ancillary(&self) -> bool {
+        self.get_property_bit(0)
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.get_property_bit(1)
+    }
+
+    pub fn is_safe_to_copy(&self) -> bool {
+        self.get_property_bit(3)
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        let chunk_size = usize_to_be(self.chunk_data.len());
+        let chunk_crc = self.get_crc().to_be_bytes();
+
+        result.extend(chunk_size);
+        result.extend(self.chunk_type.as_bytes());
+        result.extend(&self.chunk_data);
+        result.extend(chunk_crc);
+
+        result
+    }
+}
+
+impl PNGContainer {
+    pub fn new(chunks: Vec<PNGChunk>) -> Self {
+        Self { chunks }
+    }
+
+    pub fn get_chunks(&self) -> &Vec<PNGChunk> {
+        &self.chunks
+    }
+
+    pub fn get_chunks_mut(&mut self) -> &mut Vec<PNGChunk> {
+        &mut self.chunks
+    }
+
+    pub fn find_chunk(&self, chunk_type: &str) -> Option<&PNGChunk> {
+        self.chunks.iter().find(|p| p.chunk_type == chunk_type)
+    }
+
+    pub fn push_chunk(&mut self, chunk: PNGChunk) {
+        let iend_pos = self.chunks.iter().rposition(|p| p.chunk_type == "IEND");
+
+        match iend_pos {
+            Some(iend_pos) => self.chunks.insert(iend_pos, chunk),
+            None => self.chunks.push(chunk),
+        }
+    }
+
+    // Replaces every chunk of the same type, the first one keeps its position
+    pub fn set_chunk(&mut self, chunk: PNGChunk) {
+        let position = self
+            .chunks
+            .iter()
+            .position(|p| p.chunk_type == chunk.chunk_type);
+
+        self.chunks.retain(|p| p.chunk_type != chunk.chunk_type);
+
+        match position {
+            Some(position) => self.chunks.insert(position, chunk),
+            None => self.push_chunk(chunk),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::from(PNG_SIGNATURE);
+
+        self.chunks.iter().for_each(|f| {
+            result.extend(f.to_bytes());
+        });
+
+        result
+    }
+}
+
+impl TryFrom<&[u8]> for PNGChunk {
+    type Error = PngContainerError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let size_bytes = value.get(0..4).ok_or(PngContainerError::InvalidChunk)?;
+        let chunk_size = be_to_usize(size_bytes);
+        let type_bytes = value.get(4..8).ok_or(PngContainerError::InvalidChunk)?;
+        let chunk_type = str::from_utf8(type_bytes).map_err(|_| PngContainerError::InvalidChunk)?;
+        let data_end = chunk_size
+            .checked_add(8)
+            .ok_or(PngContainerError::InvalidChunk)?;
+        let crc_end = data_end
+            .checked_add(4)
+            .ok_or(PngContainerError::InvalidChunk)?;
+        let chunk_data = value
+            .get(8..data_end)
+            .ok_or(PngContainerError::InvalidChunk)?;
+        let crc_bytes = value
+            .get(data_end..crc_end)
+            .ok_or(PngContainerError::InvalidChunk)?;
+        let chunk = PNGChunk::new(chunk_type, Vec::from(chunk_data));
+
+        if be_to_usize(crc_bytes) as u32 != chunk.get_crc() {
+            return Err(PngContainerError::CrcMismatch(String::from(chunk_type)));
+        }
+
+        Ok(chunk)
+    }
+}
+
+impl TryFrom<&Vec<u8>> for PNGContainer {
+    type Error = PngContainerError;
+
+    fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
+        let signature = value.get(0..8).ok_or(PngContainerError::InvalidSignature)?;
+
+        if signature != PNG_SIGNATURE {
+            return Err(PngContainerError::InvalidSignature);
+        }
+
+        let mut chunks = Vec::new();
+        let mut counter = 8usize;
+
+        loop {
+            let chunk_slice = value
+                .get(counter..)
+                .ok_or(PngContainerError::InvalidChunk)?;
+            let chunk = PNGChunk::try_from(chunk_slice)?;
+            let is_iend = chunk.chunk_type == "IEND";
+
+            counter += chunk.chunk_data.len() + 12;
+            chunks.push(chunk);
+
+            if is_iend {
+                break;
+            }
+
+            if counter >= value.len() {
+                return Err(PngContainerError::MissingIEND);
+            }
+        }
+
+        Ok(PNGContainer { chunks })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minimal_png() -> Vec<u8> {
+        let ihdr = PNGChunk::new("IHDR", vec![0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]);
+        let idat = PNGChunk::new(
+            "IDAT",
+            vec![0x78, 0x9C, 0x63, 0x60, 0x00, 0x00, 0x00, 0x02, 0x00, 0x01],
+        );
+        let iend = PNGChunk::new("IEND", Vec::new());
+        let container = PNGContainer::new(vec![ihdr, idat, iend]);
+
+        container.to_bytes()
+    }
+
+    #[test]
+    fn crc32_success() {
+        assert_eq!(crc32("IEND".as_bytes()), 0xAE426082);
+        assert_eq!(crc32("123456789".as_bytes()), 0xCBF43926);
+    }
+
+    #[test]
+    fn png_chunk_to_bytes() {
+        let chunk = PNGChunk::new("IEND", Vec::new());
+        let bytes = chunk.to_bytes();
+
+        assert_eq!(
+            bytes,
+            [0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82]
+        );
+    }
+
+    #[test]
+    fn png_chunk_property_bits() {
+        let chunk = PNGChunk::new(CUSTOM_CHUNK_TYPE, Vec::new());
+
+        assert!(chunk.is_ancillary());
+        assert!(chunk.is_private());
+        assert!(chunk.is_safe_to_copy());
+
+        let chunk = PNGChunk::new("IDAT", Vec::new());
+
+        assert!(!chunk.is_ancillary());
+        assert!(!chunk.is_private());
+        assert!(!chunk.is_safe_to_copy());
+    }
+
+    #[test]
+    fn try_from_vec_to_png_container_success() {
+        let bytes = minimal_png();
+        let container = PNGContainer::try_from(&bytes).unwrap();
+        let chunks = container.get_chunks();
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].chunk_type, "IHDR");
+        assert_eq!(chunks[2].chunk_type, "IEND");
+        assert_eq!(container.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn try_from_vec_to_png_container_failed() {
+        let mut bytes = minimal_png();
+        bytes[0] = 0x00;
+
+        match PNGContainer::try_from(&bytes) {
+            Ok(_) => panic!("Should failed, signature is invalid"),
+            Err(err) => assert_eq!(err, PngContainerError::InvalidSignature),
+        }
+
+        let mut bytes = minimal_png();
+        bytes[20] ^= 0xFF;
+
+        match PNGContainer::try_from(&bytes) {
+            Ok(_) => panic!("Should failed, crc doesn't match"),
+            Err(err) => assert_eq!(err, PngContainerError::CrcMismatch(String::from("IHDR"))),
+        }
+
+        let bytes = minimal_png();
+        let bytes = Vec::from(&bytes[0..bytes.len() - 12]);
+
+        match PNGContainer::try_from(&bytes) {
+            Ok(_) => panic!("Should failed, IEND is missing"),
+            Err(err) => assert_eq!(err, PngContainerError::MissingIEND),
+        }
+    }
+
+    #[test]
+    fn push_chunk_before_iend() {
+        let bytes = minimal_png();
+        let mut container = PNGContainer::try_from(&bytes).unwrap();
+
+        container.push_chunk(PNGChunk::new(CUSTOM_CHUNK_TYPE, vec![0x01, 0x02]));
+
+        let chunks = container.get_chunks();
+
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[2].chunk_type, CUSTOM_CHUNK_TYPE);
+        assert_eq!(chunks[3].chunk_type, "IEND");
+
+        let found = container.find_chunk(CUSTOM_CHUNK_TYPE).unwrap();
+
+        assert_eq!(found.chunk_data, [0x01, 0x02]);
+    }
+
+    #[test]
+    fn set_chunk_replaces_existing() {
+        let bytes = minimal_png();
+        let mut container = PNGContainer::try_from(&bytes).unwrap();
+
+        container.push_chunk(PNGChunk::new(CUSTOM_CHUNK_TYPE, vec![0x01]));
+        container.push_chunk(PNGChunk::new(CUSTOM_CHUNK_TYPE, vec![0x02]));
+        container.set_chunk(PNGChunk::new(CUSTOM_CHUNK_TYPE, vec![0x03]));
+
+        let chunks = container.get_chunks();
+
+        assert_eq!(chunks.len(), 4);
+        assert_eq!(chunks[2], PNGChunk::new(CUSTOM_CHUNK_TYPE, vec![0x03]));
+        assert_eq!(chunks[3].chunk_type, "IEND");
+    }
+
+    #[test]
+    fn oversized_chunk_length_is_invalid() {
+        let mut bytes = vec![0xFF; 4];
+        bytes.extend(b"IDAT");
+
+        assert_eq!(
+            PNGChunk::try_from(&bytes[..]),
+            Err(PngContainerError::InvalidChunk)
+        );
+    }
+}
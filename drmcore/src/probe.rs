@@ -0,0 +1,184 @@
+use crate::file_format::{detect_format, FileFormat};
+use crate::jpeg::frame::{FrameComponent, FrameHeader};
+use crate::jpeg::reader::{JFIFEvent, JFIFPushParser};
+use crate::watermark_task::Dimension;
+use crate::webp_container::RIFFContainerRef;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ProbeError {
+    #[error("Invalid Jpeg file")]
+    InvalidJpegFile,
+
+    #[error("Invalid WebP file")]
+    InvalidWebpFile,
+
+    #[error("Unsupported format")]
+    UnsupportedFormat,
+
+    #[error("Image too large : {width}x{height}")]
+    ImageTooLarge { width: u32, height: u32 },
+}
+
+// Read from the container headers only, webp images have no components
+#[derive(Debug, PartialEq)]
+pub struct ImageProbe {
+    pub format: FileFormat,
+    pub dimension: Dimension,
+    pub has_alpha: bool,
+    pub progressive: bool,
+    pub components: Vec<FrameComponent>,
+}
+
+impl ImageProbe {
+    pub fn get_pixel_count(&self) -> u64 {
+        self.dimension.width as u64 * self.dimension.height as u64
+    }
+}
+
+// Bytes handed to the push parser at once, parsing stops once the frame header is known
+const PROBE_CHUNK_SIZE: usize = 4096;
+
+// Only the segments up to the frame header are parsed. A zero height is defined by the DNL
+// segment after the first scan, the parser then keeps going until it is found
+pub fn probe_jpeg(inp: &[u8]) -> Result<ImageProbe, ProbeError> {
+    let mut parser = JFIFPushParser::new();
+    let mut frame: Option<(FrameHeader, bool)> = None;
+
+    for chunk in inp.chunks(PROBE_CHUNK_SIZE) {
+        let events = parser
+            .push(chunk)
+            .map_err(|_| ProbeError::InvalidJpegFile)?;
+
+        for event in events {
+            let segment = match &event {
+                JFIFEvent::Segment(segment) => segment.as_segment_ref(),
+                _ => continue,
+            };
+
+            match frame.as_mut() {
+                None => {
+                    if let Some((data, progressive)) = segment.get_frame_data() {
+                        let header =
+                            FrameHeader::try_from(data).map_err(|_| ProbeError::InvalidJpegFile)?;
+
+                        frame = Some((header, progressive));
+                    }
+                }
+                Some((header, _)) if header.height == 0 => {
+                    if let Some(lines) = segment.get_line_count() {
+                        header.height = lines;
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        if matches!(&frame, Some((header, _)) if header.height != 0) {
+            break;
+        }
+    }
+
+    match frame {
+        Some((header, progressive)) if header.height != 0 => Ok(ImageProbe {
+            format: FileFormat::Jpeg,
+            dimension: Dimension::new(header.width as u32, header.height as u32),
+            has_alpha: false,
+            progressive,
+            components: header.components,
+        }),
+        _ => Err(ProbeError::InvalidJpegFile),
+    }
+}
+
+pub fn probe_webp(inp: &[u8]) -> Result<ImageProbe, ProbeError> {
+    let container = RIFFContainerRef::try_from(inp).map_err(|_| ProbeError::InvalidWebpFile)?;
+    let (width, height, has_alpha) = container
+        .get_canvas_info()
+        .ok_or(ProbeError::InvalidWebpFile)?;
+
+    Ok(ImageProbe {
+        format: FileFormat::WebP,
+        dimension: Dimension::new(width, height),
+        has_alpha,
+        progressive: false,
+        components: Vec::new(),
+    })
+}
+
+pub fn probe_auto(inp: &[u8]) -> Result<ImageProbe, ProbeError> {
+    match detect_format(inp) {
+        Ok(FileFormat::Jpeg) => probe_jpeg(inp),
+        Ok(FileFormat::WebP) => probe_webp(inp),
+        _ => Err(ProbeError::UnsupportedFormat),
+    }
+}
+
+// Rejects an upload before anything gets decoded
+pub fn probe_with_limit(inp: &[u8], max_pixels: u64) -> Result<ImageProbe, ProbeError> {
+    let probe = probe_auto(inp)?;
+
+    if probe.get_pixel_count() > max_pixels {
+        return Err(ProbeError::ImageTooLarge {
+            width: probe.dimension.width,
+            height: probe.dimension.height,
+        });
+    }
+
+    Ok(probe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::GenericImageView;
+
+    #[test]
+    fn probe_matches_decoded_image() {
+        for name in [
+            fixture!("test.jpeg"),
+            fixture!("crop.jpeg"),
+            fixture!("test.webp"),
+            fixture!("crop.webp"),
+        ] {
+            let content = std::fs::read(name).unwrap();
+            let probe = probe_auto(&content).unwrap();
+            let (width, height) = image::load_from_memory(&content).unwrap().dimensions();
+
+            assert_eq!(probe.dimension, Dimension::new(width, height));
+        }
+
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let probe = probe_jpeg(&content).unwrap();
+
+        assert!(!probe.progressive);
+        assert_eq!(probe.components.len(), 3);
+        assert_eq!(probe.get_pixel_count(), 480 * 360);
+    }
+
+    #[test]
+    fn probe_rejects_large_and_unknown_images() {
+        let content = std::fs::read(fixture!("watermark.webp")).unwrap();
+        let probe = probe_webp(&content).unwrap();
+
+        assert!(probe_with_limit(&content, probe.get_pixel_count()).is_ok());
+        assert_eq!(
+            probe_with_limit(&content, probe.get_pixel_count() - 1),
+            Err(ProbeError::ImageTooLarge {
+                width: probe.dimension.width,
+                height: probe.dimension.height,
+            })
+        );
+        assert_eq!(probe_auto(&[0x00; 16]), Err(ProbeError::UnsupportedFormat));
+        assert_eq!(probe_jpeg(&[0xFF, 0xD8]), Err(ProbeError::InvalidJpegFile));
+    }
+
+    #[test]
+    fn probe_jpeg_stops_at_frame_header() {
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let probe = probe_jpeg(&content).unwrap();
+        let truncated = &content[..content.len() / 2];
+
+        assert_eq!(probe_jpeg(truncated), Ok(probe));
+    }
+}
@@ -0,0 +1,762 @@
+use std::collections::HashSet;
+use std::convert::TryFrom;
+use std::ops::Range;
+use thiserror::Error;
+
+// Private tag range starts at 32768
+pub const CUSTOM_TAG: u16 = 0xFDE8;
+
+pub const STRIP_OFFSETS_TAG: u16 = 273;
+pub const STRIP_BYTE_COUNTS_TAG: u16 = 279;
+pub const TILE_OFFSETS_TAG: u16 = 324;
+pub const TILE_BYTE_COUNTS_TAG: u16 = 325;
+pub const SUB_IFDS_TAG: u16 = 330;
+pub const EXIF_IFD_TAG: u16 = 34665;
+pub const GPS_IFD_TAG: u16 = 34853;
+pub const INTEROP_IFD_TAG: u16 = 40965;
+pub const JPEG_INTERCHANGE_FORMAT_TAG: u16 = 513;
+pub const JPEG_INTERCHANGE_FORMAT_LENGTH_TAG: u16 = 514;
+pub const COMPRESSION_TAG: u16 = 259;
+pub const ARTIST_TAG: u16 = 315;
+pub const COPYRIGHT_TAG: u16 = 33432;
+pub const MAKER_NOTE_TAG: u16 = 37500;
+pub const FREE_OFFSETS_TAG: u16 = 288;
+pub const FREE_BYTE_COUNTS_TAG: u16 = 289;
+
+const SUB_IFD_TAGS: [u16; 4] = [SUB_IFDS_TAG, EXIF_IFD_TAG, GPS_IFD_TAG, INTEROP_IFD_TAG];
+
+// Out of line values of these tags hold offsets relative to the file, a maker note usually
+// points into itself. They are written back at the offset they were read from
+const FIXED_VALUE_TAGS: [u16; 1] = [MAKER_NOTE_TAG];
+
+// Sub IFDs nested deeper than this are rejected
+const MAX_IFD_DEPTH: usize = 8;
+
+const ASCII_TYPE: u16 = 2;
+const SHORT_TYPE: u16 = 3;
+const LONG_TYPE: u16 = 4;
+const UNDEFINED_TYPE: u16 = 7;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum TiffContainerError {
+    #[error("Invalid tiff header")]
+    InvalidHeader,
+
+    #[error("Invalid IFD at offset `{0}`")]
+    InvalidIFD(usize),
+
+    #[error("Invalid entry with tag `{0}`")]
+    InvalidEntry(u16),
+
+    #[error("Image data is outside of the file")]
+    InvalidImageData,
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct TIFFEntry {
+    pub tag: u16,
+    pub field_type: u16,
+    pub count: u32,
+    pub value: Vec<u8>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct TIFFIFD {
+    pub entries: Vec<TIFFEntry>,
+    pub sub_ifds: Vec<(u16, Vec<TIFFIFD>)>,
+    pub data_blocks: Vec<Vec<u8>>,
+    // Tags from FIXED_VALUE_TAGS with the offset of their value
+    pub fixed_values: Vec<(u16, u32)>,
+    // Free space listed by FreeOffsets and FreeByteCounts, kept where it was
+    pub free_blocks: Vec<(u32, Vec<u8>)>,
+}
+
+pub struct TIFFContainer {
+    byte_order: ByteOrder,
+    ifds: Vec<TIFFIFD>,
+}
+
+fn get_type_size(field_type: u16) -> Option<usize> {
+    match field_type {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 => Some(8),
+        _ => None,
+    }
+}
+
+fn pad_to_word(out: &mut Vec<u8>) {
+    if out.len() % 2 == 1 {
+        out.push(0x00);
+    }
+}
+
+// Pads to a word boundary and past every fixed range the next `len` bytes would overlap
+fn reserve(out: &mut Vec<u8>, len: usize, fixed: &[Range<usize>]) {
+    loop {
+        pad_to_word(out);
+
+        let start = out.len();
+
+        match fixed
+            .iter()
+            .find(|f| f.start < start + len && start < f.end)
+        {
+            Some(range) => out.resize(range.end, 0x00),
+            None => break,
+        }
+    }
+}
+
+impl ByteOrder {
+    pub fn read_u16(&self, inp: &[u8]) -> u16 {
+        match self {
+            ByteOrder::LittleEndian => u16::from_le_bytes([inp[0], inp[1]]),
+            ByteOrder::BigEndian => u16::from_be_bytes([inp[0], inp[1]]),
+        }
+    }
+
+    pub fn read_u32(&self, inp: &[u8]) -> u32 {
+        let bytes = [inp[0], inp[1], inp[2], inp[3]];
+
+        match self {
+            ByteOrder::LittleEndian => u32::from_le_bytes(bytes),
+            ByteOrder::BigEndian => u32::from_be_bytes(bytes),
+        }
+    }
+
+    pub fn write_u16(&self, value: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+            ByteOrder::BigEndian => value.to_be_bytes(),
+        }
+    }
+
+    pub fn write_u32(&self, value: u32) -> [u8; 4] {
+        match self {
+            ByteOrder::LittleEndian => value.to_le_bytes(),
+            ByteOrder::BigEndian => value.to_be_bytes(),
+        }
+    }
+}
+
+impl TIFFEntry {
+    pub fn new_undefined(tag: u16, data: Vec<u8>) -> Self {
+        Self {
+            tag,
+            field_type: UNDEFINED_TYPE,
+            count: data.len() as u32,
+            value: data,
+        }
+    }
+
+    pub fn new_ascii(tag: u16, text: &str) -> Self {
+        let mut value = Vec::from(text.as_bytes());
+        value.push(0x00);
+
+        Self {
+            tag,
+            field_type: ASCII_TYPE,
+            count: value.len() as u32,
+            value,
+        }
+    }
+
+    pub fn new_short(tag: u16, values: &[u16], byte_order: ByteOrder) -> Self {
+        Self {
+            tag,
+            field_type: SHORT_TYPE,
+            count: values.len() as u32,
+            value: values
+                .iter()
+                .flat_map(|f| byte_order.write_u16(*f))
+                .collect(),
+        }
+    }
+
+    pub fn new_long(tag: u16, values: &[u32], byte_order: ByteOrder) -> Self {
+        Self {
+            tag,
+            field_type: LONG_TYPE,
+            count: values.len() as u32,
+            value: values
+                .iter()
+                .flat_map(|f| byte_order.write_u32(*f))
+                .collect(),
+        }
+    }
+
+    pub fn get_values(&self, byte_order: ByteOrder) -> Vec<u32> {
+        match self.field_type {
+            SHORT_TYPE => self
+                .value
+                .chunks_exact(2)
+                .map(|f| byte_order.read_u16(f) as u32)
+                .collect(),
+            LONG_TYPE | 13 => self
+                .value
+                .chunks_exact(4)
+                .map(|f| byte_order.read_u32(f))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl TIFFIFD {
+    pub fn new(entries: Vec<TIFFEntry>) -> Self {
+        Self {
+            entries,
+            sub_ifds: Vec::new(),
+            data_blocks: Vec::new(),
+            fixed_values: Vec::new(),
+            free_blocks: Vec::new(),
+        }
+    }
+
+    pub fn find_entry(&self, tag: u16) -> Option<&TIFFEntry> {
+        self.entries.iter().find(|p| p.tag == tag)
+    }
+
+    // Entries have to stay sorted by tag
+    pub fn put_entry(&mut self, entry: TIFFEntry) {
+        self.entries.retain(|p| p.tag != entry.tag);
+        self.fixed_values.retain(|p| p.0 != entry.tag);
+
+        let position = self
+            .entries
+            .iter()
+            .position(|p| p.tag > entry.tag)
+            .unwrap_or(self.entries.len());
+
+        self.entries.insert(position, entry);
+    }
+
+    pub fn remove_entry(&mut self, tag: u16) -> Option<TIFFEntry> {
+        let position = self.entries.iter().position(|p| p.tag == tag)?;
+        self.fixed_values.retain(|p| p.0 != tag);
+
+        Some(self.entries.remove(position))
+    }
+
+    pub(crate) fn get_data_tags(&self) -> Option<(u16, u16)> {
+        if self.find_entry(TILE_OFFSETS_TAG).is_some() {
+            return Some((TILE_OFFSETS_TAG, TILE_BYTE_COUNTS_TAG));
+        }
+
+        if self.find_entry(STRIP_OFFSETS_TAG).is_some() {
+            return Some((STRIP_OFFSETS_TAG, STRIP_BYTE_COUNTS_TAG));
+        }
+
+        // EXIF thumbnails in IFD1 point at their JPEG data the same way
+        if self.find_entry(JPEG_INTERCHANGE_FORMAT_TAG).is_some() {
+            return Some((
+                JPEG_INTERCHANGE_FORMAT_TAG,
+                JPEG_INTERCHANGE_FORMAT_LENGTH_TAG,
+            ));
+        }
+
+        None
+    }
+
+    fn get_fixed_offset(&self, tag: u16) -> Option<u32> {
+        self.fixed_values.iter().find(|p| p.0 == tag).map(|f| f.1)
+    }
+
+    // Values and free space that keep their offset, in this IFD and its sub IFDs
+    fn get_fixed_blocks(&self) -> Vec<(usize, &[u8])> {
+        let mut blocks: Vec<(usize, &[u8])> = self
+            .fixed_values
+            .iter()
+            .filter_map(|(tag, offset)| Some((*offset as usize, &self.find_entry(*tag)?.value[..])))
+            .chain(
+                self.free_blocks
+                    .iter()
+                    .map(|(offset, block)| (*offset as usize, &block[..])),
+            )
+            .collect();
+
+        for (_, sub_ifds) in &self.sub_ifds {
+            sub_ifds
+                .iter()
+                .for_each(|f| blocks.extend(f.get_fixed_blocks()));
+        }
+
+        blocks
+    }
+
+    // Image data, sub IFDs, the IFD itself then out of line values. Nothing is written over the
+    // fixed ranges
+    fn write(
+        &self,
+        out: &mut Vec<u8>,
+        byte_order: ByteOrder,
+        fixed: &[Range<usize>],
+    ) -> (u32, usize) {
+        let mut entries = self.entries.clone();
+
+        if let Some((offsets_tag, _)) = self.get_data_tags() {
+            let mut offsets = Vec::new();
+
+            for block in &self.data_blocks {
+                reserve(out, block.len(), fixed);
+                offsets.push(out.len() as u32);
+                out.extend(block);
+            }
+
+            let entry = TIFFEntry::new_long(offsets_tag, &offsets, byte_order);
+            entries
+                .iter_mut()
+                .filter(|p| p.tag == offsets_tag)
+                .for_each(|f| *f = entry.clone());
+        }
+
+        for (tag, sub_ifds) in &self.sub_ifds {
+            let offsets: Vec<u32> = sub_ifds
+                .iter()
+                .map(|f| f.write(out, byte_order, fixed).0)
+                .collect();
+            let mut entry = TIFFEntry::new_long(*tag, &offsets, byte_order);
+
+            if let Some(original) = entries.iter_mut().find(|p| p.tag == *tag) {
+                entry.field_type = original.field_type;
+                *original = entry;
+            }
+        }
+
+        let is_fixed =
+            |entry: &TIFFEntry| entry.value.len() > 4 && self.get_fixed_offset(entry.tag).is_some();
+        let extra_size: usize = entries
+            .iter()
+            .filter(|p| p.value.len() > 4 && !is_fixed(p))
+            .map(|f| f.value.len() + f.value.len() % 2)
+            .sum();
+
+        reserve(out, 2 + entries.len() * 12 + 4 + extra_size, fixed);
+
+        let ifd_offset = out.len();
+        let mut extra_offset = ifd_offset + 2 + entries.len() * 12 + 4;
+        let mut extra = Vec::new();
+
+        out.extend(byte_order.write_u16(entries.len() as u16));
+
+        for entry in &entries {
+            out.extend(byte_order.write_u16(entry.tag));
+            out.extend(byte_order.write_u16(entry.field_type));
+            out.extend(byte_order.write_u32(entry.count));
+
+            if is_fixed(entry) {
+                out.extend(byte_order.write_u32(self.get_fixed_offset(entry.tag).unwrap()));
+            } else if entry.value.len() > 4 {
+                out.extend(byte_order.write_u32(extra_offset as u32));
+                extra.extend(&entry.value);
+
+                if entry.value.len() % 2 == 1 {
+                    extra.push(0x00);
+                }
+
+                extra_offset = ifd_offset + 2 + entries.len() * 12 + 4 + extra.len();
+            } else {
+                let mut inline = entry.value.clone();
+                inline.resize(4, 0x00);
+                out.extend(inline);
+            }
+        }
+
+        let next_pointer = out.len();
+        out.extend([0u8; 4]);
+        out.extend(extra);
+
+        (ifd_offset as u32, next_pointer)
+    }
+}
+
+struct IFDReader<'a> {
+    inp: &'a [u8],
+    byte_order: ByteOrder,
+    visited: HashSet<usize>,
+}
+
+impl IFDReader<'_> {
+    fn get(&self, start: usize, len: usize) -> Option<&[u8]> {
+        self.inp.get(start..start.checked_add(len)?)
+    }
+
+    fn read_ifd(
+        &mut self,
+        offset: usize,
+        depth: usize,
+    ) -> Result<(TIFFIFD, usize), TiffContainerError> {
+        let invalid_ifd = TiffContainerError::InvalidIFD(offset);
+
+        if depth > MAX_IFD_DEPTH || !self.visited.insert(offset) {
+            return Err(invalid_ifd);
+        }
+
+        let byte_order = self.byte_order;
+        let entry_count = byte_order.read_u16(self.get(offset, 2).ok_or(invalid_ifd)?) as usize;
+        let entries_bytes = self
+            .get(offset + 2, entry_count * 12 + 4)
+            .ok_or(TiffContainerError::InvalidIFD(offset))?;
+        let next_offset = byte_order.read_u32(&entries_bytes[entry_count * 12..]) as usize;
+        let mut entries = Vec::new();
+        let mut fixed_values = Vec::new();
+
+        for entry_bytes in entries_bytes[..entry_count * 12].chunks_exact(12) {
+            let tag = byte_order.read_u16(&entry_bytes[0..2]);
+            let field_type = byte_order.read_u16(&entry_bytes[2..4]);
+            let count = byte_order.read_u32(&entry_bytes[4..8]);
+            let value_size = get_type_size(field_type)
+                .and_then(|f| f.checked_mul(count as usize))
+                .ok_or(TiffContainerError::InvalidEntry(tag))?;
+            let value = if value_size > 4 {
+                let value_offset = byte_order.read_u32(&entry_bytes[8..12]) as usize;
+
+                if FIXED_VALUE_TAGS.contains(&tag) {
+                    // The header is always written first
+                    if value_offset < 8 {
+                        return Err(TiffContainerError::InvalidEntry(tag));
+                    }
+
+                    fixed_values.push((tag, value_offset as u32));
+                }
+
+                self.get(value_offset, value_size)
+                    .ok_or(TiffContainerError::InvalidEntry(tag))?
+            } else {
+                &entry_bytes[8..8 + value_size]
+            };
+
+            entries.push(TIFFEntry {
+                tag,
+                field_type,
+                count,
+                value: Vec::from(value),
+            });
+        }
+
+        let mut ifd = TIFFIFD::new(entries);
+        ifd.fixed_values = fixed_values;
+
+        for tag in SUB_IFD_TAGS {
+            if let Some(entry) = ifd.find_entry(tag) {
+                let mut sub_ifds = Vec::new();
+
+                for sub_offset in entry.get_values(byte_order) {
+                    sub_ifds.push(self.read_ifd(sub_offset as usize, depth + 1)?.0);
+                }
+
+                ifd.sub_ifds.push((tag, sub_ifds));
+            }
+        }
+
+        let free_offsets = ifd
+            .find_entry(FREE_OFFSETS_TAG)
+            .map(|f| f.get_values(byte_order))
+            .unwrap_or_default();
+        let free_counts = ifd
+            .find_entry(FREE_BYTE_COUNTS_TAG)
+            .map(|f| f.get_values(byte_order))
+            .unwrap_or_default();
+
+        if free_offsets.len() != free_counts.len() {
+            return Err(TiffContainerError::InvalidEntry(FREE_OFFSETS_TAG));
+        }
+
+        for (free_offset, count) in free_offsets.iter().zip(free_counts.iter()) {
+            let block = self
+                .get(*free_offset as usize, *count as usize)
+                .filter(|_| *free_offset >= 8)
+                .ok_or(TiffContainerError::InvalidEntry(FREE_OFFSETS_TAG))?;
+            ifd.free_blocks.push((*free_offset, Vec::from(block)));
+        }
+
+        if let Some((offsets_tag, counts_tag)) = ifd.get_data_tags() {
+            let offsets = ifd
+                .find_entry(offsets_tag)
+                .map(|f| f.get_values(byte_order))
+                .unwrap_or_default();
+            let counts = ifd
+                .find_entry(counts_tag)
+                .map(|f| f.get_values(byte_order))
+                .unwrap_or_default();
+
+            if offsets.len() != counts.len() {
+                return Err(TiffContainerError::InvalidImageData);
+            }
+
+            for (data_offset, count) in offsets.iter().zip(counts.iter()) {
+                let block = self
+                    .get(*data_offset as usize, *count as usize)
+                    .ok_or(TiffContainerError::InvalidImageData)?;
+                ifd.data_blocks.push(Vec::from(block));
+            }
+        }
+
+        Ok((ifd, next_offset))
+    }
+}
+
+impl TIFFContainer {
+    pub fn new(byte_order: ByteOrder, ifds: Vec<TIFFIFD>) -> Self {
+        Self { byte_order, ifds }
+    }
+
+    pub fn get_byte_order(&self) -> ByteOrder {
+        self.byte_order
+    }
+
+    pub fn get_ifds(&self) -> &Vec<TIFFIFD> {
+        &self.ifds
+    }
+
+    pub fn get_ifds_mut(&mut self) -> &mut Vec<TIFFIFD> {
+        &mut self.ifds
+    }
+
+    pub fn find_entry(&self, tag: u16) -> Option<&TIFFEntry> {
+        self.ifds.first().and_then(|f| f.find_entry(tag))
+    }
+
+    pub fn put_entry(&mut self, entry: TIFFEntry) {
+        if let Some(ifd) = self.ifds.first_mut() {
+            ifd.put_entry(entry);
+        }
+    }
+
+    pub fn remove_entry(&mut self, tag: u16) -> Option<TIFFEntry> {
+        self.ifds.first_mut().and_then(|f| f.remove_entry(tag))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let byte_order = self.byte_order;
+        let mut result = match byte_order {
+            ByteOrder::LittleEndian => vec![0x49, 0x49, 0x2A, 0x00],
+            ByteOrder::BigEndian => vec![0x4D, 0x4D, 0x00, 0x2A],
+        };
+        let mut pointer_position = 4usize;
+        result.extend([0u8; 4]);
+
+        let fixed_blocks: Vec<(usize, &[u8])> = self
+            .ifds
+            .iter()
+            .flat_map(|f| f.get_fixed_blocks())
+            .collect();
+        let fixed: Vec<Range<usize>> = fixed_blocks
+            .iter()
+            .map(|(offset, block)| *offset..offset + block.len())
+            .collect();
+
+        for ifd in &self.ifds {
+            let (ifd_offset, next_pointer) = ifd.write(&mut result, byte_order, &fixed);
+
+            result[pointer_position..pointer_position + 4]
+                .copy_from_slice(&byte_order.write_u32(ifd_offset));
+            pointer_position = next_pointer;
+        }
+
+        for (offset, block) in fixed_blocks {
+            if result.len() < offset + block.len() {
+                result.resize(offset + block.len(), 0x00);
+            }
+
+            result[offset..offset + block.len()].copy_from_slice(block);
+        }
+
+        result
+    }
+}
+
+impl TryFrom<&Vec<u8>> for TIFFContainer {
+    type Error = TiffContainerError;
+
+    fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
+        let header = value.get(0..8).ok_or(TiffContainerError::InvalidHeader)?;
+        let byte_order = match &header[0..4] {
+            [0x49, 0x49, 0x2A, 0x00] => ByteOrder::LittleEndian,
+            [0x4D, 0x4D, 0x00, 0x2A] => ByteOrder::BigEndian,
+            _ => return Err(TiffContainerError::InvalidHeader),
+        };
+        let mut reader = IFDReader {
+            inp: value,
+            byte_order,
+            visited: HashSet::new(),
+        };
+        let mut ifds = Vec::new();
+        let mut offset = byte_order.read_u32(&header[4..8]) as usize;
+
+        while offset != 0 {
+            let (ifd, next_offset) = reader.read_ifd(offset, 0)?;
+
+            ifds.push(ifd);
+            offset = next_offset;
+        }
+
+        if ifds.is_empty() {
+            return Err(TiffContainerError::InvalidIFD(0));
+        }
+
+        Ok(TIFFContainer { byte_order, ifds })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2x2 8-bit grayscale, two strips and an out of line resolution value
+    fn minimal_tiff(byte_order: ByteOrder) -> Vec<u8> {
+        let entries = vec![
+            TIFFEntry::new_long(256, &[2], byte_order),
+            TIFFEntry::new_long(257, &[2], byte_order),
+            TIFFEntry::new_long(262, &[1], byte_order),
+            TIFFEntry::new_long(STRIP_OFFSETS_TAG, &[0, 0], byte_order),
+            TIFFEntry::new_long(278, &[1], byte_order),
+            TIFFEntry::new_long(STRIP_BYTE_COUNTS_TAG, &[2, 2], byte_order),
+            TIFFEntry {
+                tag: 282,
+                field_type: 5,
+                count: 1,
+                value: [byte_order.write_u32(72), byte_order.write_u32(1)].concat(),
+            },
+        ];
+        let mut ifd = TIFFIFD::new(entries);
+        ifd.data_blocks = vec![vec![0x10, 0x20], vec![0x30, 0x40]];
+        let container = TIFFContainer {
+            byte_order,
+            ifds: vec![ifd],
+        };
+
+        container.to_bytes()
+    }
+
+    #[test]
+    fn try_from_vec_to_tiff_container_success() {
+        for byte_order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let bytes = minimal_tiff(byte_order);
+            let container = TIFFContainer::try_from(&bytes).unwrap();
+            let ifd = &container.get_ifds()[0];
+            let offsets = ifd
+                .find_entry(STRIP_OFFSETS_TAG)
+                .unwrap()
+                .get_values(byte_order);
+
+            assert_eq!(container.get_byte_order(), byte_order);
+            assert_eq!(ifd.data_blocks, [vec![0x10, 0x20], vec![0x30, 0x40]]);
+            assert_eq!(
+                bytes[offsets[1] as usize..offsets[1] as usize + 2],
+                [0x30, 0x40]
+            );
+            assert_eq!(ifd.find_entry(282).unwrap().value.len(), 8);
+            assert_eq!(container.to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn try_from_vec_to_tiff_container_failed() {
+        let mut bytes = minimal_tiff(ByteOrder::LittleEndian);
+        bytes[2] = 0x2B;
+
+        match TIFFContainer::try_from(&bytes) {
+            Ok(_) => panic!("Should failed, BigTIFF is not supported"),
+            Err(err) => assert_eq!(err, TiffContainerError::InvalidHeader),
+        }
+
+        let mut bytes = minimal_tiff(ByteOrder::LittleEndian);
+        let len = bytes.len() as u32;
+        bytes[4..8].copy_from_slice(&len.to_le_bytes());
+
+        match TIFFContainer::try_from(&bytes) {
+            Ok(_) => panic!("Should failed, IFD is outside of the file"),
+            Err(err) => assert_eq!(err, TiffContainerError::InvalidIFD(len as usize)),
+        }
+    }
+
+    #[test]
+    fn put_custom_entry_rewrites_offsets() {
+        for byte_order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+            let bytes = minimal_tiff(byte_order);
+            let mut container = TIFFContainer::try_from(&bytes).unwrap();
+
+            container.put_entry(TIFFEntry::new_undefined(CUSTOM_TAG, vec![0xAB; 33]));
+
+            let output = container.to_bytes();
+            let output_container = TIFFContainer::try_from(&output).unwrap();
+            let ifd = &output_container.get_ifds()[0];
+            let tags: Vec<u16> = ifd.entries.iter().map(|f| f.tag).collect();
+
+            assert_eq!(tags.last(), Some(&CUSTOM_TAG));
+            assert_eq!(ifd.find_entry(CUSTOM_TAG).unwrap().value, vec![0xAB; 33]);
+            assert_eq!(ifd.data_blocks, [vec![0x10, 0x20], vec![0x30, 0x40]]);
+
+            let mut output_container = output_container;
+            output_container.remove_entry(CUSTOM_TAG);
+
+            assert_eq!(output_container.to_bytes(), bytes);
+        }
+    }
+
+    #[test]
+    fn nested_sub_ifds_are_limited() {
+        let byte_order = ByteOrder::LittleEndian;
+        let nested = |depth: usize| {
+            let mut ifd = TIFFIFD::new(vec![TIFFEntry::new_long(256, &[2], byte_order)]);
+
+            for _ in 0..depth {
+                let mut parent =
+                    TIFFIFD::new(vec![TIFFEntry::new_long(EXIF_IFD_TAG, &[0], byte_order)]);
+                parent.sub_ifds.push((EXIF_IFD_TAG, vec![ifd]));
+                ifd = parent;
+            }
+
+            TIFFContainer::new(byte_order, vec![ifd]).to_bytes()
+        };
+
+        assert!(TIFFContainer::try_from(&nested(MAX_IFD_DEPTH)).is_ok());
+        assert!(matches!(
+            TIFFContainer::try_from(&nested(MAX_IFD_DEPTH + 1)),
+            Err(TiffContainerError::InvalidIFD(_))
+        ));
+    }
+
+    #[test]
+    fn fixed_values_keep_their_offset() {
+        let byte_order = ByteOrder::LittleEndian;
+        let mut container = TIFFContainer::try_from(&minimal_tiff(byte_order)).unwrap();
+        let maker_note: Vec<u8> = (0..24).collect();
+        let ifd = &mut container.get_ifds_mut()[0];
+
+        // Right behind the header, where the image data would go
+        ifd.put_entry(TIFFEntry::new_undefined(MAKER_NOTE_TAG, maker_note.clone()));
+        ifd.fixed_values.push((MAKER_NOTE_TAG, 8));
+        ifd.put_entry(TIFFEntry::new_long(FREE_OFFSETS_TAG, &[40], byte_order));
+        ifd.put_entry(TIFFEntry::new_long(FREE_BYTE_COUNTS_TAG, &[4], byte_order));
+        ifd.free_blocks.push((40, vec![0xEE; 4]));
+
+        let bytes = container.to_bytes();
+        let mut container = TIFFContainer::try_from(&bytes).unwrap();
+
+        assert_eq!(bytes[8..32], maker_note);
+        assert_eq!(bytes[40..44], [0xEE; 4]);
+        assert_eq!(container.to_bytes(), bytes);
+
+        container.put_entry(TIFFEntry::new_undefined(CUSTOM_TAG, vec![0xAB; 33]));
+
+        let output = container.to_bytes();
+        let output_container = TIFFContainer::try_from(&output).unwrap();
+        let ifd = &output_container.get_ifds()[0];
+
+        assert_eq!(output[8..32], maker_note);
+        assert_eq!(ifd.find_entry(MAKER_NOTE_TAG).unwrap().value, maker_note);
+        assert_eq!(ifd.fixed_values, [(MAKER_NOTE_TAG, 8)]);
+        assert_eq!(ifd.free_blocks, [(40, vec![0xEE; 4])]);
+        assert_eq!(ifd.data_blocks, [vec![0x10, 0x20], vec![0x30, 0x40]]);
+    }
+}
@@ -1,18 +1,29 @@
+use crate::file_format::{detect_format, FileFormat};
 use crate::file_joiner::{le_to_u32, usize_to_le};
+use crate::img::{make_thumbnail, watermark_thumbnail};
+use crate::jpeg::thumbnail::{get_exif_thumbnail, set_exif_thumbnail, ThumbnailPolicy};
+use crate::metadata::{read_metadata, ImageMetadata, MetadataError, RightsInfo};
+use crate::probe::probe_with_limit;
+use crate::webp_container::RIFFContainer;
 use anyhow::{anyhow, Result};
+use image::codecs::gif::GifDecoder;
 use image::{
-    load_from_memory_with_format, DynamicImage, GenericImage, GenericImageView, ImageFormat,
-    ImageResult, Pixel,
+    load_from_memory_with_format, AnimationDecoder, Delay, DynamicImage, GenericImage,
+    GenericImageView, ImageFormat, ImageResult, Pixel,
 };
+use std::io::Cursor;
 use thiserror::Error;
 
-#[derive(PartialEq, Eq, Debug)]
+// Around 400MB once decoded to RGBA
+pub const DEFAULT_MAX_PIXELS: u64 = 100_000_000;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum OriginX {
     Left,
     Right,
 }
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum OriginY {
     Top,
     Bottom,
@@ -22,8 +33,19 @@ pub enum OriginY {
 pub struct WatermarkTask {
     watermark: Option<DynamicImage>,
     target: Option<DynamicImage>,
+    target_dimension: Option<Dimension>,
+    max_pixels: u64,
     output: Option<DynamicImage>,
     old_section: Option<DynamicImage>,
+    target_frames: Option<Vec<AnimationFrame>>,
+    output_frames: Option<Vec<AnimationFrame>>,
+    old_section_frames: Option<Vec<FrameSection>>,
+    target_source: Option<Vec<u8>>,
+    target_format: Option<FileFormat>,
+    target_metadata: ImageMetadata,
+    rights: Option<RightsInfo>,
+    thumbnail_policy: ThumbnailPolicy,
+    watermark_mpf_images: bool,
     origin_x: OriginX,
     origin_y: OriginY,
     x: u32,
@@ -31,13 +53,28 @@ pub struct WatermarkTask {
     key: Option<[u8; 32]>,
 }
 
+#[derive(Debug, Clone)]
+pub struct AnimationFrame {
+    pub image: DynamicImage,
+    pub left: u32,
+    pub top: u32,
+    pub delay: Delay,
+}
+
 #[derive(Debug)]
+pub struct FrameSection {
+    pub index: usize,
+    pub position: Point,
+    pub section: DynamicImage,
+}
+
+#[derive(Debug, PartialEq)]
 pub struct Point {
     pub x: u32,
     pub y: u32,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Dimension {
     pub width: u32,
     pub height: u32,
@@ -78,6 +115,58 @@ fn solve_absolute_position(
     Point { x: abs_x, y: abs_y }
 }
 
+// Watermark the part of the frame overlapping the watermark, position is on the canvas
+fn watermark_frame(
+    frame: &AnimationFrame,
+    watermark: &DynamicImage,
+    position: &Point,
+    index: usize,
+) -> (AnimationFrame, Option<FrameSection>) {
+    let (watermark_w, watermark_h) = watermark.dimensions();
+    let (frame_w, frame_h) = frame.image.dimensions();
+    let start_x = position.x.max(frame.left);
+    let start_y = position.y.max(frame.top);
+    let end_x = (position.x + watermark_w).min(frame.left + frame_w);
+    let end_y = (position.y + watermark_h).min(frame.top + frame_h);
+
+    if start_x >= end_x || start_y >= end_y {
+        return (frame.clone(), None);
+    }
+
+    let mut output = frame.clone();
+    let local = Point {
+        x: start_x - frame.left,
+        y: start_y - frame.top,
+    };
+    let mut sub_img = output
+        .image
+        .sub_image(local.x, local.y, end_x - start_x, end_y - start_y);
+    let copy_sub_img: DynamicImage = sub_img.to_image().into();
+
+    for x in 0..end_x - start_x {
+        for y in 0..end_y - start_y {
+            let pix_src = watermark.get_pixel(start_x - position.x + x, start_y - position.y + y);
+            let mut pix_tar = sub_img.get_pixel(x, y);
+            pix_tar.blend(&pix_src);
+            sub_img.put_pixel(x, y, pix_tar);
+        }
+    }
+
+    let section = FrameSection {
+        index,
+        position: local,
+        section: copy_sub_img,
+    };
+
+    (output, Some(section))
+}
+
+impl Default for WatermarkTask {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl WatermarkTask {
     pub fn new() -> Self {
         WatermarkTask {
@@ -86,9 +175,20 @@ impl WatermarkTask {
             x: 0,
             y: 0,
             target: None,
+            target_dimension: None,
+            max_pixels: DEFAULT_MAX_PIXELS,
             watermark: None,
             output: None,
             old_section: None,
+            target_frames: None,
+            output_frames: None,
+            old_section_frames: None,
+            target_source: None,
+            target_format: None,
+            target_metadata: ImageMetadata::default(),
+            rights: None,
+            thumbnail_policy: ThumbnailPolicy::default(),
+            watermark_mpf_images: false,
             key: None,
         }
     }
@@ -115,7 +215,12 @@ impl WatermarkTask {
         }
     }
 
+    // The probed dimension when the target went through set_target_auto, the decoded one otherwise
     pub fn get_target_dimension(&self) -> Option<Dimension> {
+        if self.target_dimension.is_some() {
+            return self.target_dimension;
+        }
+
         match &self.target {
             Some(target_img) => {
                 let (width, height) = target_img.dimensions();
@@ -138,19 +243,16 @@ impl WatermarkTask {
         let watermark_dim = self.get_watermark_dimension();
         let target_dim = self.get_target_dimension();
 
-        match (target_dim, watermark_dim) {
-            (Some(target_dim), Some(watermark_dim)) => {
-                let pos = solve_absolute_position(
-                    &target_dim,
-                    &watermark_dim,
-                    origin_x,
-                    origin_y,
-                    &offset,
-                );
-
-                return Some(pos);
-            }
-            _ => {}
+        if let (Some(target_dim), Some(watermark_dim)) = (target_dim, watermark_dim) {
+            let pos = solve_absolute_position(
+                &target_dim,
+                &watermark_dim,
+                origin_x,
+                origin_y,
+                &offset,
+            );
+
+            return Some(pos);
         }
 
         None
@@ -165,6 +267,125 @@ impl WatermarkTask {
 
     pub fn set_target(&mut self, target: Option<DynamicImage>) {
         self.target = target;
+        self.target_dimension = None;
+    }
+
+    pub fn set_target_dimension(&mut self, target_dimension: Option<Dimension>) {
+        self.target_dimension = target_dimension;
+    }
+
+    pub fn get_max_pixels(&self) -> u64 {
+        self.max_pixels
+    }
+
+    pub fn set_max_pixels(&mut self, max_pixels: u64) {
+        self.max_pixels = max_pixels;
+    }
+
+    pub fn set_target_frames(&mut self, target_frames: Option<Vec<AnimationFrame>>) {
+        self.target_frames = target_frames;
+    }
+
+    pub fn get_target_source(&self) -> &Option<Vec<u8>> {
+        &self.target_source
+    }
+
+    pub fn set_target_source(&mut self, target_source: Option<Vec<u8>>) {
+        self.target_source = target_source;
+    }
+
+    pub fn get_target_format(&self) -> Option<FileFormat> {
+        self.target_format
+    }
+
+    pub fn set_target_format(&mut self, target_format: Option<FileFormat>) {
+        self.target_format = target_format;
+    }
+
+    pub fn get_target_metadata(&self) -> &ImageMetadata {
+        &self.target_metadata
+    }
+
+    pub fn set_target_metadata(&mut self, target_metadata: ImageMetadata) {
+        self.target_metadata = target_metadata;
+    }
+
+    pub fn get_rights(&self) -> &Option<RightsInfo> {
+        &self.rights
+    }
+
+    pub fn set_rights(&mut self, rights: Option<RightsInfo>) {
+        self.rights = rights;
+    }
+
+    pub fn get_thumbnail_policy(&self) -> ThumbnailPolicy {
+        self.thumbnail_policy
+    }
+
+    pub fn set_thumbnail_policy(&mut self, thumbnail_policy: ThumbnailPolicy) {
+        self.thumbnail_policy = thumbnail_policy;
+    }
+
+    pub fn get_watermark_mpf_images(&self) -> bool {
+        self.watermark_mpf_images
+    }
+
+    pub fn set_watermark_mpf_images(&mut self, watermark_mpf_images: bool) {
+        self.watermark_mpf_images = watermark_mpf_images;
+    }
+
+    fn watermark_secondary_image(&self, image: &[u8]) -> Result<Vec<u8>> {
+        let watermark = self.watermark.as_ref().ok_or(anyhow!("Watermark not set"))?;
+        let position = self
+            .get_absolute_watermark_position()
+            .ok_or(anyhow!("Watermark or target image is not set"))?;
+        let target = self.get_target_dimension().unwrap();
+
+        Ok(watermark_thumbnail(image, watermark, &position, &target)?)
+    }
+
+    fn get_output_thumbnail(&self, thumbnail: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.thumbnail_policy {
+            ThumbnailPolicy::Keep => Ok(Some(Vec::from(thumbnail))),
+            ThumbnailPolicy::Remove => Ok(None),
+            ThumbnailPolicy::Regenerate => {
+                let output = self.output.as_ref().ok_or(anyhow!("Task not yet processed"))?;
+
+                Ok(Some(make_thumbnail(output, Some(thumbnail))?))
+            }
+            ThumbnailPolicy::Watermark => Ok(Some(self.watermark_secondary_image(thumbnail)?)),
+        }
+    }
+
+    // Metadata of the target with the rights statement merged in, if one is set, and the
+    // EXIF thumbnail handled according to the thumbnail policy, MPF images are watermarked too
+    // when enabled
+    pub fn get_output_metadata(&self) -> std::result::Result<ImageMetadata, MetadataError> {
+        let mut metadata = match &self.rights {
+            Some(rights) => self.target_metadata.with_rights(rights)?,
+            None => self.target_metadata.clone(),
+        };
+        let thumbnail = metadata.exif.as_ref().and_then(|f| get_exif_thumbnail(f));
+
+        if let (Some(exif), Some(thumbnail)) = (&metadata.exif, thumbnail) {
+            let output_thumbnail = self
+                .get_output_thumbnail(&thumbnail)
+                .map_err(|_| MetadataError::InvalidThumbnail)?;
+            let exif = set_exif_thumbnail(exif, output_thumbnail.as_deref())
+                .map_err(|_| MetadataError::InvalidExif)?;
+
+            metadata.exif = Some(exif);
+        }
+
+        if self.watermark_mpf_images {
+            for image in metadata.mpf_images.iter_mut() {
+                image.data = self
+                    .watermark_secondary_image(&image.data)
+                    .map_err(|_| MetadataError::InvalidMpf)?;
+            }
+        }
+
+        Ok(metadata)
     }
 
     pub fn set_watermark(&mut self, watermark: Option<DynamicImage>) {
@@ -179,6 +400,38 @@ impl WatermarkTask {
         &self.output
     }
 
+    pub fn get_old_section_frames(&self) -> &Option<Vec<FrameSection>> {
+        &self.old_section_frames
+    }
+
+    pub fn get_output_frames(&self) -> &Option<Vec<AnimationFrame>> {
+        &self.output_frames
+    }
+
+    fn process_frames(&mut self) {
+        let position = self.get_absolute_watermark_position();
+
+        if let (Some(frames), Some(watermark_img), Some(position)) =
+            (&self.target_frames, &self.watermark, position)
+        {
+            let mut output_frames = Vec::new();
+            let mut old_section_frames = Vec::new();
+
+            for (index, frame) in frames.iter().enumerate() {
+                let (output, section) = watermark_frame(frame, watermark_img, &position, index);
+
+                output_frames.push(output);
+
+                if let Some(section) = section {
+                    old_section_frames.push(section);
+                }
+            }
+
+            self.output_frames = Some(output_frames);
+            self.old_section_frames = Some(old_section_frames);
+        }
+    }
+
     pub fn process(&mut self) -> Result<()> {
         let target = &self.target;
         let watermark = &self.watermark;
@@ -203,6 +456,7 @@ impl WatermarkTask {
 
                 self.output = Some(clone_target);
                 self.old_section = Some(copy_sub_img);
+                self.process_frames();
 
                 Ok(())
             }
@@ -229,6 +483,113 @@ pub fn set_target(
 ) -> ImageResult<()> {
     let target = load_from_memory_with_format(bytes, format)?;
     watermark_task.set_target(Some(target));
+    watermark_task.set_target_frames(None);
+    watermark_task.set_target_source(None);
+    watermark_task.set_target_metadata(read_metadata(bytes));
+
+    Ok(())
+}
+
+pub fn get_frames_gif(bytes: &[u8]) -> ImageResult<Vec<AnimationFrame>> {
+    let decoder = GifDecoder::new(Cursor::new(bytes))?;
+    let frames = decoder.into_frames().collect_frames()?;
+
+    Ok(frames
+        .into_iter()
+        .map(|f| AnimationFrame {
+            left: f.left(),
+            top: f.top(),
+            delay: f.delay(),
+            image: DynamicImage::ImageRgba8(f.into_buffer()),
+        })
+        .collect())
+}
+
+pub fn is_animated_webp(bytes: &[u8]) -> bool {
+    RIFFContainer::try_from(&Vec::from(bytes))
+        .map(|f| f.is_animated())
+        .unwrap_or(false)
+}
+
+pub fn get_frames_webp(bytes: &[u8]) -> Result<Vec<AnimationFrame>> {
+    let container = RIFFContainer::try_from(&Vec::from(bytes))?;
+    let mut result = Vec::new();
+
+    for frame in container.get_frames() {
+        let image = load_from_memory_with_format(&frame.to_webp(), ImageFormat::WebP)?;
+
+        result.push(AnimationFrame {
+            image,
+            left: frame.x,
+            top: frame.y,
+            delay: Delay::from_numer_denom_ms(frame.duration, 1),
+        });
+    }
+
+    Ok(result)
+}
+
+// Frames keep their own offsets, the source is kept to rebuild the ANMF chunks
+pub fn set_target_webp_animated(watermark_task: &mut WatermarkTask, bytes: &[u8]) -> Result<()> {
+    let frames = get_frames_webp(bytes)?;
+    let target = load_from_memory_with_format(bytes, ImageFormat::WebP)?;
+    watermark_task.set_target(Some(target));
+    watermark_task.set_target_frames(Some(frames));
+    watermark_task.set_target_source(Some(Vec::from(bytes)));
+    watermark_task.set_target_format(Some(FileFormat::WebP));
+    watermark_task.set_target_metadata(read_metadata(bytes));
+
+    Ok(())
+}
+
+// The first frame stands in as the target so dimensions and position resolve as usual, the
+// source is kept to re-encode only the watermarked frames
+pub fn set_target_gif(watermark_task: &mut WatermarkTask, bytes: &[u8]) -> ImageResult<()> {
+    let frames = get_frames_gif(bytes)?;
+    let target = load_from_memory_with_format(bytes, ImageFormat::Gif)?;
+    watermark_task.set_target(Some(target));
+    watermark_task.set_target_frames(Some(frames));
+    watermark_task.set_target_source(Some(Vec::from(bytes)));
+    watermark_task.set_target_format(Some(FileFormat::Gif));
+    watermark_task.set_target_metadata(read_metadata(bytes));
+
+    Ok(())
+}
+
+// HEIF is a carrier for split and join only, there is no encoder for a watermarked output.
+// JPEG and WebP are probed first, an image over the pixel limit is rejected before decoding
+pub fn set_target_auto(watermark_task: &mut WatermarkTask, bytes: &[u8]) -> Result<()> {
+    let format = detect_format(bytes)?;
+
+    if format == FileFormat::Heif {
+        return Err(anyhow!("HEIF output cannot be encoded"));
+    }
+
+    if format == FileFormat::Gif {
+        set_target_gif(watermark_task, bytes)?;
+
+        return Ok(());
+    }
+
+    let dimension = match format {
+        FileFormat::Jpeg | FileFormat::WebP => {
+            Some(probe_with_limit(bytes, watermark_task.get_max_pixels())?.dimension)
+        }
+        _ => None,
+    };
+
+    if format == FileFormat::WebP && is_animated_webp(bytes) {
+        set_target_webp_animated(watermark_task, bytes)?;
+    } else {
+        let target = load_from_memory_with_format(bytes, format.get_image_format())?;
+        watermark_task.set_target(Some(target));
+        watermark_task.set_target_frames(None);
+        watermark_task.set_target_source(None);
+        watermark_task.set_target_format(Some(format));
+        watermark_task.set_target_metadata(read_metadata(bytes));
+    }
+
+    watermark_task.set_target_dimension(dimension);
 
     Ok(())
 }
@@ -258,15 +619,15 @@ impl TryFrom<&[u8]> for Dimension {
         let w = value.get(0..4);
         let h = value.get(4..8);
 
-        return match (w, h) {
+        match (w, h) {
             (Some(w), Some(h)) => {
                 let width = le_to_u32(w);
                 let height = le_to_u32(h);
 
-                return Ok(Dimension { height, width });
+                Ok(Dimension { height, width })
             }
             _ => Err(ConversionError::ConversionError),
-        };
+        }
     }
 }
 
@@ -277,15 +638,15 @@ impl TryFrom<&[u8]> for Point {
         let x = value.get(0..4);
         let y = value.get(4..8);
 
-        return match (x, y) {
+        match (x, y) {
             (Some(x), Some(y)) => {
                 let x_pos = le_to_u32(x);
                 let y_pos = le_to_u32(y);
 
-                return Ok(Point { x: x_pos, y: y_pos });
+                Ok(Point { x: x_pos, y: y_pos })
             }
             _ => Err(ConversionError::ConversionError),
-        };
+        }
     }
 }
 
@@ -296,8 +657,8 @@ mod tests {
     #[test]
     fn test_jpeg_watermark_task() {
         let mut watermark_task = WatermarkTask::new();
-        let watermark = image::open("../watermark.webp").unwrap();
-        let img = image::open("../test.webp").unwrap();
+        let watermark = image::open(fixture!("watermark.webp")).unwrap();
+        let img = image::open(fixture!("test.webp")).unwrap();
 
         watermark_task.set_target(Some(img));
         watermark_task.set_watermark(Some(watermark));
@@ -329,4 +690,107 @@ mod tests {
         // // Write the contents of this image to the Writer in PNG format.
         // img.save("../testx.jpeg").unwrap();
     }
+
+    #[test]
+    fn test_set_target_auto() {
+        let mut watermark_task = WatermarkTask::new();
+        let content = std::fs::read(fixture!("test.jpeg")).unwrap();
+        let result = set_target_auto(&mut watermark_task, &content);
+
+        assert!(result.is_ok());
+        assert_eq!(watermark_task.get_target_format(), Some(FileFormat::Jpeg));
+        assert!(watermark_task.get_target_dimension().is_some());
+
+        let content = vec![0x89u8, 0x50, 0x4E, 0x47];
+        let result = set_target_auto(&mut watermark_task, &content);
+
+        assert!(result.is_err());
+        assert_eq!(watermark_task.get_target_format(), Some(FileFormat::Jpeg));
+
+        let mut content = vec![0u8, 0, 0, 0x10];
+        content.extend("ftypavif".as_bytes());
+        content.extend([0, 0, 0, 0]);
+        let result = set_target_auto(&mut watermark_task, &content);
+
+        assert!(result.is_err());
+        assert_eq!(watermark_task.get_target_format(), Some(FileFormat::Jpeg));
+    }
+
+    #[test]
+    fn set_target_auto_takes_probed_dimension() {
+        let mut watermark_task = WatermarkTask::new();
+        let content = std::fs::read(fixture!("test.webp")).unwrap();
+        let probe = probe_with_limit(&content, u64::MAX).unwrap();
+
+        set_target_auto(&mut watermark_task, &content).unwrap();
+
+        assert_eq!(watermark_task.get_target_dimension(), Some(probe.dimension));
+
+        let mut watermark_task = WatermarkTask::new();
+        watermark_task.set_max_pixels(probe.get_pixel_count() - 1);
+        let result = set_target_auto(&mut watermark_task, &content);
+
+        assert!(result.is_err());
+        assert!(watermark_task.target.is_none());
+        assert_eq!(watermark_task.get_target_dimension(), None);
+    }
+
+    #[test]
+    fn test_output_metadata_thumbnail_policy() {
+        use crate::metadata::write_metadata;
+        use crate::tiff_container::{ByteOrder, TIFFContainer, TIFFIFD};
+        use image::{Rgb, RgbImage, Rgba, RgbaImage};
+
+        let encode = |img: DynamicImage| {
+            let mut bytes: Vec<u8> = Vec::new();
+            img.write_to(&mut Cursor::new(&mut bytes), ImageFormat::Jpeg)
+                .unwrap();
+            bytes
+        };
+        let thumbnail = encode(DynamicImage::ImageRgb8(RgbImage::from_pixel(
+            16,
+            12,
+            Rgb([255, 255, 255]),
+        )));
+        let exif = TIFFContainer::new(ByteOrder::LittleEndian, vec![TIFFIFD::new(Vec::new())]);
+        let exif = set_exif_thumbnail(&exif.to_bytes(), Some(&thumbnail)).unwrap();
+        let metadata = ImageMetadata {
+            exif: Some(exif),
+            ..Default::default()
+        };
+        let target = encode(DynamicImage::ImageRgb8(RgbImage::new(64, 48)));
+        let target = write_metadata(&target, &metadata).unwrap();
+        let watermark = RgbaImage::from_pixel(16, 16, Rgba([255, 0, 0, 255]));
+        let mut watermark_task = WatermarkTask::new();
+
+        set_target_auto(&mut watermark_task, &target).unwrap();
+        watermark_task.set_watermark(Some(DynamicImage::ImageRgba8(watermark)));
+        watermark_task.set_position(0, 0, OriginX::Left, OriginY::Top);
+        watermark_task.process().unwrap();
+
+        let output_thumbnail = |watermark_task: &WatermarkTask| {
+            let metadata = watermark_task.get_output_metadata().unwrap();
+            get_exif_thumbnail(&metadata.exif.unwrap())
+                .map(|f| load_from_memory_with_format(&f, ImageFormat::Jpeg).unwrap())
+        };
+
+        assert!(output_thumbnail(&watermark_task).is_none());
+
+        watermark_task.set_thumbnail_policy(ThumbnailPolicy::Keep);
+        let kept = output_thumbnail(&watermark_task).unwrap().to_rgb8();
+
+        assert!(kept.get_pixel(1, 1)[1] > 200);
+
+        watermark_task.set_thumbnail_policy(ThumbnailPolicy::Regenerate);
+        let regenerated = output_thumbnail(&watermark_task).unwrap().to_rgb8();
+
+        assert_eq!(regenerated.dimensions(), (16, 12));
+        assert!(regenerated.get_pixel(15, 11)[0] < 50);
+
+        watermark_task.set_thumbnail_policy(ThumbnailPolicy::Watermark);
+        let watermarked = output_thumbnail(&watermark_task).unwrap().to_rgb8();
+
+        assert!(watermarked.get_pixel(1, 1)[0] > 200 && watermarked.get_pixel(1, 1)[1] < 80);
+        assert!(watermarked.get_pixel(12, 9)[1] > 200);
+    }
 }
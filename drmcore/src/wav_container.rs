@@ -0,0 +1,431 @@
+use std::convert::TryFrom;
+use std::f32::consts::PI;
+use thiserror::Error;
+
+use crate::file_joiner::{le_to_u32, usize_to_le};
+use crate::webp_container::{Chunk, RIFFContainer, RegularChunk, RiffChunk};
+
+pub const CUSTOM_CHUNK_ID: &str = "milf";
+pub const TONE_FREQUENCY: f32 = 1000.0;
+pub const TONE_AMPLITUDE: f32 = 0.25;
+
+const PCM_FORMAT: u16 = 1;
+const FLOAT_FORMAT: u16 = 3;
+const EXTENSIBLE_FORMAT: u16 = 0xFFFE;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum WavContainerError {
+    #[error("Invalid wave file")]
+    InvalidWaveFile,
+
+    #[error("Missing chunk `{0}`")]
+    MissingChunk(String),
+
+    #[error("Invalid fmt chunk")]
+    InvalidFormatChunk,
+
+    #[error("Unsupported sample format")]
+    UnsupportedSampleFormat,
+
+    #[error("Range is outside of the samples")]
+    InvalidRange,
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct WaveFormat {
+    pub audio_format: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub byte_rate: u32,
+    pub block_align: u16,
+    pub bits_per_sample: u16,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct SampleSection {
+    pub offset: u32,
+    pub samples: Vec<u8>,
+}
+
+pub struct WAVContainer {
+    container: RIFFContainer,
+    format: WaveFormat,
+}
+
+pub fn is_wav(inp: &[u8]) -> bool {
+    inp.get(0..4) == Some("RIFF".as_bytes()) && inp.get(8..12) == Some("WAVE".as_bytes())
+}
+
+impl WaveFormat {
+    fn get_sample_size(&self) -> usize {
+        (self.bits_per_sample as usize).div_ceil(8)
+    }
+
+    fn read_sample(&self, bytes: &[u8]) -> f32 {
+        match (self.audio_format, self.bits_per_sample) {
+            (PCM_FORMAT, 8) => (bytes[0] as f32 - 128.0) / 128.0,
+            (PCM_FORMAT, 16) => i16::from_le_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+            (PCM_FORMAT, 24) => {
+                let value = i32::from_le_bytes([0, bytes[0], bytes[1], bytes[2]]) >> 8;
+                value as f32 / 8388608.0
+            }
+            (PCM_FORMAT, 32) => {
+                i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32 / 2147483648.0
+            }
+            _ => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+
+    fn write_sample(&self, value: f32, bytes: &mut [u8]) {
+        let value = value.clamp(-1.0, 1.0);
+
+        match (self.audio_format, self.bits_per_sample) {
+            (PCM_FORMAT, 8) => bytes[0] = (value * 127.0 + 128.0).round() as u8,
+            (PCM_FORMAT, 16) => {
+                bytes.copy_from_slice(&((value * 32767.0).round() as i16).to_le_bytes())
+            }
+            (PCM_FORMAT, 24) => {
+                let value = ((value * 8388607.0).round() as i32).to_le_bytes();
+                bytes.copy_from_slice(&value[0..3]);
+            }
+            (PCM_FORMAT, 32) => {
+                let value = (value as f64 * 2147483647.0).round() as i32;
+                bytes.copy_from_slice(&value.to_le_bytes());
+            }
+            _ => bytes.copy_from_slice(&value.to_le_bytes()),
+        }
+    }
+
+    // A frame holds exactly one sample per channel, anything else splits samples across frames
+    fn is_supported(&self) -> bool {
+        let sample_format = matches!(
+            (self.audio_format, self.bits_per_sample),
+            (PCM_FORMAT, 8)
+                | (PCM_FORMAT, 16)
+                | (PCM_FORMAT, 24)
+                | (PCM_FORMAT, 32)
+                | (FLOAT_FORMAT, 32)
+        );
+
+        sample_format
+            && self.block_align as usize == self.channels as usize * self.get_sample_size()
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+
+        result.extend(self.audio_format.to_le_bytes());
+        result.extend(self.channels.to_le_bytes());
+        result.extend(self.sample_rate.to_le_bytes());
+        result.extend(self.byte_rate.to_le_bytes());
+        result.extend(self.block_align.to_le_bytes());
+        result.extend(self.bits_per_sample.to_le_bytes());
+
+        result
+    }
+}
+
+impl TryFrom<&[u8]> for WaveFormat {
+    type Error = WavContainerError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let data = value
+            .get(0..16)
+            .ok_or(WavContainerError::InvalidFormatChunk)?;
+        let format = WaveFormat {
+            audio_format: u16::from_le_bytes([data[0], data[1]]),
+            channels: u16::from_le_bytes([data[2], data[3]]),
+            sample_rate: le_to_u32(&data[4..8]),
+            byte_rate: le_to_u32(&data[8..12]),
+            block_align: u16::from_le_bytes([data[12], data[13]]),
+            bits_per_sample: u16::from_le_bytes([data[14], data[15]]),
+        };
+
+        if format.channels == 0 || format.block_align == 0 {
+            return Err(WavContainerError::InvalidFormatChunk);
+        }
+
+        // Extensible fmt carries the actual format tag in the sub format GUID
+        if format.audio_format == EXTENSIBLE_FORMAT {
+            let sub_format = value
+                .get(24..26)
+                .ok_or(WavContainerError::InvalidFormatChunk)?;
+
+            return Ok(WaveFormat {
+                audio_format: u16::from_le_bytes([sub_format[0], sub_format[1]]),
+                ..format
+            });
+        }
+
+        Ok(format)
+    }
+}
+
+impl From<&SampleSection> for Vec<u8> {
+    fn from(value: &SampleSection) -> Self {
+        let mut result = value.samples.clone();
+        result.extend(usize_to_le(value.offset as usize));
+
+        result
+    }
+}
+
+impl TryFrom<&[u8]> for SampleSection {
+    type Error = WavContainerError;
+
+    fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
+        let len = value.len();
+
+        if len < 4 {
+            return Err(WavContainerError::InvalidRange);
+        }
+
+        Ok(SampleSection {
+            offset: le_to_u32(&value[len - 4..]),
+            samples: Vec::from(&value[0..len - 4]),
+        })
+    }
+}
+
+impl WAVContainer {
+    pub fn get_format(&self) -> &WaveFormat {
+        &self.format
+    }
+
+    pub fn get_samples(&self) -> Vec<u8> {
+        self.container
+            .find_subchunk("data")
+            .map(|f| f.get_chunk_bytes())
+            .unwrap_or_default()
+    }
+
+    pub fn set_samples(&mut self, samples: Vec<u8>) {
+        self.container.replace_subchunk(Box::new(RegularChunk {
+            chunk_id: String::from("data"),
+            chunk_data: samples,
+        }));
+    }
+
+    pub fn get_duration_ms(&self) -> u64 {
+        let frames = self.get_samples().len() as u64 / self.format.block_align as u64;
+
+        frames * 1000 / self.format.sample_rate.max(1) as u64
+    }
+
+    pub fn find_chunk(&self, chunk_id: &str) -> Option<&dyn Chunk> {
+        self.container.find_subchunk(chunk_id)
+    }
+
+    pub fn push_chunk(&mut self, chunk: Box<dyn Chunk>) {
+        self.container.push_subchunk(chunk);
+    }
+
+    pub fn remove_chunk(&mut self, chunk_id: &str) -> Option<Box<dyn Chunk>> {
+        self.container.remove_subchunk(chunk_id)
+    }
+
+    // Byte range of the data chunk, aligned to whole sample frames
+    pub fn get_byte_range(&self, start_ms: u32, duration_ms: u32) -> Option<(usize, usize)> {
+        let block_align = self.format.block_align as usize;
+        let frames_per_ms = self.format.sample_rate as f64 / 1000.0;
+        let start = ((start_ms as f64 * frames_per_ms) as usize).checked_mul(block_align)?;
+        let length = ((duration_ms as f64 * frames_per_ms) as usize).checked_mul(block_align)?;
+        let samples_len = self.get_samples().len();
+
+        if length == 0 || start.checked_add(length)? > samples_len - samples_len % block_align {
+            return None;
+        }
+
+        Some((start, length))
+    }
+
+    pub fn overlay_tone(
+        &mut self,
+        start_ms: u32,
+        duration_ms: u32,
+        frequency: f32,
+        amplitude: f32,
+    ) -> Result<SampleSection, WavContainerError> {
+        if !self.format.is_supported() {
+            return Err(WavContainerError::UnsupportedSampleFormat);
+        }
+
+        let (start, length) = self
+            .get_byte_range(start_ms, duration_ms)
+            .ok_or(WavContainerError::InvalidRange)?;
+        let mut samples = self.get_samples();
+        let original = Vec::from(&samples[start..start + length]);
+        let sample_size = self.format.get_sample_size();
+        let block_align = self.format.block_align as usize;
+        let sample_rate = self.format.sample_rate as f32;
+
+        for (frame, block) in samples[start..start + length]
+            .chunks_mut(block_align)
+            .enumerate()
+        {
+            let tone = amplitude * (2.0 * PI * frequency * frame as f32 / sample_rate).sin();
+
+            for sample in block.chunks_mut(sample_size) {
+                let value = self.format.read_sample(sample);
+                self.format.write_sample(value + tone, sample);
+            }
+        }
+
+        self.set_samples(samples);
+
+        Ok(SampleSection {
+            offset: start as u32,
+            samples: original,
+        })
+    }
+
+    pub fn restore_section(&mut self, section: &SampleSection) -> Result<(), WavContainerError> {
+        let mut samples = self.get_samples();
+        let start = section.offset as usize;
+        let end = start
+            .checked_add(section.samples.len())
+            .ok_or(WavContainerError::InvalidRange)?;
+        let target = samples
+            .get_mut(start..end)
+            .ok_or(WavContainerError::InvalidRange)?;
+
+        target.copy_from_slice(&section.samples);
+        self.set_samples(samples);
+
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.container.to_bytes()
+    }
+}
+
+impl TryFrom<&Vec<u8>> for WAVContainer {
+    type Error = WavContainerError;
+
+    fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
+        let container =
+            RIFFContainer::try_from(value).map_err(|_| WavContainerError::InvalidWaveFile)?;
+
+        if container.get_chunk_frame_id() != "WAVE" {
+            return Err(WavContainerError::InvalidWaveFile);
+        }
+
+        let fmt_chunk = container
+            .find_subchunk("fmt ")
+            .ok_or(WavContainerError::MissingChunk(String::from("fmt ")))?;
+        let format = WaveFormat::try_from(&fmt_chunk.get_chunk_bytes()[..])?;
+
+        if container.find_subchunk("data").is_none() {
+            return Err(WavContainerError::MissingChunk(String::from("data")));
+        }
+
+        Ok(WAVContainer { container, format })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pcm16_wav(samples: &[i16]) -> Vec<u8> {
+        let format = WaveFormat {
+            audio_format: PCM_FORMAT,
+            channels: 1,
+            sample_rate: 1000,
+            byte_rate: 2000,
+            block_align: 2,
+            bits_per_sample: 16,
+        };
+        let data: Vec<u8> = samples.iter().flat_map(|f| f.to_le_bytes()).collect();
+        let container = RIFFContainer::new(
+            "WAVE",
+            vec![
+                Box::new(RegularChunk {
+                    chunk_id: String::from("fmt "),
+                    chunk_data: format.to_bytes(),
+                }),
+                Box::new(RegularChunk {
+                    chunk_id: String::from("data"),
+                    chunk_data: data,
+                }),
+            ],
+        );
+
+        container.to_bytes()
+    }
+
+    #[test]
+    fn try_from_vec_to_wav_container_success() {
+        let bytes = pcm16_wav(&[0; 500]);
+        let container = WAVContainer::try_from(&bytes).unwrap();
+        let format = container.get_format();
+
+        assert!(is_wav(&bytes));
+        assert_eq!(format.channels, 1);
+        assert_eq!(format.sample_rate, 1000);
+        assert_eq!(format.bits_per_sample, 16);
+        assert_eq!(container.get_samples().len(), 1000);
+        assert_eq!(container.get_duration_ms(), 500);
+        assert_eq!(container.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn try_from_vec_to_wav_container_failed() {
+        let mut bytes = pcm16_wav(&[0; 4]);
+        bytes[8..12].copy_from_slice("WEBP".as_bytes());
+
+        match WAVContainer::try_from(&bytes) {
+            Ok(_) => panic!("Should failed, frame id is not WAVE"),
+            Err(err) => assert_eq!(err, WavContainerError::InvalidWaveFile),
+        }
+
+        let container = RIFFContainer::new("WAVE", Vec::new());
+
+        match WAVContainer::try_from(&container.to_bytes()) {
+            Ok(_) => panic!("Should failed, fmt chunk is missing"),
+            Err(err) => assert_eq!(err, WavContainerError::MissingChunk(String::from("fmt "))),
+        }
+    }
+
+    #[test]
+    fn overlay_tone_and_restore() {
+        let original: Vec<i16> = (0..1000).map(|f| (f % 100) as i16).collect();
+        let bytes = pcm16_wav(&original);
+        let mut container = WAVContainer::try_from(&bytes).unwrap();
+        let section = container.overlay_tone(100, 200, 250.0, 0.5).unwrap();
+
+        assert_eq!(section.offset, 200);
+        assert_eq!(section.samples.len(), 400);
+        assert_eq!(container.get_samples()[0..200], bytes[44..244]);
+        assert_ne!(container.get_samples()[200..600], section.samples[..]);
+
+        let section_bytes: Vec<u8> = (&section).into();
+        let parsed = SampleSection::try_from(&section_bytes[..]).unwrap();
+
+        assert_eq!(parsed, section);
+
+        container.restore_section(&parsed).unwrap();
+
+        assert_eq!(container.to_bytes(), bytes);
+        assert_eq!(
+            container.overlay_tone(900, 200, 250.0, 0.5),
+            Err(WavContainerError::InvalidRange)
+        );
+        assert_eq!(container.get_byte_range(u32::MAX, u32::MAX), None);
+    }
+
+    #[test]
+    fn overlay_tone_rejects_mismatched_block_align() {
+        let mut bytes = pcm16_wav(&[0; 500]);
+        // block_align of 3 for mono 16 bit
+        bytes[32..34].copy_from_slice(&3u16.to_le_bytes());
+        let mut container = WAVContainer::try_from(&bytes).unwrap();
+
+        assert_eq!(container.get_format().block_align, 3);
+        assert_eq!(
+            container.overlay_tone(0, 100, 250.0, 0.5),
+            Err(WavContainerError::UnsupportedSampleFormat)
+        );
+    }
+}
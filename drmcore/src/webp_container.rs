@@ -1,8 +1,10 @@
+use std::any::Any;
 use std::convert::TryFrom;
+use std::io::{self, Write};
 use std::str;
 use thiserror::Error;
 
-use crate::file_joiner::{le_to_u32, usize_to_le};
+use crate::file_joiner::{le_to_u32, usize_to_le, PayloadPlacement};
 
 #[derive(Debug, Error, PartialEq)]
 pub enum RiffContainerError {
@@ -14,8 +16,21 @@ pub enum RiffContainerError {
 
     #[error("Missing header")]
     MissingHeader,
+
+    #[error("Cannot determine canvas size")]
+    UnknownCanvasSize,
 }
 
+pub const CUSTOM_CHUNK_ID: &str = "milf";
+
+pub const VP8X_ICC_FLAG: u8 = 0x20;
+pub const VP8X_ALPHA_FLAG: u8 = 0x10;
+pub const VP8X_EXIF_FLAG: u8 = 0x08;
+pub const VP8X_XMP_FLAG: u8 = 0x04;
+pub const VP8X_ANIMATION_FLAG: u8 = 0x02;
+
+const IMAGE_CHUNK_IDS: [&str; 4] = ["ALPH", "VP8 ", "VP8L", "ANMF"];
+
 #[derive(Debug, Error, PartialEq)]
 pub enum ChunkError {
     #[error("Invalid chunk")]
@@ -36,160 +51,932 @@ pub struct RegularChunk {
     pub chunk_data: Vec<u8>,
 }
 
+// Bytes at the end of a chunk list that do not form a valid chunk
+#[derive(PartialEq, Debug)]
+pub struct UnparsedData {
+    pub data: Vec<u8>,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct VP8XChunk {
+    pub flags: u8,
+    pub canvas_width: u32,
+    pub canvas_height: u32,
+}
+
+#[derive(PartialEq, Debug)]
+pub struct AnimChunk {
+    pub background_color: [u8; 4],
+    pub loop_count: u16,
+}
+
+pub struct AnmfChunk {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub duration: u32,
+    pub blend: bool,
+    pub dispose: bool,
+    pub frame_chunks: Vec<Box<dyn Chunk>>,
+}
+
 pub trait Chunk {
     fn get_chunk_id(&self) -> &str;
     fn get_chunk_size(&self) -> usize;
     fn get_chunk_data(&self) -> Option<&Vec<Box<dyn Chunk>>>;
     fn get_chunk_bytes(&self) -> Vec<u8>;
     fn to_bytes(&self) -> Vec<u8>;
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+
+    // Bytes taken by the written chunk, header and pad byte included
+    fn get_total_size(&self) -> usize {
+        let chunk_size = self.get_chunk_size();
+
+        8 + chunk_size + chunk_size % 2
+    }
+
+    fn write_chunk(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(&self.to_bytes())
+    }
+}
+
+// Size from the VP8 frame header or the VP8L header, alpha of a lossy image is in its ALPH chunk
+fn read_bitstream_info(chunk_id: &str, data: &[u8]) -> Option<(u32, u32, bool)> {
+    match chunk_id {
+        "VP8 " if data.get(3..6) == Some(&[0x9D, 0x01, 0x2A]) => {
+            let width = le_to_u32(data.get(6..8)?) & 0x3FFF;
+            let height = le_to_u32(data.get(8..10)?) & 0x3FFF;
+
+            // A canvas cannot be empty, VP8X stores its dimensions minus one
+            if width == 0 || height == 0 {
+                return None;
+            }
+
+            Some((width, height, false))
+        }
+        "VP8L" if data.first() == Some(&0x2F) => {
+            let bits = le_to_u32(data.get(1..5)?);
+            let width = (bits & 0x3FFF) + 1;
+            let height = ((bits >> 14) & 0x3FFF) + 1;
+
+            Some((width, height, (bits >> 28) & 1 == 1))
+        }
+        _ => None,
+    }
+}
+
+fn write_chunk_header(writer: &mut dyn Write, chunk_id: &str, chunk_size: usize) -> io::Result<()> {
+    let chunk_size = u32::try_from(chunk_size)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Chunk too large"))?;
+
+    writer.write_all(chunk_id.as_bytes())?;
+    writer.write_all(&chunk_size.to_le_bytes())
+}
+
+fn write_chunk_pad(writer: &mut dyn Write, chunk_size: usize) -> io::Result<()> {
+    if chunk_size % 2 == 1 {
+        writer.write_all(&[0x00])?;
+    }
+
+    Ok(())
+}
+
+fn chunk_to_vec(chunk: &dyn Chunk) -> Vec<u8> {
+    let mut result = Vec::with_capacity(chunk.get_total_size());
+
+    // Writing into a Vec only fails for chunks over 4 GiB, which cannot be parsed either
+    chunk.write_chunk(&mut result).unwrap();
+    result
+}
+
+pub trait RiffChunk {
+    fn get_chunk_frame_id(&self) -> &str;
+}
+
+impl RIFFContainer {
+    pub fn generate_sub_chunk(inp: &[u8]) -> Vec<Box<dyn Chunk>> {
+        let (chunks, unparsed) = parse_chunks(inp);
+
+        RIFFContainer::generate_owned_chunks(&chunks, unparsed)
+    }
+
+    fn generate_owned_chunks(chunks: &[RIFFChunkRef], unparsed: &[u8]) -> Vec<Box<dyn Chunk>> {
+        let mut result: Vec<Box<dyn Chunk>> = chunks
+            .iter()
+            .map(RIFFContainer::generate_typed_chunk)
+            .collect();
+
+        if !unparsed.is_empty() {
+            result.push(Box::new(UnparsedData {
+                data: Vec::from(unparsed),
+            }));
+        }
+
+        result
+    }
+
+    // Reserved bits are not kept by the typed chunks, chunks that set them stay regular chunks so
+    // they are written back unchanged
+    fn generate_typed_chunk(chunk: &RIFFChunkRef) -> Box<dyn Chunk> {
+        let data = chunk.chunk_data;
+        let is_canonical = match chunk.chunk_id {
+            "VP8X" => data.get(1..4) == Some(&[0, 0, 0]),
+            "ANMF" => data.get(15).is_some_and(|f| f & !0b11 == 0),
+            _ => true,
+        };
+        let typed_chunk: Option<Box<dyn Chunk>> = match chunk.chunk_id {
+            _ if !is_canonical => None,
+            "VP8X" => VP8XChunk::try_from(chunk)
+                .ok()
+                .map(|f| Box::new(f) as Box<dyn Chunk>),
+            "ANIM" => AnimChunk::try_from(chunk)
+                .ok()
+                .map(|f| Box::new(f) as Box<dyn Chunk>),
+            "ANMF" => AnmfChunk::try_from(chunk)
+                .ok()
+                .map(|f| Box::new(f) as Box<dyn Chunk>),
+            _ => None,
+        };
+
+        typed_chunk.unwrap_or_else(|| Box::new(RegularChunk::from(chunk)))
+    }
+
+    pub fn new(frame_id: &str, subchunks: Vec<Box<dyn Chunk>>) -> Self {
+        Self {
+            frame_id: String::from(frame_id),
+            subchunks,
+        }
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.write_chunk(writer)
+    }
+
+    // Unparsed trailing bytes stay at the end
+    fn get_end_position(&self) -> usize {
+        match self.subchunks.last() {
+            Some(last) if last.as_any().is::<UnparsedData>() => self.subchunks.len() - 1,
+            _ => self.subchunks.len(),
+        }
+    }
+
+    pub fn push_subchunk(&mut self, chunk: Box<dyn Chunk>) {
+        let index = self.get_end_position();
+
+        self.subchunks.insert(index, chunk);
+    }
+
+    // Early placement goes in front of the image data but never in front of VP8X, ICCP or ANIM,
+    // which have to come first in an extended file even when a writer put them later. Decoders
+    // that only skip unknown chunks at the end of the file, like the one in the image crate,
+    // cannot decode such a file as a whole
+    pub fn get_payload_position(&self, placement: PayloadPlacement) -> usize {
+        let end = self.get_end_position();
+        let find_last = |chunk_ids: &[&str]| {
+            self.subchunks
+                .iter()
+                .rposition(|f| chunk_ids.contains(&f.get_chunk_id()))
+                .map(|f| f + 1)
+        };
+
+        match placement {
+            PayloadPlacement::Early => {
+                let header_end = find_last(&["VP8X", "ICCP", "ANIM"]).unwrap_or(0);
+                let image_start = self
+                    .subchunks
+                    .iter()
+                    .position(|f| IMAGE_CHUNK_IDS.contains(&f.get_chunk_id()))
+                    .unwrap_or(header_end);
+
+                image_start.max(header_end)
+            }
+            PayloadPlacement::AfterMetadata => find_last(&["ICCP", "EXIF", "XMP "]).unwrap_or(end),
+            PayloadPlacement::End => end,
+        }
+    }
+
+    pub fn insert_subchunk(&mut self, index: usize, chunk: Box<dyn Chunk>) {
+        let index = index.min(self.subchunks.len());
+
+        self.subchunks.insert(index, chunk);
+    }
+
+    pub fn remove_subchunk(&mut self, chunk_id: &str) -> Option<Box<dyn Chunk>> {
+        let position = self
+            .subchunks
+            .iter()
+            .position(|p| p.get_chunk_id() == chunk_id)?;
+
+        Some(self.subchunks.remove(position))
+    }
+
+    pub fn get_anim(&self) -> Option<&AnimChunk> {
+        self.subchunks
+            .iter()
+            .find_map(|f| f.as_any().downcast_ref::<AnimChunk>())
+    }
+
+    pub fn get_frames(&self) -> Vec<&AnmfChunk> {
+        self.subchunks
+            .iter()
+            .filter_map(|f| f.as_any().downcast_ref::<AnmfChunk>())
+            .collect()
+    }
+
+    pub fn get_frames_mut(&mut self) -> Vec<&mut AnmfChunk> {
+        self.subchunks
+            .iter_mut()
+            .filter_map(|f| f.as_any_mut().downcast_mut::<AnmfChunk>())
+            .collect()
+    }
+
+    // By chunk id, so frames with chunks kept as regular chunks still count
+    pub fn is_animated(&self) -> bool {
+        self.has_subchunk("ANIM") || self.has_subchunk("ANMF")
+    }
+
+    pub fn get_vp8x(&self) -> Option<&VP8XChunk> {
+        self.subchunks
+            .iter()
+            .find_map(|f| f.as_any().downcast_ref::<VP8XChunk>())
+    }
+
+    fn has_subchunk(&self, chunk_id: &str) -> bool {
+        self.subchunks.iter().any(|p| p.get_chunk_id() == chunk_id)
+    }
+
+    // Canvas size and alpha usage from the VP8 / VP8L bitstream header
+    fn get_bitstream_info(&self) -> Option<(u32, u32, bool)> {
+        let (width, height, has_alpha) = self
+            .subchunks
+            .iter()
+            .find_map(|f| read_bitstream_info(f.get_chunk_id(), &f.get_chunk_bytes()))?;
+
+        Some((width, height, has_alpha || self.has_subchunk("ALPH")))
+    }
+
+    // Unknown chunks are only allowed in the extended format, so add or refresh VP8X
+    pub fn ensure_extended(&mut self) -> Result<(), RiffContainerError> {
+        let mut flags = 0u8;
+        let flag_chunks = [
+            ("ICCP", VP8X_ICC_FLAG),
+            ("EXIF", VP8X_EXIF_FLAG),
+            ("XMP ", VP8X_XMP_FLAG),
+            ("ANIM", VP8X_ANIMATION_FLAG),
+            ("ALPH", VP8X_ALPHA_FLAG),
+        ];
+
+        for (chunk_id, flag) in flag_chunks {
+            if self.has_subchunk(chunk_id) {
+                flags |= flag;
+            }
+        }
+
+        let vp8x = match self.get_vp8x() {
+            Some(vp8x) => VP8XChunk {
+                flags: flags | (vp8x.flags & VP8X_ALPHA_FLAG),
+                canvas_width: vp8x.canvas_width,
+                canvas_height: vp8x.canvas_height,
+            },
+            None => {
+                let (canvas_width, canvas_height, has_alpha) = self
+                    .get_bitstream_info()
+                    .ok_or(RiffContainerError::UnknownCanvasSize)?;
+
+                if has_alpha {
+                    flags |= VP8X_ALPHA_FLAG;
+                }
+
+                VP8XChunk {
+                    flags,
+                    canvas_width,
+                    canvas_height,
+                }
+            }
+        };
+
+        self.remove_subchunk("VP8X");
+        self.subchunks.insert(0, Box::new(vp8x));
+
+        Ok(())
+    }
+
+    pub fn replace_subchunk(&mut self, chunk: Box<dyn Chunk>) -> bool {
+        let position = self
+            .subchunks
+            .iter()
+            .position(|p| p.get_chunk_id() == chunk.get_chunk_id());
+
+        match position {
+            Some(position) => {
+                self.subchunks[position] = chunk;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn find_subchunk(&self, chunk_id: &str) -> Option<&dyn Chunk> {
+        let output = self.subchunks.iter().find(|p| {
+            let x = *p;
+            x.get_chunk_id() == chunk_id
+        });
+
+        if let Some(chunk) = output {
+            return Some(chunk.as_ref());
+        }
+
+        None
+    }
+}
+
+impl RiffChunk for RIFFContainer {
+    fn get_chunk_frame_id(&self) -> &str {
+        self.frame_id.as_str()
+    }
+}
+
+impl Chunk for RIFFContainer {
+    fn get_chunk_id(&self) -> &str {
+        "RIFF"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn get_chunk_data(&self) -> Option<&Vec<Box<dyn Chunk>>> {
+        Some(&self.subchunks)
+    }
+
+    fn get_chunk_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::new();
+        let subchunk = &self.subchunks;
+
+        subchunk.iter().for_each(|f| {
+            let bytes = f.to_bytes();
+            bytes.iter().for_each(|g| result.push(*g));
+        });
+
+        result
+    }
+
+    fn get_chunk_size(&self) -> usize {
+        let chunk_size: usize = self.subchunks.iter().map(|f| f.get_total_size()).sum();
+        chunk_size + 4 // 4 bytes from frame id
+    }
+
+    // The RIFF chunk has no pad byte, its size covers the whole file
+    fn get_total_size(&self) -> usize {
+        8 + self.get_chunk_size()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        chunk_to_vec(self)
+    }
+
+    fn write_chunk(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_chunk_header(writer, self.get_chunk_id(), self.get_chunk_size())?;
+        writer.write_all(self.frame_id.as_bytes())?;
+        self.subchunks.iter().try_for_each(|f| f.write_chunk(writer))
+    }
+}
+
+impl Chunk for RegularChunk {
+    fn get_chunk_data(&self) -> Option<&Vec<Box<dyn Chunk>>> {
+        None
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn get_chunk_bytes(&self) -> Vec<u8> {
+        self.chunk_data.clone()
+    }
+
+    fn get_chunk_id(&self) -> &str {
+        &self.chunk_id
+    }
+
+    fn get_chunk_size(&self) -> usize {
+        self.chunk_data.len()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        chunk_to_vec(self)
+    }
+
+    fn write_chunk(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_chunk_header(writer, self.get_chunk_id(), self.chunk_data.len())?;
+        writer.write_all(&self.chunk_data)?;
+        write_chunk_pad(writer, self.chunk_data.len())
+    }
+}
+
+impl Chunk for UnparsedData {
+    fn get_chunk_data(&self) -> Option<&Vec<Box<dyn Chunk>>> {
+        None
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+
+    fn get_chunk_bytes(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    fn get_chunk_id(&self) -> &str {
+        ""
+    }
+
+    fn get_chunk_size(&self) -> usize {
+        self.data.len()
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.data.clone()
+    }
+
+    fn get_total_size(&self) -> usize {
+        self.data.len()
+    }
+
+    fn write_chunk(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(&self.data)
+    }
+}
+
+fn chunk_to_bytes(chunk_id: &str, chunk_bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::new();
+
+    result.extend(chunk_id.as_bytes());
+    result.extend(usize_to_le(chunk_bytes.len()));
+    result.extend(chunk_bytes);
+
+    if chunk_bytes.len() % 2 == 1 {
+        result.push(0x00);
+    }
+
+    result
+}
+
+impl Chunk for VP8XChunk {
+    fn get_chunk_id(&self) -> &str {
+        "VP8X"
+    }
+
+    fn get_chunk_size(&self) -> usize {
+        10
+    }
+
+    fn get_chunk_data(&self) -> Option<&Vec<Box<dyn Chunk>>> {
+        None
+    }
+
+    fn get_chunk_bytes(&self) -> Vec<u8> {
+        let mut result = vec![self.flags, 0, 0, 0];
+        result.extend(&usize_to_le(self.canvas_width as usize - 1)[0..3]);
+        result.extend(&usize_to_le(self.canvas_height as usize - 1)[0..3]);
+
+        result
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        chunk_to_bytes(self.get_chunk_id(), &self.get_chunk_bytes())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Chunk for AnimChunk {
+    fn get_chunk_id(&self) -> &str {
+        "ANIM"
+    }
+
+    fn get_chunk_size(&self) -> usize {
+        6
+    }
+
+    fn get_chunk_data(&self) -> Option<&Vec<Box<dyn Chunk>>> {
+        None
+    }
+
+    fn get_chunk_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::from(self.background_color);
+        result.extend(self.loop_count.to_le_bytes());
+
+        result
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        chunk_to_bytes(self.get_chunk_id(), &self.get_chunk_bytes())
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl AnmfChunk {
+    fn get_frame_header(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(16);
+
+        result.extend(&usize_to_le(self.x as usize / 2)[0..3]);
+        result.extend(&usize_to_le(self.y as usize / 2)[0..3]);
+        result.extend(&usize_to_le(self.width as usize - 1)[0..3]);
+        result.extend(&usize_to_le(self.height as usize - 1)[0..3]);
+        result.extend(&usize_to_le(self.duration as usize)[0..3]);
+        result.push(self.get_flags());
+
+        result
+    }
+
+    fn get_flags(&self) -> u8 {
+        let mut flags = 0u8;
+
+        if !self.blend {
+            flags |= 0b10;
+        }
+
+        if self.dispose {
+            flags |= 0b01;
+        }
+
+        flags
+    }
+
+    // Wrap the frame bitstream into a still webp, alpha with lossy data needs VP8X
+    pub fn to_webp(&self) -> Vec<u8> {
+        let mut subchunks: Vec<Box<dyn Chunk>> = Vec::new();
+        let has_alpha = self
+            .frame_chunks
+            .iter()
+            .any(|p| p.get_chunk_id() == "ALPH");
+
+        if has_alpha {
+            subchunks.push(Box::new(VP8XChunk {
+                flags: VP8X_ALPHA_FLAG,
+                canvas_width: self.width,
+                canvas_height: self.height,
+            }));
+        }
+
+        self.frame_chunks.iter().for_each(|f| {
+            subchunks.push(Box::new(RegularChunk {
+                chunk_id: String::from(f.get_chunk_id()),
+                chunk_data: f.get_chunk_bytes(),
+            }));
+        });
+
+        RIFFContainer::new("WEBP", subchunks).to_bytes()
+    }
+
+    pub fn set_frame_webp(&mut self, webp: &Vec<u8>) -> Result<(), RiffContainerError> {
+        let container = RIFFContainer::try_from(webp)?;
+        let frame_chunks: Vec<Box<dyn Chunk>> = container
+            .subchunks
+            .into_iter()
+            .filter(|p| matches!(p.get_chunk_id(), "ALPH" | "VP8 " | "VP8L"))
+            .collect();
+
+        if frame_chunks.is_empty() {
+            return Err(RiffContainerError::InvalidRiffFile);
+        }
+
+        self.frame_chunks = frame_chunks;
+
+        Ok(())
+    }
+}
+
+impl Chunk for AnmfChunk {
+    fn get_chunk_id(&self) -> &str {
+        "ANMF"
+    }
+
+    fn get_chunk_size(&self) -> usize {
+        let frame_size: usize = self.frame_chunks.iter().map(|f| f.get_total_size()).sum();
+        frame_size + 16 // 16 bytes of frame header
+    }
+
+    fn get_chunk_data(&self) -> Option<&Vec<Box<dyn Chunk>>> {
+        Some(&self.frame_chunks)
+    }
+
+    fn get_chunk_bytes(&self) -> Vec<u8> {
+        let mut result = self.get_frame_header();
+
+        self.frame_chunks.iter().for_each(|f| {
+            result.extend(f.to_bytes());
+        });
+
+        result
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        chunk_to_vec(self)
+    }
+
+    fn write_chunk(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let chunk_size = self.get_chunk_size();
+
+        write_chunk_header(writer, self.get_chunk_id(), chunk_size)?;
+        writer.write_all(&self.get_frame_header())?;
+        self.frame_chunks
+            .iter()
+            .try_for_each(|f| f.write_chunk(writer))?;
+        write_chunk_pad(writer, chunk_size)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl TryFrom<&Vec<u8>> for VP8XChunk {
+    type Error = ChunkError;
+
+    fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
+        VP8XChunk::try_from(&RIFFChunkRef::try_from(&value[..])?)
+    }
+}
+
+impl TryFrom<&RIFFChunkRef<'_>> for VP8XChunk {
+    type Error = ChunkError;
+
+    fn try_from(chunk: &RIFFChunkRef<'_>) -> Result<Self, Self::Error> {
+        let data = chunk.chunk_data;
+
+        if chunk.chunk_id != "VP8X" || data.len() != 10 {
+            return Err(ChunkError::InvalidChunk);
+        }
+
+        Ok(VP8XChunk {
+            flags: data[0],
+            canvas_width: le_to_u32(&data[4..7]) + 1,
+            canvas_height: le_to_u32(&data[7..10]) + 1,
+        })
+    }
+}
+
+impl TryFrom<&Vec<u8>> for AnimChunk {
+    type Error = ChunkError;
+
+    fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
+        AnimChunk::try_from(&RIFFChunkRef::try_from(&value[..])?)
+    }
+}
+
+impl TryFrom<&RIFFChunkRef<'_>> for AnimChunk {
+    type Error = ChunkError;
+
+    fn try_from(chunk: &RIFFChunkRef<'_>) -> Result<Self, Self::Error> {
+        let data = chunk.chunk_data;
+
+        if chunk.chunk_id != "ANIM" || data.len() != 6 {
+            return Err(ChunkError::InvalidChunk);
+        }
+
+        Ok(AnimChunk {
+            background_color: [data[0], data[1], data[2], data[3]],
+            loop_count: u16::from_le_bytes([data[4], data[5]]),
+        })
+    }
+}
+
+impl TryFrom<&Vec<u8>> for AnmfChunk {
+    type Error = ChunkError;
+
+    fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
+        AnmfChunk::try_from(&RIFFChunkRef::try_from(&value[..])?)
+    }
+}
+
+impl TryFrom<&RIFFChunkRef<'_>> for AnmfChunk {
+    type Error = ChunkError;
+
+    fn try_from(chunk: &RIFFChunkRef<'_>) -> Result<Self, Self::Error> {
+        let data = chunk.chunk_data;
+
+        if chunk.chunk_id != "ANMF" || data.len() < 16 {
+            return Err(ChunkError::InvalidChunk);
+        }
+
+        let flags = data[15];
+
+        Ok(AnmfChunk {
+            x: le_to_u32(&data[0..3]) * 2,
+            y: le_to_u32(&data[3..6]) * 2,
+            width: le_to_u32(&data[6..9]) + 1,
+            height: le_to_u32(&data[9..12]) + 1,
+            duration: le_to_u32(&data[12..15]),
+            blend: flags & 0b10 == 0,
+            dispose: flags & 0b01 != 0,
+            frame_chunks: RIFFContainer::generate_sub_chunk(&data[16..]),
+        })
+    }
+}
+
+impl TryFrom<&Vec<u8>> for RIFFContainer {
+    type Error = RiffContainerError;
+
+    fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
+        RIFFContainerRef::try_from(&value[..]).map(RIFFContainer::from)
+    }
+}
+
+impl TryFrom<&Vec<u8>> for RegularChunk {
+    type Error = ChunkError;
+
+    fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
+        RIFFChunkRef::try_from(&value[..]).map(|f| RegularChunk::from(&f))
+    }
+}
+
+impl From<&RIFFChunkRef<'_>> for RegularChunk {
+    fn from(value: &RIFFChunkRef<'_>) -> Self {
+        RegularChunk {
+            chunk_id: String::from(value.chunk_id),
+            chunk_data: Vec::from(value.chunk_data),
+        }
+    }
+}
+
+// Borrowed chunk, the id and data point into the parsed input
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct RIFFChunkRef<'a> {
+    pub chunk_id: &'a str,
+    pub chunk_data: &'a [u8],
+}
+
+impl RIFFChunkRef<'_> {
+    pub fn write_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_chunk_header(writer, self.chunk_id, self.chunk_data.len())?;
+        writer.write_all(self.chunk_data)?;
+        write_chunk_pad(writer, self.chunk_data.len())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        chunk_to_bytes(self.chunk_id, self.chunk_data)
+    }
 }
 
-pub trait RiffChunk {
-    fn get_chunk_frame_id(&self) -> &str;
-}
+// The input has to be exactly one chunk, without its pad byte
+impl<'a> TryFrom<&'a [u8]> for RIFFChunkRef<'a> {
+    type Error = ChunkError;
 
-impl RIFFContainer {
-    pub fn generate_sub_chunk(inp: &Vec<u8>) -> Vec<Box<dyn Chunk>> {
-        let mut result = Vec::new();
-        let mut counter = 0usize;
-        let inp_size = inp.len();
-
-        while counter < inp_size {
-            let size_start = counter + 4;
-            let size_end = counter + 8;
-            let chunk_size_opt = inp.get(size_start..size_end);
-
-            if let Some(chunk_size) = chunk_size_opt {
-                let real_chunk_size = le_to_u32(chunk_size) as usize;
-                let chunk_start = counter;
-                let chunk_end = counter + 8 + real_chunk_size;
-                let chunk_slice_opt = inp.get(chunk_start..chunk_end);
-
-                if let Some(chunk_slice) = chunk_slice_opt {
-                    let chunk_vec = Vec::from(chunk_slice);
-                    let chunk = RegularChunk::try_from(&chunk_vec);
-
-                    if let Ok(chunk) = chunk {
-                        let chunk_box: Box<dyn Chunk> = Box::new(chunk);
-                        result.push(chunk_box);
-
-                        counter = chunk_end;
-                    }
-                } else {
-                    break;
-                }
-            } else {
-                break;
-            }
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        let first_header = value.get(0..4).ok_or(ChunkError::InvalidChunk)?;
+        let size_id = value.get(4..8).ok_or(ChunkError::InvalidChunk)?;
+        let chunk_size = le_to_u32(size_id) as usize;
+
+        if chunk_size.checked_add(8) != Some(value.len()) {
+            return Err(ChunkError::SizeMismatch);
         }
 
-        result
+        Ok(RIFFChunkRef {
+            chunk_id: str::from_utf8(first_header).map_err(|_| ChunkError::InvalidChunk)?,
+            chunk_data: &value[8..],
+        })
     }
+}
 
-    pub fn push_subchunk(&mut self, chunk: Box<dyn Chunk>) {
-        self.subchunks.push(chunk);
-    }
+// Splits a chunk list, whatever cannot be written back unchanged is returned as unparsed bytes
+fn parse_chunks(inp: &[u8]) -> (Vec<RIFFChunkRef<'_>>, &[u8]) {
+    let mut result = Vec::new();
+    let mut counter = 0usize;
 
-    pub fn find_subchunk(&mut self, chunk_id: &str) -> Option<&Box<dyn Chunk>> {
-        let output = self.subchunks.iter().find(|p| {
-            let x = *p;
-            x.get_chunk_id() == chunk_id
+    while counter < inp.len() {
+        let chunk = inp.get(counter + 4..counter + 8).and_then(|size| {
+            let chunk_size = le_to_u32(size) as usize;
+            let chunk_end = chunk_size.checked_add(counter + 8)?;
+            let chunk = RIFFChunkRef::try_from(inp.get(counter..chunk_end)?).ok()?;
+
+            // Odd sized chunks are followed by a zero pad byte
+            if chunk_size % 2 == 1 && inp.get(chunk_end) != Some(&0) {
+                return None;
+            }
+
+            Some((chunk, chunk_end + chunk_size % 2))
         });
 
-        if let Some(chunk) = output {
-            return Some(chunk);
+        match chunk {
+            Some((chunk, next)) => {
+                result.push(chunk);
+                counter = next;
+            }
+            None => break,
         }
-
-        None
     }
+
+    (result, &inp[counter..])
 }
 
-impl RiffChunk for RIFFContainer {
-    fn get_chunk_frame_id(&self) -> &str {
-        self.frame_id.as_str()
-    }
+pub struct RIFFContainerRef<'a> {
+    frame_id: &'a str,
+    chunks: Vec<RIFFChunkRef<'a>>,
+    unparsed: &'a [u8],
 }
 
-impl Chunk for RIFFContainer {
-    fn get_chunk_id(&self) -> &str {
-        "RIFF"
+impl<'a> RIFFContainerRef<'a> {
+    pub fn get_chunk_frame_id(&self) -> &'a str {
+        self.frame_id
     }
 
-    fn get_chunk_data(&self) -> Option<&Vec<Box<dyn Chunk>>> {
-        Some(&self.subchunks)
+    pub fn get_chunks(&self) -> &Vec<RIFFChunkRef<'a>> {
+        &self.chunks
     }
 
-    fn get_chunk_bytes(&self) -> Vec<u8> {
-        let mut result = Vec::new();
-        let subchunk = &self.subchunks;
-
-        subchunk.iter().for_each(|f| {
-            let bytes = f.to_bytes();
-            bytes.iter().for_each(|g| result.push(*g));
-        });
-
-        result
+    pub fn get_chunk(&self, chunk_id: &str) -> Option<&RIFFChunkRef<'a>> {
+        self.chunks.iter().find(|f| f.chunk_id == chunk_id)
     }
 
-    fn get_chunk_size(&self) -> usize {
-        let chunk_bytes = self.get_chunk_bytes();
-        chunk_bytes.len() + 4 // 4 bytes from frame id
+    pub fn get_unparsed(&self) -> &'a [u8] {
+        self.unparsed
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut result = Vec::new();
-        let chunk_id_bytes = self.get_chunk_id().as_bytes();
-        let chunk_size = usize_to_le(self.get_chunk_size());
-        let chunk_bytes = self.get_chunk_bytes();
-        let frame_id = self.frame_id.as_bytes();
+    // The VP8X canvas when there is one, otherwise the size of the image bitstream
+    pub fn get_canvas_info(&self) -> Option<(u32, u32, bool)> {
+        if let Some(vp8x) = self.get_chunk("VP8X") {
+            let vp8x = VP8XChunk::try_from(vp8x).ok()?;
 
-        chunk_id_bytes.iter().for_each(|f| result.push(*f));
-        chunk_size.iter().for_each(|f| result.push(*f));
-        frame_id.iter().for_each(|f| result.push(*f));
-        chunk_bytes.iter().for_each(|f| result.push(*f));
+            return Some((
+                vp8x.canvas_width,
+                vp8x.canvas_height,
+                vp8x.flags & VP8X_ALPHA_FLAG != 0,
+            ));
+        }
 
-        result
-    }
-}
+        let (width, height, has_alpha) = self
+            .chunks
+            .iter()
+            .find_map(|f| read_bitstream_info(f.chunk_id, f.chunk_data))?;
 
-impl Chunk for RegularChunk {
-    fn get_chunk_data(&self) -> Option<&Vec<Box<dyn Chunk>>> {
-        None
+        Some((width, height, has_alpha || self.get_chunk("ALPH").is_some()))
     }
 
-    fn get_chunk_bytes(&self) -> Vec<u8> {
-        self.chunk_data.clone()
-    }
+    pub fn get_chunk_size(&self) -> usize {
+        let chunk_size: usize = self
+            .chunks
+            .iter()
+            .map(|f| 8 + f.chunk_data.len() + f.chunk_data.len() % 2)
+            .sum();
 
-    fn get_chunk_id(&self) -> &str {
-        &self.chunk_id
+        chunk_size + self.unparsed.len() + 4
     }
 
-    fn get_chunk_size(&self) -> usize {
-        self.chunk_data.len()
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_chunk_header(writer, "RIFF", self.get_chunk_size())?;
+        writer.write_all(self.frame_id.as_bytes())?;
+        self.chunks.iter().try_for_each(|f| f.write_to(writer))?;
+        writer.write_all(self.unparsed)
     }
 
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut result = Vec::new();
-        let chunk_id_bytes = self.get_chunk_id().as_bytes();
-        let chunk_size = usize_to_le(self.get_chunk_size());
-        let chunk_bytes = &self.chunk_data;
-
-        chunk_id_bytes.iter().for_each(|f| result.push(*f));
-        chunk_size.iter().for_each(|f| result.push(*f));
-        chunk_bytes.iter().for_each(|f| result.push(*f));
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut result = Vec::with_capacity(self.get_chunk_size() + 8);
 
+        self.write_to(&mut result).unwrap();
         result
     }
 }
 
-impl TryFrom<&Vec<u8>> for RIFFContainer {
+impl<'a> TryFrom<&'a [u8]> for RIFFContainerRef<'a> {
     type Error = RiffContainerError;
 
-    fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
         let first_header = value
             .get(0..4)
-            .ok_or_else(|| RiffContainerError::InvalidRiffFile)?;
+            .ok_or(RiffContainerError::InvalidRiffFile)?;
 
         if first_header != "RIFF".as_bytes() {
             return Err(RiffContainerError::MissingHeader);
@@ -197,57 +984,360 @@ impl TryFrom<&Vec<u8>> for RIFFContainer {
 
         let size_id = value
             .get(4..8)
-            .ok_or_else(|| RiffContainerError::InvalidRiffFile)?;
+            .ok_or(RiffContainerError::InvalidRiffFile)?;
         let chunk_size = le_to_u32(size_id) as usize;
         let vec_size = value.len();
 
-        if chunk_size + 8 != vec_size {
+        if chunk_size.checked_add(8) != Some(vec_size) {
             return Err(RiffContainerError::SizeMismatch);
         }
 
         let frame_id_bytes = value
             .get(8..12)
-            .ok_or_else(|| RiffContainerError::InvalidRiffFile)?;
-        let frame_id = str::from_utf8(frame_id_bytes).unwrap();
-        let subchunk_slice = value
-            .get(12..)
-            .ok_or_else(|| RiffContainerError::InvalidRiffFile)?;
-        let subchunk = RIFFContainer::generate_sub_chunk(&Vec::from(subchunk_slice));
-
-        Ok(RIFFContainer {
-            frame_id: String::from(frame_id),
-            subchunks: subchunk,
+            .ok_or(RiffContainerError::InvalidRiffFile)?;
+        let frame_id =
+            str::from_utf8(frame_id_bytes).map_err(|_| RiffContainerError::InvalidRiffFile)?;
+        let (chunks, unparsed) = parse_chunks(&value[12..]);
+
+        Ok(RIFFContainerRef {
+            frame_id,
+            chunks,
+            unparsed,
         })
     }
 }
 
-impl TryFrom<&Vec<u8>> for RegularChunk {
-    type Error = ChunkError;
+// Copies every chunk once, same as JFIFContainer the owned chunks are edited in place
+impl From<RIFFContainerRef<'_>> for RIFFContainer {
+    fn from(value: RIFFContainerRef<'_>) -> Self {
+        let subchunks = RIFFContainer::generate_owned_chunks(&value.chunks, value.unparsed);
 
-    fn try_from(value: &Vec<u8>) -> Result<Self, Self::Error> {
-        let first_header = value.get(0..4).ok_or_else(|| ChunkError::InvalidChunk)?;
-        let size_id = value.get(4..8).ok_or_else(|| ChunkError::InvalidChunk)?;
-        let chunk_size = le_to_u32(size_id) as usize;
-        let vec_size = value.len();
+        RIFFContainer::new(value.frame_id, subchunks)
+    }
+}
 
-        if chunk_size + 8 != vec_size {
-            return Err(ChunkError::SizeMismatch);
+// Chunks are emitted once complete, bytes that do not form a chunk are emitted as they arrive
+#[derive(PartialEq, Debug)]
+pub enum RIFFEvent {
+    Header { frame_id: String, chunk_size: usize },
+    Chunk(RegularChunk),
+    Unparsed(Vec<u8>),
+}
+
+// Consumed bytes stay in the buffer behind the cursor, they are dropped once per push
+#[derive(Default)]
+pub struct RIFFPushParser {
+    buffer: Vec<u8>,
+    cursor: usize,
+    offset: usize,
+    total_size: Option<usize>,
+    unparsed: bool,
+}
+
+impl RIFFPushParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn pending(&self) -> &[u8] {
+        &self.buffer[self.cursor..]
+    }
+
+    fn consume(&mut self, size: usize) -> &[u8] {
+        let start = self.cursor;
+
+        self.cursor += size;
+        self.offset += size;
+        &self.buffer[start..self.cursor]
+    }
+
+    fn read_header(&mut self) -> Result<Option<RIFFEvent>, RiffContainerError> {
+        let header = self.pending();
+
+        if header.len() < 12 {
+            return Ok(None);
+        }
+
+        if &header[0..4] != "RIFF".as_bytes() {
+            return Err(RiffContainerError::MissingHeader);
+        }
+
+        let chunk_size = le_to_u32(&header[4..8]) as usize;
+        let frame_id = str::from_utf8(&header[8..12])
+            .map_err(|_| RiffContainerError::InvalidRiffFile)?
+            .to_string();
+
+        if chunk_size < 4 {
+            return Err(RiffContainerError::SizeMismatch);
+        }
+
+        self.total_size = Some(chunk_size + 8);
+        self.consume(12);
+
+        Ok(Some(RIFFEvent::Header {
+            frame_id,
+            chunk_size,
+        }))
+    }
+
+    // Same rules as parse_chunks, a chunk that cannot be written back unchanged ends the chunk list
+    fn read_chunk(&mut self, remaining: usize) -> Option<Option<RIFFEvent>> {
+        if remaining < 8 {
+            return None;
+        }
+
+        let pending = self.pending();
+
+        if pending.len() < 8 {
+            return Some(None);
+        }
+
+        let chunk_size = le_to_u32(&pending[4..8]) as usize;
+        let chunk_end = chunk_size.checked_add(8)?;
+        let padded_end = chunk_end + chunk_size % 2;
+
+        if padded_end > remaining {
+            return None;
+        }
+
+        if pending.len() < padded_end {
+            return Some(None);
+        }
+
+        let chunk = RIFFChunkRef::try_from(&pending[..chunk_end]).ok()?;
+
+        if chunk_size % 2 == 1 && pending[chunk_end] != 0 {
+            return None;
+        }
+
+        let chunk = RegularChunk::from(&chunk);
+        self.consume(padded_end);
+
+        Some(Some(RIFFEvent::Chunk(chunk)))
+    }
+
+    pub fn push(&mut self, data: &[u8]) -> Result<Vec<RIFFEvent>, RiffContainerError> {
+        let mut events = Vec::new();
+
+        self.buffer.drain(..self.cursor);
+        self.cursor = 0;
+        self.buffer.extend_from_slice(data);
+
+        if self.total_size.is_none() {
+            if let Some(header) = self.read_header()? {
+                events.push(header);
+            }
         }
 
-        let chunk_id = str::from_utf8(first_header).unwrap();
-        let chunk_data = value.get(8..).ok_or_else(|| ChunkError::InvalidChunk)?;
-        let result = RegularChunk {
-            chunk_id: String::from(chunk_id),
-            chunk_data: Vec::from(chunk_data),
+        let total_size = match self.total_size {
+            Some(total_size) => total_size,
+            None => return Ok(events),
         };
 
-        Ok(result)
+        if self.offset + self.pending().len() > total_size {
+            return Err(RiffContainerError::SizeMismatch);
+        }
+
+        while !self.unparsed {
+            match self.read_chunk(total_size - self.offset) {
+                Some(Some(chunk)) => events.push(chunk),
+                Some(None) => break,
+                None => self.unparsed = true,
+            }
+        }
+
+        if self.unparsed && !self.pending().is_empty() {
+            let data = Vec::from(self.consume(self.pending().len()));
+            events.push(RIFFEvent::Unparsed(data));
+        }
+
+        Ok(events)
+    }
+
+    pub fn finish(&self) -> Result<(), RiffContainerError> {
+        match self.total_size {
+            Some(total_size) if self.offset == total_size => Ok(()),
+            Some(_) => Err(RiffContainerError::SizeMismatch),
+            None => Err(RiffContainerError::InvalidRiffFile),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use proptest::prelude::*;
+
+    fn chunk_strategy() -> impl Strategy<Value = Vec<u8>> {
+        let known_ids = prop::sample::select(vec![*b"VP8X", *b"ANIM", *b"ANMF", *b"VP8L", *b"EXIF"]);
+        let chunk_id = prop_oneof![known_ids, any::<[u8; 4]>()];
+
+        (
+            chunk_id,
+            prop::collection::vec(any::<u8>(), 0..40),
+            prop_oneof![Just(0u8), any::<u8>()],
+        )
+            .prop_map(|(chunk_id, data, pad)| {
+                let mut result = Vec::from(chunk_id);
+
+                result.extend((data.len() as u32).to_le_bytes());
+                result.extend(&data);
+
+                if data.len() % 2 == 1 {
+                    result.push(pad);
+                }
+
+                result
+            })
+    }
+
+    fn push_in_chunks(inp: &[u8], chunk_size: usize) -> Result<Vec<u8>, RiffContainerError> {
+        let mut parser = RIFFPushParser::new();
+        let mut output = Vec::new();
+
+        for chunk in inp.chunks(chunk_size) {
+            parser.push(chunk)?.iter().for_each(|f| match f {
+                RIFFEvent::Header {
+                    frame_id,
+                    chunk_size,
+                } => {
+                    write_chunk_header(&mut output, "RIFF", *chunk_size).unwrap();
+                    output.extend(frame_id.as_bytes());
+                }
+                RIFFEvent::Chunk(chunk) => output.extend(chunk.to_bytes()),
+                RIFFEvent::Unparsed(data) => output.extend(data),
+            });
+        }
+
+        parser.finish()?;
+
+        Ok(output)
+    }
+
+    proptest! {
+        #[test]
+        fn riff_round_trip_is_identity(
+            chunks in prop::collection::vec(chunk_strategy(), 0..8),
+            trailing in prop::collection::vec(any::<u8>(), 0..12),
+        ) {
+            let mut body = Vec::from(&b"WEBP"[..]);
+
+            chunks.iter().for_each(|f| body.extend(f));
+            body.extend(&trailing);
+
+            let mut inp = Vec::from(&b"RIFF"[..]);
+            inp.extend((body.len() as u32).to_le_bytes());
+            inp.extend(body);
+
+            let container = RIFFContainer::try_from(&inp).unwrap();
+
+            prop_assert_eq!(container.to_bytes(), inp);
+        }
+
+        #[test]
+        fn push_parser_matches_whole_parser(
+            chunks in prop::collection::vec(chunk_strategy(), 0..8),
+            trailing in prop::collection::vec(any::<u8>(), 0..12),
+            chunk_size in 1..16usize,
+        ) {
+            let mut body = Vec::from(&b"WEBP"[..]);
+
+            chunks.iter().for_each(|f| body.extend(f));
+            body.extend(&trailing);
+
+            let mut inp = Vec::from(&b"RIFF"[..]);
+            inp.extend((body.len() as u32).to_le_bytes());
+            inp.extend(body);
+
+            let container = RIFFContainerRef::try_from(&inp[..]).unwrap();
+            let mut parser = RIFFPushParser::new();
+            let events: Vec<RIFFEvent> = inp
+                .chunks(chunk_size)
+                .flat_map(|f| parser.push(f).unwrap())
+                .collect();
+            let parsed: Vec<&RegularChunk> = events
+                .iter()
+                .filter_map(|f| match f {
+                    RIFFEvent::Chunk(chunk) => Some(chunk),
+                    _ => None,
+                })
+                .collect();
+
+            prop_assert_eq!(parsed.len(), container.get_chunks().len());
+            prop_assert_eq!(push_in_chunks(&inp, chunk_size), Ok(inp.clone()));
+        }
+    }
+
+    #[test]
+    fn borrowed_riff_container() {
+        let content = std::fs::read(fixture!("test.webp")).unwrap();
+        let container = RIFFContainerRef::try_from(&content[..]).unwrap();
+        let chunk = container.get_chunks()[0];
+
+        assert_eq!(container.get_chunk_frame_id(), "WEBP");
+        assert!(content.as_ptr_range().contains(&chunk.chunk_data.as_ptr()));
+        assert!(container.get_unparsed().is_empty());
+        assert_eq!(container.to_bytes(), content);
+
+        let owned = RIFFContainer::from(container);
+
+        assert_eq!(owned.to_bytes(), content);
+    }
+
+    #[test]
+    fn riff_corpus_round_trip() {
+        for name in [fixture!("test.webp"), fixture!("crop.webp"), fixture!("watermark.webp")] {
+            let content = std::fs::read(name).unwrap();
+            let container = RIFFContainer::try_from(&content).unwrap();
+
+            assert_eq!(container.to_bytes(), content);
+        }
+    }
+
+    #[test]
+    fn push_parser_reassembles_corpus() {
+        for name in [fixture!("test.webp"), fixture!("crop.webp"), fixture!("watermark.webp")] {
+            let content = std::fs::read(name).unwrap();
+
+            for chunk_size in [7, 1000, content.len()] {
+                assert_eq!(push_in_chunks(&content, chunk_size), Ok(content.clone()));
+            }
+        }
+
+        let content = std::fs::read(fixture!("crop.webp")).unwrap();
+        let mut parser = RIFFPushParser::new();
+
+        assert_eq!(parser.finish(), Err(RiffContainerError::InvalidRiffFile));
+
+        parser.push(&content[..content.len() - 1]).unwrap();
+
+        assert_eq!(parser.finish(), Err(RiffContainerError::SizeMismatch));
+        assert_eq!(parser.push(&[0, 0]), Err(RiffContainerError::SizeMismatch));
+    }
+
+    #[test]
+    fn riff_keeps_unparsed_data() {
+        let inp = vec![
+            0x52, 0x49, 0x46, 0x46, 0x0F, 0, 0, 0, 0x57, 0x45, 0x42, 0x50, 0x45, 0x58, 0x49,
+            0x46, 0x01, 0, 0, 0, 0x0A, 0x0B, 0x0C,
+        ];
+        let mut container = RIFFContainer::try_from(&inp).unwrap();
+        let chunks = container.get_chunk_data().unwrap();
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].get_chunk_id(), "");
+        assert_eq!(container.to_bytes(), inp);
+
+        container.push_subchunk(Box::new(RegularChunk {
+            chunk_id: String::from("XMP "),
+            chunk_data: vec![0x01, 0x02],
+        }));
+
+        let chunks = container.get_chunk_data().unwrap();
+
+        assert_eq!(chunks[0].get_chunk_id(), "XMP ");
+        assert_eq!(chunks[1].get_chunk_id(), "");
+    }
 
     #[test]
     fn it_can_create_riff_container() {
@@ -292,7 +1382,7 @@ mod tests {
         if let Some(chunk_data) = chunk_data {
             assert_eq!(chunk_data.len(), 1);
 
-            let first_elem = chunk_data.get(0);
+            let first_elem = chunk_data.first();
             if let Some(first_elem) = first_elem {
                 assert_eq!(first_elem.get_chunk_id(), "VP8L");
             }
@@ -390,7 +1480,7 @@ mod tests {
         };
         let chunk_data = chunk.get_chunk_data();
 
-        if let Some(_) = chunk_data {
+        if chunk_data.is_some() {
             panic!("Chunk data for RegularChunk should always be None variant");
         }
     }
@@ -460,7 +1550,7 @@ mod tests {
             assert_eq!(chunk.get_chunk_size(), 4);
             assert_eq!(chunk.get_chunk_bytes(), [0x52u8, 0x49, 0x46, 0x46]);
 
-            if let Some(_) = chunk_data {
+            if chunk_data.is_some() {
                 panic!("Regular chunk data should be None");
             }
         } else {
@@ -473,14 +1563,14 @@ mod tests {
         let chunk_bytes = vec![0x56u8, 0x50, 0x38];
         let chunk = RegularChunk::try_from(&chunk_bytes);
 
-        if let Ok(_) = chunk {
+        if chunk.is_ok() {
             panic!("Should failed, chunk_id cannot be identified");
         }
 
         let chunk_bytes = vec![0x56u8, 0x50, 0x38, 0x4C, 0x04, 0];
         let chunk = RegularChunk::try_from(&chunk_bytes);
 
-        if let Ok(_) = chunk {
+        if chunk.is_ok() {
             panic!("Should failed, chunk_size cannot be identified");
         }
 
@@ -526,7 +1616,7 @@ mod tests {
                     [0x56, 0x50, 0x38, 0x4C, 0x04, 0, 0, 0, 0x52, 0x49, 0x46, 0x46]
                 );
 
-                if let None = chunk_data {
+                if chunk_data.is_none() {
                     panic!("RIFFContainer data should be Some");
                 }
             }
@@ -583,4 +1673,219 @@ mod tests {
             }
         }
     }
+
+    fn animated_webp() -> Vec<u8> {
+        let anim = AnimChunk {
+            background_color: [0xFF, 0xFF, 0xFF, 0xFF],
+            loop_count: 0,
+        };
+        let frame = AnmfChunk {
+            x: 4,
+            y: 2,
+            width: 8,
+            height: 6,
+            duration: 120,
+            blend: false,
+            dispose: true,
+            frame_chunks: vec![Box::new(RegularChunk {
+                chunk_id: String::from("VP8L"),
+                chunk_data: vec![0x2F, 0x07, 0x40, 0x01, 0x00],
+            })],
+        };
+        let container = RIFFContainer::new("WEBP", vec![Box::new(anim), Box::new(frame)]);
+
+        container.to_bytes()
+    }
+
+    #[test]
+    fn reserved_bits_keep_regular_chunk() {
+        let data = [0x10, 0x01, 0x00, 0x00, 0x03, 0x00, 0x00, 0x04, 0x00, 0x00];
+        let chunk = RIFFChunkRef {
+            chunk_id: "VP8X",
+            chunk_data: &data,
+        };
+        let regular = RIFFContainer::generate_typed_chunk(&chunk);
+
+        assert!(regular.as_any().is::<RegularChunk>());
+        assert_eq!(regular.to_bytes(), chunk.to_bytes());
+
+        let data = [0x10, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x04, 0x00, 0x00];
+        let chunk = RIFFChunkRef {
+            chunk_id: "VP8X",
+            chunk_data: &data,
+        };
+        let typed = RIFFContainer::generate_typed_chunk(&chunk);
+
+        assert!(typed.as_any().is::<VP8XChunk>());
+        assert_eq!(typed.to_bytes(), chunk.to_bytes());
+    }
+
+    #[test]
+    fn anmf_chunk_round_trip() {
+        let bytes = animated_webp();
+        let container = RIFFContainer::try_from(&bytes).unwrap();
+        let anim = container.get_anim().unwrap();
+        let frames = container.get_frames();
+
+        assert!(container.is_animated());
+        assert_eq!(anim.loop_count, 0);
+        assert_eq!(frames.len(), 1);
+        assert_eq!((frames[0].x, frames[0].y), (4, 2));
+        assert_eq!((frames[0].width, frames[0].height), (8, 6));
+        assert_eq!(frames[0].duration, 120);
+        assert!(!frames[0].blend);
+        assert!(frames[0].dispose);
+        assert_eq!(frames[0].frame_chunks[0].get_chunk_id(), "VP8L");
+        assert_eq!(container.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn write_to_matches_computed_size() {
+        let bytes = animated_webp();
+        let container = RIFFContainer::try_from(&bytes).unwrap();
+        let frame = container.get_frames()[0];
+        let mut output = Vec::new();
+
+        container.write_to(&mut output).unwrap();
+
+        assert_eq!(output, bytes);
+        assert_eq!(container.get_total_size(), bytes.len());
+        assert_eq!(frame.get_total_size(), frame.to_bytes().len());
+        assert_eq!(frame.get_chunk_size(), frame.get_chunk_bytes().len());
+
+        let container = RIFFContainerRef::try_from(&bytes[..]).unwrap();
+        let mut output = Vec::new();
+
+        container.write_to(&mut output).unwrap();
+
+        assert_eq!(output, bytes);
+    }
+
+    #[test]
+    fn anmf_chunk_replace_frame_webp() {
+        let bytes = animated_webp();
+        let mut container = RIFFContainer::try_from(&bytes).unwrap();
+        let frame_bytes = container.get_frames()[0].to_webp();
+        let still = RIFFContainer::try_from(&frame_bytes).unwrap();
+
+        assert!(!still.is_animated());
+        assert_eq!(
+            still.get_chunk_bytes(),
+            container.get_frames()[0].frame_chunks[0].to_bytes()
+        );
+
+        let replacement = RIFFContainer::new(
+            "WEBP",
+            vec![Box::new(RegularChunk {
+                chunk_id: String::from("VP8L"),
+                chunk_data: vec![0x01, 0x02],
+            })],
+        );
+        container.get_frames_mut()[0]
+            .set_frame_webp(&replacement.to_bytes())
+            .unwrap();
+
+        let frames = container.get_frames();
+
+        assert_eq!(frames[0].frame_chunks[0].get_chunk_bytes(), [0x01, 0x02]);
+        assert_eq!(frames[0].duration, 120);
+        assert_eq!(frames[0].x, 4);
+    }
+
+    #[test]
+    fn odd_sized_chunk_is_padded() {
+        let chunk = RegularChunk {
+            chunk_id: String::from("milf"),
+            chunk_data: vec![0x01, 0x02, 0x03],
+        };
+
+        assert_eq!(
+            chunk.to_bytes(),
+            [0x6D, 0x69, 0x6C, 0x66, 0x03, 0, 0, 0, 0x01, 0x02, 0x03, 0x00]
+        );
+
+        let mut container = RIFFContainer::new("WEBP", Vec::new());
+        container.push_subchunk(Box::new(chunk));
+        container.push_subchunk(Box::new(RegularChunk {
+            chunk_id: String::from("EXIF"),
+            chunk_data: vec![0x04, 0x05],
+        }));
+
+        let bytes = container.to_bytes();
+
+        assert_eq!(le_to_u32(&bytes[4..8]) as usize, bytes.len() - 8);
+
+        let container = RIFFContainer::try_from(&bytes).unwrap();
+        let chunks = container.get_chunk_data().unwrap();
+
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].get_chunk_bytes(), [0x01, 0x02, 0x03]);
+        assert_eq!(chunks[1].get_chunk_id(), "EXIF");
+        assert_eq!(container.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn ensure_extended_from_simple_format() {
+        // 2x3 VP8L header with alpha_is_used set
+        let bits: u32 = 1 | (2 << 14) | (1 << 28);
+        let mut vp8l_data = vec![0x2Fu8];
+        vp8l_data.extend(bits.to_le_bytes());
+        let mut container = RIFFContainer::new(
+            "WEBP",
+            vec![Box::new(RegularChunk {
+                chunk_id: String::from("VP8L"),
+                chunk_data: vp8l_data,
+            })],
+        );
+
+        container.ensure_extended().unwrap();
+
+        let vp8x = container.get_vp8x().unwrap();
+
+        assert_eq!((vp8x.canvas_width, vp8x.canvas_height), (2, 3));
+        assert_eq!(vp8x.flags, VP8X_ALPHA_FLAG);
+        assert_eq!(
+            container.get_chunk_data().unwrap()[0].get_chunk_id(),
+            "VP8X"
+        );
+
+        container.push_subchunk(Box::new(RegularChunk {
+            chunk_id: String::from("EXIF"),
+            chunk_data: vec![0x00],
+        }));
+        container.ensure_extended().unwrap();
+
+        let bytes = container.to_bytes();
+        let container = RIFFContainer::try_from(&bytes).unwrap();
+        let vp8x = container.get_vp8x().unwrap();
+
+        assert_eq!(vp8x.flags, VP8X_ALPHA_FLAG | VP8X_EXIF_FLAG);
+        assert_eq!(container.get_chunk_data().unwrap().len(), 3);
+
+        let mut container = RIFFContainer::new("WEBP", Vec::new());
+
+        assert_eq!(
+            container.ensure_extended(),
+            Err(RiffContainerError::UnknownCanvasSize)
+        );
+    }
+
+    #[test]
+    fn ensure_extended_rejects_empty_vp8_frame() {
+        // Key frame header with a zero width and height
+        let vp8_data = vec![0x10, 0x00, 0x00, 0x9D, 0x01, 0x2A, 0x00, 0x00, 0x00, 0x00];
+        let mut container = RIFFContainer::new(
+            "WEBP",
+            vec![Box::new(RegularChunk {
+                chunk_id: String::from("VP8 "),
+                chunk_data: vp8_data,
+            })],
+        );
+
+        assert_eq!(
+            container.ensure_extended(),
+            Err(RiffContainerError::UnknownCanvasSize)
+        );
+        assert!(container.get_vp8x().is_none());
+    }
 }
@@ -0,0 +1,43 @@
+use drmcore::archive::{watermark_archive as core_watermark_archive, PagePlacement};
+use drmcore::watermark_task::{OriginX, OriginY};
+use wasm_bindgen::prelude::*;
+
+// Page i is watermarked at (xs[i], ys[i]), pages past the end of either list are left untouched
+#[wasm_bindgen]
+pub fn watermark_archive(
+    inp_bytes: Vec<u8>,
+    watermark_bytes: Vec<u8>,
+    xs: Vec<u32>,
+    ys: Vec<u32>,
+    key: Vec<u8>,
+) -> Result<JsValue, JsValue> {
+    match watermark_archive_native(&inp_bytes, &watermark_bytes, &xs, &ys, &key) {
+        Ok(result) => Ok(serde_wasm_bindgen::to_value(&result)?),
+        Err(err) => Err(serde_wasm_bindgen::to_value(err)?),
+    }
+}
+
+fn watermark_archive_native(
+    inp_bytes: &[u8],
+    watermark_bytes: &[u8],
+    xs: &[u32],
+    ys: &[u32],
+    key: &[u8],
+) -> Result<Vec<u8>, &'static str> {
+    let enc_key: [u8; 32] = key.try_into().map_err(|_| "Enc key must 32 bytes")?;
+    let watermark = image::load_from_memory(watermark_bytes).map_err(|_| "Invalid watermark")?;
+    let placement = |index: usize, _: &str| {
+        let x = xs.get(index)?;
+        let y = ys.get(index)?;
+
+        Some(PagePlacement {
+            x: *x,
+            y: *y,
+            origin_x: OriginX::Left,
+            origin_y: OriginY::Top,
+        })
+    };
+
+    core_watermark_archive(inp_bytes, &watermark, placement, &enc_key)
+        .map_err(|_| "Cannot watermark archive")
+}
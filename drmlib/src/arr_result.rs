@@ -10,9 +10,8 @@ pub fn create_arr_result() -> *mut ArrResult {
     let arr_result = ArrResult {
         arr: Vec::new(),
     };
-    let ptr = unsafe { transmute(Box::new(arr_result)) };
 
-    ptr
+    unsafe { transmute(Box::new(arr_result)) }
 }
 
 #[wasm_bindgen]
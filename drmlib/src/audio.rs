@@ -0,0 +1,33 @@
+use drmcore::file_joiner::join_wav;
+use drmcore::wav_container::{WAVContainer, TONE_AMPLITUDE, TONE_FREQUENCY};
+use wasm_bindgen::prelude::*;
+
+#[wasm_bindgen]
+pub fn watermark_wav(
+    inp_bytes: Vec<u8>,
+    start_ms: u32,
+    duration_ms: u32,
+    key: Vec<u8>,
+) -> Result<JsValue, JsValue> {
+    match watermark_wav_native(&inp_bytes, start_ms, duration_ms, &key) {
+        Ok(result) => Ok(serde_wasm_bindgen::to_value(&result)?),
+        Err(err) => Err(serde_wasm_bindgen::to_value(err)?),
+    }
+}
+
+fn watermark_wav_native(
+    inp_bytes: &[u8],
+    start_ms: u32,
+    duration_ms: u32,
+    key: &[u8],
+) -> Result<Vec<u8>, &'static str> {
+    let enc_key: [u8; 32] = key.try_into().map_err(|_| "Enc key must 32 bytes")?;
+    let mut container =
+        WAVContainer::try_from(&Vec::from(inp_bytes)).map_err(|_| "Invalid wav file")?;
+    let section = container
+        .overlay_tone(start_ms, duration_ms, TONE_FREQUENCY, TONE_AMPLITUDE)
+        .map_err(|_| "Cannot overlay tone")?;
+    let old_bytes: Vec<u8> = (&section).into();
+
+    join_wav(&container.to_bytes(), &old_bytes, &enc_key).map_err(|_| "Unknown error")
+}
@@ -1,5 +1,7 @@
 use std::mem::transmute;
 use drmcore::img::get_section_jpeg as img_get_section_jpeg;
+use drmcore::img::get_section_png as img_get_section_png;
+use drmcore::img::get_section_tiff as img_get_section_tiff;
 use drmcore::img::get_section_webp as img_get_section_webp;
 use crate::arr_result::ArrResult;
 use wasm_bindgen::prelude::*;
@@ -18,9 +20,8 @@ pub fn get_section_webp(
     let arr_result = ArrResult {
         arr: result.clone(),
     };
-    let ptr = unsafe { transmute(Box::new(arr_result)) };
 
-    ptr
+    unsafe { transmute(Box::new(arr_result)) }
 }
 
 #[wasm_bindgen]
@@ -37,7 +38,42 @@ pub fn get_section_jpeg(
     let arr_result = ArrResult {
         arr: result.clone(),
     };
-    let ptr = unsafe { transmute(Box::new(arr_result)) };
 
-    ptr
+    unsafe { transmute(Box::new(arr_result)) }
+}
+
+#[wasm_bindgen]
+pub fn get_section_png(
+    byts_ptr: *const u8,
+    byts_len: usize,
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+) -> *mut ArrResult {
+    let byts = unsafe { std::slice::from_raw_parts(byts_ptr, byts_len) };
+    let result = img_get_section_png(byts, x, y, w, h);
+    let arr_result = ArrResult {
+        arr: result.clone(),
+    };
+
+    unsafe { transmute(Box::new(arr_result)) }
+}
+
+#[wasm_bindgen]
+pub fn get_section_tiff(
+    byts_ptr: *const u8,
+    byts_len: usize,
+    x: u32,
+    y: u32,
+    w: u32,
+    h: u32,
+) -> *mut ArrResult {
+    let byts = unsafe { std::slice::from_raw_parts(byts_ptr, byts_len) };
+    let result = img_get_section_tiff(byts, x, y, w, h);
+    let arr_result = ArrResult {
+        arr: result.clone(),
+    };
+
+    unsafe { transmute(Box::new(arr_result)) }
 }
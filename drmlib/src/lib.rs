@@ -1,4 +1,6 @@
+mod archive;
 mod arr_result;
+mod audio;
 mod misc;
 mod img;
 mod macro_helper;
@@ -32,10 +32,14 @@ macro_rules! create_get_output_func {
         #[wasm_bindgen]
         pub fn $t(ptr: *mut WatermarkTask) -> Result<JsValue, JsValue> {
             let watermark_task = unsafe { &mut *ptr };
-            return $u(watermark_task);
+
+            match $u(watermark_task) {
+                Ok(result) => Ok(serde_wasm_bindgen::to_value(&result)?),
+                Err(err) => Err(serde_wasm_bindgen::to_value(err)?),
+            }
         }
 
-        fn $u(watermark_task: &mut WatermarkTask) -> Result<JsValue, JsValue> {
+        fn $u(watermark_task: &mut WatermarkTask) -> Result<Vec<u8>, &'static str> {
             let output = watermark_task.get_output();
             let old_section = watermark_task.get_old_section();
             let mut bytes: Vec<u8> = Vec::new();
@@ -45,51 +49,73 @@ macro_rules! create_get_output_func {
                 let mut cur = Cursor::new(&mut bytes);
                 let output_bin = output_img.write_to(&mut cur, $export);
 
-                if let Err(_) = output_bin {
-                    let err_message = serde_wasm_bindgen::to_value("Cannot write output bytes")?;
-                    return Err(err_message);
+                if output_bin.is_err() {
+                    return Err("Cannot write output bytes");
                 }
             } else {
-                let err_message = serde_wasm_bindgen::to_value("Task not yet processed")?;
-                return Err(err_message);
+                return Err("Task not yet processed");
             }
 
+            let bytes = watermark_task
+                .get_output_metadata()
+                .and_then(|f| write_metadata(&bytes, &f))
+                .map_err(|_| "Cannot write metadata")?;
+
             if let Some(old_img) = old_section {
                 let mut cur_old = Cursor::new(&mut old_bytes);
                 let output_old = old_img.write_to(&mut cur_old, $export);
 
-                if let Err(_) = output_old {
-                    let err_message =
-                        serde_wasm_bindgen::to_value("Cannot write old section bytes")?;
-                    return Err(err_message);
+                if output_old.is_err() {
+                    return Err("Cannot write old section bytes");
                 }
             } else {
-                let err_message = serde_wasm_bindgen::to_value("Task not yet processed")?;
-                return Err(err_message);
+                return Err("Task not yet processed");
             }
 
-            if let None = watermark_task.get_key() {
-                let err_message = serde_wasm_bindgen::to_value("Encryption key not set")?;
-                return Err(err_message);
-            }
+            join_output(watermark_task, &bytes, old_bytes, $v)
+        }
+    };
+}
+
+// Animated outputs, $payloads encodes the original frames from the target source, the old
+// sections and the watermarked frame indices
+#[macro_export]
+macro_rules! create_get_output_frames_func {
+    ($t:ident, $u:ident, $encode:ident, $payloads:expr, $v:ident) => {
+        #[wasm_bindgen]
+        pub fn $t(ptr: *mut WatermarkTask) -> Result<JsValue, JsValue> {
+            let watermark_task = unsafe { &mut *ptr };
 
-            let watermark_pos: [u8; 8] = watermark_task
-                .get_absolute_watermark_position()
-                .unwrap()
-                .into();
-            let watermark_dim: [u8; 8] = watermark_task.get_watermark_dimension().unwrap().into();
-            old_bytes.extend(watermark_pos);
-            old_bytes.extend(watermark_dim);
-
-            let enc_key = watermark_task.get_key().unwrap();
-            let join_result = $v(&bytes, &old_bytes, &enc_key);
-            if let Ok(result) = join_result {
-                let result_values = serde_wasm_bindgen::to_value(&result)?;
-                return Ok(result_values);
+            match $u(watermark_task) {
+                Ok(result) => Ok(serde_wasm_bindgen::to_value(&result)?),
+                Err(err) => Err(serde_wasm_bindgen::to_value(err)?),
             }
+        }
 
-            let err_message = serde_wasm_bindgen::to_value("Unknown error")?;
-            return Err(err_message);
+        fn $u(watermark_task: &mut WatermarkTask) -> Result<Vec<u8>, &'static str> {
+            let output_frames = watermark_task.get_output_frames();
+            let old_section_frames = watermark_task.get_old_section_frames();
+            let target_source = watermark_task.get_target_source();
+
+            let (output_frames, old_section_frames, target_source) =
+                match (output_frames, old_section_frames, target_source) {
+                    (Some(output_frames), Some(old_section_frames), Some(target_source)) => {
+                        (output_frames, old_section_frames, target_source)
+                    }
+                    _ => return Err("Task not yet processed"),
+                };
+
+            let indices: Vec<usize> = old_section_frames.iter().map(|f| f.index).collect();
+            let bytes = $encode(target_source, output_frames, &indices)
+                .map_err(|_| "Cannot write output bytes")?;
+            let bytes = watermark_task
+                .get_output_metadata()
+                .and_then(|f| write_metadata(&bytes, &f))
+                .map_err(|_| "Cannot write metadata")?;
+            let payloads = ($payloads)(target_source, old_section_frames, &indices)
+                .ok_or("Cannot write old section bytes")?;
+
+            join_output(watermark_task, &bytes, pack_frames(&payloads), $v)
         }
     };
 }
@@ -2,21 +2,30 @@ use image::ImageFormat;
 use std::io::Cursor;
 use std::mem::transmute;
 
-use drmcore::file_joiner::{join_jpeg, join_webp};
-use drmcore::watermark_task::{set_target, set_watermark, OriginX, OriginY, WatermarkTask};
+use drmcore::file_format::FileFormat;
+use drmcore::file_joiner::{join_gif, join_jpeg, join_png, join_tiff, join_webp, JoinError};
+use drmcore::frame_payload::{encode_frame_sections, encode_gif_frames, pack_frames};
+use drmcore::img::{encode_frames_gif, encode_frames_webp};
+use drmcore::jpeg::thumbnail::ThumbnailPolicy;
+use drmcore::metadata::{write_metadata, RightsInfo};
+use drmcore::watermark_task::set_target_auto as task_set_target_auto;
+use drmcore::watermark_task::set_target_gif as task_set_target_gif;
+use drmcore::watermark_task::set_target_webp_animated as task_set_target_webp_animated;
+use drmcore::watermark_task::{
+    set_target, set_watermark, FrameSection, OriginX, OriginY, WatermarkTask,
+};
 use wasm_bindgen::prelude::*;
 
 use crate::{
-    create_get_old_section_func, create_get_output_func, create_set_target_func,
-    create_set_watermark_func,
+    create_get_old_section_func, create_get_output_frames_func, create_get_output_func,
+    create_set_target_func, create_set_watermark_func,
 };
 
 #[wasm_bindgen]
 pub fn create_watermarktask() -> *mut WatermarkTask {
     let watermark_task = WatermarkTask::new();
-    let ptr = unsafe { transmute(Box::new(watermark_task)) };
 
-    ptr
+    unsafe { transmute(Box::new(watermark_task)) }
 }
 
 #[wasm_bindgen]
@@ -39,13 +48,61 @@ pub fn set_position_watermark(ptr: *mut WatermarkTask, x: u32, y: u32, origin_x:
 pub fn set_key(ptr: *mut WatermarkTask, key: Vec<u8>) -> Result<(),JsValue> {
     let watermark_task = unsafe { &mut *ptr };
 
-    if let Err(_) = watermark_task.set_key(&key) {
+    if watermark_task.set_key(&key).is_err() {
         let err_msg = serde_wasm_bindgen::to_value("Cannot set encryption key")?;
         return Err(err_msg);
     }
     Ok(())
 }
 
+#[wasm_bindgen]
+pub fn set_rights(
+    ptr: *mut WatermarkTask,
+    copyright: Option<String>,
+    artist: Option<String>,
+    web_statement: Option<String>,
+) {
+    let watermark_task = unsafe { &mut *ptr };
+    let rights = RightsInfo {
+        copyright,
+        artist,
+        web_statement,
+    };
+
+    if rights == RightsInfo::default() {
+        watermark_task.set_rights(None);
+    } else {
+        watermark_task.set_rights(Some(rights));
+    }
+}
+
+// 0 keeps the original EXIF thumbnail, 1 removes it, 2 regenerates it from the output and
+// 3 applies the watermark to it
+#[wasm_bindgen]
+pub fn set_thumbnail_policy(ptr: *mut WatermarkTask, policy: u8) {
+    let watermark_task = unsafe { &mut *ptr };
+    let real_policy = match policy {
+        0 => ThumbnailPolicy::Keep,
+        2 => ThumbnailPolicy::Regenerate,
+        3 => ThumbnailPolicy::Watermark,
+        _ => ThumbnailPolicy::Remove,
+    };
+    watermark_task.set_thumbnail_policy(real_policy);
+}
+
+#[wasm_bindgen]
+pub fn set_watermark_mpf_images(ptr: *mut WatermarkTask, enabled: bool) {
+    let watermark_task = unsafe { &mut *ptr };
+    watermark_task.set_watermark_mpf_images(enabled);
+}
+
+// Checked by set_target_auto on JPEG and WebP targets before they are decoded
+#[wasm_bindgen]
+pub fn set_max_pixels(ptr: *mut WatermarkTask, max_pixels: u64) {
+    let watermark_task = unsafe { &mut *ptr };
+    watermark_task.set_max_pixels(max_pixels);
+}
+
 #[wasm_bindgen]
 pub fn destroy_watermarktask(ptr: *mut WatermarkTask) {
     let _counter: Box<WatermarkTask> = unsafe { transmute(ptr) };
@@ -57,7 +114,7 @@ pub fn process_watermark(ptr: *mut WatermarkTask) -> Result<(),JsValue> {
     let watermark_task = unsafe { &mut *ptr };
     let output = watermark_task.process();
 
-    if let Err(_) = output {
+    if output.is_err() {
         let err_msg = serde_wasm_bindgen::to_value("Cannot process")?;
         return Err(err_msg);
     }
@@ -66,12 +123,114 @@ pub fn process_watermark(ptr: *mut WatermarkTask) -> Result<(),JsValue> {
 
 create_set_target_func! {set_target_webp,ImageFormat::WebP}
 create_set_target_func! {set_target_jpeg,ImageFormat::Jpeg}
+create_set_target_func! {set_target_png,ImageFormat::Png}
+create_set_target_func! {set_target_tiff,ImageFormat::Tiff}
 create_set_watermark_func! {set_watermark_webp,ImageFormat::WebP}
 create_set_watermark_func! {set_watermark_jpeg,ImageFormat::Jpeg}
+create_set_watermark_func! {set_watermark_png,ImageFormat::Png}
+create_set_watermark_func! {set_watermark_tiff,ImageFormat::Tiff}
+
+type JoinFn = fn(&[u8], &[u8], &[u8; 32]) -> Result<Vec<u8>, JoinError>;
+
+// Appends the watermark position and dimension to the old section and joins it to the output
+fn join_output(
+    watermark_task: &WatermarkTask,
+    bytes: &[u8],
+    mut old_bytes: Vec<u8>,
+    join: JoinFn,
+) -> Result<Vec<u8>, &'static str> {
+    let enc_key = watermark_task.get_key().ok_or("Encryption key not set")?;
+    let watermark_pos: [u8; 8] = watermark_task
+        .get_absolute_watermark_position()
+        .unwrap()
+        .into();
+    let watermark_dim: [u8; 8] = watermark_task.get_watermark_dimension().unwrap().into();
+    old_bytes.extend(watermark_pos);
+    old_bytes.extend(watermark_dim);
+
+    join(bytes, &old_bytes, &enc_key).map_err(|_| "Unknown error")
+}
+
 create_get_old_section_func! {get_old_section_jpeg,ImageFormat::Jpeg}
 create_get_old_section_func! {get_old_section_webp,ImageFormat::WebP}
+create_get_old_section_func! {get_old_section_png,ImageFormat::Png}
+create_get_old_section_func! {get_old_section_tiff,ImageFormat::Tiff}
 create_get_output_func! {get_output_jpeg,get_output_jpeg_native,join_jpeg,ImageFormat::Jpeg}
 create_get_output_func! {get_output_webp,get_output_webp_native,join_webp,ImageFormat::WebP}
+create_get_output_func! {get_output_png,get_output_png_native,join_png,ImageFormat::Png}
+create_get_output_func! {get_output_tiff,get_output_tiff_native,join_tiff,ImageFormat::Tiff}
+
+#[wasm_bindgen]
+pub fn set_target_gif(ptr: *mut WatermarkTask, inp_bytes: Vec<u8>) -> Result<(), JsValue> {
+    let watermark_task = unsafe { &mut *ptr };
+
+    if task_set_target_gif(watermark_task, &inp_bytes).is_err() {
+        let err_message = serde_wasm_bindgen::to_value("Cannot set target")?;
+        return Err(err_message);
+    }
+    Ok(())
+}
+
+create_get_output_frames_func! {
+    get_output_gif,
+    get_output_gif_native,
+    encode_frames_gif,
+    |source: &[u8], _: &[FrameSection], indices: &[usize]| encode_gif_frames(source, indices),
+    join_gif
+}
+
+#[wasm_bindgen]
+pub fn set_target_webp_animated(ptr: *mut WatermarkTask, inp_bytes: Vec<u8>) -> Result<(), JsValue> {
+    let watermark_task = unsafe { &mut *ptr };
+
+    if task_set_target_webp_animated(watermark_task, &inp_bytes).is_err() {
+        let err_message = serde_wasm_bindgen::to_value("Cannot set target")?;
+        return Err(err_message);
+    }
+    Ok(())
+}
+
+create_get_output_frames_func! {
+    get_output_webp_animated,
+    get_output_webp_animated_native,
+    encode_frames_webp,
+    |_: &[u8], sections: &[FrameSection], _: &[usize]| encode_frame_sections(sections).ok(),
+    join_webp
+}
+
+#[wasm_bindgen]
+pub fn set_target_auto(ptr: *mut WatermarkTask, inp_bytes: Vec<u8>) -> Result<(), JsValue> {
+    let watermark_task = unsafe { &mut *ptr };
+
+    if task_set_target_auto(watermark_task, &inp_bytes).is_err() {
+        let err_message = serde_wasm_bindgen::to_value("Cannot set target")?;
+        return Err(err_message);
+    }
+    Ok(())
+}
+
+#[wasm_bindgen]
+pub fn get_output_auto(ptr: *mut WatermarkTask) -> Result<JsValue, JsValue> {
+    let watermark_task = unsafe { &mut *ptr };
+    let output = match watermark_task.get_target_format() {
+        Some(FileFormat::Jpeg) => get_output_jpeg_native(watermark_task),
+        Some(FileFormat::WebP) if watermark_task.get_target_source().is_some() => {
+            get_output_webp_animated_native(watermark_task)
+        }
+        Some(FileFormat::WebP) => get_output_webp_native(watermark_task),
+        Some(FileFormat::Png) => get_output_png_native(watermark_task),
+        // Rejected by set_target_auto
+        Some(FileFormat::Heif) => Err("Cannot encode HEIF output"),
+        Some(FileFormat::Gif) => get_output_gif_native(watermark_task),
+        Some(FileFormat::Tiff) => get_output_tiff_native(watermark_task),
+        None => Err("Target format unknown"),
+    };
+
+    match output {
+        Ok(result) => Ok(serde_wasm_bindgen::to_value(&result)?),
+        Err(err) => Err(serde_wasm_bindgen::to_value(err)?),
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -80,8 +239,9 @@ mod tests {
     #[test]
     fn test_webp_watermark_task() {
         let mut watermark_task = WatermarkTask::new();
-        let watermark = image::open("../watermark.webp").unwrap();
-        let img = image::open("../test.webp").unwrap();
+        let fixtures = concat!(env!("CARGO_MANIFEST_DIR"), "/../drmcore/tests/fixtures");
+        let watermark = image::open(format!("{fixtures}/watermark.webp")).unwrap();
+        let img = image::open(format!("{fixtures}/test.webp")).unwrap();
 
         watermark_task.set_target(Some(img));
         watermark_task.set_watermark(Some(watermark));
@@ -95,6 +255,6 @@ mod tests {
             }
         }
 
-        let output = get_output_webp_native(&mut watermark_task);
+        let _output = get_output_webp_native(&mut watermark_task);
     }
 }
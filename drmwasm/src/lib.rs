@@ -1,6 +1,12 @@
+mod stream;
 mod utils;
 
-use drmcore::file_splitter::{split_jpeg, split_webp};
+use drmcore::archive::{restore_archive, restore_archive_page};
+use drmcore::file_splitter::{
+    restore_gif, restore_wav, restore_webp, split_auto, split_gif_frames, split_heif, split_jpeg,
+    split_png, split_tiff, split_webp, split_webp_frames, FramesSplitResult, SplitResult,
+};
+use drmcore::probe::probe_auto;
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
 
@@ -19,66 +25,211 @@ pub struct ReplacementImage {
     real_img: Vec<u8>,
 }
 
-#[wasm_bindgen]
-pub fn get_replacement_jpeg(inp_bytes: Vec<u8>, enc_key: Vec<u8>) -> Result<JsValue, JsValue> {
-    let enc_key_slice = enc_key.get(0..32);
-
-    if let None = enc_key_slice {
-        let err_data = serde_wasm_bindgen::to_value("Enc key must 32 bytes")?;
-        return Err(err_data);
+impl From<SplitResult> for ReplacementImage {
+    fn from(split_data: SplitResult) -> Self {
+        ReplacementImage {
+            real_img: split_data.old_section_img,
+            x: split_data.position.x,
+            y: split_data.position.y,
+            height: split_data.dimension.height,
+            width: split_data.dimension.width,
+        }
     }
+}
+
+#[derive(Serialize)]
+pub struct ReplacementFrame {
+    pub index: usize,
+    pub x: u32,
+    pub y: u32,
+    real_img: Vec<u8>,
+}
 
-    let enc_key_slice = enc_key_slice.unwrap();
-    let fixed_enc_key: &[u8;32] = enc_key_slice.try_into().unwrap();
-    let split_result = split_jpeg(&inp_bytes, fixed_enc_key);
-
-    match split_result {
-        Ok(split_data) => {
-            let replacement = ReplacementImage {
-                real_img: split_data.old_section_img,
-                x: split_data.position.x,
-                y: split_data.position.y,
-                height: split_data.dimension.height,
-                width: split_data.dimension.width,
-            };
-
-            return Ok(serde_wasm_bindgen::to_value(&replacement)?);
+#[derive(Serialize)]
+pub struct ReplacementFrames {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    frames: Vec<ReplacementFrame>,
+}
+
+impl From<FramesSplitResult> for ReplacementFrames {
+    fn from(split_data: FramesSplitResult) -> Self {
+        ReplacementFrames {
+            x: split_data.position.x,
+            y: split_data.position.y,
+            height: split_data.dimension.height,
+            width: split_data.dimension.width,
+            frames: split_data
+                .frames
+                .into_iter()
+                .map(|f| ReplacementFrame {
+                    index: f.index,
+                    x: f.position.x,
+                    y: f.position.y,
+                    real_img: f.section_img,
+                })
+                .collect(),
         }
-        Err(err) => {
-            let err_data = serde_wasm_bindgen::to_value(&err.to_string())?;
-            return Err(err_data);
-        },
     }
 }
 
+#[derive(Serialize)]
+pub struct ImageInfo {
+    pub width: u32,
+    pub height: u32,
+    pub has_alpha: bool,
+    pub progressive: bool,
+    pub components: usize,
+}
+
+// Only the first 32 bytes of the key are used
+pub(crate) fn get_enc_key(enc_key: &[u8]) -> Result<&[u8; 32], JsValue> {
+    match enc_key.get(0..32) {
+        Some(enc_key_slice) => Ok(enc_key_slice.try_into().unwrap()),
+        None => Err(serde_wasm_bindgen::to_value("Enc key must 32 bytes")?),
+    }
+}
+
+pub(crate) fn to_js_error<E: ToString>(err: E) -> JsValue {
+    JsValue::from_str(&err.to_string())
+}
+
+#[wasm_bindgen]
+pub fn get_image_info(inp_bytes: Vec<u8>) -> Result<JsValue, JsValue> {
+    let probe = probe_auto(&inp_bytes).map_err(to_js_error)?;
+    let info = ImageInfo {
+        width: probe.dimension.width,
+        height: probe.dimension.height,
+        has_alpha: probe.has_alpha,
+        progressive: probe.progressive,
+        components: probe.components.len(),
+    };
+
+    Ok(serde_wasm_bindgen::to_value(&info)?)
+}
+
+#[wasm_bindgen]
+pub fn get_replacement_jpeg(inp_bytes: Vec<u8>, enc_key: Vec<u8>) -> Result<JsValue, JsValue> {
+    let fixed_enc_key = get_enc_key(&enc_key)?;
+    let split_data = split_jpeg(&inp_bytes, fixed_enc_key).map_err(to_js_error)?;
+    let replacement = ReplacementImage::from(split_data);
+
+    Ok(serde_wasm_bindgen::to_value(&replacement)?)
+}
+
 #[wasm_bindgen]
 pub fn get_replacement_webp(inp_bytes: Vec<u8>, enc_key: Vec<u8>) -> Result<JsValue, JsValue> {
-    let enc_key_slice = enc_key.get(0..32);
+    let fixed_enc_key = get_enc_key(&enc_key)?;
+    let split_data = split_webp(&inp_bytes, fixed_enc_key).map_err(to_js_error)?;
+    let replacement = ReplacementImage::from(split_data);
 
-    if let None = enc_key_slice {
-        let err_data = serde_wasm_bindgen::to_value("Enc key must 32 bytes")?;
-        return Err(err_data);
-    }
+    Ok(serde_wasm_bindgen::to_value(&replacement)?)
+}
 
-    let enc_key_slice = enc_key_slice.unwrap();
-    let fixed_enc_key: &[u8;32] = enc_key_slice.try_into().unwrap();
-    let split_result = split_webp(&inp_bytes, fixed_enc_key);
-
-    match split_result {
-        Ok(split_data) => {
-            let replacement = ReplacementImage {
-                real_img: split_data.old_section_img,
-                x: split_data.position.x,
-                y: split_data.position.y,
-                height: split_data.dimension.height,
-                width: split_data.dimension.width,
-            };
-
-            return Ok(serde_wasm_bindgen::to_value(&replacement)?);
-        }
-        Err(err) => {
-            let err_data = serde_wasm_bindgen::to_value(&err.to_string())?;
-            return Err(err_data);
-        },
-    }
+#[wasm_bindgen]
+pub fn get_replacement_png(inp_bytes: Vec<u8>, enc_key: Vec<u8>) -> Result<JsValue, JsValue> {
+    let fixed_enc_key = get_enc_key(&enc_key)?;
+    let split_data = split_png(&inp_bytes, fixed_enc_key).map_err(to_js_error)?;
+    let replacement = ReplacementImage::from(split_data);
+
+    Ok(serde_wasm_bindgen::to_value(&replacement)?)
+}
+
+#[wasm_bindgen]
+pub fn get_replacement_tiff(inp_bytes: Vec<u8>, enc_key: Vec<u8>) -> Result<JsValue, JsValue> {
+    let fixed_enc_key = get_enc_key(&enc_key)?;
+    let split_data = split_tiff(&inp_bytes, fixed_enc_key).map_err(to_js_error)?;
+    let replacement = ReplacementImage::from(split_data);
+
+    Ok(serde_wasm_bindgen::to_value(&replacement)?)
+}
+
+#[wasm_bindgen]
+pub fn get_replacement_heif(inp_bytes: Vec<u8>, enc_key: Vec<u8>) -> Result<JsValue, JsValue> {
+    let fixed_enc_key = get_enc_key(&enc_key)?;
+    let split_data = split_heif(&inp_bytes, fixed_enc_key).map_err(to_js_error)?;
+    let replacement = ReplacementImage::from(split_data);
+
+    Ok(serde_wasm_bindgen::to_value(&replacement)?)
+}
+
+// Each frame is the original gif frame as a gif of its own, drawn at its offset it takes the
+// place of the watermarked frame
+#[wasm_bindgen]
+pub fn get_replacement_gif(inp_bytes: Vec<u8>, enc_key: Vec<u8>) -> Result<JsValue, JsValue> {
+    let fixed_enc_key = get_enc_key(&enc_key)?;
+    let split_data = split_gif_frames(&inp_bytes, fixed_enc_key).map_err(to_js_error)?;
+    let replacement = ReplacementFrames::from(split_data);
+
+    Ok(serde_wasm_bindgen::to_value(&replacement)?)
+}
+
+// Same as get_replacement_gif for an animated webp, each frame is a webp of its own
+#[wasm_bindgen]
+pub fn get_replacement_webp_frames(
+    inp_bytes: Vec<u8>,
+    enc_key: Vec<u8>,
+) -> Result<JsValue, JsValue> {
+    let fixed_enc_key = get_enc_key(&enc_key)?;
+    let split_data = split_webp_frames(&inp_bytes, fixed_enc_key).map_err(to_js_error)?;
+    let replacement = ReplacementFrames::from(split_data);
+
+    Ok(serde_wasm_bindgen::to_value(&replacement)?)
+}
+
+// Still images only, an animated gif or webp is read with get_replacement_gif or
+// get_replacement_webp_frames
+#[wasm_bindgen]
+pub fn get_replacement_auto(inp_bytes: Vec<u8>, enc_key: Vec<u8>) -> Result<JsValue, JsValue> {
+    let fixed_enc_key = get_enc_key(&enc_key)?;
+    let split_data = split_auto(&inp_bytes, fixed_enc_key).map_err(to_js_error)?;
+    let replacement = ReplacementImage::from(split_data);
+
+    Ok(serde_wasm_bindgen::to_value(&replacement)?)
+}
+
+#[wasm_bindgen]
+pub fn get_restored_gif(inp_bytes: Vec<u8>, enc_key: Vec<u8>) -> Result<JsValue, JsValue> {
+    let fixed_enc_key = get_enc_key(&enc_key)?;
+    let restored = restore_gif(&inp_bytes, fixed_enc_key).map_err(to_js_error)?;
+
+    Ok(serde_wasm_bindgen::to_value(&restored)?)
+}
+
+#[wasm_bindgen]
+pub fn get_restored_webp(inp_bytes: Vec<u8>, enc_key: Vec<u8>) -> Result<JsValue, JsValue> {
+    let fixed_enc_key = get_enc_key(&enc_key)?;
+    let restored = restore_webp(&inp_bytes, fixed_enc_key).map_err(to_js_error)?;
+
+    Ok(serde_wasm_bindgen::to_value(&restored)?)
+}
+
+#[wasm_bindgen]
+pub fn get_restored_wav(inp_bytes: Vec<u8>, enc_key: Vec<u8>) -> Result<JsValue, JsValue> {
+    let fixed_enc_key = get_enc_key(&enc_key)?;
+    let restored = restore_wav(&inp_bytes, fixed_enc_key).map_err(to_js_error)?;
+
+    Ok(serde_wasm_bindgen::to_value(&restored)?)
+}
+
+#[wasm_bindgen]
+pub fn get_restored_archive(inp_bytes: Vec<u8>, enc_key: Vec<u8>) -> Result<JsValue, JsValue> {
+    let fixed_enc_key = get_enc_key(&enc_key)?;
+    let restored = restore_archive(&inp_bytes, fixed_enc_key).map_err(to_js_error)?;
+
+    Ok(serde_wasm_bindgen::to_value(&restored)?)
+}
+
+#[wasm_bindgen]
+pub fn get_restored_archive_page(
+    inp_bytes: Vec<u8>,
+    name: String,
+    enc_key: Vec<u8>,
+) -> Result<JsValue, JsValue> {
+    let fixed_enc_key = get_enc_key(&enc_key)?;
+    let restored = restore_archive_page(&inp_bytes, &name, fixed_enc_key).map_err(to_js_error)?;
+
+    Ok(serde_wasm_bindgen::to_value(&restored)?)
 }
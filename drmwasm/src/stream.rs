@@ -0,0 +1,50 @@
+use crate::{get_enc_key, to_js_error, ReplacementImage};
+use drmcore::file_format::FileFormat;
+use drmcore::file_splitter::StreamSplitter;
+use std::mem::transmute;
+use wasm_bindgen::prelude::*;
+
+fn create_replacement_stream(
+    format: FileFormat,
+    enc_key: Vec<u8>,
+) -> Result<*mut StreamSplitter, JsValue> {
+    let splitter = StreamSplitter::new(format, get_enc_key(&enc_key)?).map_err(to_js_error)?;
+
+    let ptr: *mut StreamSplitter = unsafe { transmute(Box::new(splitter)) };
+
+    Ok(ptr)
+}
+
+#[wasm_bindgen]
+pub fn create_replacement_stream_jpeg(enc_key: Vec<u8>) -> Result<*mut StreamSplitter, JsValue> {
+    create_replacement_stream(FileFormat::Jpeg, enc_key)
+}
+
+#[wasm_bindgen]
+pub fn create_replacement_stream_webp(enc_key: Vec<u8>) -> Result<*mut StreamSplitter, JsValue> {
+    create_replacement_stream(FileFormat::WebP, enc_key)
+}
+
+// Returns null until the replacement is complete, which for a jpeg is before its image data
+#[wasm_bindgen]
+pub fn push_replacement_stream(
+    ptr: *mut StreamSplitter,
+    chunk: Vec<u8>,
+) -> Result<JsValue, JsValue> {
+    let splitter = unsafe { &mut *ptr };
+
+    match splitter.push(&chunk).map_err(to_js_error)? {
+        Some(split_data) => {
+            let replacement = ReplacementImage::from(split_data);
+
+            Ok(serde_wasm_bindgen::to_value(&replacement)?)
+        }
+        None => Ok(JsValue::NULL),
+    }
+}
+
+#[wasm_bindgen]
+pub fn destroy_replacement_stream(ptr: *mut StreamSplitter) {
+    let _splitter: Box<StreamSplitter> = unsafe { transmute(ptr) };
+    // Drop
+}